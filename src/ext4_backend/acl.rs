@@ -0,0 +1,581 @@
+//! POSIX ACL 模块
+//!
+//! 在扩展属性之上读写 system.posix_acl_access / system.posix_acl_default。
+//! 磁盘上为 ext4 紧凑格式：4 字节版本号后跟条目，USER/GROUP 条目 8 字节（带 id），其余条目 4 字节。
+//! 另外提供创建子项时的默认 ACL 继承，以及按 uid/gid 集合的访问检查。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::endian::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::xattr::*;
+use alloc::vec::Vec;
+use log::debug;
+
+/// ext4 磁盘 ACL 版本号
+pub const EXT4_ACL_VERSION: u32 = 0x0001;
+
+/// 权限位（与 rwx 的低三位一致）
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+/// 磁盘上的条目标签（e_tag）
+pub const ACL_USER_OBJ: u16 = 0x01;
+pub const ACL_USER: u16 = 0x02;
+pub const ACL_GROUP_OBJ: u16 = 0x04;
+pub const ACL_GROUP: u16 = 0x08;
+pub const ACL_MASK: u16 = 0x10;
+pub const ACL_OTHER: u16 = 0x20;
+
+/// 访问 ACL 的扩展属性名
+pub const XATTR_NAME_POSIX_ACL_ACCESS: &str = "system.posix_acl_access";
+/// 默认 ACL 的扩展属性名
+pub const XATTR_NAME_POSIX_ACL_DEFAULT: &str = "system.posix_acl_default";
+
+/// ACL 条目标签，USER/GROUP 带 uid/gid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclTag {
+    UserObj,
+    User(u32),
+    GroupObj,
+    Group(u32),
+    Mask,
+    Other,
+}
+
+impl AclTag {
+    /// 磁盘上的 e_tag 取值
+    pub fn disk_tag(&self) -> u16 {
+        match self {
+            AclTag::UserObj => ACL_USER_OBJ,
+            AclTag::User(_) => ACL_USER,
+            AclTag::GroupObj => ACL_GROUP_OBJ,
+            AclTag::Group(_) => ACL_GROUP,
+            AclTag::Mask => ACL_MASK,
+            AclTag::Other => ACL_OTHER,
+        }
+    }
+}
+
+/// 一条 ACL 条目
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perm: u16,
+}
+
+/// ACL 种类：访问 ACL 或目录的默认 ACL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclType {
+    Access,
+    Default,
+}
+
+impl AclType {
+    /// 对应的扩展属性名
+    pub fn xattr_name(&self) -> &'static str {
+        match self {
+            AclType::Access => XATTR_NAME_POSIX_ACL_ACCESS,
+            AclType::Default => XATTR_NAME_POSIX_ACL_DEFAULT,
+        }
+    }
+}
+
+/// 发起访问的身份：fsuid、fsgid 和附加组
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// gid 是否属于该身份（主组或附加组）
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+/// POSIX ACL，条目按 USER_OBJ、USER、GROUP_OBJ、GROUP、MASK、OTHER 排序，同类按 id 升序
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Acl {
+    pub entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// 由 mode 权限位构造最小 ACL（USER_OBJ / GROUP_OBJ / OTHER）
+    pub fn from_mode(mode: u16) -> Self {
+        Self {
+            entries: alloc::vec![
+                AclEntry { tag: AclTag::UserObj, perm: (mode >> 6) & 7 },
+                AclEntry { tag: AclTag::GroupObj, perm: (mode >> 3) & 7 },
+                AclEntry { tag: AclTag::Other, perm: mode & 7 },
+            ],
+        }
+    }
+
+    /// 解析 ext4 紧凑格式
+    pub fn from_disk(bytes: &[u8]) -> BlockDevResult<Self> {
+        if bytes.len() < 4 || read_u32_le(&bytes[0..4]) != EXT4_ACL_VERSION {
+            return Err(BlockDevError::Corrupted);
+        }
+        let mut entries = Vec::new();
+        let mut off = 4;
+        while off < bytes.len() {
+            if off + 4 > bytes.len() {
+                return Err(BlockDevError::Corrupted);
+            }
+            let tag = read_u16_le(&bytes[off..off + 2]);
+            let perm = read_u16_le(&bytes[off + 2..off + 4]);
+            let id = || -> BlockDevResult<u32> {
+                if off + 8 > bytes.len() {
+                    return Err(BlockDevError::Corrupted);
+                }
+                Ok(read_u32_le(&bytes[off + 4..off + 8]))
+            };
+            let (tag, len) = match tag {
+                ACL_USER_OBJ => (AclTag::UserObj, 4),
+                ACL_USER => (AclTag::User(id()?), 8),
+                ACL_GROUP_OBJ => (AclTag::GroupObj, 4),
+                ACL_GROUP => (AclTag::Group(id()?), 8),
+                ACL_MASK => (AclTag::Mask, 4),
+                ACL_OTHER => (AclTag::Other, 4),
+                _ => return Err(BlockDevError::Corrupted),
+            };
+            entries.push(AclEntry { tag, perm });
+            off += len;
+        }
+        Ok(Self { entries })
+    }
+
+    /// 编码为 ext4 紧凑格式
+    pub fn to_disk(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(4 + self.entries.len() * 8);
+        out.extend_from_slice(&EXT4_ACL_VERSION.to_le_bytes());
+        for e in &self.entries {
+            out.extend_from_slice(&e.tag.disk_tag().to_le_bytes());
+            out.extend_from_slice(&e.perm.to_le_bytes());
+            if let AclTag::User(id) | AclTag::Group(id) = e.tag {
+                out.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// 检查条目顺序和完整性（与内核 posix_acl_valid 一致）：
+    /// 必须有 USER_OBJ、GROUP_OBJ、OTHER 各一条，有命名条目时必须有 MASK，命名条目 id 严格递增
+    pub fn is_valid(&self) -> bool {
+        // 0 = 期待 USER_OBJ，1 = USER，2 = GROUP，3 = 期待 OTHER，4 = 结束
+        let mut state = 0;
+        let mut needs_mask = false;
+        let mut last_id: Option<u32> = None;
+        for e in &self.entries {
+            if e.perm & !(ACL_READ | ACL_WRITE | ACL_EXECUTE) != 0 {
+                return false;
+            }
+            match e.tag {
+                AclTag::UserObj if state == 0 => state = 1,
+                AclTag::User(id) if state == 1 => {
+                    if last_id.is_some_and(|prev| id <= prev) {
+                        return false;
+                    }
+                    last_id = Some(id);
+                    needs_mask = true;
+                }
+                AclTag::GroupObj if state == 1 => {
+                    state = 2;
+                    last_id = None;
+                }
+                AclTag::Group(id) if state == 2 => {
+                    if last_id.is_some_and(|prev| id <= prev) {
+                        return false;
+                    }
+                    last_id = Some(id);
+                    needs_mask = true;
+                }
+                AclTag::Mask if state == 2 => state = 3,
+                AclTag::Other if state == 3 || (state == 2 && !needs_mask) => state = 4,
+                _ => return false,
+            }
+        }
+        state == 4
+    }
+
+    /// 是否为最小 ACL（只有 USER_OBJ / GROUP_OBJ / OTHER，完全可以用 mode 表达）
+    pub fn is_minimal(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|e| matches!(e.tag, AclTag::UserObj | AclTag::GroupObj | AclTag::Other))
+    }
+
+    /// ACL 对应的 mode 权限位：组权限取 MASK（没有 MASK 时取 GROUP_OBJ）
+    pub fn mode(&self) -> u16 {
+        let mut mode = 0u16;
+        for e in &self.entries {
+            match e.tag {
+                AclTag::UserObj => mode |= (e.perm & 7) << 6,
+                AclTag::GroupObj => mode |= (e.perm & 7) << 3,
+                AclTag::Other => mode |= e.perm & 7,
+                _ => {}
+            }
+        }
+        if let Some(mask) = self.entries.iter().find(|e| e.tag == AclTag::Mask) {
+            mode = (mode & !Ext4Inode::S_IRWXG) | ((mask.perm & 7) << 3);
+        }
+        mode
+    }
+
+    /// chmod 后按新 mode 改写对应条目（__posix_acl_chmod）：有 MASK 时改 MASK，否则改 GROUP_OBJ
+    pub fn chmod(&mut self, mode: u16) {
+        let has_mask = self.entries.iter().any(|e| e.tag == AclTag::Mask);
+        for e in &mut self.entries {
+            match e.tag {
+                AclTag::UserObj => e.perm = (mode >> 6) & 7,
+                AclTag::GroupObj if !has_mask => e.perm = (mode >> 3) & 7,
+                AclTag::Mask => e.perm = (mode >> 3) & 7,
+                AclTag::Other => e.perm = mode & 7,
+                _ => {}
+            }
+        }
+    }
+
+    /// 新建子项时用请求的 mode 收紧继承来的 ACL，同时按 ACL 收紧 mode（posix_acl_create_masq）。
+    /// 返回 ACL 是否仍需单独保存（不能仅用 mode 表达）
+    fn create_masq(&mut self, mode: &mut u16) -> bool {
+        let mut not_equiv = false;
+        let mut group_obj: Option<usize> = None;
+        let mut mask_obj: Option<usize> = None;
+        for (i, e) in self.entries.iter_mut().enumerate() {
+            match e.tag {
+                AclTag::UserObj => {
+                    e.perm &= (*mode >> 6) & 7;
+                    *mode &= (e.perm << 6) | !Ext4Inode::S_IRWXU;
+                }
+                AclTag::User(_) | AclTag::Group(_) => not_equiv = true,
+                AclTag::GroupObj => group_obj = Some(i),
+                AclTag::Mask => {
+                    mask_obj = Some(i);
+                    not_equiv = true;
+                }
+                AclTag::Other => {
+                    e.perm &= *mode & 7;
+                    *mode &= e.perm | !Ext4Inode::S_IRWXO;
+                }
+            }
+        }
+        if let Some(i) = mask_obj.or(group_obj) {
+            let e = &mut self.entries[i];
+            e.perm &= (*mode >> 3) & 7;
+            *mode &= (e.perm << 3) | !Ext4Inode::S_IRWXG;
+        }
+        not_equiv
+    }
+
+    /// 按 ACL 判断 cred 对属主为 (owner_uid, owner_gid) 的文件是否拥有 want 中的全部权限（posix_acl_permission）
+    pub fn permission(&self, owner_uid: u32, owner_gid: u32, cred: &Credentials, want: u16) -> bool {
+        let want = want & (ACL_READ | ACL_WRITE | ACL_EXECUTE);
+        let masked = |idx: usize| {
+            let perm = self.entries[idx].perm;
+            match self.entries[idx + 1..].iter().find(|e| e.tag == AclTag::Mask) {
+                Some(mask) => perm & mask.perm & want == want,
+                None => perm & want == want,
+            }
+        };
+        let mut found_group = false;
+        for (i, e) in self.entries.iter().enumerate() {
+            match e.tag {
+                AclTag::UserObj if owner_uid == cred.uid => return e.perm & want == want,
+                AclTag::User(uid) if uid == cred.uid => return masked(i),
+                AclTag::GroupObj if cred.in_group(owner_gid) => {
+                    found_group = true;
+                    if e.perm & want == want {
+                        return masked(i);
+                    }
+                }
+                AclTag::Group(gid) if cred.in_group(gid) => {
+                    found_group = true;
+                    if e.perm & want == want {
+                        return masked(i);
+                    }
+                }
+                AclTag::Other => return !found_group && e.perm & want == want,
+                _ => {}
+            }
+        }
+        false
+    }
+}
+
+/// 按 ACL（没有时按 mode 权限位）判断 cred 是否拥有 want 中的全部权限。
+/// 不包含 root（CAP_DAC_OVERRIDE）的特权绕过，由调用方决定
+pub fn check_permission(inode: &Ext4Inode, acl: Option<&Acl>, cred: &Credentials, want: u16) -> bool {
+    if let Some(acl) = acl {
+        return acl.permission(inode.uid(), inode.gid(), cred, want);
+    }
+    let want = want & (ACL_READ | ACL_WRITE | ACL_EXECUTE);
+    let perm = if inode.uid() == cred.uid {
+        inode.i_mode >> 6
+    } else if cred.in_group(inode.gid()) {
+        inode.i_mode >> 3
+    } else {
+        inode.i_mode
+    } & 7;
+    perm & want == want
+}
+
+/// 读取 inode 的 ACL；没有设置时返回 None
+pub fn get_acl<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    acl_type: AclType,
+) -> BlockDevResult<Option<Acl>> {
+    let Some(value) = getxattr(fs, block_dev, inode_num, acl_type.xattr_name())? else {
+        return Ok(None);
+    };
+    let acl = Acl::from_disk(&value)?;
+    Ok((!acl.entries.is_empty()).then_some(acl))
+}
+
+/// 设置或删除（acl 为 None）inode 的 ACL。
+/// 访问 ACL 会同步 mode 权限位，能用 mode 表达的访问 ACL 不单独保存；默认 ACL 只能设置在目录上
+pub fn set_acl<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    acl_type: AclType,
+    acl: Option<&Acl>,
+) -> BlockDevResult<()> {
+    let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    if let Some(acl) = acl
+        && !acl.is_valid()
+    {
+        return Err(BlockDevError::InvalidInput);
+    }
+    if acl_type == AclType::Default && acl.is_some() && !inode.is_dir() {
+        return Err(BlockDevError::InvalidInput);
+    }
+
+    let mut stored = acl;
+    if acl_type == AclType::Access
+        && let Some(acl) = acl
+    {
+        let mode = (inode.i_mode & !0o777) | acl.mode();
+        fs.modify_inode(block_dev, inode_num, |inode| inode.i_mode = mode)?;
+        if acl.is_minimal() {
+            stored = None;
+        }
+    }
+
+    match stored {
+        Some(acl) => setxattr(fs, block_dev, inode_num, acl_type.xattr_name(), &acl.to_disk(), 0),
+        None => match removexattr(fs, block_dev, inode_num, acl_type.xattr_name()) {
+            Ok(()) | Err(BlockDevError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+/// 新建子项时继承父目录的默认 ACL（posix_acl_create）：
+/// 子目录复制默认 ACL，访问 ACL 由默认 ACL 按子项 mode 收紧得到，同时收紧子项 mode。符号链接不处理
+pub fn inherit_acl<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    parent_ino: u32,
+    child_ino: u32,
+) -> BlockDevResult<()> {
+    let child = fs.get_inode_by_num(block_dev, child_ino)?;
+    if child.is_symlink() {
+        return Ok(());
+    }
+    let Some(default_acl) = get_acl(fs, block_dev, parent_ino, AclType::Default)? else {
+        return Ok(());
+    };
+
+    if child.is_dir() {
+        setxattr(
+            fs,
+            block_dev,
+            child_ino,
+            XATTR_NAME_POSIX_ACL_DEFAULT,
+            &default_acl.to_disk(),
+            0,
+        )?;
+    }
+
+    let mut access = default_acl;
+    let mut mode = child.i_mode;
+    if access.create_masq(&mut mode) {
+        setxattr(
+            fs,
+            block_dev,
+            child_ino,
+            XATTR_NAME_POSIX_ACL_ACCESS,
+            &access.to_disk(),
+            0,
+        )?;
+    }
+    fs.modify_inode(block_dev, child_ino, |inode| inode.i_mode = mode)?;
+    debug!("Inode {child_ino} inherited default ACL of dir {parent_ino}, mode={mode:o}");
+    Ok(())
+}
+
+/// 检查 cred 对 inode 是否拥有 want 中的全部权限（ACL 加 mode 权限位）
+pub fn check_access<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    cred: &Credentials,
+    want: u16,
+) -> BlockDevResult<bool> {
+    let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let acl = get_acl(fs, block_dev, inode_num, AclType::Access)?;
+    Ok(check_permission(&inode, acl.as_ref(), cred, want))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::dir::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::test_support::*;
+
+    fn entry(tag: AclTag, perm: u16) -> AclEntry {
+        AclEntry { tag, perm }
+    }
+
+    /// user::rwx user:1000:rw- group::r-x group:50:rwx mask::r-x other::---
+    fn sample_acl() -> Acl {
+        Acl {
+            entries: alloc::vec![
+                entry(AclTag::UserObj, 7),
+                entry(AclTag::User(1000), 6),
+                entry(AclTag::GroupObj, 5),
+                entry(AclTag::Group(50), 7),
+                entry(AclTag::Mask, 5),
+                entry(AclTag::Other, 0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_acl_disk_roundtrip() {
+        let acl = sample_acl();
+        let bytes = acl.to_disk();
+        // 4 字节头 + 4 个短条目 + 2 个带 id 的条目
+        assert_eq!(bytes.len(), 4 + 4 * 4 + 2 * 8);
+        assert_eq!(&bytes[0..4], &[1, 0, 0, 0]);
+        assert_eq!(&bytes[8..16], &[0x02, 0, 6, 0, 0xE8, 0x03, 0, 0]);
+        assert_eq!(Acl::from_disk(&bytes).unwrap(), acl);
+        assert_eq!(Acl::from_disk(&bytes[..bytes.len() - 2]), Err(BlockDevError::Corrupted));
+    }
+
+    #[test]
+    fn test_acl_validity_and_mode() {
+        let acl = sample_acl();
+        assert!(acl.is_valid());
+        assert!(!acl.is_minimal());
+        assert_eq!(acl.mode(), 0o750);
+
+        let minimal = Acl::from_mode(0o640);
+        assert!(minimal.is_valid() && minimal.is_minimal());
+        assert_eq!(minimal.mode(), 0o640);
+
+        let mut no_mask = sample_acl();
+        no_mask.entries.remove(4);
+        assert!(!no_mask.is_valid());
+        let mut unsorted = sample_acl();
+        unsorted.entries.swap(0, 1);
+        assert!(!unsorted.is_valid());
+    }
+
+    #[test]
+    fn test_acl_permission() {
+        let acl = sample_acl();
+        let owner = Credentials { uid: 1, gid: 1, groups: Vec::new() };
+        let named = Credentials { uid: 1000, gid: 9, groups: Vec::new() };
+        let member = Credentials { uid: 7, gid: 9, groups: alloc::vec![50] };
+        let other = Credentials { uid: 7, gid: 9, groups: Vec::new() };
+        assert!(acl.permission(1, 2, &owner, ACL_READ | ACL_WRITE | ACL_EXECUTE));
+        assert!(acl.permission(1, 2, &named, ACL_READ));
+        // 命名用户的 w 被 mask 屏蔽
+        assert!(!acl.permission(1, 2, &named, ACL_WRITE));
+        assert!(acl.permission(1, 2, &member, ACL_READ | ACL_EXECUTE));
+        assert!(!acl.permission(1, 2, &member, ACL_WRITE));
+        assert!(!acl.permission(1, 2, &other, ACL_READ));
+
+        let mut inode = Ext4Inode::default();
+        inode.i_mode = Ext4Inode::S_IFREG | 0o604;
+        inode.i_uid = 1;
+        inode.i_gid = 2;
+        assert!(check_permission(&inode, None, &owner, ACL_READ | ACL_WRITE));
+        assert!(check_permission(&inode, None, &other, ACL_READ));
+        assert!(!check_permission(&inode, None, &Credentials { uid: 5, gid: 2, groups: Vec::new() }, ACL_READ));
+    }
+
+    #[test]
+    fn test_create_masq_inherits_default() {
+        let mut acl = sample_acl();
+        let mut mode = Ext4Inode::S_IFREG | 0o644;
+        assert!(acl.create_masq(&mut mode));
+        assert_eq!(mode, Ext4Inode::S_IFREG | 0o640);
+        assert_eq!(acl.entries[0].perm, 6);
+        assert_eq!(acl.entries[4].perm, 4);
+        assert_eq!(acl.entries[5].perm, 0);
+
+        let mut minimal = Acl::from_mode(0o750);
+        let mut mode = Ext4Inode::S_IFDIR | 0o755;
+        assert!(!minimal.create_masq(&mut mode));
+        assert_eq!(mode, Ext4Inode::S_IFDIR | 0o750);
+    }
+
+    #[test]
+    fn test_default_acl_inherited_by_new_children() {
+        let (mut dev, mut fs) = setup();
+        let (dir, _) = mkdir_with_ino(&mut dev, &mut fs, "/shared").unwrap();
+        set_acl(&mut fs, &mut dev, dir, AclType::Default, Some(&sample_acl())).unwrap();
+        assert_eq!(get_acl(&mut fs, &mut dev, dir, AclType::Default).unwrap(), Some(sample_acl()));
+        assert_eq!(get_acl(&mut fs, &mut dev, dir, AclType::Access).unwrap(), None);
+
+        // 文件：访问 ACL 由默认 ACL 按 0644 收紧，mode 跟着收紧，没有默认 ACL
+        let (file, inode) = mkfile_with_ino(&mut dev, &mut fs, "/shared/f", None, None).unwrap();
+        assert_eq!(inode.i_mode, Ext4Inode::S_IFREG | 0o640);
+        let access = get_acl(&mut fs, &mut dev, file, AclType::Access).unwrap().unwrap();
+        assert_eq!(access.entries[0], entry(AclTag::UserObj, 6));
+        assert_eq!(access.entries[1], entry(AclTag::User(1000), 6));
+        assert_eq!(access.entries[4], entry(AclTag::Mask, 4));
+        assert_eq!(access.entries[5], entry(AclTag::Other, 0));
+        assert_eq!(get_acl(&mut fs, &mut dev, file, AclType::Default).unwrap(), None);
+
+        // 子目录：默认 ACL 原样复制，访问 ACL 按 0755 收紧
+        let (sub, inode) = mkdir_with_ino(&mut dev, &mut fs, "/shared/sub").unwrap();
+        assert_eq!(inode.i_mode, Ext4Inode::S_IFDIR | 0o750);
+        assert_eq!(get_acl(&mut fs, &mut dev, sub, AclType::Default).unwrap(), Some(sample_acl()));
+        assert_eq!(get_acl(&mut fs, &mut dev, sub, AclType::Access).unwrap(), Some(sample_acl()));
+
+        let owner = Credentials { uid: 0, gid: 0, groups: Vec::new() };
+        let named = Credentials { uid: 1000, gid: 9, groups: Vec::new() };
+        let member = Credentials { uid: 7, gid: 9, groups: alloc::vec![50] };
+        let other = Credentials { uid: 7, gid: 9, groups: Vec::new() };
+        assert!(check_access(&mut fs, &mut dev, file, &owner, ACL_READ | ACL_WRITE).unwrap());
+        assert!(check_access(&mut fs, &mut dev, file, &named, ACL_READ).unwrap());
+        assert!(!check_access(&mut fs, &mut dev, file, &named, ACL_WRITE).unwrap());
+        assert!(check_access(&mut fs, &mut dev, file, &member, ACL_READ).unwrap());
+        assert!(!check_access(&mut fs, &mut dev, file, &member, ACL_WRITE).unwrap());
+        assert!(!check_access(&mut fs, &mut dev, file, &other, ACL_READ).unwrap());
+        assert!(check_access(&mut fs, &mut dev, sub, &member, ACL_READ | ACL_EXECUTE).unwrap());
+
+        // 最小 ACL 只改 mode 权限位，并去掉已保存的扩展 ACL
+        let before = fs.get_inode_raw(&mut dev, file).unwrap();
+        set_acl(&mut fs, &mut dev, file, AclType::Access, Some(&Acl::from_mode(0o600))).unwrap();
+        let after = fs.get_inode_raw(&mut dev, file).unwrap();
+        assert_eq!(fs.get_inode_by_num(&mut dev, file).unwrap().i_mode, Ext4Inode::S_IFREG | 0o600);
+        // i_mode 之后到 l_i_checksum_lo 之前的字段都不变
+        assert_eq!(before[2..0x7C], after[2..0x7C]);
+        assert_eq!(get_acl(&mut fs, &mut dev, file, AclType::Access).unwrap(), None);
+        assert!(check_access(&mut fs, &mut dev, file, &owner, ACL_READ | ACL_WRITE).unwrap());
+        assert!(!check_access(&mut fs, &mut dev, file, &named, ACL_READ).unwrap());
+    }
+}
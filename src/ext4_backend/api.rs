@@ -4,11 +4,13 @@ use crate::ext4_backend::blockdev::*;
 use crate::ext4_backend::dir::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::extent_status::*;
+use crate::ext4_backend::fiemap::*;
 use crate::ext4_backend::file::*;
+use crate::ext4_backend::inline_data::*;
 use crate::ext4_backend::loopfile::*;
 use crate::ext4_backend::error::*;
 use crate::ext4_backend::*;
-use crate::BLOCK_SIZE;
 /// 文件句柄
 pub struct OpenFile {
     pub inode_num:u32,
@@ -34,6 +36,37 @@ pub fn lseek(
         true
     }
 
+///按 whence 移动文件偏移并返回新偏移，支持 SEEK_SET/CUR/END/DATA/HOLE。
+///SEEK_DATA/SEEK_HOLE 的 offset 在 EOF 之后或没有更多数据时返回 NotFound（对应 ENXIO）
+pub fn lseek_whence<B: BlockDevice>(
+    dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    file: &mut OpenFile,
+    offset: i64,
+    whence: u32,
+) -> BlockDevResult<u64> {
+    refresh_open_file_inode(dev, fs, file)?;
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.offset,
+        SEEK_END => file.inode.size(),
+        SEEK_DATA | SEEK_HOLE => {
+            let start = u64::try_from(offset).map_err(|_| BlockDevError::InvalidInput)?;
+            let pos = if whence == SEEK_DATA {
+                seek_data(dev, fs, file.inode_num, start)?
+            } else {
+                seek_hole(dev, fs, file.inode_num, start)?
+            };
+            file.offset = pos;
+            return Ok(pos);
+        }
+        _ => return Err(BlockDevError::InvalidInput),
+    };
+    let pos = base.checked_add_signed(offset).ok_or(BlockDevError::InvalidInput)?;
+    file.offset = pos;
+    Ok(pos)
+}
+
 fn refresh_open_file_inode<B: BlockDevice>(
     dev: &mut Jbd2Dev<B>,
     fs: &mut Ext4FileSystem,
@@ -106,6 +139,16 @@ pub fn write_at<B: BlockDevice>(
     Ok(())
 }
 
+///fsync:把文件已写入的修改持久化（启用 fast commit 时优先只写 fast commit 块）
+pub fn fsync<B: BlockDevice>(
+    dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    file: &mut OpenFile,
+) -> BlockDevResult<()> {
+    fast_commit::fsync(fs, dev)?;
+    refresh_open_file_inode(dev, fs, file)
+}
+
 ///读取整个文件内容
 pub fn read<B: BlockDevice>(
     dev: &mut Jbd2Dev<B>,
@@ -127,6 +170,7 @@ pub fn read_at<B: BlockDevice>(
     }
 
     refresh_open_file_inode(dev, fs, file)?;
+    fs.touch_atime(dev, file.inode_num);
 
     let file_size = file.inode.size() as u64;
     if file.offset >= file_size {
@@ -139,21 +183,39 @@ pub fn read_at<B: BlockDevice>(
         return Ok(Vec::new());
     }
 
-    if !file.inode.have_extend_header_and_use_extend() {
-        return Err(BlockDevError::Unsupported);
+    // 内联文件：内容整体在 inode 中
+    if file.inode.has_inline_data() {
+        let content = read_inline_data(fs, dev, file.inode_num)?;
+        let start = file.offset as usize;
+        let out = content[start..start + to_read as usize].to_vec();
+        file.offset = file.offset.saturating_add(out.len() as u64);
+        return Ok(out);
     }
 
-    let block_bytes = BLOCK_SIZE as u64;
+    let block_bytes = fs.block_size() as u64;
     let start_off = file.offset;
     let end_off = start_off + to_read; // exclusive
 
     let start_lbn = start_off / block_bytes;
     let end_lbn = (end_off - 1) / block_bytes;
 
-    let extent_map = resolve_inode_block_allextend(fs, dev, &mut file.inode)?;
+    // 只解析本次读取覆盖的块范围（extent 状态缓存），unwritten extent 视为空洞
+    let mapping = map_extent_range(
+        fs,
+        dev,
+        file.inode_num,
+        &mut file.inode,
+        start_lbn as u32,
+        (end_lbn - start_lbn + 1) as u32,
+    )?;
 
     let mut out = Vec::with_capacity(to_read as usize);
+    let mut seg = 0;
     for lbn in start_lbn..=end_lbn {
+        while mapping[seg].end() <= lbn {
+            seg += 1;
+        }
+        let es = &mapping[seg];
         let lbn_start = lbn * block_bytes;
         let lbn_end = lbn_start + block_bytes;
 
@@ -164,7 +226,7 @@ pub fn read_at<B: BlockDevice>(
             continue;
         }
 
-        if let Some(&phys) = extent_map.get(&(lbn as u32)) {
+        if let Some(phys) = es.pblk_of(lbn).filter(|_| es.is_written()) {
             let cached = fs.datablock_cache.get_or_load(dev, phys)?;
             let data = &cached.data[..block_bytes as usize];
             out.extend_from_slice(&data[copy_start as usize ..(copy_start + copy_len) as usize]);
@@ -103,15 +103,31 @@ impl BitmapCache {
         key: CacheKey,
         block_num: u64,
     ) -> BlockDevResult<&CachedBitmap> {
+        self.get_or_load_verified(block_dev, key, block_num, |_| true)
+    }
+
+    /// 获取位图；不在缓存中时从磁盘加载，加载时先用 `verify` 检查，检查不过不放入缓存
+    pub fn get_or_load_verified<B, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        key: CacheKey,
+        block_num: u64,
+        verify: F,
+    ) -> BlockDevResult<&CachedBitmap>
+    where
+        B: BlockDevice,
+        F: FnOnce(&[u8]) -> bool,
+    {
         if !self.cache.contains_key(&key) {
+            block_dev.read_block(block_num)?;
+            let data = block_dev.buffer().to_vec();
+            if !verify(&data) {
+                return Err(BlockDevError::ChecksumError);
+            }
+
             if self.cache.len() >= self.max_entries {
                 self.evict_lru(block_dev)?;
             }
-
-            block_dev.read_block(block_num as u32)?;
-            let buffer = block_dev.buffer();
-            let data = buffer.to_vec();
-
             let bitmap = CachedBitmap::new(data, block_num);
             self.cache.insert(key, bitmap);
         }
@@ -136,7 +152,7 @@ impl BitmapCache {
                 self.evict_lru(block_dev)?;
             }
 
-            block_dev.read_block(block_num as u32)?;
+            block_dev.read_block(block_num)?;
             let buffer = block_dev.buffer();
             let data = buffer.to_vec();
 
@@ -291,10 +307,10 @@ impl BitmapCache {
         block_num: u64,
         data: &[u8],
     ) -> BlockDevResult<()> {
-        block_dev.read_block(block_num as u32)?;
+        block_dev.read_block(block_num)?;
         let buffer = block_dev.buffer_mut();
         buffer[..data.len()].copy_from_slice(data);
-        block_dev.write_block(block_num as u32, true)?;
+        block_dev.write_block(block_num, true)?;
         Ok(())
     }
 
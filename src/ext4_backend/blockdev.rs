@@ -1,11 +1,14 @@
 
 use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
 use alloc::vec::Vec;
 use log::{error, trace, warn};
 
 use crate::ext4_backend::config::*;
+use crate::ext4_backend::jbd2::jbd2;
 use crate::ext4_backend::jbd2::jbdstruct::*;
 use crate::ext4_backend::error::*;
+use crate::ext4_backend::fast_commit::FcRecord;
 use crate::ext4_backend::config::JBD2_BUFFER_MAX;
 
 
@@ -18,13 +21,13 @@ pub trait BlockDevice {
     /// * `buffer` - 要写入的数据
     /// * `block_id` - 起始块号
     /// * `count` - 块数量
-    fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()>;
+    fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()>;
 
     /// 从块设备读取数据
     /// * `buffer` - 读取数据的目标缓冲区
     /// * `block_id` - 起始块号
     /// * `count` - 块数量
-    fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()>;
+    fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()>;
 
     /// 打开块设备
     fn open(&mut self) -> BlockDevResult<()>;
@@ -56,27 +59,59 @@ pub trait BlockDevice {
     }
 }
 
+/// 以文件系统块为单位读取底层设备
+/// 文件系统块号按 `block_size / dev.block_size()` 换算为设备块号
+pub fn read_fs_blocks<B: BlockDevice + ?Sized>(
+    dev: &mut B,
+    block_size: usize,
+    buffer: &mut [u8],
+    block_id: u64,
+    count: u32,
+) -> BlockDevResult<()> {
+    let ratio = (block_size / dev.block_size() as usize) as u32;
+    dev.read(buffer, block_id * ratio as u64, count * ratio)
+}
+
+/// 以文件系统块为单位写入底层设备
+pub fn write_fs_blocks<B: BlockDevice + ?Sized>(
+    dev: &mut B,
+    block_size: usize,
+    buffer: &[u8],
+    block_id: u64,
+    count: u32,
+) -> BlockDevResult<()> {
+    let ratio = (block_size / dev.block_size() as usize) as u32;
+    dev.write(buffer, block_id * ratio as u64, count * ratio)
+}
+
 /// 块设备缓存
 pub struct BlockBuffer {
-    buffer: Box<[u8;BLOCK_SIZE]>,
+    buffer: Box<[u8]>,
 }
 
 impl BlockBuffer {
-    /// 创建新的块缓冲区
-    pub fn new() -> Self {
+    /// 创建指定大小的块缓冲区
+    pub fn new(size: usize) -> Self {
         Self {
-            buffer: Box::new([0;BLOCK_SIZE]),
+            buffer: alloc::vec![0; size].into_boxed_slice(),
+        }
+    }
+
+    /// 按新的块大小重新分配缓冲区（内容清零）
+    pub fn resize(&mut self, size: usize) {
+        if self.buffer.len() != size {
+            self.buffer = alloc::vec![0; size].into_boxed_slice();
         }
     }
 
     /// 获取缓冲区引用
     pub fn as_slice(&self) -> &[u8] {
-        &*self.buffer
+        &self.buffer
     }
 
     /// 获取可变缓冲区引用
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        &mut *self.buffer
+        &mut self.buffer
     }
 
     /// 获取缓冲区大小
@@ -92,7 +127,7 @@ impl BlockBuffer {
 
 impl Default for BlockBuffer {
     fn default() -> Self {
-        Self::new()
+        Self::new(BLOCK_SIZE)
     }
 }
 
@@ -102,18 +137,33 @@ struct BlockDev<B: BlockDevice> {
     dev: B,
     buffer: BlockBuffer,
     is_dirty: bool,            // 缓冲区是否已修改
-    cached_block: Option<u32>, // 当前缓存的块号
+    cached_block: Option<u64>, // 当前缓存的块号
+    block_size: usize,         // 文件系统块大小，挂载时由超级块决定
 }
 pub enum Jbd2RunState {
     Commit,
     Replay,
 }
+
+/// 日志模式（对应 ext4 的 data= 挂载选项）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JournalMode {
+    /// data=journal：文件数据块和元数据一起写入日志
+    Journal,
+    /// data=ordered：提交元数据事务前，先把本次操作的脏数据块写到设备
+    #[default]
+    Ordered,
+    /// data=writeback：只记录元数据，数据块的落盘顺序不做保证
+    Writeback,
+}
+
 pub struct Jbd2Dev<B: BlockDevice> {
-    _mode: u8, //日志级别，默认ordered 0
+    mode: JournalMode, //日志模式，默认ordered
     inner: BlockDev<B>,
     journal_use: bool, //是否启用日志系统
     _state: Jbd2RunState,
     systeam: Option<JBD2DEVSYSTEM>,
+    journal_dev: Option<Box<dyn BlockDevice>>, //外部日志设备，注入 journal 超级块后移交给 JBD2DEVSYSTEM
 }
 
 ///jbd2代理blockdev
@@ -121,32 +171,120 @@ pub struct Jbd2Dev<B: BlockDevice> {
 /// 采用Jouranl超级快注入的思想，必须需要使用mount来给块设备注入超级块，之后才能使用日志。
 impl<B: BlockDevice> Jbd2Dev<B> {
     ///你拿到我之后应该先把超级块给我传进来吧
-    pub fn initial_jbd2dev(_mode: u8, block_dev:B, use_journal: bool) -> Self {
+    pub fn initial_jbd2dev(mode: JournalMode, block_dev:B, use_journal: bool) -> Self {
         let block_dev = BlockDev::new(block_dev);
         Self {
-            _mode,
+            mode,
             inner: block_dev,
             journal_use: use_journal,
             _state: Jbd2RunState::Commit,
             systeam: None,
+            journal_dev: None,
         }
     }
 
+    /// 接上外部日志设备（INCOMPAT_JOURNAL_DEV），日志块全部写到这个设备上，应在 mkfs/mount 之前调用
+    pub fn set_journal_device<J: BlockDevice + 'static>(&mut self, journal_dev: J) {
+        let journal_dev: Box<dyn BlockDevice> = Box::new(journal_dev);
+        match self.systeam.as_mut() {
+            Some(systeam) => systeam.journal_dev = Some(journal_dev),
+            None => self.journal_dev = Some(journal_dev),
+        }
+    }
+
+    /// 是否接了外部日志设备
+    pub fn has_journal_device(&self) -> bool {
+        self.journal_dev.is_some() || self.systeam.as_ref().is_some_and(|s| s.journal_dev.is_some())
+    }
+
+    fn journal_device_mut(&mut self) -> BlockDevResult<&mut (dyn BlockDevice + 'static)> {
+        match self.systeam.as_mut().and_then(|s| s.journal_dev.as_deref_mut()) {
+            Some(dev) => Ok(dev),
+            None => self.journal_dev.as_deref_mut().ok_or(BlockDevError::DeviceNotOpen),
+        }
+    }
+
+    /// 把外部日志设备格式化为空日志，块大小与文件系统相同
+    pub fn format_journal_device(&mut self, uuid: [u8; 16]) -> BlockDevResult<()> {
+        let block_size = self.inner.block_size;
+        jbd2::format_journal_device(self.journal_device_mut()?, block_size, uuid)
+    }
+
+    /// 把文件系统登记到外部日志设备上，返回应写入 s_journal_uuid 的日志设备 UUID
+    pub fn attach_journal_device(&mut self, fs_uuid: [u8; 16]) -> BlockDevResult<[u8; 16]> {
+        let block_size = self.inner.block_size;
+        jbd2::attach_journal_device(self.journal_device_mut()?, block_size, fs_uuid)
+    }
+
+    /// 挂载时读取并校验外部日志设备的超级块，然后初始化 JBD2DEVSYSTEM
+    pub fn load_journal_device(&mut self, journal_uuid: &[u8; 16]) -> BlockDevResult<()> {
+        let block_size = self.inner.block_size;
+        let super_block = jbd2::load_journal_device(self.journal_device_mut()?, block_size, journal_uuid)?;
+        // 外部日志的块号都是设备上的绝对块号
+        self.install_journal(super_block, 0, jbd2::journal_dev_sb_block(block_size), true);
+        Ok(())
+    }
+
     pub fn is_use_journal(&self) -> bool {
         self.journal_use
     }
 
+    /// 当前日志模式
+    pub fn journal_mode(&self) -> JournalMode {
+        self.mode
+    }
+
+    /// 切换日志模式，应在挂载前或没有运行中的事务句柄时调用
+    pub fn set_journal_mode(&mut self, mode: JournalMode) {
+        self.mode = mode;
+    }
+
+    /// 文件数据块是否也要写入日志（data=journal 且启用了日志）
+    pub fn journals_data(&self) -> bool {
+        self.journal_use && self.mode == JournalMode::Journal
+    }
+
+    /// 单个事务最多记录的块数，未启用日志时为 None
+    pub fn max_transaction_blocks(&self) -> Option<u32> {
+        match &self.systeam {
+            Some(systeam) if self.journal_use => Some(systeam.max_transaction_blocks()),
+            _ => None,
+        }
+    }
+
     ///外部重放journal日志入口 注意性能影响
-    pub fn journal_replay(&mut self) {
-        if self.journal_use {
-            let dev = &mut self.inner.dev;
-            let jbd_sys = &mut self
-                .systeam
-                .as_mut()
-                .expect("jbd2dev are not initial,please initial the jbd2dev first!");
-            jbd_sys.replay(&mut *dev);
-        } else {
+    /// 返回是否有事务被重放（此时调用方缓存的元数据已过期）
+    pub fn journal_replay(&mut self) -> BlockDevResult<bool> {
+        if !self.journal_use {
             warn!("Jouranl function not turn ,please turn on this function and retry!");
+            return Ok(false);
+        }
+        let Some(jbd_sys) = self.systeam.as_mut() else {
+            error!("jbd2dev are not initial,please initial the jbd2dev first!");
+            return Err(BlockDevError::InvalidInput);
+        };
+        jbd_sys.replay(&mut self.inner.dev)
+    }
+
+    /// 日志是否已因写入错误而中止
+    pub fn is_journal_aborted(&self) -> bool {
+        self.journal_use && self.systeam.as_ref().is_some_and(|s| s.is_aborted())
+    }
+
+    /// 打开日志特性（64BIT / CSUM_V3 等），日志必须为空
+    pub fn set_journal_features(&mut self, incompat: u32) -> BlockDevResult<()> {
+        match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam.set_features(&mut self.inner.dev, incompat),
+            _ => Ok(()),
+        }
+    }
+
+    /// 块被释放时调用：若日志中还有该块的旧元数据，记录撤销，防止重放时覆盖块的新内容
+    pub fn revoke_block(&mut self, block_id: u64) {
+        if self.journal_use
+            && let Some(systeam) = self.systeam.as_mut()
+        {
+            systeam.revoke_block(block_id);
         }
     }
 
@@ -160,36 +298,233 @@ impl<B: BlockDevice> Jbd2Dev<B> {
     pub fn set_journal_superblock(
         &mut self,
         super_block: JournalSuperBllockS,
-        jouranl_start_block: u32,
+        jouranl_start_block: u64,
     ) {
+        self.install_journal(super_block, jouranl_start_block, jouranl_start_block, false);
+    }
+
+    fn install_journal(&mut self, super_block: JournalSuperBllockS, start_block: u64, sb_block: u64, external: bool) {
+        // 重新注入时先把外部日志设备收回来
+        if let Some(old) = self.systeam.take()
+            && old.journal_dev.is_some()
+        {
+            self.journal_dev = old.journal_dev;
+        }
+        let journal_dev = if external { self.journal_dev.take() } else { None };
+        let fc_blocks = match super_block.num_fc_blocks() {
+            n if n < super_block.s_maxlen / 2 => n,
+            n => {
+                warn!("[JBD2] ignore invalid fast commit area: {n} of {} blocks", super_block.s_maxlen);
+                0
+            }
+        };
         let system = JBD2DEVSYSTEM {
-            start_block: jouranl_start_block,
+            block_size: self.inner.block_size,
+            start_block,
+            sb_block,
             max_len: super_block.s_maxlen,
             head: 0,
             sequence: super_block.s_sequence,
             jbd2_super_block: super_block,
             commit_queue: Vec::new(),
+            handle_count: 0,
+            reserved_credits: 0,
+            revoke_queue: Vec::new(),
+            logged_blocks: BTreeSet::new(),
+            checkpoint_list: VecDeque::new(),
+            aborted: false,
+            journal_dev,
+            fc_blocks,
+            fc_enabled: false,
+            fc_off: 0,
+            deferred: false,
+            fc_replay: Vec::new(),
         };
         self.systeam = Some(system);
     }
 
-    ///防止滥用，仅仅umount调用，确保事务缓存全部提交完毕
-    pub fn umount_commit(&mut self) {
-        if self.journal_use {
-            self.systeam
-                .as_mut()
-                .unwrap()
-                .commit_transaction(&mut self.inner.dev).expect("Translation commit failed!!!");
-        } else {
-            warn!("Jouranl not use , no thing to commit")
+    /// 开启事务句柄，为本次操作预留 `credits` 个元数据块
+    /// 句柄可以嵌套：内层句柄加入同一个运行中的事务，最外层句柄提交时才写日志
+    pub fn begin_transaction(&mut self, credits: u32) -> BlockDevResult<Jbd2Handle> {
+        let systeam = match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam,
+            // 未启用日志时句柄只是占位
+            _ => return Ok(Jbd2Handle { tid: 0, credits }),
+        };
+        if systeam.is_aborted() {
+            return Err(BlockDevError::ReadOnly);
+        }
+
+        let max_blocks = systeam.max_transaction_blocks();
+        if credits > max_blocks {
+            error!("[JBD2 handle] credits {credits} exceed transaction limit {max_blocks}");
+            return Err(BlockDevError::NoSpace);
         }
+        if systeam.handle_count == 0 && !systeam.deferred && !systeam.is_transaction_empty() {
+            // 句柄之外写入的元数据先单独提交，保证新事务只包含本次操作
+            // 推迟提交的事务（等待 fast commit）继续接纳新的操作
+            systeam.commit_transaction(&mut self.inner.dev)?;
+        }
+        systeam.handle_count += 1;
+        systeam.reserved_credits = systeam.reserved_credits.saturating_add(credits);
+        trace!(
+            "[JBD2 handle] start tid={} depth={} credits={} reserved={}",
+            systeam.sequence, systeam.handle_count, credits, systeam.reserved_credits
+        );
+        Ok(Jbd2Handle {
+            tid: systeam.sequence,
+            credits,
+        })
+    }
+
+    /// 结束事务句柄；最外层句柄结束时原子提交整个事务
+    pub fn commit(&mut self, handle: Jbd2Handle) -> BlockDevResult<()> {
+        self.stop_handle(handle, false)
     }
 
-    pub fn write_block(&mut self, block_id: u32, is_metadata: bool) -> BlockDevResult<()> {
+    /// 结束事务句柄但不提交：事务留在内存里继续运行，由之后的 fsync（fast commit）或完整提交落盘
+    pub fn commit_deferred(&mut self, handle: Jbd2Handle) -> BlockDevResult<()> {
+        self.stop_handle(handle, true)
+    }
+
+    fn stop_handle(&mut self, handle: Jbd2Handle, defer: bool) -> BlockDevResult<()> {
+        let systeam = match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use && systeam.handle_count > 0 => systeam,
+            _ => return Ok(()),
+        };
+        if handle.tid != systeam.sequence {
+            warn!(
+                "[JBD2 handle] handle tid={} does not match running transaction {}",
+                handle.tid, systeam.sequence
+            );
+        }
+        systeam.handle_count -= 1;
+        if systeam.handle_count > 0 {
+            return Ok(());
+        }
+
+        let used = systeam.commit_queue.len() as u32;
+        if used > systeam.reserved_credits {
+            warn!(
+                "[JBD2 handle] transaction {} used {} blocks but reserved {}",
+                systeam.sequence, used, systeam.reserved_credits
+            );
+        }
+        systeam.reserved_credits = 0;
+        if systeam.is_transaction_empty() {
+            return Ok(());
+        }
+        if defer {
+            systeam.deferred = true;
+            return Ok(());
+        }
+        systeam.commit_transaction(&mut self.inner.dev)?;
+        Ok(())
+    }
+
+    /// 完整提交运行中的事务（fsync 无法走 fast commit 时使用），必须在句柄之外调用
+    pub fn commit_running(&mut self) -> BlockDevResult<()> {
+        let systeam = match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam,
+            _ => return self.inner.flush(),
+        };
+        if systeam.handle_count > 0 {
+            return Err(BlockDevError::DeviceBusy);
+        }
+        if systeam.is_transaction_empty() {
+            return self.inner.flush();
+        }
+        systeam.commit_transaction(&mut self.inner.dev)?;
+        Ok(())
+    }
+
+    /// 运行中事务已记录的元数据块数
+    pub fn running_transaction_blocks(&self) -> u32 {
+        match &self.systeam {
+            Some(systeam) if self.journal_use => systeam.commit_queue.len() as u32,
+            _ => 0,
+        }
+    }
+
+    /// 在日志里划出 fast commit 区并允许 fast commit（data=journal 时数据要进日志，不使用 fast commit）
+    /// 日志太小时返回 false
+    pub fn enable_fast_commit(&mut self) -> BlockDevResult<bool> {
+        match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam.enable_fast_commit(&mut self.inner.dev),
+            _ => Ok(false),
+        }
+    }
+
+    /// 可以写 fast commit 时返回 (运行中事务号, 是否需要写 HEAD 记录)
+    pub fn fast_commit_tid(&self) -> Option<(u32, bool)> {
+        match &self.systeam {
+            Some(systeam)
+                if self.journal_use
+                    && systeam.fc_enabled
+                    && !systeam.is_aborted()
+                    && self.mode != JournalMode::Journal =>
+            {
+                Some((systeam.sequence, systeam.fc_off == 0))
+            }
+            _ => None,
+        }
+    }
+
+    /// 把编码好的 fast commit 块写进 fast commit 区
+    pub fn write_fast_commit(&mut self, blocks: &[Vec<u8>]) -> BlockDevResult<()> {
+        match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam.write_fast_commit(&mut self.inner.dev, blocks),
+            _ => Err(BlockDevError::Unsupported),
+        }
+    }
+
+    /// 取走重放时扫描出的 fast commit 记录
+    pub fn take_fast_commit_records(&mut self) -> Vec<FcRecord> {
+        match self.systeam.as_mut() {
+            Some(systeam) => core::mem::take(&mut systeam.fc_replay),
+            None => Vec::new(),
+        }
+    }
+
+    /// fast commit 记录已写回原位置：作废 fast commit 区并把日志标记为空
+    pub fn finish_fast_commit_replay(&mut self) -> BlockDevResult<()> {
+        match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => systeam.finish_fast_commit_replay(&mut self.inner.dev),
+            _ => Ok(()),
+        }
+    }
+
+    /// 当前打开的事务句柄层数，0 表示没有运行中的句柄
+    pub fn transaction_depth(&self) -> u32 {
+        match &self.systeam {
+            Some(systeam) if self.journal_use => systeam.handle_count,
+            _ => 0,
+        }
+    }
+
+    ///防止滥用，仅仅umount调用，确保事务缓存全部提交完毕，并做检查点把日志标记为空
+    pub fn umount_commit(&mut self) -> BlockDevResult<()> {
+        match self.systeam.as_mut() {
+            Some(systeam) if self.journal_use => {
+                systeam.commit_transaction(&mut self.inner.dev)?;
+                systeam.mark_journal_empty(&mut self.inner.dev)
+            }
+            _ => {
+                warn!("Jouranl not use , no thing to commit");
+                Ok(())
+            }
+        }
+    }
+
+    pub fn write_block(&mut self, block_id: u64, is_metadata: bool) -> BlockDevResult<()> {
         //error!("write block :{} ,use journal?:{} ismetadata:{}",block_id,self.journal_use,is_metadata);
 
         // 1) 非元数据 或 未开启日志：直接写回到底层块设备
         if !self.journal_use || !is_metadata {
+            // 同一块之前作为元数据进了事务，以这次直接写的内容为准
+            if let Some(systeam) = self.systeam.as_mut() {
+                systeam.forget_block(block_id);
+            }
             // BlockDev 内部的 buffer 已经被上层写好，直接把当前 buffer 写到 block_id
             return self.inner.write_block(block_id);//把缓存直接写入盘
         }
@@ -198,11 +533,9 @@ impl<B: BlockDevice> Jbd2Dev<B> {
         //    此时之前的普通数据块已经完成写入
         //由于分布提交机制，必须需要拷贝数据牺牲性能来确保日志提交
 
-        let meta_vec = self.inner.buffer();
-        let mut new_buf = Box::new([0;BLOCK_SIZE]);
-        new_buf[..].copy_from_slice(meta_vec);
+        let new_buf: Box<[u8]> = self.inner.buffer().into();
         let updates = Jbd2Update( //把缓存变成事务
-            block_id as u64,
+            block_id,
             new_buf
         );
 
@@ -215,31 +548,49 @@ impl<B: BlockDevice> Jbd2Dev<B> {
         }
 
         let systeam = self.systeam.as_mut().unwrap();
+        if systeam.is_aborted() {
+            return Err(BlockDevError::ReadOnly);
+        }
 
         // 使用原始底层块设备提交事务
-        let raw_dev = self.inner.device_mut();
+        let raw_dev = &mut self.inner.dev;
+
+        // 句柄内：只进事务，提交之后才写回原位置，保证整个操作原子
+        if systeam.handle_count > 0 {
+            systeam.queue_update(updates);
+            if systeam.commit_queue.len() as u32 > systeam.max_transaction_blocks() {
+                warn!("[JBD2 handle] transaction too large, committing early");
+                systeam.commit_transaction(raw_dev)?;
+            }
+            self.inner.mark_clean(block_id);
+            return Ok(());
+        }
 
         //先写入缓存
         if systeam.commit_queue.len() > JBD2_BUFFER_MAX {
             //缓存已满 直接提交，然后再塞入缓存
-            let _ = systeam.commit_transaction(raw_dev);
+            systeam.commit_transaction(raw_dev)?;
             //赛入缓存
-            systeam.commit_queue.push(updates);
+            systeam.queue_update(updates);
             trace!("[JBD2 BUFFER] BUFFER IS FULL ,FLUSHED!")
         } else {
             //赛入缓存
-            systeam.commit_queue.push(updates);
-        }
-
-        if self._mode == 0 {//ordered模式
-            //再写入主盘
-            self.inner.write_block(block_id)?;
+            systeam.queue_update(updates);
         }
 
+        //再写入主盘
+        self.inner.write_block(block_id)?;
 
         Ok(())
     }
-    pub fn read_block(&mut self, block_id: u32) -> BlockDevResult<()> {
+    pub fn read_block(&mut self, block_id: u64) -> BlockDevResult<()> {
+        // 运行中事务里尚未写回原位置的元数据块，以事务里的内容为准
+        if self.journal_use
+            && let Some(systeam) = self.systeam.as_ref()
+            && let Some(data) = systeam.queued_block(block_id)
+        {
+            return self.inner.load_block(block_id, data);
+        }
         self.inner.read_block(block_id)
     }
     pub fn buffer(&self) -> &[u8] {
@@ -248,19 +599,39 @@ impl<B: BlockDevice> Jbd2Dev<B> {
     pub fn buffer_mut(&mut self) -> &mut [u8] {
         self.inner.buffer_mut()
     }
-    pub fn read_blocks(&mut self, buf: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-        self.inner.read_blocks(buf, block_id, count)
+    pub fn read_blocks(&mut self, buf: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+        self.inner.read_blocks(buf, block_id, count)?;
+        if self.journal_use
+            && let Some(systeam) = self.systeam.as_ref()
+            && !systeam.commit_queue.is_empty()
+        {
+            let block_size = self.inner.block_size;
+            for i in 0..count as usize {
+                if let Some(data) = systeam.queued_block(block_id + i as u64) {
+                    buf[i * block_size..(i + 1) * block_size].copy_from_slice(data);
+                }
+            }
+        }
+        Ok(())
     }
     pub fn write_blocks(
         &mut self,
         buf: &[u8],
-        block_id: u32,
+        block_id: u64,
         count: u32,
         is_metadata: bool,
     ) -> BlockDevResult<()> {
 
+        // 内部单块缓冲可能缓存着这些块的旧内容
+        self.inner.forget_cached(block_id, count);
+
         // 1) 非元数据 或 未开启日志：直接写回到底层块设备
         if !self.journal_use || !is_metadata {
+            if let Some(systeam) = self.systeam.as_mut() {
+                for i in 0..count as u64 {
+                    systeam.forget_block(block_id + i);
+                }
+            }
             // BlockDev 内部的 buffer 已经被上层写好，直接把当前 buffer 写到 block_id
             return self.inner.write_blocks(buf, block_id, count);
         }
@@ -279,29 +650,41 @@ impl<B: BlockDevice> Jbd2Dev<B> {
             return self.inner.write_blocks(buf, block_id, count);
         }
 
+        let block_size = self.inner.block_size;
         let systeam = self.systeam.as_mut().unwrap();
+        if systeam.is_aborted() {
+            return Err(BlockDevError::ReadOnly);
+        }
 
         // 使用原始底层块设备提交事务
         let raw_dev = self.inner.device_mut();
 
 
         for i in 0..count {
-              let off = (i as usize) * (BLOCK_SIZE as usize);
-              let mut boxbuf = Box::new([0;BLOCK_SIZE]);
-                boxbuf[..].copy_from_slice(&buf[off..off + (BLOCK_SIZE as usize)]);  
-            let updates = Jbd2Update((block_id + i) as u64, boxbuf);
-            
+            let off = (i as usize) * block_size;
+            let boxbuf: Box<[u8]> = buf[off..off + block_size].into();
+            let updates = Jbd2Update(block_id + i as u64, boxbuf);
+
+            // 句柄内：只进事务，提交之后才写回原位置
+            if systeam.handle_count > 0 {
+                systeam.queue_update(updates);
+                if systeam.commit_queue.len() as u32 > systeam.max_transaction_blocks() {
+                    warn!("[JBD2 handle] transaction too large, committing early");
+                    systeam.commit_transaction(raw_dev)?;
+                }
+                continue;
+            }
 
             //先写入缓存
             if systeam.commit_queue.len() > JBD2_BUFFER_MAX {
                 //缓存已满 直接提交，然后再塞入缓存
-                let _ = systeam.commit_transaction(raw_dev);
+                systeam.commit_transaction(raw_dev)?;
                 //赛入缓存
-                systeam.commit_queue.push(updates);
+                systeam.queue_update(updates);
                 trace!("[JBD2 BUFFER] BUFFER IS FULL ,FLUSHED!")
             } else {
                 //赛入缓存
-                systeam.commit_queue.push(updates);
+                systeam.queue_update(updates);
             }
         }
 
@@ -318,11 +701,21 @@ impl<B: BlockDevice> Jbd2Dev<B> {
         }
     }
 
+    /// 文件系统块总数（按当前块大小换算）
     pub fn total_blocks(&self) -> u64 {
         self.inner.total_blocks()
     }
+    /// 当前文件系统块大小
     pub fn block_size(&self) -> u32 {
-        self.inner.block_size()
+        self.inner.block_size as u32
+    }
+    /// 设置文件系统块大小（挂载/mkfs 时调用），须为设备块大小的整数倍
+    pub fn set_block_size(&mut self, block_size: usize) -> BlockDevResult<()> {
+        self.inner.set_block_size(block_size)
+    }
+    /// 底层设备的块（扇区）大小
+    pub fn device_block_size(&self) -> u32 {
+        self.inner.dev.block_size()
     }
 }
 
@@ -331,12 +724,35 @@ impl<B: BlockDevice> BlockDev<B> {
     pub fn new(dev:B) -> Self {
         Self {
             dev,
-            buffer: BlockBuffer::new(),
+            buffer: BlockBuffer::default(),
             is_dirty: false,
             cached_block: None,
+            block_size: BLOCK_SIZE,
         }
     }
 
+    /// 切换文件系统块大小，会先写回并丢弃当前缓冲块
+    pub fn set_block_size(&mut self, block_size: usize) -> BlockDevResult<()> {
+        let dev_block_size = self.dev.block_size() as usize;
+        if dev_block_size == 0
+            || block_size < dev_block_size
+            || block_size % dev_block_size != 0
+        {
+            return Err(BlockDevError::InvalidBlockSize {
+                size: block_size,
+                expected: dev_block_size,
+            });
+        }
+        if block_size == self.block_size {
+            return Ok(());
+        }
+        self.flush()?;
+        self.block_size = block_size;
+        self.buffer.resize(block_size);
+        self.cached_block = None;
+        Ok(())
+    }
+
     /// 使用指定缓冲区初始化块设备
     pub fn _with_buffer(dev:B, buffer: BlockBuffer) -> BlockDevResult<Self> {
         if buffer.len() < 512 {
@@ -346,11 +762,13 @@ impl<B: BlockDevice> BlockDev<B> {
             });
         }
 
+        let block_size = buffer.len();
         Ok(Self {
             dev,
             buffer,
             is_dirty: false,
             cached_block: None,
+            block_size,
         })
     }
 
@@ -366,7 +784,7 @@ impl<B: BlockDevice> BlockDev<B> {
     }
 
     /// 读取指定块到内部缓冲区
-    pub fn read_block(&mut self, block_id: u32) -> BlockDevResult<()> {
+    pub fn read_block(&mut self, block_id: u64) -> BlockDevResult<()> {
         // 检查是否需要刷新脏数据
         if self.is_dirty && self.cached_block != Some(block_id) {
             self.flush()?;
@@ -378,7 +796,7 @@ impl<B: BlockDevice> BlockDev<B> {
         }
 
         // 读取块
-        self.dev.read(self.buffer.as_mut_slice(), block_id, 1)?;
+        read_fs_blocks(&mut self.dev, self.block_size, self.buffer.as_mut_slice(), block_id, 1)?;
         self.cached_block = Some(block_id);
         self.is_dirty = false;
 
@@ -387,22 +805,49 @@ impl<B: BlockDevice> BlockDev<B> {
 
     /// 写入内部缓冲区到指定块
     ///
-    pub fn write_block(&mut self, block_id: u32) -> BlockDevResult<()> {
+    pub fn write_block(&mut self, block_id: u64) -> BlockDevResult<()> {
         if self.dev.is_readonly() {
             return Err(BlockDevError::ReadOnly);
         }
 
-        self.dev.write(self.buffer.as_slice(), block_id, 1)?;
+        write_fs_blocks(&mut self.dev, self.block_size, self.buffer.as_slice(), block_id, 1)?;
         self.cached_block = Some(block_id);
         self.is_dirty = false;
 
         Ok(())
     }
 
+    /// 用给定内容填充内部缓冲区（不读盘），视为已缓存且干净
+    pub fn load_block(&mut self, block_id: u64, data: &[u8]) -> BlockDevResult<()> {
+        if self.is_dirty && self.cached_block != Some(block_id) {
+            self.flush()?;
+        }
+        self.buffer.as_mut_slice().copy_from_slice(data);
+        self.cached_block = Some(block_id);
+        self.is_dirty = false;
+        Ok(())
+    }
+
+    /// 缓冲区内容已由日志接管（尚未写回原位置），标记为该块的干净缓存
+    pub fn mark_clean(&mut self, block_id: u64) {
+        self.cached_block = Some(block_id);
+        self.is_dirty = false;
+    }
+
+    /// 丢弃落在 [block_id, block_id + count) 内的缓冲块（不写回）
+    pub fn forget_cached(&mut self, block_id: u64, count: u32) {
+        if let Some(cached) = self.cached_block
+            && cached >= block_id
+            && cached < block_id + count as u64
+        {
+            self.cached_block = None;
+            self.is_dirty = false;
+        }
+    }
+
     /// 直接读取多个块
-    pub fn read_blocks(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-        let block_size = self.dev.block_size() as usize;
-        let required_size = block_size * count as usize;
+    pub fn read_blocks(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+        let required_size = self.block_size * count as usize;
 
         if buffer.len() < required_size {
             return Err(BlockDevError::BufferTooSmall {
@@ -411,17 +856,16 @@ impl<B: BlockDevice> BlockDev<B> {
             });
         }
 
-        self.dev.read(buffer, block_id, count)
+        read_fs_blocks(&mut self.dev, self.block_size, buffer, block_id, count)
     }
 
     /// 直接写入多个块
-    pub fn write_blocks(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+    pub fn write_blocks(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()> {
         if self.dev.is_readonly() {
             return Err(BlockDevError::ReadOnly);
         }
 
-        let block_size = self.dev.block_size() as usize;
-        let required_size = block_size * count as usize;
+        let required_size = self.block_size * count as usize;
 
         if buffer.len() < required_size {
             return Err(BlockDevError::BufferTooSmall {
@@ -430,7 +874,7 @@ impl<B: BlockDevice> BlockDev<B> {
             });
         }
 
-        self.dev.write(buffer, block_id, count)
+        write_fs_blocks(&mut self.dev, self.block_size, buffer, block_id, count)
     }
 
     /// 获取缓冲区引用
@@ -453,14 +897,9 @@ impl<B: BlockDevice> BlockDev<B> {
         self.dev.flush()
     }
 
-    /// 获取总块数
+    /// 获取总块数（文件系统块）
     pub fn total_blocks(&self) -> u64 {
-        self.dev.total_blocks()
-    }
-
-    /// 获取块大小
-    pub fn block_size(&self) -> u32 {
-        self.dev.block_size()
+        self.dev.total_blocks() * self.dev.block_size() as u64 / self.block_size as u64
     }
 
     /// 获取内部设备引用
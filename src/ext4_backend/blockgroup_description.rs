@@ -60,6 +60,24 @@ impl Ext4GroupDesc {
         (self.bg_inode_table_hi as u64) << 32 | self.bg_inode_table_lo as u64
     }
 
+    /// 设置块位图块号（64位）
+    pub fn set_block_bitmap(&mut self, block: u64) {
+        self.bg_block_bitmap_lo = block as u32;
+        self.bg_block_bitmap_hi = (block >> 32) as u32;
+    }
+
+    /// 设置inode位图块号（64位）
+    pub fn set_inode_bitmap(&mut self, block: u64) {
+        self.bg_inode_bitmap_lo = block as u32;
+        self.bg_inode_bitmap_hi = (block >> 32) as u32;
+    }
+
+    /// 设置inode表起始块号（64位）
+    pub fn set_inode_table(&mut self, block: u64) {
+        self.bg_inode_table_lo = block as u32;
+        self.bg_inode_table_hi = (block >> 32) as u32;
+    }
+
     /// 获取空闲块数（32位）
     pub fn free_blocks_count(&self) -> u32 {
         (self.bg_free_blocks_count_hi as u32) << 16 | self.bg_free_blocks_count_lo as u32
@@ -80,6 +98,12 @@ impl Ext4GroupDesc {
         (self.bg_itable_unused_hi as u32) << 16 | self.bg_itable_unused_lo as u32
     }
 
+    /// 设置未使用的inode数（32位）
+    pub fn set_itable_unused(&mut self, count: u32) {
+        self.bg_itable_unused_lo = count as u16;
+        self.bg_itable_unused_hi = (count >> 16) as u16;
+    }
+
     /// 获取快照排除位图块号（64位）
     pub fn exclude_bitmap(&self) -> u64 {
         (self.bg_exclude_bitmap_hi as u64) << 32 | self.bg_exclude_bitmap_lo as u64
@@ -538,6 +562,10 @@ impl DiskFormat for Ext4GroupDesc {
         write_u16_le(self.bg_inode_bitmap_csum_lo, &mut bytes[26..28]);
         write_u16_le(self.bg_itable_unused_lo, &mut bytes[28..30]);
         write_u16_le(self.bg_checksum, &mut bytes[30..32]);
+        // 旧版 32 字节描述符（无 64BIT 特性）没有高位字段
+        if bytes.len() < 64 {
+            return;
+        }
         write_u32_le(self.bg_block_bitmap_hi, &mut bytes[32..36]);
         write_u32_le(self.bg_inode_bitmap_hi, &mut bytes[36..40]);
         write_u32_le(self.bg_inode_table_hi, &mut bytes[40..44]);
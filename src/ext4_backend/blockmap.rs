@@ -0,0 +1,450 @@
+//! 传统 ext2/ext3 间接块映射：12 个直接块 + 1/2/3 级间接块
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::endian::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+
+/// 直接块个数
+pub const EXT4_NDIR_BLOCKS: usize = 12;
+/// 一级间接块在 i_block 中的槽位
+pub const EXT4_IND_BLOCK: usize = 12;
+/// 二级间接块在 i_block 中的槽位
+pub const EXT4_DIND_BLOCK: usize = 13;
+/// 三级间接块在 i_block 中的槽位
+pub const EXT4_TIND_BLOCK: usize = 14;
+
+/// 每个间接块可容纳的块指针数
+fn ptrs_per_block(block_size: usize) -> u64 {
+    (block_size / 4) as u64
+}
+
+/// 间接槽位 (slot, 级数, 覆盖的起始逻辑块号)
+fn indirect_slots(block_size: usize) -> [(usize, u32, u64); 3] {
+    let ptrs = ptrs_per_block(block_size);
+    let ind_base = EXT4_NDIR_BLOCKS as u64;
+    let dind_base = ind_base + ptrs;
+    let tind_base = dind_base + ptrs * ptrs;
+    [
+        (EXT4_IND_BLOCK, 1, ind_base),
+        (EXT4_DIND_BLOCK, 2, dind_base),
+        (EXT4_TIND_BLOCK, 3, tind_base),
+    ]
+}
+
+/// 把逻辑块号拆成 i_block 槽位 + 各级间接块内的下标
+/// 超出三级间接块寻址范围时返回 None
+fn block_to_path(logical_block: u32, block_size: usize) -> Option<(usize, Vec<usize>)> {
+    let lbn = logical_block as u64;
+    if lbn < EXT4_NDIR_BLOCKS as u64 {
+        return Some((lbn as usize, Vec::new()));
+    }
+    let ptrs = ptrs_per_block(block_size);
+    for (slot, depth, base) in indirect_slots(block_size) {
+        let span = ptrs.pow(depth);
+        if lbn >= base + span {
+            continue;
+        }
+        let mut rel = lbn - base;
+        let mut path = Vec::with_capacity(depth as usize);
+        for level in (0..depth).rev() {
+            let unit = ptrs.pow(level);
+            path.push((rel / unit) as usize);
+            rel %= unit;
+        }
+        return Some((slot, path));
+    }
+    None
+}
+
+/// 绑定到单个 inode 的间接块映射视图（不持有 BlockDev，按需传入）
+pub struct BlockMap<'a> {
+    pub inode: &'a mut Ext4Inode,
+}
+
+impl<'a> BlockMap<'a> {
+    pub fn new(inode: &'a mut Ext4Inode) -> Self {
+        Self { inode }
+    }
+
+    fn add_inode_sectors_for_block(&mut self, block_size: usize) {
+        let add_sectors = (block_size / 512) as u64;
+        let newv = self.inode.blocks_count().saturating_add(add_sectors);
+        self.inode.i_blocks_lo = (newv & 0xFFFF_FFFF) as u32;
+        self.inode.l_i_blocks_high = ((newv >> 32) & 0xFFFF) as u16;
+    }
+
+    fn sub_inode_sectors_for_block(&mut self, block_size: usize) {
+        let sub_sectors = (block_size / 512) as u64;
+        let newv = self.inode.blocks_count().saturating_sub(sub_sectors);
+        self.inode.i_blocks_lo = (newv & 0xFFFF_FFFF) as u32;
+        self.inode.l_i_blocks_high = ((newv >> 32) & 0xFFFF) as u16;
+    }
+
+    /// 读取间接块中的全部指针
+    fn read_ptrs<B: BlockDevice>(dev: &mut Jbd2Dev<B>, block: u64) -> BlockDevResult<Vec<u32>> {
+        dev.read_block(block)?;
+        Ok(dev.buffer().chunks_exact(4).map(read_u32_le).collect())
+    }
+
+    /// 修改间接块中的单个指针
+    fn write_ptr<B: BlockDevice>(
+        dev: &mut Jbd2Dev<B>,
+        block: u64,
+        idx: usize,
+        value: u32,
+    ) -> BlockDevResult<()> {
+        dev.read_block(block)?;
+        write_u32_le(value, &mut dev.buffer_mut()[idx * 4..idx * 4 + 4]);
+        dev.write_block(block, true)
+    }
+
+    /// 分配一个清零的间接块，并计入 inode 的 i_blocks
+    fn alloc_index_block<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<B>,
+    ) -> BlockDevResult<u32> {
+        let block = fs.alloc_block(dev)?;
+        if block > u32::MAX as u64 {
+            fs.free_block(dev, block)?;
+            return Err(BlockDevError::Unsupported);
+        }
+        // 该块可能曾作为数据块留在缓存里，避免之后被旧数据覆盖
+        fs.datablock_cache.invalidate(block);
+        dev.read_block(block)?;
+        dev.buffer_mut().fill(0);
+        dev.write_block(block, true)?;
+        self.add_inode_sectors_for_block(fs.block_size());
+        Ok(block as u32)
+    }
+
+    /// 查找逻辑块对应的物理块，空洞返回 None
+    pub fn lookup<B: BlockDevice>(
+        &self,
+        dev: &mut Jbd2Dev<B>,
+        logical_block: u32,
+    ) -> BlockDevResult<Option<u64>> {
+        let Some((slot, path)) = block_to_path(logical_block, dev.block_size() as usize) else {
+            return Ok(None);
+        };
+        let mut block = self.inode.i_block[slot];
+        for idx in path {
+            if block == 0 {
+                return Ok(None);
+            }
+            dev.read_block(block as u64)?;
+            block = read_u32_le(&dev.buffer()[idx * 4..idx * 4 + 4]);
+        }
+        Ok((block != 0).then_some(block as u64))
+    }
+
+    /// 把逻辑块映射到给定物理块，必要时分配中间的间接块
+    /// 新分配的间接块计入 i_blocks，数据块本身由调用方计入
+    pub fn set_block<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<B>,
+        logical_block: u32,
+        phys: u64,
+    ) -> BlockDevResult<()> {
+        if phys > u32::MAX as u64 {
+            return Err(BlockDevError::Unsupported);
+        }
+        let (slot, path) =
+            block_to_path(logical_block, fs.block_size()).ok_or(BlockDevError::InvalidInput)?;
+        if path.is_empty() {
+            self.inode.i_block[slot] = phys as u32;
+            return Ok(());
+        }
+
+        let mut parent = self.inode.i_block[slot];
+        if parent == 0 {
+            parent = self.alloc_index_block(fs, dev)?;
+            self.inode.i_block[slot] = parent;
+        }
+        let last = path.len() - 1;
+        for (level, &idx) in path.iter().enumerate() {
+            if level == last {
+                Self::write_ptr(dev, parent as u64, idx, phys as u32)?;
+                break;
+            }
+            dev.read_block(parent as u64)?;
+            let mut child = read_u32_le(&dev.buffer()[idx * 4..idx * 4 + 4]);
+            if child == 0 {
+                child = self.alloc_index_block(fs, dev)?;
+                Self::write_ptr(dev, parent as u64, idx, child)?;
+            }
+            parent = child;
+        }
+        Ok(())
+    }
+
+    /// 查找逻辑块，空洞时分配一个新数据块并建立映射
+    /// 返回 (物理块号, 是否为新分配)，新数据块的内容由调用方初始化
+    pub fn map_or_alloc<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<B>,
+        logical_block: u32,
+    ) -> BlockDevResult<(u64, bool)> {
+        if let Some(phys) = self.lookup(dev, logical_block)? {
+            return Ok((phys, false));
+        }
+        let phys = fs.alloc_block(dev)?;
+        if let Err(e) = self.set_block(fs, dev, logical_block, phys) {
+            fs.free_block(dev, phys)?;
+            return Err(e);
+        }
+        self.add_inode_sectors_for_block(fs.block_size());
+        Ok((phys, true))
+    }
+
+    /// 遍历整棵映射，收集数据块 (逻辑块号 -> 物理块号) 与间接块
+    fn walk<B: BlockDevice>(
+        &self,
+        dev: &mut Jbd2Dev<B>,
+        data: &mut BTreeMap<u32, u64>,
+        index: &mut Vec<u64>,
+    ) -> BlockDevResult<()> {
+        fn walk_level<B: BlockDevice>(
+            dev: &mut Jbd2Dev<B>,
+            block: u64,
+            depth: u32,
+            base: u64,
+            ptrs: u64,
+            data: &mut BTreeMap<u32, u64>,
+            index: &mut Vec<u64>,
+        ) -> BlockDevResult<()> {
+            index.push(block);
+            let unit = ptrs.pow(depth - 1);
+            let entries = BlockMap::read_ptrs(dev, block)?;
+            for (i, &ptr) in entries.iter().enumerate() {
+                let child_base = base + i as u64 * unit;
+                if ptr == 0 || child_base > u32::MAX as u64 {
+                    continue;
+                }
+                if depth == 1 {
+                    data.insert(child_base as u32, ptr as u64);
+                } else {
+                    walk_level(dev, ptr as u64, depth - 1, child_base, ptrs, data, index)?;
+                }
+            }
+            Ok(())
+        }
+
+        for (lbn, &ptr) in self.inode.i_block[..EXT4_NDIR_BLOCKS].iter().enumerate() {
+            if ptr != 0 {
+                data.insert(lbn as u32, ptr as u64);
+            }
+        }
+        let block_size = dev.block_size() as usize;
+        let ptrs = ptrs_per_block(block_size);
+        for (slot, depth, base) in indirect_slots(block_size) {
+            let ptr = self.inode.i_block[slot];
+            if ptr != 0 {
+                walk_level(dev, ptr as u64, depth, base, ptrs, data, index)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 收集所有已映射的数据块：逻辑块号 -> 物理块号
+    pub fn data_blocks<B: BlockDevice>(
+        &self,
+        dev: &mut Jbd2Dev<B>,
+    ) -> BlockDevResult<BTreeMap<u32, u64>> {
+        let mut data = BTreeMap::new();
+        let mut index = Vec::new();
+        self.walk(dev, &mut data, &mut index)?;
+        Ok(data)
+    }
+
+    /// 收集所有间接块（不含数据块）
+    pub fn index_blocks<B: BlockDevice>(&self, dev: &mut Jbd2Dev<B>) -> BlockDevResult<Vec<u64>> {
+        let mut data = BTreeMap::new();
+        let mut index = Vec::new();
+        self.walk(dev, &mut data, &mut index)?;
+        Ok(index)
+    }
+
+    /// 释放逻辑块号 >= start 的全部数据块，以及因此变空的间接块
+    /// 释放的数据块和间接块都会从 i_blocks 中扣除
+    pub fn truncate_from<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<B>,
+        start: u32,
+    ) -> BlockDevResult<()> {
+        let start = start as u64;
+        let block_size = fs.block_size();
+
+        for lbn in (start as usize).min(EXT4_NDIR_BLOCKS)..EXT4_NDIR_BLOCKS {
+            let ptr = self.inode.i_block[lbn];
+            if ptr != 0 {
+                fs.free_block(dev, ptr as u64)?;
+                self.sub_inode_sectors_for_block(block_size);
+                self.inode.i_block[lbn] = 0;
+            }
+        }
+
+        let ptrs = ptrs_per_block(block_size);
+        for (slot, depth, base) in indirect_slots(block_size) {
+            let ptr = self.inode.i_block[slot];
+            if ptr == 0 || base + ptrs.pow(depth) <= start {
+                continue;
+            }
+            if self.truncate_level(fs, dev, ptr as u64, depth, base, start)? {
+                fs.free_block(dev, ptr as u64)?;
+                self.sub_inode_sectors_for_block(block_size);
+                self.inode.i_block[slot] = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// 在一个间接块内截断，返回该间接块截断后是否已为空
+    fn truncate_level<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<B>,
+        block: u64,
+        depth: u32,
+        base: u64,
+        start: u64,
+    ) -> BlockDevResult<bool> {
+        let block_size = fs.block_size();
+        let unit = ptrs_per_block(block_size).pow(depth - 1);
+        let mut entries = Self::read_ptrs(dev, block)?;
+        let mut changed = false;
+
+        for (i, ptr) in entries.iter_mut().enumerate() {
+            let child_base = base + i as u64 * unit;
+            if *ptr == 0 || child_base + unit <= start {
+                continue;
+            }
+            let release = if depth == 1 {
+                true
+            } else {
+                self.truncate_level(fs, dev, *ptr as u64, depth - 1, child_base, start)?
+            };
+            if release {
+                fs.free_block(dev, *ptr as u64)?;
+                self.sub_inode_sectors_for_block(block_size);
+                *ptr = 0;
+                changed = true;
+            }
+        }
+
+        let empty = entries.iter().all(|&p| p == 0);
+        if changed && !empty {
+            dev.read_block(block)?;
+            for (chunk, &ptr) in dev.buffer_mut().chunks_exact_mut(4).zip(entries.iter()) {
+                write_u32_le(ptr, chunk);
+            }
+            dev.write_block(block, true)?;
+        }
+        Ok(empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::api::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::loopfile::*;
+    use crate::ext4_backend::superblock::*;
+    use crate::ext4_backend::test_support::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_block_to_path_levels() {
+        // 4K 块：每个间接块 1024 个指针
+        assert_eq!(block_to_path(0, 4096), Some((0, Vec::new())));
+        assert_eq!(block_to_path(11, 4096), Some((11, Vec::new())));
+        assert_eq!(block_to_path(12, 4096), Some((EXT4_IND_BLOCK, alloc::vec![0])));
+        assert_eq!(block_to_path(12 + 1023, 4096), Some((EXT4_IND_BLOCK, alloc::vec![1023])));
+        assert_eq!(block_to_path(12 + 1024, 4096), Some((EXT4_DIND_BLOCK, alloc::vec![0, 0])));
+        assert_eq!(
+            block_to_path(12 + 1024 + 1024 * 1024 - 1, 4096),
+            Some((EXT4_DIND_BLOCK, alloc::vec![1023, 1023]))
+        );
+        assert_eq!(
+            block_to_path(12 + 1024 + 1024 * 1024 + 1025, 4096),
+            Some((EXT4_TIND_BLOCK, alloc::vec![0, 1, 1]))
+        );
+    }
+
+    #[test]
+    fn test_block_to_path_out_of_range_on_1k() {
+        // 1K 块：三级间接块最多覆盖 12 + 256 + 256^2 + 256^3 个逻辑块
+        let limit = 12u64 + 256 + 256 * 256 + 256 * 256 * 256;
+        assert!(block_to_path((limit - 1) as u32, 1024).is_some());
+        assert_eq!(block_to_path(limit as u32, 1024), None);
+    }
+
+    fn read_range(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, offset: u64, len: usize) -> Vec<u8> {
+        let mut file = open(dev, fs, "/legacy", false).unwrap();
+        file.offset = offset;
+        read_at(dev, fs, &mut file, len).unwrap()
+    }
+
+    #[test]
+    fn test_legacy_inode_write_read_truncate_delete() {
+        let (mut dev, mut fs) = setup();
+        // 去掉 extents 特性后新建的文件用间接块映射
+        fs.superblock.s_feature_incompat &= !Ext4Superblock::EXT4_FEATURE_INCOMPAT_EXTENTS;
+        let free_before = fs.superblock.free_blocks_count();
+        let (ino, inode) = mkfile_with_ino(&mut dev, &mut fs, "/legacy", None, None).unwrap();
+        assert!(!inode.have_extend_header_and_use_extend());
+
+        // 1K 块：一级间接块从 12 开始，二级从 268 开始，三级从 12 + 256 + 256^2 开始
+        let bs = fs.block_size() as u64;
+        let tind_start = 12 + 256 + 256 * 256;
+        let dense: Vec<u8> = (0..300 * bs).map(|i| (i / bs) as u8 ^ i as u8).collect();
+        write_file(&mut dev, &mut fs, "/legacy", 0, &dense).unwrap();
+        let tail = [0xc3u8; 3000];
+        let tail_off = (tind_start + 5) * bs + 100;
+        write_file(&mut dev, &mut fs, "/legacy", tail_off, &tail).unwrap();
+
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(!inode.have_extend_header_and_use_extend());
+        assert_eq!(inode.size(), tail_off + tail.len() as u64);
+        for slot in [EXT4_IND_BLOCK, EXT4_DIND_BLOCK, EXT4_TIND_BLOCK] {
+            assert_ne!(inode.i_block[slot], 0, "slot {slot}");
+        }
+        assert_eq!(read_range(&mut fs, &mut dev, 0, dense.len()), dense);
+        assert_eq!(read_range(&mut fs, &mut dev, tail_off, tail.len()), tail);
+        assert!(read_range(&mut fs, &mut dev, tind_start * bs, 4096).iter().all(|&b| b == 0));
+
+        // 截断到一级间接块范围内：二、三级间接块连同数据块一起释放
+        let free_full = fs.superblock.free_blocks_count();
+        truncate(&mut dev, &mut fs, "/legacy", 200 * bs).unwrap();
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert_eq!(inode.size(), 200 * bs);
+        assert_eq!(inode.i_block[EXT4_DIND_BLOCK], 0);
+        assert_eq!(inode.i_block[EXT4_TIND_BLOCK], 0);
+        assert!(fs.superblock.free_blocks_count() > free_full);
+        let left = read_file(&mut dev, &mut fs, "/legacy").unwrap().unwrap();
+        assert_eq!(left, dense[..200 * bs as usize]);
+
+        delete_file(&mut fs, &mut dev, "/legacy").unwrap();
+        assert!(get_file_inode(&mut fs, &mut dev, "/legacy").unwrap().is_none());
+        assert_eq!(fs.superblock.free_blocks_count(), free_before);
+    }
+
+    #[test]
+    fn test_legacy_mkfile_out_of_space_fails_without_panic() {
+        let (mut dev, mut fs) = setup();
+        fs.superblock.s_feature_incompat &= !Ext4Superblock::EXT4_FEATURE_INCOMPAT_EXTENTS;
+        // 数据块把空闲块全部用完，间接块已无处分配
+        let bs = fs.block_size() as u64;
+        let data = vec![0x5au8; ((fs.superblock.free_blocks_count() + 16) * bs) as usize];
+        assert!(mkfile(&mut dev, &mut fs, "/full", Some(&data), None).is_none());
+    }
+}
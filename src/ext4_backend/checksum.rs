@@ -0,0 +1,427 @@
+//! 元数据校验和模块（metadata_csum）
+//!
+//! 提供 crc32c 实现以及超级块、块组描述符、位图、inode、extent 块、目录块、扩展属性块的校验和计算与校验。
+//! 与内核 ext4_chksum 一致：crc32c 不做最终取反，初始值由调用方给出。
+
+use crate::ext4_backend::blockgroup_description::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::superblock::*;
+
+/// crc32c（Castagnoli）反射多项式
+const CRC32C_POLY: u32 = 0x82F6_3B78;
+
+/// crc32c 查表
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ CRC32C_POLY;
+            } else {
+                crc >>= 1;
+            }
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// 超级块中 s_checksum 的偏移
+pub const SUPERBLOCK_CSUM_OFFSET: usize = 0x3FC;
+/// 块组描述符中 bg_checksum 的偏移
+pub const GROUP_DESC_CSUM_OFFSET: usize = 0x1E;
+/// inode 中 l_i_checksum_lo 的偏移
+pub const INODE_CSUM_LO_OFFSET: usize = 0x7C;
+/// inode 中 i_checksum_hi 的偏移
+pub const INODE_CSUM_HI_OFFSET: usize = 0x82;
+/// 扩展属性块头部中 h_checksum 的偏移
+pub const XATTR_BLOCK_CSUM_OFFSET: usize = 0x10;
+/// ext4 超级块 s_checksum_type 的 crc32c 编号
+pub const EXT4_CRC32C_CHKSUM: u8 = 1;
+
+/// 原始 crc32c（不取反），等价于内核 ext4_chksum
+pub fn crc32c(seed: u32, data: &[u8]) -> u32 {
+    let mut crc = seed;
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// 超级块校验和：覆盖 s_checksum 之前的全部字节
+pub fn superblock_csum(sb_bytes: &[u8]) -> u32 {
+    crc32c(!0, &sb_bytes[..SUPERBLOCK_CSUM_OFFSET])
+}
+
+/// 在序列化好的超级块字节上填写校验和
+pub fn set_superblock_csum(sb: &Ext4Superblock, sb_bytes: &mut [u8]) {
+    if !sb.has_metadata_csum() {
+        return;
+    }
+    let csum = superblock_csum(sb_bytes);
+    sb_bytes[SUPERBLOCK_CSUM_OFFSET..SUPERBLOCK_CSUM_OFFSET + 4].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验磁盘上的超级块
+pub fn verify_superblock_csum(sb: &Ext4Superblock, sb_bytes: &[u8]) -> bool {
+    !sb.has_metadata_csum() || superblock_csum(sb_bytes) == sb.s_checksum
+}
+
+/// 块组描述符校验和（低16位），计算时 bg_checksum 视为 0
+pub fn group_desc_csum(seed: u32, group: u32, desc_bytes: &[u8]) -> u16 {
+    let mut crc = crc32c(seed, &group.to_le_bytes());
+    crc = crc32c(crc, &desc_bytes[..GROUP_DESC_CSUM_OFFSET]);
+    crc = crc32c(crc, &[0, 0]);
+    if desc_bytes.len() > GROUP_DESC_CSUM_OFFSET + 2 {
+        crc = crc32c(crc, &desc_bytes[GROUP_DESC_CSUM_OFFSET + 2..]);
+    }
+    (crc & 0xFFFF) as u16
+}
+
+/// 在序列化好的块组描述符字节上填写校验和
+pub fn set_group_desc_csum(sb: &Ext4Superblock, group: u32, desc_bytes: &mut [u8]) {
+    if !sb.has_metadata_csum() {
+        return;
+    }
+    let csum = group_desc_csum(sb.csum_seed(), group, desc_bytes);
+    desc_bytes[GROUP_DESC_CSUM_OFFSET..GROUP_DESC_CSUM_OFFSET + 2].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验磁盘上的块组描述符
+pub fn verify_group_desc_csum(sb: &Ext4Superblock, group: u32, desc_bytes: &[u8]) -> bool {
+    if !sb.has_metadata_csum() {
+        return true;
+    }
+    let stored = u16::from_le_bytes([
+        desc_bytes[GROUP_DESC_CSUM_OFFSET],
+        desc_bytes[GROUP_DESC_CSUM_OFFSET + 1],
+    ]);
+    group_desc_csum(sb.csum_seed(), group, desc_bytes) == stored
+}
+
+/// 块位图校验和：覆盖 clusters_per_group / 8 字节
+pub fn block_bitmap_csum(sb: &Ext4Superblock, bitmap: &[u8]) -> u32 {
+    let len = (sb.s_clusters_per_group as usize / 8).min(bitmap.len());
+    crc32c(sb.csum_seed(), &bitmap[..len])
+}
+
+/// inode 位图校验和：覆盖 inodes_per_group / 8 字节
+pub fn inode_bitmap_csum(sb: &Ext4Superblock, bitmap: &[u8]) -> u32 {
+    let len = (sb.s_inodes_per_group as usize / 8).min(bitmap.len());
+    crc32c(sb.csum_seed(), &bitmap[..len])
+}
+
+/// 32 字节描述符只保存位图校验和的低16位
+fn desc_has_csum_hi(sb: &Ext4Superblock) -> bool {
+    sb.get_desc_size() as usize >= Ext4GroupDesc::EXT4_DESC_SIZE_64BIT
+}
+
+/// 重新计算块位图校验和并写入描述符
+pub fn set_block_bitmap_csum(sb: &Ext4Superblock, desc: &mut Ext4GroupDesc, bitmap: &[u8]) {
+    if !sb.has_metadata_csum() {
+        return;
+    }
+    let csum = block_bitmap_csum(sb, bitmap);
+    desc.bg_block_bitmap_csum_lo = (csum & 0xFFFF) as u16;
+    if desc_has_csum_hi(sb) {
+        desc.bg_block_bitmap_csum_hi = (csum >> 16) as u16;
+    }
+}
+
+/// 重新计算 inode 位图校验和并写入描述符
+pub fn set_inode_bitmap_csum(sb: &Ext4Superblock, desc: &mut Ext4GroupDesc, bitmap: &[u8]) {
+    if !sb.has_metadata_csum() {
+        return;
+    }
+    let csum = inode_bitmap_csum(sb, bitmap);
+    desc.bg_inode_bitmap_csum_lo = (csum & 0xFFFF) as u16;
+    if desc_has_csum_hi(sb) {
+        desc.bg_inode_bitmap_csum_hi = (csum >> 16) as u16;
+    }
+}
+
+/// 校验块位图；位图未初始化（BLOCK_UNINIT）时不校验
+pub fn verify_block_bitmap_csum(sb: &Ext4Superblock, desc: &Ext4GroupDesc, bitmap: &[u8]) -> bool {
+    if !sb.has_metadata_csum() || desc.is_block_bitmap_uninit() {
+        return true;
+    }
+    let csum = block_bitmap_csum(sb, bitmap);
+    if desc_has_csum_hi(sb) {
+        csum == desc.block_bitmap_csum()
+    } else {
+        (csum & 0xFFFF) as u16 == desc.bg_block_bitmap_csum_lo
+    }
+}
+
+/// 校验 inode 位图；位图未初始化（INODE_UNINIT）时不校验
+pub fn verify_inode_bitmap_csum(sb: &Ext4Superblock, desc: &Ext4GroupDesc, bitmap: &[u8]) -> bool {
+    if !sb.has_metadata_csum() || desc.is_inode_bitmap_uninit() {
+        return true;
+    }
+    let csum = inode_bitmap_csum(sb, bitmap);
+    if desc_has_csum_hi(sb) {
+        csum == desc.inode_bitmap_csum()
+    } else {
+        (csum & 0xFFFF) as u16 == desc.bg_inode_bitmap_csum_lo
+    }
+}
+
+/// 单个 inode 的校验种子：crc(crc(fs_seed, ino), generation)
+pub fn inode_csum_seed(fs_seed: u32, inode_num: u32, generation: u32) -> u32 {
+    let crc = crc32c(fs_seed, &inode_num.to_le_bytes());
+    crc32c(crc, &generation.to_le_bytes())
+}
+
+/// i_checksum_hi 是否落在 i_extra_isize 覆盖的范围内
+fn inode_has_csum_hi(raw: &[u8]) -> bool {
+    if raw.len() <= 128 {
+        return false;
+    }
+    let extra_isize = u16::from_le_bytes([raw[128], raw[129]]) as usize;
+    128 + extra_isize >= INODE_CSUM_HI_OFFSET + 2
+}
+
+/// inode 校验和，raw 为完整的 inode_size 字节
+pub fn inode_csum(fs_seed: u32, inode_num: u32, raw: &[u8]) -> u32 {
+    let generation = u32::from_le_bytes([raw[100], raw[101], raw[102], raw[103]]);
+    let seed = inode_csum_seed(fs_seed, inode_num, generation);
+
+    let mut crc = crc32c(seed, &raw[..INODE_CSUM_LO_OFFSET]);
+    crc = crc32c(crc, &[0, 0]);
+    crc = crc32c(crc, &raw[INODE_CSUM_LO_OFFSET + 2..raw.len().min(128)]);
+    if raw.len() > 128 {
+        let mut offset = INODE_CSUM_HI_OFFSET;
+        crc = crc32c(crc, &raw[128..offset]);
+        if inode_has_csum_hi(raw) {
+            crc = crc32c(crc, &[0, 0]);
+            offset += 2;
+        }
+        crc = crc32c(crc, &raw[offset..]);
+    }
+    crc
+}
+
+/// 在序列化好的 inode 字节上填写校验和
+pub fn set_inode_csum(fs_seed: u32, inode_num: u32, raw: &mut [u8]) {
+    let csum = inode_csum(fs_seed, inode_num, raw);
+    raw[INODE_CSUM_LO_OFFSET..INODE_CSUM_LO_OFFSET + 2]
+        .copy_from_slice(&((csum & 0xFFFF) as u16).to_le_bytes());
+    if inode_has_csum_hi(raw) {
+        raw[INODE_CSUM_HI_OFFSET..INODE_CSUM_HI_OFFSET + 2]
+            .copy_from_slice(&((csum >> 16) as u16).to_le_bytes());
+    }
+}
+
+/// 校验磁盘上的 inode；全零的 inode 槽（从未使用过）直接视为有效
+pub fn verify_inode_csum(fs_seed: u32, inode_num: u32, raw: &[u8]) -> bool {
+    if raw.iter().all(|&b| b == 0) {
+        return true;
+    }
+    let csum = inode_csum(fs_seed, inode_num, raw);
+    let lo = u16::from_le_bytes([raw[INODE_CSUM_LO_OFFSET], raw[INODE_CSUM_LO_OFFSET + 1]]);
+    if inode_has_csum_hi(raw) {
+        let hi = u16::from_le_bytes([raw[INODE_CSUM_HI_OFFSET], raw[INODE_CSUM_HI_OFFSET + 1]]);
+        csum == ((hi as u32) << 16 | lo as u32)
+    } else {
+        (csum & 0xFFFF) as u16 == lo
+    }
+}
+
+/// extent 块尾部（ext4_extent_tail）偏移：header + eh_max 个条目之后
+pub fn extent_tail_offset(block: &[u8]) -> usize {
+    let eh_max = u16::from_le_bytes([block[4], block[5]]) as usize;
+    12 + eh_max * 12
+}
+
+/// 在 extent 块上填写尾部校验和
+pub fn set_extent_block_csum(inode_seed: u32, block: &mut [u8]) {
+    let off = extent_tail_offset(block);
+    if off + 4 > block.len() {
+        return;
+    }
+    let csum = crc32c(inode_seed, &block[..off]);
+    block[off..off + 4].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验 extent 块尾部校验和
+pub fn verify_extent_block_csum(inode_seed: u32, block: &[u8]) -> bool {
+    let off = extent_tail_offset(block);
+    if off + 4 > block.len() {
+        return false;
+    }
+    let stored = u32::from_le_bytes([block[off], block[off + 1], block[off + 2], block[off + 3]]);
+    crc32c(inode_seed, &block[..off]) == stored
+}
+
+/// 目录块末尾是否存在校验和尾部（ext4_dir_entry_tail）
+pub fn has_dir_tail(block: &[u8]) -> bool {
+    let tail_len = Ext4DirEntryTail::TAIL_LEN as usize;
+    if block.len() < tail_len {
+        return false;
+    }
+    let t = &block[block.len() - tail_len..];
+    u32::from_le_bytes([t[0], t[1], t[2], t[3]]) == 0
+        && u16::from_le_bytes([t[4], t[5]]) == Ext4DirEntryTail::TAIL_LEN
+        && t[6] == 0
+        && t[7] == Ext4DirEntryTail::RESERVED_FT
+}
+
+/// 在目录块末尾写入空的校验和尾部
+pub fn init_dir_tail(block: &mut [u8]) {
+    let tail_len = Ext4DirEntryTail::TAIL_LEN as usize;
+    let start = block.len() - tail_len;
+    let t = &mut block[start..];
+    t.fill(0);
+    t[4..6].copy_from_slice(&Ext4DirEntryTail::TAIL_LEN.to_le_bytes());
+    t[7] = Ext4DirEntryTail::RESERVED_FT;
+}
+
+/// 目录叶子块校验和：覆盖尾部之前的全部字节
+pub fn dir_block_csum(inode_seed: u32, block: &[u8]) -> u32 {
+    let tail_len = Ext4DirEntryTail::TAIL_LEN as usize;
+    crc32c(inode_seed, &block[..block.len() - tail_len])
+}
+
+/// 在目录块尾部填写校验和；块内没有尾部时不做任何事
+pub fn set_dir_block_csum(inode_seed: u32, block: &mut [u8]) {
+    if !has_dir_tail(block) {
+        return;
+    }
+    let csum = dir_block_csum(inode_seed, block);
+    let len = block.len();
+    block[len - 4..].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验目录块尾部校验和；没有尾部的块视为无法校验
+pub fn verify_dir_block_csum(inode_seed: u32, block: &[u8]) -> bool {
+    if !has_dir_tail(block) {
+        return false;
+    }
+    let len = block.len();
+    let stored = u32::from_le_bytes([block[len - 4], block[len - 3], block[len - 2], block[len - 1]]);
+    dir_block_csum(inode_seed, block) == stored
+}
+
+/// 识别 dx 索引块并返回 count/limit 的偏移：
+/// dx_root 以 "."(12) + ".."(bs-12) 开头且 info_length 为 8，dx_node 以 inode 为 0、占满整块的伪目录项开头
+pub fn dx_count_offset(block: &[u8]) -> Option<usize> {
+    let bs = block.len();
+    let rec_len = |off: usize| u16::from_le_bytes([block[off + 4], block[off + 5]]) as usize;
+    let inode0 = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+    if inode0 == 0 && block[6] == 0 && (rec_len(0) == bs || (bs == 65536 && rec_len(0) == 0xFFFF)) {
+        return Some(8);
+    }
+    if rec_len(0) == 12 && block[6] == 1 && rec_len(12) == bs - 12 && block[18] == 2 && block[29] == 8 {
+        return Some(32);
+    }
+    None
+}
+
+/// dx 索引块尾部（dx_tail）偏移：count/limit 之后 limit 个 dx_entry
+pub fn dx_tail_offset(block: &[u8], count_offset: usize) -> usize {
+    let limit = u16::from_le_bytes([block[count_offset], block[count_offset + 1]]) as usize;
+    count_offset + limit * 8
+}
+
+/// dx 索引块校验和：覆盖 count 个条目之前的全部字节，再续上 dt_reserved 和置零的 dt_checksum
+pub fn dx_block_csum(inode_seed: u32, block: &[u8], count_offset: usize) -> u32 {
+    let count = u16::from_le_bytes([block[count_offset + 2], block[count_offset + 3]]) as usize;
+    let tail = dx_tail_offset(block, count_offset);
+    let csum = crc32c(inode_seed, &block[..count_offset + count * 8]);
+    let csum = crc32c(csum, &block[tail..tail + 4]);
+    crc32c(csum, &[0u8; 4])
+}
+
+/// 在 dx 索引块尾部填写校验和；limit 未给尾部留出空间时不做任何事
+pub fn set_dx_block_csum(inode_seed: u32, block: &mut [u8], count_offset: usize) {
+    let tail = dx_tail_offset(block, count_offset);
+    if tail + 8 > block.len() {
+        return;
+    }
+    let csum = dx_block_csum(inode_seed, block, count_offset);
+    block[tail + 4..tail + 8].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验 dx 索引块尾部校验和
+pub fn verify_dx_block_csum(inode_seed: u32, block: &[u8], count_offset: usize) -> bool {
+    let tail = dx_tail_offset(block, count_offset);
+    if tail + 8 > block.len() {
+        return false;
+    }
+    let stored = u32::from_le_bytes([block[tail + 4], block[tail + 5], block[tail + 6], block[tail + 7]]);
+    dx_block_csum(inode_seed, block, count_offset) == stored
+}
+
+/// 扩展属性块校验和：文件系统种子续上 64 位块号，再覆盖整块（h_checksum 按 0 计算）
+pub fn xattr_block_csum(fs_seed: u32, block_nr: u64, block: &[u8]) -> u32 {
+    let csum = crc32c(fs_seed, &block_nr.to_le_bytes());
+    let csum = crc32c(csum, &block[..XATTR_BLOCK_CSUM_OFFSET]);
+    let csum = crc32c(csum, &[0u8; 4]);
+    crc32c(csum, &block[XATTR_BLOCK_CSUM_OFFSET + 4..])
+}
+
+/// 在扩展属性块头部填写校验和
+pub fn set_xattr_block_csum(fs_seed: u32, block_nr: u64, block: &mut [u8]) {
+    let csum = xattr_block_csum(fs_seed, block_nr, block);
+    block[XATTR_BLOCK_CSUM_OFFSET..XATTR_BLOCK_CSUM_OFFSET + 4].copy_from_slice(&csum.to_le_bytes());
+}
+
+/// 校验扩展属性块头部校验和
+pub fn verify_xattr_block_csum(fs_seed: u32, block_nr: u64, block: &[u8]) -> bool {
+    let o = XATTR_BLOCK_CSUM_OFFSET;
+    let stored = u32::from_le_bytes([block[o], block[o + 1], block[o + 2], block[o + 3]]);
+    xattr_block_csum(fs_seed, block_nr, block) == stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32c_known_vector() {
+        // 标准 crc32c("123456789") = 0xE3069283（初值 ~0，结果取反）
+        assert_eq!(!crc32c(!0, b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(0, &[]), 0);
+    }
+
+    #[test]
+    fn test_inode_csum_roundtrip() {
+        let mut raw = [0u8; 256];
+        raw[0] = 0xA4;
+        raw[1] = 0x81;
+        raw[128] = 32; // i_extra_isize
+        set_inode_csum(0x1234_5678, 12, &mut raw);
+        assert!(verify_inode_csum(0x1234_5678, 12, &raw));
+        assert!(!verify_inode_csum(0x1234_5678, 13, &raw));
+        raw[4] ^= 1;
+        assert!(!verify_inode_csum(0x1234_5678, 12, &raw));
+    }
+
+    #[test]
+    fn test_dir_tail_csum_roundtrip() {
+        let mut block = [0u8; 1024];
+        block[4..6].copy_from_slice(&(1024u16 - 12).to_le_bytes());
+        assert!(!has_dir_tail(&block));
+        init_dir_tail(&mut block);
+        assert!(has_dir_tail(&block));
+        set_dir_block_csum(7, &mut block);
+        assert!(verify_dir_block_csum(7, &block));
+        block[0] = 1;
+        assert!(!verify_dir_block_csum(7, &block));
+    }
+
+    #[test]
+    fn test_group_desc_csum_ignores_csum_field() {
+        let mut desc = [0x5Au8; 64];
+        let a = group_desc_csum(1, 3, &desc);
+        desc[GROUP_DESC_CSUM_OFFSET] = 0;
+        desc[GROUP_DESC_CSUM_OFFSET + 1] = 0;
+        assert_eq!(a, group_desc_csum(1, 3, &desc));
+        assert_ne!(a, group_desc_csum(1, 4, &desc));
+    }
+}
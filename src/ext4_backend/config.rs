@@ -5,11 +5,22 @@ use crate::ext4_backend::superblock::*;
 // ============================================================================
 /// JBD2 日志缓冲区最大数量
 pub const JBD2_BUFFER_MAX: usize = 10; //最多10条缓存
+/// 修改单个 inode 数据所需的日志额度（inode、位图、块组描述符、extent 树与 xattr 块）
+pub const EXT4_DATA_TRANS_BLOCKS: u32 = 26;
+/// HTree 目录插入/删除额外需要的日志额度
+pub const EXT4_INDEX_EXTRA_TRANS_BLOCKS: u32 = 12;
+/// 创建/删除目录项（mkdir、mknod、unlink、link 等）所需的日志额度
+pub const EXT4_DIR_TRANS_BLOCKS: u32 = EXT4_DATA_TRANS_BLOCKS + EXT4_INDEX_EXTRA_TRANS_BLOCKS + 3;
+/// rename 同时修改两个目录，所需的日志额度
+pub const EXT4_RENAME_TRANS_BLOCKS: u32 =
+    2 * EXT4_DATA_TRANS_BLOCKS + EXT4_INDEX_EXTRA_TRANS_BLOCKS + 2;
+/// 只改 inode 本身（如 atime）所需的日志额度：inode 表块和提交时写回的块组描述符块
+pub const EXT4_INODE_TRANS_BLOCKS: u32 = 2;
 
 // ============================================================================
 // 块相关配置
 // ============================================================================
-/// Ext4 块大小（字节）
+/// Ext4 默认块大小（字节），实际块大小在挂载时从超级块读取
 pub const BLOCK_SIZE: usize = 4096;//usize没问题
 pub const BLOCK_SIZE_U32: u32 = BLOCK_SIZE as u32;
 
@@ -17,6 +28,13 @@ pub const BLOCK_SIZE_U32: u32 = BLOCK_SIZE as u32;
 
 /// 用于超级块的 s_log_block_size 字段
 pub const LOG_BLOCK_SIZE: u32 = 2; // 4096 = 1024 << 2
+
+/// 支持的最小块大小（字节）
+pub const MIN_BLOCK_SIZE: usize = 1024;
+/// 支持的最大块大小（字节）
+pub const MAX_BLOCK_SIZE: usize = 65536;
+/// s_log_block_size 的最大合法值（65536 = 1024 << 6）
+pub const MAX_LOG_BLOCK_SIZE: u32 = 6;
 // ============================================================================
 // 块组相关配置
 // ============================================================================
@@ -26,6 +44,11 @@ pub const LOG_BLOCK_SIZE: u32 = 2; // 4096 = 1024 << 2
 pub const GROUP_DESC_SIZE: u16 = 64;
 /// 旧版 Ext4（32位）：32字节
 pub const GROUP_DESC_SIZE_OLD: u16 = 32;
+
+/// 每组块数上限（块组描述符中的 16 位空闲计数限制，与 mke2fs 一致）
+pub const MAX_BLOCKS_PER_GROUP: u32 = 65528;
+/// 末尾块组除元数据外至少要留下的空闲块数，否则舍弃该块组
+pub const MIN_LAST_GROUP_FREE_BLOCKS: u64 = 50;
 // ============================================================================
 // Inode 相关配置
 // ============================================================================
@@ -36,6 +59,9 @@ pub const GROUP_DESC_SIZE_OLD: u16 = 32;
 /// This constant should only be used as a fallback when s_inode_size is 0.
 pub const DEFAULT_INODE_SIZE: u16 = 256;
 
+/// 路径解析时连续跟随符号链接的最大次数，超过视为循环
+pub const MAX_SYMLINK_FOLLOW: usize = 8;
+
 // ============================================================================
 // 数据结构缓存相关配置,在小的嵌入式系统中可以适当调小防止崩内存
 // ============================================================================
@@ -45,6 +71,22 @@ pub const INODE_CACHE_MAX: usize = 128;
 pub const DATABLOCK_CACHE_MAX: usize = 128;
 ///BITMAP cache数量
 pub const BITMAP_CACHE_MAX: usize = 128;
+///Extent 状态缓存条目数（所有 inode 合计）
+pub const EXTENT_STATUS_CACHE_MAX: usize = 4096;
+
+// ============================================================================
+// 多块分配器（mballoc）预分配配置
+// ============================================================================
+///inode 预分配窗口的最小字节数
+pub const MB_PREALLOC_MIN_BYTES: u64 = 16 * 1024;
+///inode 预分配窗口的最大字节数
+pub const MB_PREALLOC_MAX_BYTES: u64 = 8 * 1024 * 1024;
+///写入后文件不超过这么多块时视为小文件，使用共享的局部性窗口
+pub const MB_STREAM_REQ_BLOCKS: u32 = 16;
+///局部性窗口的块数
+pub const MB_GROUP_PREALLOC_BLOCKS: u32 = 512;
+///最多保留的 inode 预分配窗口数
+pub const MB_MAX_INODE_PREALLOC: usize = 64;
 
 //============================================================================
 //目录项DirEntry配置
@@ -72,16 +114,23 @@ pub const RESERVED_GDT_BLOCKS: u32 = 0;
 // ============================================================================
 
 /// 默认的兼容特性标志
-pub const DEFAULT_FEATURE_COMPAT: u32 =
-    Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL | Ext4Superblock::EXT4_FEATURE_COMPAT_DIR_INDEX;
+pub const DEFAULT_FEATURE_COMPAT: u32 = Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL
+    | Ext4Superblock::EXT4_FEATURE_COMPAT_EXT_ATTR
+    | Ext4Superblock::EXT4_FEATURE_COMPAT_DIR_INDEX;
 /// 默认的不兼容特性标志
 pub const DEFAULT_FEATURE_INCOMPAT: u32 = Ext4Superblock::EXT4_FEATURE_INCOMPAT_FILETYPE
     | Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT
     | Ext4Superblock::EXT4_FEATURE_INCOMPAT_EXTENTS;
 
 /// 默认的只读兼容特性标志
+#[cfg(not(feature = "CONFIG_META_CSUM_ENABLE"))]
 pub const DEFAULT_FEATURE_RO_COMPAT: u32 = Ext4Superblock::EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
     | Ext4Superblock::EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER;
+/// 默认的只读兼容特性标志（开启 metadata_csum）
+#[cfg(feature = "CONFIG_META_CSUM_ENABLE")]
+pub const DEFAULT_FEATURE_RO_COMPAT: u32 = Ext4Superblock::EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | Ext4Superblock::EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER
+    | Ext4Superblock::EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
 
 // ============================================================================
 // 魔数和版本
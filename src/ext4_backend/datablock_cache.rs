@@ -21,6 +21,8 @@ pub struct CachedBlock {
     pub block_num: u64,
     /// 最后访问时间戳（用于LRU）
     pub last_access: u64,
+    /// 是否为元数据块（目录、xattr 等），开启日志时随事务提交；文件数据块为 false
+    pub metadata: bool,
 }
 
 impl CachedBlock {
@@ -30,6 +32,7 @@ impl CachedBlock {
             dirty: false,
             block_num,
             last_access: 0,
+            metadata: true,
         }
     }
 
@@ -77,7 +80,7 @@ impl DataBlockCache {
         block_dev: &mut Jbd2Dev<B>,
         block_num: u64,
     ) -> BlockDevResult<Vec<u8>> {
-        block_dev.read_block(block_num as u32)?;
+        block_dev.read_block(block_num)?;
         let buffer = block_dev.buffer();
         Ok(buffer.to_vec())
     }
@@ -204,6 +207,35 @@ impl DataBlockCache {
         cached.mark_dirty();
     }
 
+    /// 修改文件数据块（不进日志事务），并自动标记为脏
+    pub fn modify_data<B, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        block_num: u64,
+        f: F,
+    ) -> BlockDevResult<()>
+    where
+        B: BlockDevice,
+        F: FnOnce(&mut [u8]),
+    {
+        let cached = self.get_or_load_mut(block_dev, block_num)?;
+        f(&mut cached.data);
+        cached.metadata = false;
+        cached.mark_dirty();
+        Ok(())
+    }
+
+    /// 初始化新分配的文件数据块（不进日志事务）
+    pub fn modify_new_data<F>(&mut self, block_num: u64, f: F)
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let cached = self.create_new(block_num);
+        f(&mut cached.data);
+        cached.metadata = false;
+        cached.mark_dirty();
+    }
+
     /// LRU淘汰：找到最久未访问的并写回（如果脏）
     fn evict_lru<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
         // 找到最小的last_access
@@ -229,26 +261,38 @@ impl DataBlockCache {
         if let Some(cached) = self.cache.remove(&block_num)
             && cached.dirty {
                 // 写回磁盘
-                Self::write_block_static(block_dev, cached.block_num, &cached.data)?;
+                Self::write_block_static(block_dev, cached.block_num, &cached.data, cached.metadata)?;
             }
         Ok(())
     }
 
     /// 刷新所有脏数据块到磁盘
+    /// 文件数据先写（data=journal 时进入事务，否则直接落盘），元数据块随后写入（开启日志时进入事务）
     pub fn flush_all<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
-        // 收集需要写回的数据块信息（block_num, data），并按块号排序
-        let mut dirty_blocks: Vec<(u64, Vec<u8>)> = self
-            .cache
-            .values()
-            .filter(|cached| cached.dirty)
-            .map(|cached| (cached.block_num, cached.data.clone()))
-            .collect();
+        self.flush_dirty(block_dev, true)
+    }
 
-        if dirty_blocks.is_empty() {
-            return Ok(());
+    /// 只刷新脏的元数据块（目录块等），文件数据留在缓存里，供 data=writeback 提交事务时使用
+    pub fn flush_metadata<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
+        self.flush_dirty(block_dev, false)
+    }
+
+    fn flush_dirty<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>, with_data: bool) -> BlockDevResult<()> {
+        // 收集需要写回的数据块信息（block_num, data），BTreeMap 保证按块号排序
+        let mut dirty_blocks: Vec<(u64, Vec<u8>)> = Vec::new();
+        let mut dirty_meta: Vec<(u64, Vec<u8>)> = Vec::new();
+        for cached in self.cache.values().filter(|cached| cached.dirty && (with_data || cached.metadata)) {
+            let item = (cached.block_num, cached.data.clone());
+            if cached.metadata {
+                dirty_meta.push(item);
+            } else {
+                dirty_blocks.push(item);
+            }
         }
 
-        dirty_blocks.sort_by_key(|(block_num, _)| *block_num);
+        if dirty_blocks.is_empty() && dirty_meta.is_empty() {
+            return Ok(());
+        }
 
         // 将连续块聚合后，使用 write_blocks 一次性写回
         let max_part_size = BLOCK_SIZE * 100; //最大聚合块数;
@@ -275,14 +319,21 @@ impl DataBlockCache {
             }
 
             // 通过底层的 write_blocks 一次性写入连续块
-            block_dev.write_blocks(&buf, start_block as u32, run_len as u32, false)?;
+            let journal_data = block_dev.journals_data();
+            block_dev.write_blocks(&buf, start_block, run_len as u32, journal_data)?;
 
             idx += run_len;
         }
 
+        for (block_num, data) in dirty_meta.iter() {
+            block_dev.write_blocks(data, *block_num, 1, true)?;
+        }
+
         // 清除脏标记
         for cached in self.cache.values_mut() {
-            cached.dirty = false;
+            if with_data || cached.metadata {
+                cached.dirty = false;
+            }
         }
 
         Ok(())
@@ -297,7 +348,8 @@ impl DataBlockCache {
         if let Some(cached) = self.cache.get(&block_num)
             && cached.dirty {
                 let data = cached.data.clone();
-                Self::write_block_static(block_dev, block_num, &data)?;
+                let metadata = cached.metadata;
+                Self::write_block_static(block_dev, block_num, &data, metadata)?;
 
                 if let Some(cached) = self.cache.get_mut(&block_num) {
                     cached.dirty = false;
@@ -311,11 +363,13 @@ impl DataBlockCache {
         block_dev: &mut Jbd2Dev<B>,
         block_num: u64,
         data: &[u8],
+        metadata: bool,
     ) -> BlockDevResult<()> {
-        block_dev.read_block(block_num as u32)?;
+        block_dev.read_block(block_num)?;
         let buffer = block_dev.buffer_mut();
         buffer[..data.len()].copy_from_slice(data);
-        block_dev.write_block(block_num as u32, false)?;
+        let journaled = metadata || block_dev.journals_data();
+        block_dev.write_block(block_num, journaled)?;
         Ok(())
     }
 
@@ -393,4 +447,18 @@ mod tests {
         cache.invalidate(100);
         assert_eq!(cache.cache.len(), 0);
     }
+
+    #[test]
+    fn test_block_above_u32_range() {
+        let mut cache = DataBlockCache::new(8, BLOCK_SIZE);
+        let high = u32::MAX as u64 + 100;
+
+        cache.create_new(high);
+        cache.create_new(99);
+        assert_eq!(cache.cache.len(), 2);
+        assert_eq!(cache.cache.get(&high).unwrap().block_num, high);
+
+        cache.invalidate(high);
+        assert_eq!(cache.cache.len(), 1);
+    }
 }
@@ -2,15 +2,21 @@
 
 
 use crate::alloc::string::ToString;
+use crate::ext4_backend::acl::*;
 use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::blockmap::*;
 use crate::ext4_backend::config::*;
+use crate::ext4_backend::checksum::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
 use crate::ext4_backend::entries::*;
 use crate::ext4_backend::ext4::*;
 use crate::ext4_backend::extents_tree::*;
 use crate::ext4_backend::file::*;
+use crate::ext4_backend::hashtree::*;
+use crate::ext4_backend::inline_data::*;
 use crate::ext4_backend::loopfile::*;
+use crate::ext4_backend::superblock::*;
 use crate::ext4_backend::error::*;
 use alloc::string::String;
 use alloc::vec::Vec;
@@ -81,35 +87,8 @@ pub fn get_inode_with_num<B: BlockDevice>(
             continue;
         }
 
-        let target = name.as_bytes();
-
-        let total_size = current_inode.size() as usize;
-        let block_bytes = BLOCK_SIZE;
-        let total_blocks = if total_size == 0 {
-            0
-        } else {
-            total_size.div_ceil(block_bytes)
-        };
-
-        let mut found_inode_num: Option<u64> = None;
-
-        for lbn in 0..total_blocks {
-            let phys = match resolve_inode_block( device, &mut current_inode, lbn as u32)? {
-                Some(b) => b,
-                None => continue,
-            };
-
-            let cached_block = fs.datablock_cache.get_or_load(device, phys as u64)?;
-            let block_data = &cached_block.data[..block_bytes];
-
-            if let Some(entry) = classic_dir::find_entry(block_data, target) {
-                found_inode_num = Some(entry.inode as u64);
-                break;
-            }
-        }
-
-        let inode_num = match found_inode_num {
-            Some(n) => n,
+        let inode_num = match lookup_dir_entry(fs, device, current_ino, &mut current_inode, name.as_bytes())? {
+            Some(n) => n as u64,
             None => return Ok(None),
         };
 
@@ -124,7 +103,7 @@ pub fn get_inode_with_num<B: BlockDevice>(
             inode_num as u32,
             fs.superblock.s_inodes_per_group,
             inode_table_start,
-            BLOCK_SIZE,
+            fs.block_size(),
         );
 
         let cached_inode = fs
@@ -137,8 +116,43 @@ pub fn get_inode_with_num<B: BlockDevice>(
     Ok(Some((current_ino, current_inode)))
 }
 
+/// 在目录中按名字查找目录项，返回其 inode 号
+pub fn lookup_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    device: &mut Jbd2Dev<B>,
+    dir_ino: u32,
+    dir_inode: &mut Ext4Inode,
+    name: &[u8],
+) -> BlockDevResult<Option<u32>> {
+    // 内联目录：目录项在 inode 内，没有数据块可扫描
+    if dir_inode.has_inline_data() {
+        return Ok(find_inline_dir_entry(fs, device, dir_ino, name)?.map(|e| e.inode));
+    }
+
+    let total_size = dir_inode.size() as usize;
+    let block_bytes = fs.block_size();
+    let total_blocks = total_size.div_ceil(block_bytes);
+
+    for lbn in 0..total_blocks {
+        let phys = match resolve_inode_block(fs, device, dir_ino, dir_inode, lbn as u32)? {
+            Some(b) => b,
+            None => continue,
+        };
+
+        fs.verify_dir_block_csum(device, dir_ino, phys as u64)?;
+        let cached_block = fs.datablock_cache.get_or_load(device, phys as u64)?;
+        let block_data = &cached_block.data[..block_bytes];
+
+        if let Some(entry) = classic_dir::find_entry(block_data, name) {
+            return Ok(Some(entry.inode));
+        }
+    }
+    Ok(None)
+}
+
 /// 在父目录的所有逻辑块中查找空闲空间并插入一个目录项；
 /// 若所有现有块都无法容纳，则自动为目录分配一个新数据块并扩展 inode 映射和大小。
+/// 哈希索引目录按哈希插入对应叶子块，并按需分裂叶子、扩展索引。插入后更新目录的 mtime/ctime。
 pub fn insert_dir_entry<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     device: &mut Jbd2Dev<B>,
@@ -147,10 +161,22 @@ pub fn insert_dir_entry<B: BlockDevice>(
     child_ino: u32,
     child_name: &str,
     file_type: u8,
+) -> BlockDevResult<()> {
+    do_insert_dir_entry(fs, device, parent_ino_num, parent_inode, child_ino, child_name, file_type)?;
+    fs.touch_mtime_ctime(device, parent_ino_num)
+}
+
+fn do_insert_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    device: &mut Jbd2Dev<B>,
+    parent_ino_num: u32,
+    parent_inode: &mut Ext4Inode,
+    child_ino: u32,
+    child_name: &str,
+    file_type: u8,
 ) -> BlockDevResult<()> {
     let name_bytes = child_name.as_bytes();
     let name_len = core::cmp::min(name_bytes.len(), Ext4DirEntry2::MAX_NAME_LEN as usize);
-    let new_rec_len = Ext4DirEntry2::entry_len(name_len as u8) as usize;
     let new_entry = Ext4DirEntry2::new(
         child_ino,
         Ext4DirEntry2::entry_len(name_len as u8),
@@ -158,23 +184,44 @@ pub fn insert_dir_entry<B: BlockDevice>(
         &name_bytes[..name_len],
     );
 
+    // 内联目录：先尝试放在 inode 内，放不下时转换为单块目录再按普通目录插入
+    if parent_inode.has_inline_data() {
+        if add_inline_dir_entry(fs, device, parent_ino_num, &new_entry)? {
+            return Ok(());
+        }
+        convert_inline_dir(fs, device, parent_ino_num, parent_inode)?;
+    }
+
+    let mut dx_fallback = false;
+    if parent_inode.is_htree_indexed() {
+        let manager = create_hash_tree_manager(fs);
+        if manager.insert_entry(fs, device, parent_ino_num, parent_inode, &new_entry)? {
+            return Ok(());
+        }
+        // 索引不可用：metadata_csum 下视为损坏，否则清除索引标志按线性目录处理（同内核）
+        if fs.superblock.has_metadata_csum() {
+            error!("insert_dir_entry: corrupted htree index in dir {parent_ino_num}");
+            return Err(BlockDevError::Corrupted);
+        }
+        parent_inode.i_flags &= !Ext4Inode::EXT4_INDEX_FL;
+        let flags = parent_inode.i_flags;
+        fs.modify_inode(device, parent_ino_num, |inode| inode.i_flags = flags)?;
+        dx_fallback = true;
+    }
+
     let total_size = parent_inode.size() as usize;
-    let block_bytes = BLOCK_SIZE;
+    let block_bytes = fs.block_size();
     let total_blocks = if total_size == 0 {
         0
     } else {
         total_size.div_ceil(block_bytes)
     };
 
-    let mut inserted = false;
+    let usable_len = fs.dir_block_usable_len();
 
-    let blocks = resolve_inode_block_allextend(fs, device, parent_inode)?;
+    let blocks = resolve_inode_block_allextend(fs, device, parent_ino_num, parent_inode)?;
 
     for lbn in 0..total_blocks {
-        if inserted {
-            break;
-        }
-
         let phys = match blocks.get(&(lbn as u32)) {
             Some(&b) => b,
             None => {
@@ -186,84 +233,65 @@ pub fn insert_dir_entry<B: BlockDevice>(
             }
         };
 
-        let _ = fs.datablock_cache.modify(device, phys as u64, |data| {
-            if inserted {
-                return;
-            }
-
-            let block_bytes = BLOCK_SIZE;
-
-            let mut offset = 0usize;
-            while offset + 8 <= block_bytes {
-                let inode = u32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]);
-                let rec_len = u16::from_le_bytes([data[offset + 4], data[offset + 5]]) as usize;
-                if rec_len < 8 {
-                    return;
-                }
-                let entry_end = offset + rec_len;
-                if entry_end > block_bytes {
-                    return;
-                }
-
-                // Free entry: directly use it if it can hold the new entry.
-                if inode == 0 {
-                    if rec_len >= new_rec_len {
-                        let mut full_entry = new_entry;
-                        full_entry.rec_len = rec_len as u16;
-                        full_entry.to_disk_bytes(&mut data[offset..offset + 8]);
-                        let nlen = full_entry.name_len as usize;
-                        data[offset + 8..offset + 8 + nlen]
-                            .copy_from_slice(&full_entry.name[..nlen]);
-                        inserted = true;
-                    }
-                    return;
-                }
-
-                // Occupied entry: try to split tail space.
-                let cur_name_len = data[offset + 6] as usize;
-                let mut ideal = 8 + cur_name_len;
-                ideal = (ideal + 3) & !3;
-                if ideal <= rec_len {
-                    let tail = rec_len - ideal;
-                    if tail >= new_rec_len {
-                        let ideal_bytes = (ideal as u16).to_le_bytes();
-                        data[offset + 4] = ideal_bytes[0];
-                        data[offset + 5] = ideal_bytes[1];
-
-                        let new_off = offset + ideal;
-                        let mut full_entry = new_entry;
-                        full_entry.rec_len = tail as u16;
-                        full_entry.to_disk_bytes(&mut data[new_off..new_off + 8]);
-                        let nlen = full_entry.name_len as usize;
-                        data[new_off + 8..new_off + 8 + nlen]
-                            .copy_from_slice(&full_entry.name[..nlen]);
-                        inserted = true;
-                        return;
-                    }
-                }
-
-                if entry_end == block_bytes {
-                    return;
-                }
-                offset = entry_end;
-            }
-        });
+        let mut inserted = false;
+        fs.datablock_cache.modify(device, phys, |data| {
+            inserted = classic_dir::insert_entry(data, usable_len, &new_entry);
+        })?;
+        if inserted {
+            fs.update_dir_block_csum(device, parent_ino_num, phys)?;
+            return Ok(());
+        }
     }
 
-    if inserted {
-        return Ok(());
+    // 单块目录装满且启用了 dir_index：转换为哈希索引目录（刚因索引损坏退回线性的目录除外）
+    if total_blocks == 1
+        && !dx_fallback
+        && fs
+            .superblock
+            .has_feature_compat(Ext4Superblock::EXT4_FEATURE_COMPAT_DIR_INDEX)
+    {
+        let manager = create_hash_tree_manager(fs);
+        if manager.make_indexed_dir(fs, device, parent_ino_num, parent_inode, &new_entry)? {
+            return Ok(());
+        }
     }
 
     // 所有现有逻辑块都无法容纳新目录项：为目录分配一个新数据块，并扩展 inode 映射
+    let (_new_lbn, new_block) = append_dir_block(fs, device, parent_ino_num, parent_inode)?;
+
+    // 在新分配的数据块中写入唯一的目录项，占满整个块
+    fs.datablock_cache
+        .modify(device, new_block, |data| {
+            for b in data.iter_mut() {
+                *b = 0;
+            }
+            let mut full_entry = new_entry;
+            full_entry.rec_len = rec_len_to_disk(usable_len);
+            full_entry.to_disk_bytes(&mut data[0..8]);
+            let nlen = full_entry.name_len as usize;
+            data[8..8 + nlen].copy_from_slice(&full_entry.name[..nlen]);
+            if usable_len < block_bytes {
+                init_dir_tail(data);
+            }
+        })?;
+    fs.update_dir_block_csum(device, parent_ino_num, new_block)?;
+
+    Ok(())
+}
+
+/// 为目录在末尾追加一个逻辑块：分配物理块、扩展块映射（extent 或直接/间接块）并更新 i_size/i_blocks，
+/// 返回 (逻辑块号, 物理块号)；新块内容由调用者初始化
+pub fn append_dir_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    device: &mut Jbd2Dev<B>,
+    parent_ino_num: u32,
+    parent_inode: &mut Ext4Inode,
+) -> BlockDevResult<(u32, u64)> {
     let new_block = fs.alloc_block(device)?;
 
     // 更新 parent_inode 的块映射（extent 或直接块）和大小统计
-    let block_bytes = BLOCK_SIZE;
+    let total_size = parent_inode.size() as usize;
+    let block_bytes = fs.block_size();
     let old_blocks = if total_size == 0 {
         0
     } else {
@@ -274,14 +302,11 @@ pub fn insert_dir_entry<B: BlockDevice>(
     if fs.superblock.has_extents() && parent_inode.have_extend_header_and_use_extend() {
         // extent 目录：通过 ExtentTree 追加一个长度为 1 的 extent
         let new_ext = Ext4Extent::new(new_lbn, new_block, 1);
-        let mut tree = ExtentTree::new(parent_inode);
+        let mut tree = ExtentTree::new_with_ino(fs, parent_inode, parent_ino_num);
         tree.insert_extent(fs, new_ext, device)?;
     } else {
-        // 传统直接块模式：仅支持追加到前 12 个直接块
-        if old_blocks >= 12 {
-            return Err(BlockDevError::Unsupported);
-        }
-        parent_inode.i_block[old_blocks] = new_block as u32;
+        // 传统直接/间接块模式：必要时分配间接块
+        BlockMap::new(parent_inode).set_block(fs, device, new_lbn, new_block)?;
     }
 
     // 更新 parent_inode 的 i_size / i_blocks，并写回 inode 表
@@ -290,7 +315,7 @@ pub fn insert_dir_entry<B: BlockDevice>(
     parent_inode.i_size_high = ((new_size as u64) >> 32) as u32;
     //fix:extend元数据也会占block，不能仅仅靠现有blocks_count计算，需要考虑extent树的开销
     let cur = parent_inode.blocks_count();
-    let add_sectors = (BLOCK_SIZE as u64 / 512) as u64;
+    let add_sectors = fs.block_size() as u64 / 512;
     let newv = cur.saturating_add(add_sectors);
     parent_inode.i_blocks_lo = (newv & 0xffff_ffff) as u32;
     parent_inode.l_i_blocks_high = ((newv >> 32) & 0xffff) as u16;
@@ -304,7 +329,7 @@ pub fn insert_dir_entry<B: BlockDevice>(
         parent_ino_num,
         fs.superblock.s_inodes_per_group,
         inode_table_start,
-        BLOCK_SIZE,
+        fs.block_size(),
     );
 
     fs.inodetable_cahce.modify(
@@ -322,20 +347,7 @@ pub fn insert_dir_entry<B: BlockDevice>(
         },
     )?;
 
-    // 在新分配的数据块中写入唯一的目录项，占满整个块
-    fs.datablock_cache
-        .modify(device, new_block, |data| {
-            for b in data.iter_mut() {
-                *b = 0;
-            }
-            let mut full_entry = new_entry;
-            full_entry.rec_len = BLOCK_SIZE as u16;
-            full_entry.to_disk_bytes(&mut data[0..8]);
-            let nlen = full_entry.name_len as usize;
-            data[8..8 + nlen].copy_from_slice(&full_entry.name[..nlen]);
-        })?;
-
-    Ok(())
+    Ok((new_lbn, new_block))
 }
 
 /// 默认开启hashtree查找
@@ -352,6 +364,18 @@ pub fn mkdir_with_ino<B: BlockDevice>(
     device: &mut Jbd2Dev<B>,
     fs: &mut Ext4FileSystem,
     path: &str,
+) -> Option<(u32, Ext4Inode)> {
+    fs.journaled(device, EXT4_DIR_TRANS_BLOCKS, |fs, device| {
+        do_mkdir_with_ino(device, fs, path)
+    })
+    .ok()
+    .flatten()
+}
+
+fn do_mkdir_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
 ) -> Option<(u32, Ext4Inode)> {
     // 先对传入路径做规范化（去掉重复的 '/' 等）
     let norm_path = split_paren_child_and_tranlatevalid(path);
@@ -465,6 +489,9 @@ pub fn mkdir_with_ino<B: BlockDevice>(
         };
     }
 
+    // fast commit 不记录新建目录，本次操作只能完整提交
+    fs.fast_commit.mark_ineligible();
+
     // 为新目录分配 inode（内部自动选择块组）
     let new_dir_ino = match fs.alloc_inode(device) {
         Ok(ino) => ino,
@@ -474,77 +501,117 @@ pub fn mkdir_with_ino<B: BlockDevice>(
         }
     };
 
-    // 为新目录分配数据块（内部自动选择块组）
-    let data_block = match fs.alloc_block(device) {
-        Ok(b) => b,
+    let block_bytes = fs.block_size();
+    let (group_idx, _idx) = fs.inode_allocator.global_to_group(new_dir_ino);
+
+    // 启用 inline_data 时新目录内联在 inode 中，不分配数据块
+    let inline = match create_inline_dir(fs, device, new_dir_ino, parent_ino_num) {
+        Ok(v) => v,
         Err(e) => {
-            error!("mkdir alloc_block failed path={} ino={} err={:?} ({})", path, new_dir_ino, e, e);
+            error!("mkdir create inline dir failed path={path} ino={new_dir_ino} err={e:?}");
             return None;
         }
     };
+    if inline {
+        if fs
+            .modify_inode(device, new_dir_ino, |inode| {
+                inode.i_mode = Ext4Inode::S_IFDIR | 0o755;
+                inode.i_links_count = 2;
+                inode.i_dtime = 0;
+            })
+            .is_err()
+        {
+            error!("mkdir modify_inode failed path={path} ino={new_dir_ino}");
+            return None;
+        }
+    } else {
+        // 为新目录分配数据块（内部自动选择块组）
+        let data_block = match fs.alloc_block(device) {
+            Ok(b) => b,
+            Err(e) => {
+                error!("mkdir alloc_block failed path={path} ino={new_dir_ino} err={e:?} ({e})");
+                return None;
+            }
+        };
 
-    // 初始化新目录的数据块：写 '.' 和 '..'
-    {
-        let cached = fs.datablock_cache.create_new(data_block);
-        let data = &mut cached.data;
+        // 初始化新目录的数据块：写 '.' 和 '..'
+        let usable_len = fs.dir_block_usable_len();
+        {
+            let cached = fs.datablock_cache.create_new(data_block);
+            let data = &mut cached.data;
 
-        let dot_name = b".";
-        let dot_rec_len = Ext4DirEntry2::entry_len(dot_name.len() as u8);
-        let dot = Ext4DirEntry2::new(
-            new_dir_ino,
-            dot_rec_len,
-            Ext4DirEntry2::EXT4_FT_DIR,
-            dot_name,
-        );
+            let dot_name = b".";
+            let dot_rec_len = Ext4DirEntry2::entry_len(dot_name.len() as u8);
+            let dot = Ext4DirEntry2::new(
+                new_dir_ino,
+                dot_rec_len,
+                Ext4DirEntry2::EXT4_FT_DIR,
+                dot_name,
+            );
 
-        let dotdot_name = b"..";
-        let dotdot_rec_len = (BLOCK_SIZE as u16).saturating_sub(dot_rec_len);
-        let dotdot = Ext4DirEntry2::new(
-            parent_ino_num,
-            dotdot_rec_len,
-            Ext4DirEntry2::EXT4_FT_DIR,
-            dotdot_name,
-        );
+            let dotdot_name = b"..";
+            let dotdot_rec_len = rec_len_to_disk(usable_len - dot_rec_len as usize);
+            let dotdot = Ext4DirEntry2::new(
+                parent_ino_num,
+                dotdot_rec_len,
+                Ext4DirEntry2::EXT4_FT_DIR,
+                dotdot_name,
+            );
 
-        {
-            dot.to_disk_bytes(&mut data[0..8]);
-            let name_len = dot.name_len as usize;
-            data[8..8 + name_len].copy_from_slice(&dot.name[..name_len]);
+            {
+                dot.to_disk_bytes(&mut data[0..8]);
+                let name_len = dot.name_len as usize;
+                data[8..8 + name_len].copy_from_slice(&dot.name[..name_len]);
+            }
+
+            {
+                let offset = dot_rec_len as usize;
+                dotdot.to_disk_bytes(&mut data[offset..offset + 8]);
+                let name_len = dotdot.name_len as usize;
+                data[offset + 8..offset + 8 + name_len].copy_from_slice(&dotdot.name[..name_len]);
+            }
+
+            if usable_len < block_bytes {
+                init_dir_tail(data);
+            }
         }
 
+        // 写新目录 inode（单块目录，按特性选择 extent 或直接块）
+        //仅仅的视图，修改过后的
+
+        let mut inode_pre = fs
+            .get_inode_by_num(device, new_dir_ino)
+            .expect("Can't getinode");
+        if let Err(e) = build_file_block_mapping(fs, &mut inode_pre, new_dir_ino, &[data_block], device) {
+            error!("mkdir build block mapping failed path={path} ino={new_dir_ino} err={e:?}");
+            return None;
+        }
+        if fs
+            .modify_inode(device, new_dir_ino, |inode| {
+                inode.i_block = inode_pre.i_block;
+                inode.i_mode = Ext4Inode::S_IFDIR | 0o755;
+                inode.i_links_count = 2; // . 和 entires本身
+                inode.i_size_lo = block_bytes as u32;
+                inode.i_size_high = 0;
+                inode.i_blocks_lo = (block_bytes / 512) as u32;
+                inode.l_i_blocks_high = 0;
+                inode.i_dtime = 0;
+                inode.i_flags |= inode_pre.i_flags
+
+                //由于借用冲突，暂时先把mapping移步到外面
+            })
+            .is_err()
         {
-            let offset = dot_rec_len as usize;
-            dotdot.to_disk_bytes(&mut data[offset..offset + 8]);
-            let name_len = dotdot.name_len as usize;
-            data[offset + 8..offset + 8 + name_len].copy_from_slice(&dotdot.name[..name_len]);
+            error!("mkdir modify_inode failed path={path} ino={new_dir_ino}");
+            return None;
+        }
+        if let Err(e) = fs.update_dir_block_csum(device, new_dir_ino, data_block) {
+            error!("mkdir update dir block csum failed path={path} ino={new_dir_ino} err={e:?}");
+            return None;
         }
     }
-
-    // 写新目录 inode（单块目录，按特性选择 extent 或直接块）
-    let (group_idx, _idx) = fs.inode_allocator.global_to_group(new_dir_ino);
-    //仅仅的视图，修改过后的
-
-    let mut inode_pre = fs
-        .get_inode_by_num(device, new_dir_ino)
-        .expect("Can't getinode");
-    build_file_block_mapping(fs, &mut inode_pre, &[data_block], device);
-    if fs
-        .modify_inode(device, new_dir_ino, |inode| {
-            inode.i_block = inode_pre.i_block;
-            inode.i_mode = Ext4Inode::S_IFDIR | 0o755;
-            inode.i_links_count = 2; // . 和 entires本身
-            inode.i_size_lo = BLOCK_SIZE as u32;
-            inode.i_size_high = 0;
-            inode.i_blocks_lo = (BLOCK_SIZE / 512) as u32;
-            inode.l_i_blocks_high = 0;
-            inode.i_dtime = 0;
-            inode.i_flags |= inode_pre.i_flags
-
-            //由于借用冲突，暂时先把mapping移步到外面
-        })
-        .is_err()
-    {
-        error!("mkdir modify_inode failed path={} ino={}", path, new_dir_ino);
+    if let Err(e) = inherit_acl(fs, device, parent_ino_num, new_dir_ino) {
+        error!("mkdir inherit default acl failed path={path} ino={new_dir_ino} err={e:?}");
         return None;
     }
 
@@ -562,7 +629,7 @@ pub fn mkdir_with_ino<B: BlockDevice>(
             parent_ino_num,
             fs.superblock.s_inodes_per_group,
             p_inode_table_start,
-            BLOCK_SIZE,
+            block_bytes,
         );
 
         let _ = fs.inodetable_cahce.modify(
@@ -633,6 +700,8 @@ pub fn create_root_directory_entry<B: BlockDevice>(
     let data_block = fs.alloc_block(block_dev)?;
 
     //  写入目录项 . 和 ..
+    let block_bytes = fs.block_size();
+    let usable_len = fs.dir_block_usable_len();
     {
         let cached = fs.datablock_cache.create_new(data_block);
         let data = &mut cached.data;
@@ -649,7 +718,7 @@ pub fn create_root_directory_entry<B: BlockDevice>(
 
         // ..目录项（根的父目录仍为自己）
         let dotdot_name = b"..";
-        let dotdot_rec_len = (BLOCK_SIZE as u16).saturating_sub(dot_rec_len);
+        let dotdot_rec_len = rec_len_to_disk(usable_len - dot_rec_len as usize);
         let dotdot = Ext4DirEntry2::new(
             root_inode_num,
             dotdot_rec_len,
@@ -669,26 +738,32 @@ pub fn create_root_directory_entry<B: BlockDevice>(
             let name_len = dotdot.name_len as usize;
             data[offset + 8..offset + 8 + name_len].copy_from_slice(&dotdot.name[..name_len]);
         }
+
+        if usable_len < block_bytes {
+            init_dir_tail(data);
+        }
     }
 
     //仅仅的视图，修改过后的
     let root_inode_num = fs.root_inode;
+    fs.init_new_inode(block_dev, root_inode_num)?;
     let mut inode_pre = fs
         .get_inode_by_num(block_dev, root_inode_num)
         .expect("Can't getinode");
-    build_file_block_mapping(fs, &mut inode_pre, &[data_block], block_dev);
+    build_file_block_mapping(fs, &mut inode_pre, root_inode_num, &[data_block], block_dev)?;
 
     fs.modify_inode(block_dev, fs.root_inode, |inode| {
         inode.i_flags = inode_pre.i_flags;
         inode.i_block = inode_pre.i_block;
         inode.i_mode = Ext4Inode::S_IFDIR | 0o755; // 目录 + 权限
         inode.i_links_count = 2; // . 和 ..
-        inode.i_size_lo = BLOCK_SIZE as u32;
+        inode.i_size_lo = block_bytes as u32;
         inode.i_size_high = 0;
         // i_blocks 以 512 字节为单位
-        inode.i_blocks_lo = (BLOCK_SIZE / 512) as u32;
+        inode.i_blocks_lo = (block_bytes / 512) as u32;
         inode.l_i_blocks_high = 0;
     })?;
+    fs.update_dir_block_csum(block_dev, root_inode_num, data_block)?;
 
     //块组描述符更新 目录数
     if let Some(desc) = fs.get_group_desc_mut(0) {
@@ -724,6 +799,8 @@ pub fn create_lost_found_directory<B: BlockDevice>(
     let data_block = fs.alloc_block(block_dev)?;
 
     //  初始化 lost+found 目录块（".", ".."）
+    let block_bytes = fs.block_size();
+    let usable_len = fs.dir_block_usable_len();
     {
         let cached = fs.datablock_cache.create_new(data_block);
         let data = &mut cached.data;
@@ -733,7 +810,7 @@ pub fn create_lost_found_directory<B: BlockDevice>(
         let dot = Ext4DirEntry2::new(lost_ino, dot_rec_len, Ext4DirEntry2::EXT4_FT_DIR, dot_name);
 
         let dotdot_name = b"..";
-        let dotdot_rec_len = (BLOCK_SIZE as u16).saturating_sub(dot_rec_len);
+        let dotdot_rec_len = rec_len_to_disk(usable_len - dot_rec_len as usize);
         let dotdot = Ext4DirEntry2::new(
             root_inode_num,
             dotdot_rec_len,
@@ -753,6 +830,10 @@ pub fn create_lost_found_directory<B: BlockDevice>(
             let name_len = dotdot.name_len as usize;
             data[offset + 8..offset + 8 + name_len].copy_from_slice(&dotdot.name[..name_len]);
         }
+
+        if usable_len < block_bytes {
+            init_dir_tail(data);
+        }
     }
 
     //  写 lost+found inode
@@ -762,7 +843,7 @@ pub fn create_lost_found_directory<B: BlockDevice>(
     let mut inode_pre = fs
         .get_inode_by_num(block_dev, lost_ino)
         .expect("Can't getinode");
-    build_file_block_mapping(fs, &mut inode_pre, &[data_block], block_dev);
+    build_file_block_mapping(fs, &mut inode_pre, lost_ino, &[data_block], block_dev)?;
     debug!(
         "When create lost+found inode iblock,:{:?} ,data_block:{:?}",
         inode_pre.i_block, data_block
@@ -774,9 +855,10 @@ pub fn create_lost_found_directory<B: BlockDevice>(
         inode.i_flags = inode_pre.i_flags;
         inode.i_mode = Ext4Inode::S_IFDIR | 0o755;
         inode.i_links_count = 2;
-        inode.i_size_lo = BLOCK_SIZE as u32;
-        inode.i_blocks_lo = (BLOCK_SIZE / 512) as u32;
+        inode.i_size_lo = block_bytes as u32;
+        inode.i_blocks_lo = (block_bytes / 512) as u32;
     })?;
+    fs.update_dir_block_csum(block_dev, lost_ino, data_block)?;
 
     if let Some(desc) = fs.get_group_desc_mut(lf_group) {
         let newc = desc.used_dirs_count().saturating_add(1);
@@ -788,7 +870,7 @@ pub fn create_lost_found_directory<B: BlockDevice>(
 
     //这里也需要根据extend来解析
     let mut root_inode = fs.get_root(block_dev)?;
-    let root_block = resolve_inode_block( block_dev, &mut root_inode, 0)?
+    let root_block = resolve_inode_block(fs, block_dev, root_inode_num, &mut root_inode, 0)?
         .expect("lost+found logical_block can't map to physical blcok!");
 
     if root_block == 0 {
@@ -816,7 +898,7 @@ pub fn create_lost_found_directory<B: BlockDevice>(
             );
 
             let lf_name = b"lost+found";
-            let lf_rec_len = (BLOCK_SIZE as u16).saturating_sub(dot_rec_len + dotdot_rec_len);
+            let lf_rec_len = rec_len_to_disk(usable_len - (dot_rec_len + dotdot_rec_len) as usize);
             let lost =
                 Ext4DirEntry2::new(lost_ino, lf_rec_len, Ext4DirEntry2::EXT4_FT_DIR, lf_name);
 
@@ -841,7 +923,12 @@ pub fn create_lost_found_directory<B: BlockDevice>(
             lost.to_disk_bytes(&mut data[offset..offset + 8]);
             let lf_len = lost.name_len as usize;
             data[offset + 8..offset + 8 + lf_len].copy_from_slice(&lost.name[..lf_len]);
+
+            if usable_len < block_bytes {
+                init_dir_tail(data);
+            }
         })?;
+    fs.update_dir_block_csum(block_dev, root_inode_num, root_block as u64)?;
 
     //  更新根 inode 的链接计数（多了一个子目录）
     let inode_table_start = match fs.group_descs.first() {
@@ -852,7 +939,7 @@ pub fn create_lost_found_directory<B: BlockDevice>(
         fs.root_inode,
         fs.superblock.s_inodes_per_group,
         inode_table_start,
-        BLOCK_SIZE,
+        block_bytes,
     );
 
     fs.inodetable_cahce.modify(
@@ -874,3 +961,32 @@ pub fn create_lost_found_directory<B: BlockDevice>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+
+    #[test]
+    fn test_mkdir_readdir_on_64k_blocks() {
+        // 不开 metadata_csum 时没有目录尾，整块长度 65536 需要编码为 0xFFFF
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, MemDev::new(16 << 20), false);
+        mkfs_with_journal(&mut dev, 65536, JournalSize::Disabled).unwrap();
+        let mut fs = mount(&mut dev).unwrap();
+        assert_eq!(list_dir(&mut fs, &mut dev, "/"), [".", "..", "lost+found"]);
+
+        assert!(mkdir(&mut dev, &mut fs, "/d").is_some());
+        assert!(mkdir(&mut dev, &mut fs, "/d/e").is_some());
+        mkfile(&mut dev, &mut fs, "/d/f", Some(b"x"), None).unwrap();
+        assert_eq!(list_dir(&mut fs, &mut dev, "/d"), [".", "..", "e", "f"]);
+        assert_eq!(list_dir(&mut fs, &mut dev, "/d/e"), [".", ".."]);
+
+        unlink(&mut fs, &mut dev, "/d/f");
+        assert_eq!(list_dir(&mut fs, &mut dev, "/d"), [".", "..", "e"]);
+        fs.umount(&mut dev).unwrap();
+
+        let mut fs = mount(&mut dev).unwrap();
+        assert_eq!(list_dir(&mut fs, &mut dev, "/"), [".", "..", "lost+found", "d"]);
+        fs.umount(&mut dev).unwrap();
+    }
+}
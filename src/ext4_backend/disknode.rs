@@ -1,6 +1,7 @@
 use log::debug;
 
 use crate::ext4_backend::endian::*;
+use crate::ext4_backend::time::*;
 
 /// Ext4 磁盘Inode结构
 /// Inode是文件系统中存储文件元数据的核心数据结构
@@ -80,6 +81,9 @@ impl Ext4Inode {
     /// 标准inode大小（128字节）
     pub const GOOD_OLD_INODE_SIZE: u16 = 128;
 
+    /// 覆盖全部扩展字段（到 i_projid）的 i_extra_isize
+    pub const EXTRA_ISIZE: u16 = 32;
+
     /// 大inode默认大小（256字节）
     pub const LARGE_INODE_SIZE: u16 = 256;
 
@@ -103,6 +107,18 @@ impl Ext4Inode {
         (self.l_i_gid_high as u32) << 16 | self.i_gid as u32
     }
 
+    /// 设置完整的UID（32位）
+    pub fn set_uid(&mut self, uid: u32) {
+        self.i_uid = uid as u16;
+        self.l_i_uid_high = (uid >> 16) as u16;
+    }
+
+    /// 设置完整的GID（32位）
+    pub fn set_gid(&mut self, gid: u32) {
+        self.i_gid = gid as u16;
+        self.l_i_gid_high = (gid >> 16) as u16;
+    }
+
     /// 获取完整的扩展属性块号（48位）
     pub fn file_acl(&self) -> u64 {
         (self.l_i_file_acl_high as u64) << 32 | self.i_file_acl_lo as u64
@@ -123,6 +139,23 @@ impl Ext4Inode {
         self.i_mode & Self::S_IFMT == Self::S_IFLNK
     }
 
+    /// 检查是否是快速符号链接（目标路径直接存放在 i_block 中）
+    pub fn is_fast_symlink(&self) -> bool {
+        self.is_symlink() && self.size() <= 60
+    }
+
+    /// 检查数据是否内联存放（i_block 加 inode 内的 system.data 扩展属性）
+    pub fn has_inline_data(&self) -> bool {
+        self.i_flags & Self::EXT4_INLINE_DATA_FL != 0
+    }
+
+    /// 检查 i_block 是否存放块映射（设备文件、快速符号链接、内联数据的 i_block 另作他用）
+    pub fn maps_data_blocks(&self) -> bool {
+        (self.is_file() || self.is_dir() || self.is_symlink())
+            && !self.is_fast_symlink()
+            && !self.has_inline_data()
+    }
+
     /// 检查是否使用extent树
     fn is_extent(&self) -> bool {
         self.i_flags & Self::EXT4_EXTENTS_FL != 0
@@ -157,6 +190,76 @@ impl Ext4Inode {
         self.i_atime = atime;
     }
 
+    /// 扩展字段 [.., end) 是否落在 i_extra_isize 覆盖的范围内
+    fn extra_field_fits(&self, end: usize) -> bool {
+        Self::GOOD_OLD_INODE_SIZE as usize + self.i_extra_isize as usize >= end
+    }
+
+    fn get_time(&self, sec: u32, extra: u32, extra_end: usize) -> Timespec {
+        if self.extra_field_fits(extra_end) {
+            decode_time(sec, extra)
+        } else {
+            decode_time(sec, 0)
+        }
+    }
+
+    fn put_time(&self, ts: Timespec, extra_end: usize) -> (u32, Option<u32>) {
+        if self.extra_field_fits(extra_end) {
+            let (sec, extra) = encode_time(ts);
+            (sec, Some(extra))
+        } else {
+            (clamp_legacy_time(ts), None)
+        }
+    }
+
+    pub fn atime(&self) -> Timespec {
+        self.get_time(self.i_atime, self.i_atime_extra, 144)
+    }
+    pub fn mtime(&self) -> Timespec {
+        self.get_time(self.i_mtime, self.i_mtime_extra, 140)
+    }
+    pub fn ctime(&self) -> Timespec {
+        self.get_time(self.i_ctime, self.i_ctime_extra, 136)
+    }
+    /// 创建时间；没有 i_crtime 字段的 inode 返回 None
+    pub fn crtime(&self) -> Option<Timespec> {
+        self.extra_field_fits(148)
+            .then(|| self.get_time(self.i_crtime, self.i_crtime_extra, 152))
+    }
+
+    /// 设置带纳秒的时间戳，i_extra_isize 不覆盖扩展字段时只写秒数
+    pub fn set_atime_ts(&mut self, ts: Timespec) {
+        let (sec, extra) = self.put_time(ts, 144);
+        self.i_atime = sec;
+        if let Some(extra) = extra {
+            self.i_atime_extra = extra;
+        }
+    }
+    pub fn set_mtime_ts(&mut self, ts: Timespec) {
+        let (sec, extra) = self.put_time(ts, 140);
+        self.i_mtime = sec;
+        if let Some(extra) = extra {
+            self.i_mtime_extra = extra;
+        }
+    }
+    pub fn set_ctime_ts(&mut self, ts: Timespec) {
+        let (sec, extra) = self.put_time(ts, 136);
+        self.i_ctime = sec;
+        if let Some(extra) = extra {
+            self.i_ctime_extra = extra;
+        }
+    }
+    pub fn set_crtime_ts(&mut self, ts: Timespec) {
+        if !self.extra_field_fits(148) {
+            return;
+        }
+        let (sec, extra) = self.put_time(ts, 152);
+        self.i_crtime = sec;
+        if let Some(extra) = extra {
+            self.i_crtime_extra = extra;
+        }
+    }
+
 }
 
 // 文件模式常量 - 文件类型
@@ -268,7 +371,7 @@ pub struct Ext4ExtentIdx {
 /// Extent叶子结构
 /// 用于extent树的叶子节点，表示实际的数据块映射
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Ext4Extent {
     pub ee_block: u32,    // 第一个逻辑块
     pub ee_len: u16,      // extent长度（块数）
@@ -290,8 +393,8 @@ impl Ext4Extent {
     /// extent最大长度（已初始化）
     pub const EXT_INIT_MAX_LEN: u16 = 32768;
 
-    /// extent最大长度（未初始化）
-    pub const EXT_UNINIT_MAX_LEN: u16 = 32768;
+    /// extent最大长度（未初始化），ee_len 超过 EXT_INIT_MAX_LEN 的部分是长度
+    pub const EXT_UNINIT_MAX_LEN: u16 = 32767;
 
     ///默认配置
     pub fn new(logic_start: u32, start_phy_block: u64, len: u16) -> Self {
@@ -310,10 +413,46 @@ impl Ext4Extent {
         (self.ee_start_hi as u64) << 32 | self.ee_start_lo as u64
     }
 
+    /// 未初始化（unwritten）extent：已分配物理块，但读出为 0
+    pub fn new_unwritten(logic_start: u32, start_phy_block: u64, len: u16) -> Self {
+        let mut ext = Self::new(logic_start, start_phy_block, len);
+        ext.set_len(len as u32, true);
+        ext
+    }
+
     /// 检查extent是否已初始化
     pub fn is_initialized(&self) -> bool {
         self.ee_len <= Self::EXT_INIT_MAX_LEN
     }
+
+    /// 实际覆盖的块数（去掉 unwritten 标志）
+    pub fn actual_len(&self) -> u32 {
+        if self.is_initialized() {
+            self.ee_len as u32
+        } else {
+            (self.ee_len - Self::EXT_INIT_MAX_LEN) as u32
+        }
+    }
+
+    /// 设置长度和是否为 unwritten
+    pub fn set_len(&mut self, len: u32, unwritten: bool) {
+        self.ee_len = if unwritten {
+            debug_assert!(len <= Self::EXT_UNINIT_MAX_LEN as u32);
+            len as u16 + Self::EXT_INIT_MAX_LEN
+        } else {
+            debug_assert!(len <= Self::EXT_INIT_MAX_LEN as u32);
+            len as u16
+        };
+    }
+
+    /// 同类 extent 的最大长度
+    pub fn max_len(&self) -> u32 {
+        if self.is_initialized() {
+            Self::EXT_INIT_MAX_LEN as u32
+        } else {
+            Self::EXT_UNINIT_MAX_LEN as u32
+        }
+    }
 }
 
 /// 实现 DiskFormat trait 用于字节序转换
@@ -157,7 +157,7 @@ pub struct Ext4DxNode {
 pub struct Ext4ExtentStatus {
     pub es_lblk: u64, // 第一个逻辑块
     pub es_len: u64,  // extent长度
-    pub es_pblk: u64, // 第一个物理块
+    pub es_pblk: u64, // 第一个物理块（高位为状态标志）
 }
 
 /// 用于在目录中查找文件名的辅助结构
@@ -180,7 +180,7 @@ impl<'a> Ext4DirEntryInfo<'a> {
             return None; // 无效条目
         }
 
-        let rec_len = u16::from_le_bytes([data[4], data[5]]);
+        let rec_len = rec_len_from_disk(u16::from_le_bytes([data[4], data[5]]));
         let name_len = data[6] as usize;
         let file_type = data[7];
 
@@ -228,7 +228,7 @@ impl<'a> DirEntryIterator<'a> {
 }
 
 impl<'a> Iterator for DirEntryIterator<'a> {
-    type Item = (Ext4DirEntryInfo<'a>, u16); // (条目信息, rec_len)
+    type Item = (Ext4DirEntryInfo<'a>, usize); // (条目信息, rec_len)
 
     fn next(&mut self) -> Option<Self::Item> {
         while self.offset < self.data.len() {
@@ -237,13 +237,13 @@ impl<'a> Iterator for DirEntryIterator<'a> {
                 return None;
             }
 
-            let rec_len = u16::from_le_bytes([remaining[4], remaining[5]]);
-            if rec_len < 8 || rec_len as usize > remaining.len() {
+            let rec_len = rec_len_from_disk(u16::from_le_bytes([remaining[4], remaining[5]]));
+            if rec_len < 8 || rec_len > remaining.len() {
                 return None;
             }
 
-            let entry_data = &remaining[..rec_len as usize];
-            self.offset += rec_len as usize;
+            let entry_data = &remaining[..rec_len];
+            self.offset += rec_len;
 
             // Skip unused or malformed entries but keep iterating.
             if let Some(entry_info) = Ext4DirEntryInfo::parse_from_bytes(entry_data) {
@@ -255,6 +255,25 @@ impl<'a> Iterator for DirEntryIterator<'a> {
     }
 }
 
+/// 把目录项长度编码为磁盘上的 rec_len（64KiB 块时整块长度写作 0xFFFF，与内核一致）
+pub fn rec_len_to_disk(len: usize) -> u16 {
+    if len < 65536 {
+        len as u16
+    } else if len == 65536 {
+        0xFFFF
+    } else {
+        ((len & 65532) | ((len >> 16) & 3)) as u16
+    }
+}
+
+/// 解码磁盘上的 rec_len：0xFFFF 与 0 表示占满 64KiB 块（与 e2fsprogs 一致）
+pub fn rec_len_from_disk(raw: u16) -> usize {
+    match raw {
+        0 | 0xFFFF => 65536,
+        _ => (raw as usize & 65532) | ((raw as usize & 3) << 16),
+    }
+}
+
 /// 线性目录（Classic Directory）辅助函数
 pub mod classic_dir {
     use super::*;
@@ -278,64 +297,319 @@ pub mod classic_dir {
         let iter = DirEntryIterator::new(block_data);
         iter.map(|(entry, _)| entry).collect()
     }
+
+    /// 在线性目录块的前 usable_len 字节内寻找空闲空间插入目录项
+    /// 优先复用空闲条目，否则切分已有条目尾部的富余空间；成功返回 true
+    pub fn insert_entry(block_data: &mut [u8], usable_len: usize, entry: &Ext4DirEntry2) -> bool {
+        let new_rec_len = Ext4DirEntry2::entry_len(entry.name_len) as usize;
+        let nlen = entry.name_len as usize;
+        let mut offset = 0usize;
+        while offset + 8 <= usable_len {
+            let inode = read_u32_le(&block_data[offset..offset + 4]);
+            let rec_len = rec_len_from_disk(read_u16_le(&block_data[offset + 4..offset + 6]));
+            if rec_len < 8 {
+                return false;
+            }
+            let entry_end = offset + rec_len;
+            if entry_end > usable_len {
+                return false;
+            }
+
+            // 空闲条目：能容纳则直接占用
+            if inode == 0 && rec_len >= new_rec_len {
+                let mut full_entry = *entry;
+                full_entry.rec_len = rec_len_to_disk(rec_len);
+                full_entry.to_disk_bytes(&mut block_data[offset..offset + 8]);
+                block_data[offset + 8..offset + 8 + nlen].copy_from_slice(&entry.name[..nlen]);
+                return true;
+            }
+
+            // 已占用条目：尝试切分尾部空间
+            if inode != 0 {
+                let ideal = Ext4DirEntry2::entry_len(block_data[offset + 6]) as usize;
+                if ideal <= rec_len && rec_len - ideal >= new_rec_len {
+                    write_u16_le(ideal as u16, &mut block_data[offset + 4..offset + 6]);
+                    let new_off = offset + ideal;
+                    let mut full_entry = *entry;
+                    full_entry.rec_len = (rec_len - ideal) as u16;
+                    full_entry.to_disk_bytes(&mut block_data[new_off..new_off + 8]);
+                    block_data[new_off + 8..new_off + 8 + nlen].copy_from_slice(&entry.name[..nlen]);
+                    return true;
+                }
+            }
+
+            offset = entry_end;
+        }
+        false
+    }
+
+    /// 从线性目录块的前 usable_len 字节内删除名为 name 的目录项
+    /// 空间并入前一个条目；块内首个条目则只清零 inode。成功返回 true
+    pub fn remove_entry(block_data: &mut [u8], usable_len: usize, name: &[u8]) -> bool {
+        let mut offset = 0usize;
+        let mut prev_off: Option<usize> = None;
+        while offset + 8 <= usable_len {
+            let inode = read_u32_le(&block_data[offset..offset + 4]);
+            let rec_len = rec_len_from_disk(read_u16_le(&block_data[offset + 4..offset + 6]));
+            if rec_len < 8 {
+                return false;
+            }
+            let name_len = block_data[offset + 6] as usize;
+            let entry_end = offset + rec_len;
+            if entry_end > usable_len {
+                return false;
+            }
+
+            if inode != 0
+                && name_len == name.len()
+                && offset + 8 + name_len <= entry_end
+                && &block_data[offset + 8..offset + 8 + name_len] == name
+            {
+                if let Some(poff) = prev_off {
+                    let prev_len = rec_len_from_disk(read_u16_le(&block_data[poff + 4..poff + 6]));
+                    write_u16_le(rec_len_to_disk(prev_len + rec_len), &mut block_data[poff + 4..poff + 6]);
+                }
+                write_u32_le(0, &mut block_data[offset..offset + 4]);
+                return true;
+            }
+
+            prev_off = Some(offset);
+            offset = entry_end;
+        }
+        false
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_insert_then_remove_reuses_space() {
+            let mut block = [0u8; 1024];
+            let first = Ext4DirEntry2::new(11, 1024, Ext4DirEntry2::EXT4_FT_REG_FILE, b"a");
+            first.to_disk_bytes(&mut block[0..8]);
+            block[8] = b'a';
+
+            for (ino, name) in [(12u32, &b"bb"[..]), (13, b"ccc")] {
+                let e = Ext4DirEntry2::new(ino, 0, Ext4DirEntry2::EXT4_FT_REG_FILE, name);
+                assert!(insert_entry(&mut block, 1024, &e));
+            }
+            assert_eq!(find_entry(&block, b"ccc").map(|e| e.inode), Some(13));
+            assert_eq!(list_entries(&block).len(), 3);
+
+            assert!(remove_entry(&mut block, 1024, b"bb"));
+            assert!(!remove_entry(&mut block, 1024, b"bb"));
+            assert!(find_entry(&block, b"bb").is_none());
+            // "a" 吸收了 "bb" 的空间，rec_len 之和仍覆盖整块
+            let total: usize = DirEntryIterator::new(&block).map(|(_, len)| len).sum();
+            assert_eq!(total, 1024);
+
+            // 整块已满时插入失败
+            let big = Ext4DirEntry2::new(14, 0, Ext4DirEntry2::EXT4_FT_REG_FILE, &[b'x'; 255]);
+            for _ in 0..3 {
+                assert!(insert_entry(&mut block, 1024, &big));
+            }
+            assert!(!insert_entry(&mut block, 1024, &big));
+        }
+    }
 }
 
 /// HTree索引目录（Hash Tree Directory）辅助函数
 pub mod htree_dir {
     use super::*;
 
-    /// 计算文件名的哈希值
+    /// 32 位哈希的 EOF 标记（readdir 游标使用），实际哈希值需避开它
+    pub const EXT4_HTREE_EOF_32BIT: u32 = 0x7FFF_FFFF;
+
+    /// 未设置种子时使用的默认哈希种子
+    const DEFAULT_SEED: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+    /// 计算文件名的哈希值（与 Linux ext4fs_dirhash 一致，最低位恒为 0）
     pub fn calculate_hash(name: &[u8], hash_version: u8, hash_seed: &[u32; 4]) -> u32 {
-        match hash_version {
-            Ext4DxRootInfo::DX_HASH_LEGACY => legacy_hash(name),
-            Ext4DxRootInfo::DX_HASH_HALF_MD4 => half_md4_hash(name, hash_seed),
-            Ext4DxRootInfo::DX_HASH_TEA => tea_hash(name, hash_seed),
+        let mut buf = if hash_seed.iter().any(|&w| w != 0) {
+            *hash_seed
+        } else {
+            DEFAULT_SEED
+        };
+
+        let hash = match hash_version {
+            Ext4DxRootInfo::DX_HASH_LEGACY => legacy_hash(name, false),
+            Ext4DxRootInfo::DX_HASH_LEGACY_UNSIGNED => legacy_hash(name, true),
+            Ext4DxRootInfo::DX_HASH_HALF_MD4 | Ext4DxRootInfo::DX_HASH_HALF_MD4_UNSIGNED => {
+                let unsigned = hash_version == Ext4DxRootInfo::DX_HASH_HALF_MD4_UNSIGNED;
+                let mut input = [0u32; 8];
+                for start in (0..name.len()).step_by(32) {
+                    str2hashbuf(&name[start..], &mut input, unsigned);
+                    half_md4_transform(&mut buf, &input);
+                }
+                buf[1]
+            }
+            Ext4DxRootInfo::DX_HASH_TEA | Ext4DxRootInfo::DX_HASH_TEA_UNSIGNED => {
+                let unsigned = hash_version == Ext4DxRootInfo::DX_HASH_TEA_UNSIGNED;
+                let mut input = [0u32; 4];
+                for start in (0..name.len()).step_by(16) {
+                    str2hashbuf(&name[start..], &mut input, unsigned);
+                    tea_transform(&mut buf, &input);
+                }
+                buf[0]
+            }
             _ => 0,
+        };
+
+        let hash = hash & !1;
+        if hash == EXT4_HTREE_EOF_32BIT << 1 {
+            (EXT4_HTREE_EOF_32BIT - 1) << 1
+        } else {
+            hash
         }
     }
 
-    /// 传统哈希算法（简化实现）
-    fn legacy_hash(name: &[u8]) -> u32 {
-        let mut hash = 0u32;
-        for &byte in name {
-            hash = hash.wrapping_mul(33).wrapping_add(byte as u32);
+    /// 把剩余的名字按 Linux str2hashbuf 规则打包成 u32 数组
+    /// 填充值由剩余长度决定，只取前 out.len() * 4 个字节
+    fn str2hashbuf(rest: &[u8], out: &mut [u32], unsigned: bool) {
+        let len = rest.len() as u32;
+        let mut pad = len | (len << 8);
+        pad |= pad << 16;
+
+        let mut val = pad;
+        let mut idx = 0;
+        for (i, &byte) in rest.iter().take(out.len() * 4).enumerate() {
+            let c = if unsigned {
+                byte as u32
+            } else {
+                byte as i8 as i32 as u32
+            };
+            val = c.wrapping_add(val << 8);
+            if i % 4 == 3 {
+                out[idx] = val;
+                idx += 1;
+                val = pad;
+            }
+        }
+        if idx < out.len() {
+            out[idx] = val;
+            idx += 1;
+        }
+        for slot in out[idx..].iter_mut() {
+            *slot = pad;
         }
-        hash
     }
 
-    /// Half MD4哈希算法（简化实现）
-    fn half_md4_hash(name: &[u8], seed: &[u32; 4]) -> u32 {
-        // 这是一个简化版本，实际实现需要完整的MD4算法
-        let mut hash = seed[0];
+    /// 传统哈希算法（dx_hack_hash）
+    fn legacy_hash(name: &[u8], unsigned: bool) -> u32 {
+        let mut hash0: u32 = 0x12a3_fe2d;
+        let mut hash1: u32 = 0x37ab_e8f9;
         for &byte in name {
-            hash = hash.wrapping_mul(1103515245).wrapping_add(byte as u32);
+            let c = if unsigned {
+                byte as i32
+            } else {
+                byte as i8 as i32
+            };
+            let mut hash = hash1.wrapping_add(hash0 ^ (c.wrapping_mul(7_152_373) as u32));
+            if hash & 0x8000_0000 != 0 {
+                hash = hash.wrapping_sub(0x7fff_ffff);
+            }
+            hash1 = hash0;
+            hash0 = hash;
         }
-        hash
+        hash0 << 1
     }
 
-    /// TEA哈希算法（Tiny Encryption Algorithm）
-    fn tea_hash(name: &[u8], seed: &[u32; 4]) -> u32 {
-        let mut hash = seed[0];
-        let mut buf = [0u32; 4];
+    /// Half MD4 变换
+    fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+        const K2: u32 = 0o13240474631;
+        const K3: u32 = 0o15666365641;
+        let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+        let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+        let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+
+        let [mut a, mut b, mut c, mut d] = *buf;
+        macro_rules! round {
+            ($f:expr, $a:ident, $b:ident, $c:ident, $d:ident, $x:expr, $s:expr) => {
+                $a = $a.wrapping_add($f($b, $c, $d)).wrapping_add($x).rotate_left($s);
+            };
+        }
 
-        for chunk in name.chunks(16) {
-            for (i, bytes) in chunk.chunks(4).enumerate() {
-                if i >= 4 {
-                    break;
-                }
-                let mut val = 0u32;
-                for &b in bytes {
-                    val = (val << 8) | b as u32;
-                }
-                buf[i] = val;
-            }
+        round!(f, a, b, c, d, input[0], 3);
+        round!(f, d, a, b, c, input[1], 7);
+        round!(f, c, d, a, b, input[2], 11);
+        round!(f, b, c, d, a, input[3], 19);
+        round!(f, a, b, c, d, input[4], 3);
+        round!(f, d, a, b, c, input[5], 7);
+        round!(f, c, d, a, b, input[6], 11);
+        round!(f, b, c, d, a, input[7], 19);
+
+        round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+        round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+        round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+        round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+        round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+        round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+        round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+        round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+        round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+        round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+        round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+        round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+        round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+        round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+        round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+        round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+        buf[0] = buf[0].wrapping_add(a);
+        buf[1] = buf[1].wrapping_add(b);
+        buf[2] = buf[2].wrapping_add(c);
+        buf[3] = buf[3].wrapping_add(d);
+    }
+
+    /// TEA 变换（Tiny Encryption Algorithm）
+    fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+        const DELTA: u32 = 0x9E37_79B9;
+        let mut sum: u32 = 0;
+        let (mut b0, mut b1) = (buf[0], buf[1]);
+        let [a, b, c, d] = *input;
+
+        for _ in 0..16 {
+            sum = sum.wrapping_add(DELTA);
+            b0 = b0.wrapping_add(
+                ((b1 << 4).wrapping_add(a)) ^ b1.wrapping_add(sum) ^ ((b1 >> 5).wrapping_add(b)),
+            );
+            b1 = b1.wrapping_add(
+                ((b0 << 4).wrapping_add(c)) ^ b0.wrapping_add(sum) ^ ((b0 >> 5).wrapping_add(d)),
+            );
+        }
+        buf[0] = buf[0].wrapping_add(b0);
+        buf[1] = buf[1].wrapping_add(b1);
+    }
 
-            // TEA算法的简化版本
-            for _ in 0..4 {
-                hash = hash.wrapping_add(buf[0] ^ buf[1]);
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_hash_low_bit_clear_and_stable() {
+            let seed = [0x1234_5678, 0x9abc_def0, 0x0fed_cba9, 0x8765_4321];
+            for version in 0..=5u8 {
+                let h = calculate_hash(b"lost+found", version, &seed);
+                assert_eq!(h & 1, 0);
+                assert_eq!(h, calculate_hash(b"lost+found", version, &seed));
             }
         }
-        hash
+
+        #[test]
+        fn test_signedness_only_matters_for_high_bytes() {
+            let seed = [0; 4];
+            assert_eq!(
+                calculate_hash(b"plain", Ext4DxRootInfo::DX_HASH_HALF_MD4, &seed),
+                calculate_hash(b"plain", Ext4DxRootInfo::DX_HASH_HALF_MD4_UNSIGNED, &seed)
+            );
+            let name = "caf\u{e9}".as_bytes();
+            assert_ne!(
+                calculate_hash(name, Ext4DxRootInfo::DX_HASH_TEA, &seed),
+                calculate_hash(name, Ext4DxRootInfo::DX_HASH_TEA_UNSIGNED, &seed)
+            );
+        }
     }
 }
 
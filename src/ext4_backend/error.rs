@@ -14,11 +14,14 @@ pub enum BlockDevError {
     WriteError,
 
     /// 块号超出范围
-    BlockOutOfRange { block_id: u32, max_blocks: u64 },
+    BlockOutOfRange { block_id: u64, max_blocks: u64 },
 
     /// 无效的块大小
     InvalidBlockSize { size: usize, expected: usize },
 
+    /// 块大小不在支持的范围内（或不是 2 的幂）
+    BlockSizeOutOfRange { size: usize, min: usize, max: usize },
+
     /// 缓冲区太小
     BufferTooSmall { provided: usize, required: usize },
 
@@ -58,6 +61,12 @@ pub enum BlockDevError {
     /// 校验和错误
     ChecksumError,
 
+    /// 目标不存在（如扩展属性不存在）
+    NotFound,
+
+    /// 目标已存在
+    AlreadyExists,
+
     /// 未知错误
     Unknown,
 }
@@ -78,6 +87,9 @@ impl core::fmt::Display for BlockDevError {
             BlockDevError::InvalidBlockSize { size, expected } => {
                 write!(f, "invalid block size {size} (expected {expected})")
             }
+            BlockDevError::BlockSizeOutOfRange { size, min, max } => {
+                write!(f, "invalid block size {size} (expected a power of two in {min}..={max})")
+            }
             BlockDevError::BufferTooSmall { provided, required } => {
                 write!(
                     f,
@@ -101,6 +113,8 @@ impl core::fmt::Display for BlockDevError {
             BlockDevError::PermissionDenied => write!(f, "permission denied"),
             BlockDevError::Corrupted => write!(f, "device or data is corrupted"),
             BlockDevError::ChecksumError => write!(f, "checksum error"),
+            BlockDevError::NotFound => write!(f, "no such entry"),
+            BlockDevError::AlreadyExists => write!(f, "entry already exists"),
             BlockDevError::Unknown => write!(f, "unknown error"),
         }
     }
@@ -125,6 +139,8 @@ pub enum RSEXT4Error {
     UnsupportedFeature,
     /// 已经挂载
     AlreadyMounted,
+    /// 元数据校验和错误
+    ChecksumError,
 }
 
 impl core::fmt::Display for RSEXT4Error {
@@ -136,6 +152,7 @@ impl core::fmt::Display for RSEXT4Error {
             RSEXT4Error::FilesystemHasErrors => write!(f, "文件系统有错误"),
             RSEXT4Error::UnsupportedFeature => write!(f, "不支持的特性"),
             RSEXT4Error::AlreadyMounted => write!(f, "文件系统已挂载"),
+            RSEXT4Error::ChecksumError => write!(f, "元数据校验和错误"),
         }
     }
 }
\ No newline at end of file
@@ -2,25 +2,33 @@
 //!
 //! 提供文件系统挂载、卸载、文件操作等高层接口
 
-use crate::ext4_backend::bitmap::InodeBitmap;
+use crate::ext4_backend::bitmap::{BitmapError, BlockBitmapMut, InodeBitmap, InodeBitmapMut};
 use crate::ext4_backend::bitmap_cache::*;
 use crate::ext4_backend::blockdev::*;
 use crate::ext4_backend::blockgroup_description::*;
 use crate::ext4_backend::bmalloc::*;
+use crate::ext4_backend::checksum::*;
 use crate::ext4_backend::config::*;
 use crate::ext4_backend::datablock_cache::*;
 use crate::ext4_backend::dir::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::extent_status::*;
+use crate::ext4_backend::mballoc::*;
+use crate::ext4_backend::fast_commit::*;
 use crate::ext4_backend::inodetable_cache::*;
 use crate::ext4_backend::jbd2::jbd2::*;
 use crate::ext4_backend::jbd2::jbdstruct::*;
 use crate::ext4_backend::loopfile::*;
 use crate::ext4_backend::superblock::*;
+use crate::ext4_backend::time::*;
 use crate::ext4_backend::tool::*;
+use crate::ext4_backend::xattr::*;
 use crate::ext4_backend::error::*;
 use log::trace;
 
+use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
 use alloc::vec::Vec;
 use log::{debug, error, info, warn};
@@ -50,7 +58,21 @@ pub struct Ext4FileSystem {
     /// 是否已挂载
     pub mounted: bool,
     /// Journal 超级块 开始块号
-    pub journal_sb_block_start: Option<u32>,
+    pub journal_sb_block_start: Option<u64>,
+    /// 扩展属性块共享索引（按 h_hash 查找内容相同的块）
+    pub xattr_cache: XattrBlockCache,
+    /// 按 inode 缓存 extent 映射（逻辑块范围 -> 物理块/空洞）
+    pub extent_status: ExtentStatusCache,
+    /// 多块分配器状态：块组空闲区段摘要和预分配窗口
+    pub mballoc: MbState,
+    /// 出错后按 s_errors 策略转为只读，之后拒绝所有修改
+    pub read_only: bool,
+    /// fsync 时据此判断能否只写 fast commit 记录
+    pub fast_commit: FastCommitState,
+    /// inode 时间戳使用的时钟（挂载时提供）
+    pub time_source: Box<dyn TimeSource>,
+    /// 读取时 atime 的更新策略
+    pub atime_policy: AtimePolicy,
 }
 
 impl Ext4FileSystem {
@@ -78,6 +100,10 @@ impl Ext4FileSystem {
         let bitmap_block = desc.inode_bitmap();
         let cache_key = CacheKey::new_inode(group_idx);
 
+        if let Err(e) = self.verify_bitmap_csum(device, cache_key, bitmap_block) {
+            warn!("inode_num_already_allocted: load inode bitmap failed: {e:?}");
+            return false;
+        }
         let bitmap = match self
             .bitmap_cache
             .get_or_load(device, cache_key, bitmap_block)
@@ -157,7 +183,7 @@ impl Ext4FileSystem {
             self.root_inode,
             self.superblock.s_inodes_per_group,
             inode_table_start,
-            BLOCK_SIZE,
+            self.block_size(),
         );
         let result =
             self.inodetable_cahce
@@ -185,8 +211,9 @@ impl Ext4FileSystem {
         //在mount时应该重放一遍日志
         //block_dev.set_journal_superblock(super_block, jouranl_start_block);
 
-        // 1. 读取超级块（按 ext4 标准偏移 1024 字节，大小 1024 字节）
-        let superblock = read_superblock(block_dev).map_err(|_| RSEXT4Error::IoError)?;
+        // 1. 根据超级块确定块大小，再按该块大小读取超级块
+        probe_block_size(block_dev)?;
+        let superblock = read_superblock(block_dev).map_err(mount_error)?;
 
         // 2. 验证魔数
         if superblock.s_magic != EXT4_SUPER_MAGIC {
@@ -198,6 +225,14 @@ impl Ext4FileSystem {
         }
         debug!("Superblock magic verified");
 
+        // 没有 CONFIG_META_CSUM_ENABLE 时不维护校验和，读写挂载 metadata_csum 镜像会写出过期的校验和
+        if superblock.has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_METADATA_CSUM)
+            && !superblock.has_metadata_csum()
+        {
+            error!("metadata_csum filesystem needs the CONFIG_META_CSUM_ENABLE feature to mount read-write");
+            return Err(RSEXT4Error::UnsupportedFeature);
+        }
+
         // 3. 检查文件系统状态
         if superblock.s_state == Ext4Superblock::EXT4_ERROR_FS {
             warn!("Filesystem is in error state");
@@ -230,11 +265,15 @@ impl Ext4FileSystem {
             0 => DEFAULT_INODE_SIZE as usize,
             n => n as usize,
         };
-        let inode_cache = InodeCache::new(INODE_CACHE_MAX, inode_size);
+        let mut inode_cache = InodeCache::new(INODE_CACHE_MAX, inode_size);
+        if superblock.has_metadata_csum() {
+            inode_cache.set_csum_seed(Some(superblock.csum_seed()));
+        }
         debug!("Inode cache initialized");
 
         // 初始化数据块缓存
-        let datablock_cache = DataBlockCache::new(DATABLOCK_CACHE_MAX, BLOCK_SIZE);
+        let datablock_cache =
+            DataBlockCache::new(DATABLOCK_CACHE_MAX, superblock.block_size() as usize);
         debug!("Data block cache initialized");
 
         // 构造文件系统实例
@@ -250,10 +289,122 @@ impl Ext4FileSystem {
             group_count,
             mounted: true,
             journal_sb_block_start: None,
+            xattr_cache: XattrBlockCache::new(),
+            extent_status: ExtentStatusCache::new(EXTENT_STATUS_CACHE_MAX),
+            mballoc: MbState::new(&superblock, group_count),
+            read_only: false,
+            fast_commit: FastCommitState::default(),
+            time_source: Box::new(NullTimeSource),
+            atime_policy: AtimePolicy::default(),
         };
         //详细debug输出
         debug_super_and_desc(&fs.superblock, &fs);
 
+        // journal check
+        {
+            // 日志由 mkfs 或 add_journal 创建；文件系统没有日志（或 journal inode 丢失）时不启用 JBD2
+            let mut jouranl_exist = fs.superblock.has_journal();
+            if jouranl_exist && !fs.superblock.has_external_journal() {
+                let ji = fs
+                    .get_inode_by_num(block_dev, fs.superblock.s_journal_inum)
+                    .map_err(|_| RSEXT4Error::IoError)?;
+                jouranl_exist = ji.i_mode != 0;
+            }
+            if block_dev.is_use_journal() && !jouranl_exist {
+                warn!("Filesystem has no journal, mount without journaling");
+                block_dev.set_journal_use(false);
+            }
+            //实际启用Journal
+            if block_dev.is_use_journal() && fs.superblock.has_external_journal() {
+                // 外部日志：校验日志设备的 UUID 后直接使用设备上的 journal 超级块
+                if !block_dev.has_journal_device() {
+                    error!("Filesystem uses an external journal but no journal device is attached");
+                    return Err(RSEXT4Error::UnsupportedFeature);
+                }
+                let journal_uuid = fs.superblock.s_journal_uuid;
+                block_dev.load_journal_device(&journal_uuid).map_err(|e| match e {
+                    BlockDevError::ChecksumError => RSEXT4Error::ChecksumError,
+                    BlockDevError::ReadError | BlockDevError::WriteError | BlockDevError::IoError => {
+                        RSEXT4Error::IoError
+                    }
+                    _ => RSEXT4Error::InvalidSuperblock,
+                })?;
+            } else if block_dev.is_use_journal() {
+                // 到这里为止：journal inode 一定存在
+                // 初始化 jbd2：读入 journal 超级块并塞进 Jbd2Dev
+                let mut j_inode = fs
+                    .get_inode_by_num(block_dev, JOURNAL_FILE_INODE as u32)
+                    .map_err(|_| RSEXT4Error::IoError)?;
+
+                // 解析 journal inode 第 0 号逻辑块 -> 物理块
+                let journal_first_block = resolve_inode_block(&fs, block_dev, JOURNAL_FILE_INODE as u32, &mut j_inode, 0)
+                    .and_then(|opt| opt.ok_or(BlockDevError::Corrupted))
+                    .map_err(|_| RSEXT4Error::IoError)?;
+
+                //写入fs
+                fs.journal_sb_block_start = Some(journal_first_block);
+                // 通过数据块缓存读出 journal superblock 内容
+                let journal_data = fs
+                    .datablock_cache
+                    .get_or_load(block_dev, journal_first_block)
+                    .map_err(|_| RSEXT4Error::IoError)?
+                    .data
+                    .clone();
+
+                let j_sb = JournalSuperBllockS::from_disk_bytes(&journal_data);
+
+                // 把 journal superblock 交给 Jbd2Dev，由它内部 lazy-init JBD2DEVSYSTEM
+                block_dev.set_journal_superblock(j_sb, journal_first_block);
+            }
+            if block_dev.is_use_journal() {
+
+                // Mount-time journal replay for crash recovery.
+                // 回放必须先于根目录/lost+found 检查，回放后重新加载块组描述符并丢弃已过期的缓存
+                let replayed = block_dev.journal_replay().map_err(|e| match e {
+                    BlockDevError::ChecksumError => RSEXT4Error::ChecksumError,
+                    _ => RSEXT4Error::IoError,
+                })?;
+                let fc_records = block_dev.take_fast_commit_records();
+                if replayed || !fc_records.is_empty() {
+                    fs.group_descs = Self::load_group_descriptors(block_dev, fs.group_count)?;
+                    fs.bitmap_cache.clear();
+                    fs.inodetable_cahce.clear();
+                    fs.datablock_cache.clear();
+                }
+                if !fc_records.is_empty() {
+                    // 与内核相同，fast commit 记录直接写回原位置；全部应用之后才清空日志，中途崩溃时下次挂载重新应用
+                    block_dev.set_journal_use(false);
+                    let applied = crate::ext4_backend::fast_commit::replay(&mut fs, block_dev, &fc_records);
+                    block_dev.set_journal_use(true);
+                    applied.map_err(|e| {
+                        error!("Fast commit replay failed: {e}");
+                        RSEXT4Error::IoError
+                    })?;
+                    block_dev.finish_fast_commit_replay().map_err(|_| RSEXT4Error::IoError)?;
+                }
+                // 与内核一致：64 位文件系统的 tag 带高 32 位，metadata_csum 时日志使用 checksum v3
+                let mut journal_features = 0;
+                if fs.superblock.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT) {
+                    journal_features |= JBD2_FEATURE_INCOMPAT_64BIT;
+                }
+                if fs.superblock.has_metadata_csum() {
+                    journal_features |= JBD2_FEATURE_INCOMPAT_CSUM_V3;
+                }
+                block_dev
+                    .set_journal_features(journal_features)
+                    .map_err(|_| RSEXT4Error::IoError)?;
+                if fs.superblock.has_feature_compat(Ext4Superblock::EXT4_FEATURE_COMPAT_FAST_COMMIT)
+                    && fs.superblock.has_extents()
+                {
+                    let enabled = block_dev.enable_fast_commit().map_err(|_| RSEXT4Error::IoError)?;
+                    fs.fast_commit.set_enabled(enabled);
+                }
+                // 挂载期间日志可能有未回收的事务，卸载并做完检查点后才清除
+                fs.superblock.s_feature_incompat |= Ext4Superblock::EXT4_FEATURE_INCOMPAT_RECOVER;
+                fs.sync_superblock(block_dev).map_err(|_| RSEXT4Error::IoError)?;
+            }
+        }
+
         // rootinode check !
         debug!("Checking root directory...");
         {
@@ -292,58 +443,6 @@ impl Ext4FileSystem {
             }
         }
 
-        // journal check
-        {
-            if fs.superblock.has_journal() {
-                let mut jouranl_exist: bool = true;
-                fs.modify_inode(block_dev, JOURNAL_FILE_INODE as u32, |ji| {
-                    jouranl_exist = ji.i_mode != 0;
-                })
-                .expect("file system error panic!");
-
-                if fs
-                    .superblock
-                    .has_feature_compat(Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL)
-                    && !jouranl_exist
-                {
-                    // 不存在但 superblock 声明有 journal，则创建一个新的 journal 文件
-                    create_journal_entry(&mut fs, block_dev).expect("create journal entry failed");
-                    //dump_journal_inode(&mut fs, block_dev);
-                }
-            }
-            //实际启用Journal
-            if block_dev.is_use_journal() {
-                // 到这里为止：journal inode 一定存在
-                // 初始化 jbd2：读入 journal 超级块并塞进 Jbd2Dev
-                let mut j_inode = fs
-                    .get_inode_by_num(block_dev, JOURNAL_FILE_INODE as u32)
-                    .expect("load journal inode failed");
-
-                // 解析 journal inode 第 0 号逻辑块 -> 物理块
-                let journal_first_block = resolve_inode_block( block_dev, &mut j_inode, 0)
-                    .and_then(|opt| opt.ok_or(BlockDevError::Corrupted))
-                    .expect("resolve journal first block failed");
-
-                //写入fs
-                fs.journal_sb_block_start = Some(journal_first_block);
-                // 通过数据块缓存读出 journal superblock 内容
-                let journal_data = fs
-                    .datablock_cache
-                    .get_or_load(block_dev, journal_first_block as u64)
-                    .expect("load journal superblock block failed")
-                    .data
-                    .clone();
-
-                let j_sb = JournalSuperBllockS::from_disk_bytes(&journal_data);
-
-                // 把 journal superblock 交给 Jbd2Dev，由它内部 lazy-init JBD2DEVSYSTEM
-                block_dev.set_journal_superblock(j_sb, fs.journal_sb_block_start.unwrap());
-
-                // Mount-time journal replay for crash recovery.
-                block_dev.journal_replay(); //这里是在读取超级块之后再进行回放的，目前为了快速开启日志时数据不一致问题已经在写入超级块，块组描述符时直接落盘
-            }
-        }
-
         //详细的Inode/DataBlock占用情况
         {
             let g0 = match fs.group_descs.first() {
@@ -354,16 +453,19 @@ impl Ext4FileSystem {
             let data_bitmap_blk = g0.block_bitmap();
             let inode_cache_key = CacheKey::new_inode(0);
             let data_cache_key = CacheKey::new_block(0);
+            for (key, blk) in [(inode_cache_key, inode_bitmap_blk), (data_cache_key, data_bitmap_blk)] {
+                fs.verify_bitmap_csum(block_dev, key, blk).map_err(mount_error)?;
+            }
 
             let inode_bitmap_data = fs
                 .bitmap_cache
                 .get_or_load(block_dev, inode_cache_key, inode_bitmap_blk as u64)
-                .expect("Blcok Read Failed!")
+                .map_err(|_| RSEXT4Error::IoError)?
                 .clone();
             let blockbitmap_data = fs
                 .bitmap_cache
                 .get_or_load(block_dev, data_cache_key, data_bitmap_blk as u64)
-                .expect("Blcok Read Failed!");
+                .map_err(|_| RSEXT4Error::IoError)?;
 
             let mut indoe_count: u64 = 0;
             let mut datablock_count: u64 = 0;
@@ -412,11 +514,13 @@ impl Ext4FileSystem {
         //缓存刷新回磁盘
         fs.datablock_cache
             .flush_all(block_dev)
-            .expect("flush failed!");
-        fs.bitmap_cache.flush_all(block_dev).expect("flush failed!");
+            .map_err(|_| RSEXT4Error::IoError)?;
+        fs.bitmap_cache
+            .flush_all(block_dev)
+            .map_err(|_| RSEXT4Error::IoError)?;
         fs.inodetable_cahce
             .flush_all(block_dev)
-            .expect("flush failed!");
+            .map_err(|_| RSEXT4Error::IoError)?;
 
         Ok(fs)
     }
@@ -427,27 +531,27 @@ impl Ext4FileSystem {
         group_count: u32,
     ) -> Result<Vec<Ext4GroupDesc>, RSEXT4Error> {
         let mut group_descs = Vec::new();
-        let gdt_base: u64 = BLOCK_SIZE as u64;
 
         // 为了减少重复读块，这里缓存当前块号
         let mut current_block: Option<u64> = None;
 
-        let superblock = read_superblock(block_dev).map_err(|_| RSEXT4Error::IoError)?;
+        let superblock = read_superblock(block_dev).map_err(mount_error)?;
         let desc_size = superblock.get_desc_size() as usize;
+        let block_size_u64 = superblock.block_size();
+        let gdt_base = superblock.gdt_start_block() * block_size_u64;
 
         debug!(
             "Loading group descriptors: {group_count} groups, desc_size = {desc_size} bytes"
         );
         for group_id in 0..group_count {
             let byte_offset = gdt_base + group_id as u64 * desc_size as u64;
-            let block_size_u64 = BLOCK_SIZE as u64;
             let block_num = byte_offset / block_size_u64;
             let in_block = (byte_offset % block_size_u64) as usize;
 
             // 只在块号变化时重新读取块
             if current_block != Some(block_num) {
                 block_dev
-                    .read_block(block_num as u32)
+                    .read_block(block_num)
                     .map_err(|_| RSEXT4Error::IoError)?;
                 current_block = Some(block_num);
             }
@@ -465,6 +569,10 @@ impl Ext4FileSystem {
                 return Err(RSEXT4Error::InvalidSuperblock);
             }
 
+            if !verify_group_desc_csum(&superblock, group_id, &buffer[in_block..end]) {
+                error!("Group descriptor {group_id} checksum mismatch");
+                return Err(RSEXT4Error::ChecksumError);
+            }
             let desc = Ext4GroupDesc::from_disk_bytes(&buffer[in_block..end]);
             group_descs.push(desc);
         }
@@ -483,6 +591,13 @@ impl Ext4FileSystem {
 
         debug!("Unmounting Ext4 filesystem...");
 
+        // 出错转只读后缓存和日志里的内容不再可信：不写回，日志留给下次挂载回放
+        if self.read_only {
+            warn!("Filesystem is read-only after errors, skip flushing caches");
+            self.mounted = false;
+            return Ok(());
+        }
+
         // 1. Flush dirty caches
         info!("Flushing bitmap cache...");
         self.bitmap_cache.flush_all(block_dev)?;
@@ -493,18 +608,21 @@ impl Ext4FileSystem {
         debug!("Data block cache flushed");
 
 
-        // 4. Update superblock
-        info!("Writing back superblock...");
-        self.sync_superblock(block_dev)?;
-        debug!("Superblock updated");
-
         // Write back group descriptors
         debug!("Writing back group descriptors...");
         self.sync_group_descriptors(block_dev)?;
 
-        //确保缓存已经提交完毕
-        block_dev.umount_commit();
-       
+        //确保缓存已经提交完毕，日志做完检查点后已为空
+        if let Err(e) = block_dev.umount_commit() {
+            self.handle_error(block_dev, e);
+            return Err(e);
+        }
+        self.superblock.s_feature_incompat &= !Ext4Superblock::EXT4_FEATURE_INCOMPAT_RECOVER;
+
+        // 4. Update superblock
+        info!("Writing back superblock...");
+        self.sync_superblock(block_dev)?;
+        debug!("Superblock updated");
 
         self.mounted = false;
         info!("Filesystem unmounted cleanly");
@@ -512,6 +630,225 @@ impl Ext4FileSystem {
         Ok(())
     }
 
+    /// 开启事务句柄，为一次操作预留 `credits` 个元数据块的日志空间
+    pub fn journal_start<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        credits: u32,
+    ) -> BlockDevResult<Jbd2Handle> {
+        let outermost = block_dev.transaction_depth() == 0;
+        let handle = block_dev.begin_transaction(credits)?;
+        if outermost {
+            self.fast_commit.begin_op(handle.tid);
+        }
+        Ok(handle)
+    }
+
+    /// 结束事务句柄
+    /// 最外层句柄结束时，先把本次操作弄脏的缓存写入运行中的事务，再原子提交：
+    /// data=journal 数据块随事务写入日志；data=ordered 数据块先于元数据落盘；data=writeback 数据块留在缓存里稍后写回
+    /// 启用 fast commit 且操作全部被跟踪时不提交，事务留到 fsync 或下一次完整提交
+    pub fn journal_stop<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        handle: Jbd2Handle,
+    ) -> BlockDevResult<()> {
+        let outermost = block_dev.transaction_depth() == 1;
+        let flushed = if outermost {
+            self.flush_for_commit(block_dev)
+        } else {
+            Ok(())
+        };
+        // 写回失败也要结束句柄，否则运行中的事务永远无法提交
+        let committed = if outermost && flushed.is_ok() && self.fast_commit.end_op(block_dev) {
+            block_dev.commit_deferred(handle)
+        } else {
+            block_dev.commit(handle)
+        };
+        flushed.and(committed)
+    }
+
+    /// 打开 fast commit：fsync 只把本事务里被跟踪的修改写进日志末尾的 fast commit 区
+    /// 需要 extent 和日志；日志太小时返回 false
+    pub fn enable_fast_commit<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<bool> {
+        if self.read_only {
+            return Err(BlockDevError::ReadOnly);
+        }
+        if !self.superblock.has_extents() || !block_dev.is_use_journal() {
+            return Err(BlockDevError::Unsupported);
+        }
+        if !block_dev.enable_fast_commit()? {
+            return Ok(false);
+        }
+        self.superblock.s_feature_compat |= Ext4Superblock::EXT4_FEATURE_COMPAT_FAST_COMMIT;
+        self.sync_superblock(block_dev)?;
+        self.fast_commit.set_enabled(true);
+        Ok(true)
+    }
+
+    /// 把本次操作弄脏的缓存写入运行中的事务
+    fn flush_for_commit<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
+        match block_dev.journal_mode() {
+            JournalMode::Journal | JournalMode::Ordered => self.datablock_cache.flush_all(block_dev)?,
+            JournalMode::Writeback => self.datablock_cache.flush_metadata(block_dev)?,
+        }
+        self.bitmap_cache.flush_all(block_dev)?;
+        self.inodetable_cahce.flush_all(block_dev)?;
+        self.sync_group_descriptors(block_dev)
+    }
+
+    /// 记录一次文件系统错误：超级块标记 EXT4_ERROR_FS 并累加错误计数，再按 s_errors 处理
+    /// EXT4_ERRORS_PANIC 直接 panic；EXT4_ERRORS_RO 或日志已中止时转为只读；EXT4_ERRORS_CONTINUE 继续运行
+    pub fn handle_error<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>, err: BlockDevError) {
+        error!("EXT4-fs error: {err}");
+        self.superblock.s_state |= Ext4Superblock::EXT4_ERROR_FS;
+        self.superblock.s_error_count = self.superblock.s_error_count.saturating_add(1);
+        // 超级块绕过日志直接落盘，日志中止后也能记下错误状态
+        if let Err(e) = write_superblock(block_dev, &self.superblock) {
+            warn!("EXT4-fs: record error state in superblock failed: {e}");
+        }
+        if self.superblock.s_errors == Ext4Superblock::EXT4_ERRORS_PANIC {
+            panic!("EXT4-fs: panic forced after error: {err}");
+        }
+        if (self.superblock.s_errors == Ext4Superblock::EXT4_ERRORS_RO || block_dev.is_journal_aborted())
+            && !self.read_only
+        {
+            warn!("EXT4-fs: remounting filesystem read-only");
+            self.read_only = true;
+        }
+    }
+
+    /// 写入 `len` 字节数据所需的日志额度：每跨一个块组多一个块位图和块组描述符，data=journal 时再加上数据块本身
+    pub fn data_trans_blocks<B: BlockDevice>(&self, block_dev: &Jbd2Dev<B>, len: u64) -> u32 {
+        let blocks = len.div_ceil(self.superblock.block_size());
+        let groups = blocks
+            .div_ceil(self.superblock.s_blocks_per_group.max(1) as u64)
+            .saturating_add(1)
+            .min(self.group_count as u64) as u32;
+        let data = if block_dev.journals_data() { blocks as u32 } else { 0 };
+        EXT4_DATA_TRANS_BLOCKS + 2 * groups + data
+    }
+
+    /// 一个事务里最多写入的文件数据字节数；data=journal 时数据块占用日志额度，大的写入要拆成多个事务
+    pub fn journal_write_chunk<B: BlockDevice>(&self, block_dev: &Jbd2Dev<B>) -> Option<usize> {
+        if !block_dev.journals_data() {
+            return None;
+        }
+        let max = block_dev.max_transaction_blocks()?;
+        // 一半额度留给数据块，另一半足够覆盖位图、块组描述符和 extent 树的修改
+        let blocks = (max.saturating_sub(EXT4_DATA_TRANS_BLOCKS) / 2).max(1);
+        Some(blocks as usize * self.block_size())
+    }
+
+    /// 在一个事务句柄内执行一次文件系统操作，操作的全部元数据修改在同一个事务里提交
+    pub fn journaled<B, T, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        credits: u32,
+        op: F,
+    ) -> BlockDevResult<T>
+    where
+        B: BlockDevice,
+        F: FnOnce(&mut Self, &mut Jbd2Dev<B>) -> T,
+    {
+        if self.read_only {
+            return Err(BlockDevError::ReadOnly);
+        }
+        let handle = match self.journal_start(block_dev, credits) {
+            Ok(handle) => handle,
+            Err(e) => {
+                // 额度超限之类的错误不影响文件系统状态，只有日志中止才算文件系统错误
+                if block_dev.is_journal_aborted() {
+                    self.handle_error(block_dev, e);
+                }
+                return Err(e);
+            }
+        };
+        let ret = op(self, block_dev);
+        if let Err(e) = self.journal_stop(block_dev, handle) {
+            self.handle_error(block_dev, e);
+            return Err(e);
+        }
+        Ok(ret)
+    }
+
+    /// 当前时间，来自挂载时提供的时钟
+    pub fn now(&self) -> Timespec {
+        self.time_source.now()
+    }
+
+    /// inode 内容被修改：mtime 和 ctime 更新为当前时间
+    pub fn touch_mtime_ctime<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<()> {
+        let now = self.now();
+        self.modify_inode(block_dev, inode_num, |inode| {
+            inode.set_mtime_ts(now);
+            inode.set_ctime_ts(now);
+        })
+    }
+
+    /// inode 状态被修改（链接数、所在目录等）：只更新 ctime
+    pub fn touch_ctime<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<()> {
+        let now = self.now();
+        self.modify_inode(block_dev, inode_num, |inode| inode.set_ctime_ts(now))
+    }
+
+    /// 读取后按 atime 策略更新 atime；只读时不更新，更新失败也不影响读取
+    pub fn touch_atime<B: BlockDevice>(&mut self, block_dev: &mut Jbd2Dev<B>, inode_num: u32) {
+        if self.read_only || self.atime_policy == AtimePolicy::Noatime {
+            return;
+        }
+        let now = self.now();
+        let Ok(inode) = self.get_inode_by_num(block_dev, inode_num) else {
+            return;
+        };
+        if !self
+            .atime_policy
+            .need_update(inode.atime(), inode.mtime(), inode.ctime(), now)
+        {
+            return;
+        }
+        let ret = self.journaled(block_dev, EXT4_INODE_TRANS_BLOCKS, |fs, dev| {
+            fs.modify_inode(dev, inode_num, |inode| inode.set_atime_ts(now))
+        });
+        if let Err(e) | Ok(Err(e)) = ret {
+            warn!("Failed to update atime of inode {inode_num}: {e}");
+        }
+    }
+
+    /// 初始化新 inode：同内核，预留 s_want_extra_isize（至少覆盖 Ext4Inode 的全部扩展字段），
+    /// 四个时间戳都取当前时间
+    pub fn init_new_inode<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<()> {
+        let inode_size = self.superblock.inode_size();
+        let extra_isize = if inode_size > Ext4Inode::GOOD_OLD_INODE_SIZE {
+            self.superblock
+                .s_want_extra_isize
+                .max(Ext4Inode::EXTRA_ISIZE)
+                .min(inode_size - Ext4Inode::GOOD_OLD_INODE_SIZE)
+        } else {
+            0
+        };
+        let now = self.now();
+        self.modify_inode(block_dev, inode_num, |inode| {
+            inode.i_extra_isize = extra_isize;
+            inode.set_atime_ts(now);
+            inode.set_mtime_ts(now);
+            inode.set_ctime_ts(now);
+            inode.set_crtime_ts(now);
+        })
+    }
+
     /// 同步块组描述符到磁盘
     /// 按 ext4 标准布局，将所有块组描述符写回：
     /// GDT 字节流紧跟在超级块之后
@@ -522,9 +859,9 @@ impl Ext4FileSystem {
         let total_desc_count = self.group_descs.len();
         let desc_size = self.superblock.get_desc_size() as usize;
 
-        // GDT 基地址统一为块号 1 的起始字节偏移
-        let gdt_base: u64 = BLOCK_SIZE as u64;
-        let block_size_u64 = BLOCK_SIZE as u64;
+        // GDT 紧跟在超级块所在块之后
+        let block_size_u64 = self.superblock.block_size();
+        let gdt_base = self.superblock.gdt_start_block() * block_size_u64;
 
         debug!(
             "Writing back group descriptors: {total_desc_count} descriptors, desc_size = {desc_size} bytes"
@@ -545,11 +882,11 @@ impl Ext4FileSystem {
                 if let Some(prev_block) = current_block
                     && Some(prev_block) == buffer_snapshot_block {
                         //由于目前日志回放在fs构建之后（块组描述符读取之后），目前为了快速修复防止读取到旧的超级块。直接落盘写回
-                        block_dev.write_block(prev_block as u32, false)?;
+                        block_dev.write_block(prev_block, false)?;
                     }
 
                 // 读取新块
-                block_dev.read_block(block_num as u32)?;
+                block_dev.read_block(block_num)?;
                 current_block = Some(block_num);
                 buffer_snapshot_block = Some(block_num);
             }
@@ -567,12 +904,13 @@ impl Ext4FileSystem {
             }
 
             desc.to_disk_bytes(&mut buffer[in_block..end]);
+            set_group_desc_csum(&self.superblock, idx as u32, &mut buffer[in_block..end]);
         }
 
         // 写回最后一个块
         if let Some(last_block) = current_block
             && Some(last_block) == buffer_snapshot_block {
-                block_dev.write_block(last_block as u32, true)?;
+                block_dev.write_block(last_block, true)?;
             }
 
         debug!("Group descriptors written back");
@@ -596,6 +934,11 @@ impl Ext4FileSystem {
         write_superblock(block_dev, &self.superblock)
     }
 
+    /// 文件系统块大小（字节）
+    pub fn block_size(&self) -> usize {
+        self.superblock.block_size() as usize
+    }
+
     /// 获取块组描述符
     pub fn get_group_desc(&self, group_idx: u32) -> Option<&Ext4GroupDesc> {
         self.group_descs.get(group_idx as usize)
@@ -606,43 +949,216 @@ impl Ext4FileSystem {
         self.group_descs.get_mut(group_idx as usize)
     }
 
-    /// 使用闭包修改指定 inode，内部自动计算 inode 在磁盘上的位置
-    pub fn modify_inode<B, F>(
+    /// metadata_csum 开启时返回指定 inode 的校验种子
+    pub fn inode_csum_seed(&self, inode_num: u32, generation: u32) -> Option<u32> {
+        self.superblock
+            .has_metadata_csum()
+            .then(|| inode_csum_seed(self.superblock.csum_seed(), inode_num, generation))
+    }
+
+    /// 目录块可用于目录项的字节数（metadata_csum 开启时末尾预留校验和尾部）
+    pub fn dir_block_usable_len(&self) -> usize {
+        if self.superblock.has_metadata_csum() {
+            self.block_size() - Ext4DirEntryTail::TAIL_LEN as usize
+        } else {
+            self.block_size()
+        }
+    }
+
+    /// 目录块修改后刷新尾部校验和（叶子块用目录项尾部，dx 索引块用 dx_tail；都没有时不做任何事）
+    pub fn update_dir_block_csum<B: BlockDevice>(
         &mut self,
         block_dev: &mut Jbd2Dev<B>,
-        inode_num: u32,
-        f: F,
-    ) -> BlockDevResult<()>
-    where
-        B: BlockDevice,
-        F: FnOnce(&mut Ext4Inode),
-    {
-        // 通过全局 inode 号计算所属块组
-        let (group_idx, _idx_in_group) = self.inode_allocator.global_to_group(inode_num);
+        dir_ino: u32,
+        phys: u64,
+    ) -> BlockDevResult<()> {
+        let generation = self.get_inode_by_num(block_dev, dir_ino)?.i_generation;
+        let Some(seed) = self.inode_csum_seed(dir_ino, generation) else {
+            return Ok(());
+        };
+        self.datablock_cache.modify(block_dev, phys, |data| {
+            if has_dir_tail(data) {
+                set_dir_block_csum(seed, data);
+            } else if let Some(count_offset) = dx_count_offset(data) {
+                set_dx_block_csum(seed, data, count_offset);
+            }
+        })
+    }
 
-        let inode_table_start = self
+    /// 校验目录块尾部校验和（块内没有尾部时不校验）
+    pub fn verify_dir_block_csum<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        phys: u64,
+    ) -> BlockDevResult<()> {
+        if !self.superblock.has_metadata_csum() {
+            return Ok(());
+        }
+        let generation = self.get_inode_by_num(block_dev, dir_ino)?.i_generation;
+        let seed = inode_csum_seed(self.superblock.csum_seed(), dir_ino, generation);
+        let data = &self.datablock_cache.get_or_load(block_dev, phys)?.data;
+        if has_dir_tail(data) && !verify_dir_block_csum(seed, data) {
+            error!("Directory block {phys} of inode {dir_ino} checksum mismatch");
+            return Err(BlockDevError::ChecksumError);
+        }
+        Ok(())
+    }
+
+    /// 确保位图已在缓存中；从盘上载入时校验其校验和（缓存中的位图都是载入时校验过的）
+    fn verify_bitmap_csum<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        key: CacheKey,
+        bitmap_block: u64,
+    ) -> BlockDevResult<()> {
+        let desc = *self
+            .group_descs
+            .get(key.group_id as usize)
+            .ok_or(BlockDevError::Corrupted)?;
+        let sb = &self.superblock;
+        self.bitmap_cache
+            .get_or_load_verified(block_dev, key, bitmap_block, |data| {
+                let ok = match key.bitmap_type {
+                    BitmapType::Block => verify_block_bitmap_csum(sb, &desc, data),
+                    BitmapType::Inode => verify_inode_bitmap_csum(sb, &desc, data),
+                };
+                if !ok {
+                    error!(
+                        "Bitmap checksum mismatch: group={} type={:?}",
+                        key.group_id, key.bitmap_type
+                    );
+                }
+                ok
+            })?;
+        Ok(())
+    }
+
+    /// BLOCK_UNINIT 块组的块位图在盘上没有初始化：按 ext4_init_block_bitmap 的方式
+    /// 标记组内的超级块/GDT 备份、位图和 inode 表，然后清掉该标志
+    fn init_block_bitmap<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        group_idx: u32,
+    ) -> BlockDevResult<()> {
+        let desc = *self
             .group_descs
             .get(group_idx as usize)
-            .ok_or(BlockDevError::Corrupted)?
-            .inode_table();
+            .ok_or(BlockDevError::Corrupted)?;
+        if !desc.is_block_bitmap_uninit() {
+            return Ok(());
+        }
 
-        let (block_num, offset, _g) = self.inodetable_cahce.calc_inode_location(
-            inode_num,
-            self.superblock.s_inodes_per_group,
-            inode_table_start,
-            BLOCK_SIZE,
-        );
+        let sb = &self.superblock;
+        let block_size = sb.block_size();
+        let bpg = sb.blocks_per_group() as u64;
+        let group_start = sb.s_first_data_block as u64 + group_idx as u64 * bpg;
+        let group_blocks = (sb.blocks_count() - group_start).min(bpg);
+        let group_end = group_start + group_blocks;
+
+        let mut used: Vec<u64> = Vec::new();
+        let sparse =
+            sb.has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER);
+        if !sparse || group_idx == 0 || need_redundant_backup(group_idx) {
+            let gdt_blocks = (self.group_descs.len() as u64 * sb.get_desc_size() as u64)
+                .div_ceil(block_size);
+            let meta = 1 + gdt_blocks + sb.s_reserved_gdt_blocks as u64;
+            used.extend(0..meta.min(group_blocks));
+        }
+        let itable_blocks =
+            (sb.inodes_per_group() as u64 * sb.inode_size() as u64).div_ceil(block_size);
+        for d in &self.group_descs {
+            let ranges = [
+                (d.block_bitmap(), 1),
+                (d.inode_bitmap(), 1),
+                (d.inode_table(), itable_blocks),
+            ];
+            for (start, len) in ranges {
+                used.extend(
+                    (start..start + len)
+                        .filter(|b| (group_start..group_end).contains(b))
+                        .map(|b| b - group_start),
+                );
+            }
+        }
 
-        self.inodetable_cahce
-            .modify(block_dev, inode_num as u64, block_num, offset, f)
+        let key = CacheKey::new_block(group_idx);
+        self.bitmap_cache
+            .modify(block_dev, key, desc.block_bitmap(), |data| {
+                data.fill(0);
+                for bit in &used {
+                    data[(bit / 8) as usize] |= 1 << (bit % 8);
+                }
+                mark_bitmap_padding(data, group_blocks as u32);
+            })?;
+        if let Some(desc_mut) = self.get_group_desc_mut(group_idx) {
+            desc_mut.bg_flags &= !Ext4GroupDesc::EXT4_BG_BLOCK_UNINIT;
+        }
+        self.update_bitmap_csum(key);
+        self.mballoc.invalidate_group(group_idx);
+        debug!("Initialized block bitmap of BLOCK_UNINIT group {group_idx}");
+        Ok(())
     }
 
-    /// 按 inode 号加载 inode（只读），内部自动计算在磁盘上的位置
-    pub fn get_inode_by_num<B: BlockDevice>(
+    /// 新占用的 inode 落在 bg_itable_unused 标记的未用区时下调该值（与 ext4_new_inode 相同），
+    /// 否则 e2fsck 会把未用区里的 inode 当作垃圾丢弃
+    fn shrink_itable_unused(&mut self, inode_num: u32) {
+        let (group_idx, inode_in_group) = self.inode_allocator.global_to_group(inode_num);
+        let unused = self.superblock.inodes_per_group() - (inode_in_group + 1);
+        if let Some(desc) = self.get_group_desc_mut(group_idx)
+            && desc.itable_unused() > unused
+        {
+            desc.set_itable_unused(unused);
+        }
+    }
+
+    /// INODE_UNINIT 块组的 inode 位图在盘上没有初始化：按 ext4_init_inode_bitmap 的方式
+    /// 清零并标记末尾的填充位，然后清掉该标志
+    fn init_inode_bitmap<B: BlockDevice>(
         &mut self,
         block_dev: &mut Jbd2Dev<B>,
-        inode_num: u32,
-    ) -> BlockDevResult<Ext4Inode> {
+        group_idx: u32,
+    ) -> BlockDevResult<()> {
+        let desc = *self
+            .group_descs
+            .get(group_idx as usize)
+            .ok_or(BlockDevError::Corrupted)?;
+        if !desc.is_inode_bitmap_uninit() {
+            return Ok(());
+        }
+
+        let inodes_per_group = self.superblock.inodes_per_group();
+        let key = CacheKey::new_inode(group_idx);
+        self.bitmap_cache
+            .modify(block_dev, key, desc.inode_bitmap(), |data| {
+                data.fill(0);
+                mark_bitmap_padding(data, inodes_per_group);
+            })?;
+        if let Some(desc_mut) = self.get_group_desc_mut(group_idx) {
+            desc_mut.bg_flags &= !Ext4GroupDesc::EXT4_BG_INODE_UNINIT;
+        }
+        self.update_bitmap_csum(key);
+        debug!("Initialized inode bitmap of INODE_UNINIT group {group_idx}");
+        Ok(())
+    }
+
+    /// 位图修改后重新计算块组描述符中的位图校验和
+    fn update_bitmap_csum(&mut self, key: CacheKey) {
+        let Some(bitmap) = self.bitmap_cache.get(&key) else {
+            return;
+        };
+        let Some(desc) = self.group_descs.get_mut(key.group_id as usize) else {
+            return;
+        };
+        match key.bitmap_type {
+            BitmapType::Block => set_block_bitmap_csum(&self.superblock, desc, &bitmap.data),
+            BitmapType::Inode => set_inode_bitmap_csum(&self.superblock, desc, &bitmap.data),
+        }
+    }
+
+    /// 计算 inode 在 inode 表中的位置：(块号, 块内偏移)
+    fn inode_location(&self, inode_num: u32) -> BlockDevResult<(u64, usize)> {
+        // 通过全局 inode 号计算所属块组
         let (group_idx, _idx_in_group) = self.inode_allocator.global_to_group(inode_num);
 
         let inode_table_start = self
@@ -655,15 +1171,67 @@ impl Ext4FileSystem {
             inode_num,
             self.superblock.s_inodes_per_group,
             inode_table_start,
-            BLOCK_SIZE,
+            self.block_size(),
         );
+        Ok((block_num, offset))
+    }
+
+    /// 使用闭包修改指定 inode，内部自动计算 inode 在磁盘上的位置
+    pub fn modify_inode<B, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+        f: F,
+    ) -> BlockDevResult<()>
+    where
+        B: BlockDevice,
+        F: FnOnce(&mut Ext4Inode),
+    {
+        let (block_num, offset) = self.inode_location(inode_num)?;
+        self.inodetable_cahce
+            .modify(block_dev, inode_num as u64, block_num, offset, f)
+    }
 
+    /// 按 inode 号加载 inode（只读），内部自动计算在磁盘上的位置
+    pub fn get_inode_by_num<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<Ext4Inode> {
+        let (block_num, offset) = self.inode_location(inode_num)?;
         let cached =
             self.inodetable_cahce
                 .get_or_load(block_dev, inode_num as u64, block_num, offset)?;
         Ok(cached.inode)
     }
 
+    /// 按 inode 号读取 inode 的完整原始字节（s_inode_size 字节，含 inode 内扩展属性区）
+    pub fn get_inode_raw<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<Vec<u8>> {
+        let (block_num, offset) = self.inode_location(inode_num)?;
+        self.inodetable_cahce
+            .get_raw_or_load(block_dev, inode_num as u64, block_num, offset)
+    }
+
+    /// 以原始字节的形式修改 inode，用于改写 inode 内扩展属性区
+    pub fn modify_inode_raw<B, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+        f: F,
+    ) -> BlockDevResult<()>
+    where
+        B: BlockDevice,
+        F: FnOnce(&mut [u8]),
+    {
+        let (block_num, offset) = self.inode_location(inode_num)?;
+        self.inodetable_cahce
+            .modify_raw(block_dev, inode_num as u64, block_num, offset, f)
+    }
+
     /// 在整个文件系统中分配指定数量的连续数据块
     pub fn alloc_blocks<B: BlockDevice>(
         &mut self,
@@ -673,84 +1241,246 @@ impl Ext4FileSystem {
         if count == 0 {
             return Ok(Vec::new());
         }
+        let (start, _) = self.mb_alloc(block_dev, None, count, count)?;
+        Ok((start..start + count as u64).collect())
+    }
 
-        trace!(
-            "alloc_blocks: request count={count} (will scan groups for free space)"
-        );
-
-        // 选择一个有足够空闲块的块组，并在该组内做连续分配
-        for (idx, desc) in self.group_descs.iter().enumerate() {
-            let group_idx = idx as u32;
-            let free = desc.free_blocks_count();
+    /// 从 `first_group` 开始（到最后一个块组后回绕）找一个放得下 `count` 个连续块的位置并分配
+    pub fn alloc_blocks_from_group<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        first_group: u32,
+        count: u32,
+    ) -> BlockDevResult<Vec<u64>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let goal = self.mballoc.group_start(first_group);
+        let (start, _) = self.mb_alloc(block_dev, Some(goal), count, count)?;
+        Ok((start..start + count as u64).collect())
+    }
 
-            trace!(
-                "alloc_blocks: inspect group={group_idx} free_blocks={free} need={count}"
-            );
+    /// 在 goal 附近分配 min_len..=len 个连续块，返回 (起始块, 块数)。
+    /// 找不到时丢弃全部预分配窗口后重试一次
+    pub fn mb_alloc<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        goal: Option<u64>,
+        len: u32,
+        min_len: u32,
+    ) -> BlockDevResult<(u64, u32)> {
+        if len == 0 || min_len > len {
+            return Err(BlockDevError::InvalidInput);
+        }
+        trace!("mb_alloc: goal={goal:?} len={len} min_len={min_len}");
+        let mut found = self.mb_find(block_dev, goal, len, min_len)?;
+        if found.is_none() && self.mballoc.discard_all() {
+            found = self.mb_find(block_dev, goal, len, min_len)?;
+        }
+        let (start, got) = found.ok_or(BlockDevError::NoSpace)?;
+        if !self.claim_blocks(block_dev, start, got)? {
+            return Err(BlockDevError::Corrupted);
+        }
+        Ok((start, got))
+    }
 
-            if free < count {
-                continue;
+    /// 为 inode 从逻辑块 lblk 起分配 1..=len 个连续数据块，返回 (起始块, 块数)。
+    /// 先用 inode 的预分配窗口；小文件从共享的局部性窗口取；否则按文件大小归一化出新窗口，
+    /// 多出来的部分留作该 inode 的预分配。goal 为空时从 inode 所在块组开始找
+    pub fn alloc_data_blocks<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+        lblk: u32,
+        len: u32,
+        goal: Option<u64>,
+    ) -> BlockDevResult<(u64, u32)> {
+        if len == 0 {
+            return Err(BlockDevError::InvalidInput);
+        }
+        if let Some((pblk, got)) = self.mballoc.use_inode_pa(inode_num, lblk, len) {
+            if self.claim_blocks(block_dev, pblk, got)? {
+                return Ok((pblk, got));
             }
+            self.mballoc.discard_inode(inode_num);
+        }
 
-            let bitmap_block = desc.block_bitmap();
-            let cache_key = CacheKey::new_block(group_idx);
-            let mut alloc_res: Result<BlockAlloc, BlockDevError> = Err(BlockDevError::NoSpace);
-
-            debug!(
-                "alloc_blocks: candidate group={group_idx} bitmap_block={bitmap_block} starting contiguous allocation of {count} blocks"
-            );
+        let goal = goal.or_else(|| {
+            let group = inode_num.saturating_sub(1) / self.superblock.s_inodes_per_group.max(1);
+            Some(self.mballoc.group_start(group))
+        });
+        let small = lblk as u64 + len as u64 <= MB_STREAM_REQ_BLOCKS as u64;
+        if small
+            && let Some(pblk) = self.mballoc.use_group_pa(len)
+            && self.claim_blocks(block_dev, pblk, len)?
+        {
+            return Ok((pblk, len));
+        }
 
-            self.bitmap_cache
-                .modify(block_dev, cache_key, bitmap_block, |data| {
-                    // 这里只修改位图，不直接接触 group_desc / superblock 计数
-                    let r = self
-                        .block_allocator
-                        .alloc_contiguous_blocks(data, group_idx, count);
-                    alloc_res = r.map_err(|_| BlockDevError::NoSpace);
-                })?;
+        let want = if small {
+            MB_GROUP_PREALLOC_BLOCKS.max(len)
+        } else {
+            self.mballoc.normalize_window(self.superblock.block_size(), lblk, len)
+        };
+        let Some((start, got)) = self.mb_find(block_dev, goal, want, len)? else {
+            // 放不下整段请求时不再预分配，能拿多少拿多少
+            return self.mb_alloc(block_dev, goal, len, 1);
+        };
+        let used = got.min(len);
+        if !self.claim_blocks(block_dev, start, used)? {
+            return Err(BlockDevError::Corrupted);
+        }
+        if small {
+            self.mballoc.set_group_pa(start + used as u64, got - used);
+        } else {
+            self.mballoc.add_inode_pa(inode_num, lblk + used, start + used as u64, got - used);
+        }
+        debug!("alloc_data_blocks: ino={inode_num} lblk={lblk} -> {start}+{used} (window {got})");
+        Ok((start, used))
+    }
 
-            let alloc = alloc_res?;
+    /// 从 goal 所在块组开始找 min..=want 个连续空闲块（不分配）：
+    /// 先找最长空闲段不短于 want 的块组，再放宽到 min
+    fn mb_find<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        goal: Option<u64>,
+        want: u32,
+        min: u32,
+    ) -> BlockDevResult<Option<(u64, u32)>> {
+        let groups = self.group_descs.len() as u32;
+        let goal = goal.filter(|&g| g >= self.superblock.s_first_data_block as u64 && g < self.superblock.blocks_count());
+        let goal_group = goal.map_or(0, |g| self.mballoc.group_of(g)) % groups.max(1);
+        for (pass, need) in [want, min].into_iter().enumerate() {
+            // 第二遍只在允许更短的段时才有意义
+            if pass == 1 && min >= want {
+                break;
+            }
+            for step in 0..groups {
+                let group_idx = (goal_group + step) % groups;
+                if self.group_descs[group_idx as usize].free_blocks_count() < need {
+                    continue;
+                }
+                let mut loaded = None;
+                let info = match self.mballoc.group_info(group_idx) {
+                    Some(info) => info,
+                    None if self.group_descs[group_idx as usize].is_block_bitmap_uninit() => {
+                        // 未初始化的组除元数据外都空闲，按一整段估计，真正分配时再初始化位图
+                        let free = self.group_descs[group_idx as usize].free_blocks_count();
+                        MbGroupInfo { free, largest: free, ..Default::default() }
+                    }
+                    None => {
+                        let group = self.mb_load_group(block_dev, group_idx)?;
+                        let info = group.2;
+                        loaded = Some(group);
+                        info
+                    }
+                };
+                if info.largest < need {
+                    continue;
+                }
+                let (bits, blocks, _) = match loaded {
+                    Some(group) => group,
+                    None => self.mb_load_group(block_dev, group_idx)?,
+                };
+                let group_start = self.mballoc.group_start(group_idx);
+                let goal_in_group = goal
+                    .filter(|_| step == 0)
+                    .map(|g| (g - group_start) as u32);
+                if let Some((off, len)) = find_in_group(&bits, blocks, goal_in_group, want, need) {
+                    trace!("mb_find: group={group_idx} off={off} len={len} want={want} need={need}");
+                    return Ok(Some((group_start + off as u64, len)));
+                }
+            }
+        }
+        Ok(None)
+    }
 
-            // 更新块组描述符
-            if let Some(desc_mut) = self.get_group_desc_mut(group_idx) {
-                let before = desc_mut.free_blocks_count();
-                let new_count = before.saturating_sub(count);
-                desc_mut.bg_free_blocks_count_lo = (new_count & 0xFFFF) as u16;
-                desc_mut.bg_free_blocks_count_hi = (new_count >> 16) as u16;
-
-                debug!(
-                    "alloc_blocks: group={} free_blocks_count change {} -> {} (allocated {} blocks starting at global={})",
-                    group_idx, before, new_count, count, alloc.global_block
-                );
+    /// 读出块组位图副本，把预分配窗口标为占用，并刷新该组的空闲区段摘要。
+    /// 返回 (位图, 组内块数, 摘要)
+    fn mb_load_group<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        group_idx: u32,
+    ) -> BlockDevResult<(Vec<u8>, u32, MbGroupInfo)> {
+        let bitmap_block = self
+            .get_group_desc(group_idx)
+            .ok_or(BlockDevError::Corrupted)?
+            .block_bitmap();
+        let group_start = self.mballoc.group_start(group_idx);
+        let blocks = self
+            .superblock
+            .blocks_count()
+            .saturating_sub(group_start)
+            .min(self.superblock.s_blocks_per_group as u64) as u32;
+        let key = CacheKey::new_block(group_idx);
+        self.init_block_bitmap(block_dev, group_idx)?;
+        self.verify_bitmap_csum(block_dev, key, bitmap_block)?;
+        let mut bits = self.bitmap_cache.get_or_load(block_dev, key, bitmap_block)?.data.clone();
+        for (start, len) in self.mballoc.reserved_in_group(group_idx, blocks) {
+            for bit in start..start + len {
+                bits[(bit / 8) as usize] |= 1 << (bit % 8);
             }
+        }
+        let info = MbGroupInfo::from_bitmap(&bits, blocks);
+        self.mballoc.set_group_info(group_idx, info);
+        Ok((bits, blocks, info))
+    }
 
-            // 更新超级块
-            let sb_before = self.superblock.free_blocks_count();
-            self.superblock.s_free_blocks_count_lo =
-                self.superblock.s_free_blocks_count_lo.saturating_sub(count);
-            let sb_after = self.superblock.free_blocks_count();
+    /// 把 [start, start + count) 标记为已占用并更新块组描述符和超级块计数。
+    /// 范围内有块已被占用时不做修改并返回 false
+    fn claim_blocks<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        start: u64,
+        count: u32,
+    ) -> BlockDevResult<bool> {
+        let (group_idx, block_in_group) = self.block_allocator.global_to_group(start);
+        let bitmap_block = self
+            .get_group_desc(group_idx)
+            .ok_or(BlockDevError::Corrupted)?
+            .block_bitmap();
+        let cache_key = CacheKey::new_block(group_idx);
+        let blocks_per_group = self.superblock.s_blocks_per_group;
 
-            debug!(
-                "alloc_blocks: superblock free_blocks_count change {sb_before} -> {sb_after} (delta=-{count})"
-            );
+        self.init_block_bitmap(block_dev, group_idx)?;
+        self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
+        let mut res = Ok(());
+        self.bitmap_cache
+            .modify(block_dev, cache_key, bitmap_block, |data| {
+                // 这里只修改位图，不直接接触 group_desc / superblock 计数
+                res = BlockBitmapMut::new(data, blocks_per_group).allocate_range(block_in_group, count);
+            })?;
+        self.mballoc.invalidate_group(group_idx);
+        match res {
+            Ok(()) => {}
+            Err(BitmapError::AlreadyAllocated) => return Ok(false),
+            Err(_) => return Err(BlockDevError::Corrupted),
+        }
+        self.update_bitmap_csum(cache_key);
 
-            let mut blocks = Vec::with_capacity(count as usize);
-            for off in 0..count {
-                blocks.push(alloc.global_block + off as u64);
-            }
+        // 更新块组描述符
+        if let Some(desc_mut) = self.get_group_desc_mut(group_idx) {
+            let before = desc_mut.free_blocks_count();
+            let new_count = before.saturating_sub(count);
+            desc_mut.bg_free_blocks_count_lo = (new_count & 0xFFFF) as u16;
+            desc_mut.bg_free_blocks_count_hi = (new_count >> 16) as u16;
 
             debug!(
-                "Allocated blocks: group={}, first_block_in_group={}, first_global_block={}, count={} [bitmap updated, writeback deferred]",
-                alloc.group_idx, alloc.block_in_group, alloc.global_block, count
+                "claim_blocks: group={group_idx} free_blocks_count change {before} -> {new_count} (allocated {count} blocks starting at global={start})"
             );
-
-            return Ok(blocks);
         }
 
+        // 更新超级块
+        let sb_before = self.superblock.free_blocks_count();
+        self.superblock.s_free_blocks_count_lo =
+            self.superblock.s_free_blocks_count_lo.saturating_sub(count);
+        let sb_after = self.superblock.free_blocks_count();
+
         debug!(
-            "alloc_blocks: no group has enough free blocks for request count={count}"
+            "claim_blocks: superblock free_blocks_count change {sb_before} -> {sb_after} (delta=-{count}) [bitmap updated, writeback deferred]"
         );
-
-        Err(BlockDevError::NoSpace)
+        Ok(true)
     }
 
     /// 在整个文件系统中分配一个数据块（兼容旧接口）
@@ -773,7 +1503,8 @@ impl Ext4FileSystem {
         }
 
         // 目前按“同一块组内尽量连续”策略，从第一个有足够空闲 inode 的组开始分配
-        for (idx, desc) in self.group_descs.iter().enumerate() {
+        for idx in 0..self.group_descs.len() {
+            let desc = self.group_descs[idx];
             let group_idx = idx as u32;
             let free = desc.free_inodes_count();
             if free < count {
@@ -785,13 +1516,15 @@ impl Ext4FileSystem {
 
             let mut inodes: Vec<u32> = Vec::with_capacity(count as usize);
 
+            self.init_inode_bitmap(block_dev, group_idx)?;
+            self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
             self.bitmap_cache
                 .modify(block_dev, cache_key, bitmap_block, |data| {
                     // 简化实现：在同一块组中循环调用 alloc_inode_in_group，得到 count 个 inode
                     for _ in 0..count {
                         let r = self
                             .inode_allocator
-                            .alloc_inode_in_group(data, group_idx, desc);
+                            .alloc_inode_in_group(data, group_idx, &desc);
                         match r {
                             Ok(InodeAlloc { global_inode, .. }) => {
                                 inodes.push(global_inode);
@@ -803,6 +1536,7 @@ impl Ext4FileSystem {
                     }
                 })?;
 
+            self.update_bitmap_csum(cache_key);
             if inodes.len() as u32 != count {
                 return Err(BlockDevError::NoSpace);
             }
@@ -813,11 +1547,21 @@ impl Ext4FileSystem {
                 desc_mut.bg_free_inodes_count_lo = (new_count & 0xFFFF) as u16;
                 desc_mut.bg_free_inodes_count_hi = (new_count >> 16) as u16;
             }
+            for &ino in &inodes {
+                self.shrink_itable_unused(ino);
+            }
 
             // 更新超级块
             self.superblock.s_free_inodes_count =
                 self.superblock.s_free_inodes_count.saturating_sub(count);
 
+            // 复用的 inode 槽可能残留旧内容（包括 inode 内扩展属性），分配时清零；
+            // i_generation 加一，让指向旧 inode 的 (ino, generation) 句柄失效
+            for &ino in &inodes {
+                self.modify_inode_raw(block_dev, ino, |raw| clear_inode_raw(raw, 1))?;
+                self.init_new_inode(block_dev, ino)?;
+            }
+
             debug!(
                 "Allocated inodes: group={}, first_global_inode={}, count={} [delayed write]",
                 group_idx, inodes[0], count
@@ -860,6 +1604,7 @@ impl Ext4FileSystem {
         // 在位图上清零对应 bit
         // Note: freeing the same block twice should not bring the whole filesystem down.
         // Treat AlreadyFree as a no-op.
+        self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
         let mut free_ok = Ok(());
         let mut did_free = true;
         self.bitmap_cache
@@ -876,10 +1621,15 @@ impl Ext4FileSystem {
                 };
             })?;
         free_ok?;
+        self.update_bitmap_csum(cache_key);
+        self.mballoc.invalidate_group(group_idx);
 
         if !did_free {
             return Ok(());
         }
+        // 丢弃缓存中的旧内容，并撤销日志里该块的旧元数据
+        self.datablock_cache.invalidate(global_block);
+        block_dev.revoke_block(global_block);
         let desc = self
             .get_group_desc_mut(group_idx)
             .ok_or(BlockDevError::Corrupted)?;
@@ -895,6 +1645,94 @@ impl Ext4FileSystem {
         Ok(())
     }
 
+    /// 把一个数据块标记为已占用（fast commit 重放时使用），返回位图是否由空闲变为占用
+    pub fn mark_block_used<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        global_block: u64,
+    ) -> BlockDevResult<bool> {
+        if global_block >= self.superblock.blocks_count() {
+            return Err(BlockDevError::Corrupted);
+        }
+        let (group_idx, block_in_group) = self.block_allocator.global_to_group(global_block);
+        let bitmap_block = self
+            .get_group_desc(group_idx)
+            .ok_or(BlockDevError::Corrupted)?
+            .block_bitmap();
+        let cache_key = CacheKey::new_block(group_idx);
+        let blocks_per_group = self.superblock.s_blocks_per_group;
+
+        self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
+        let mut marked = Ok(true);
+        self.bitmap_cache
+            .modify(block_dev, cache_key, bitmap_block, |data| {
+                marked = match BlockBitmapMut::new(data, blocks_per_group).allocate(block_in_group) {
+                    Ok(()) => Ok(true),
+                    Err(BitmapError::AlreadyAllocated) => Ok(false),
+                    Err(_) => Err(BlockDevError::Corrupted),
+                };
+            })?;
+        if !marked? {
+            return Ok(false);
+        }
+        self.update_bitmap_csum(cache_key);
+        self.mballoc.invalidate_group(group_idx);
+
+        let desc = self
+            .get_group_desc_mut(group_idx)
+            .ok_or(BlockDevError::Corrupted)?;
+        let new_count = desc.free_blocks_count().saturating_sub(1);
+        desc.bg_free_blocks_count_lo = (new_count & 0xFFFF) as u16;
+        desc.bg_free_blocks_count_hi = (new_count >> 16) as u16;
+        self.superblock.s_free_blocks_count_lo =
+            self.superblock.s_free_blocks_count_lo.saturating_sub(1);
+        Ok(true)
+    }
+
+    /// 把一个 inode 标记为已占用（fast commit 重放时使用），返回位图是否由空闲变为占用
+    pub fn mark_inode_used<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u32,
+    ) -> BlockDevResult<bool> {
+        if inode_num == 0 || inode_num > self.superblock.s_inodes_count {
+            return Err(BlockDevError::Corrupted);
+        }
+        let (group_idx, inode_in_group) = self.inode_allocator.global_to_group(inode_num);
+        let bitmap_block = self
+            .get_group_desc(group_idx)
+            .ok_or(BlockDevError::Corrupted)?
+            .inode_bitmap();
+        let cache_key = CacheKey::new_inode(group_idx);
+        let inodes_per_group = self.superblock.s_inodes_per_group;
+
+        self.init_inode_bitmap(block_dev, group_idx)?;
+        self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
+        let mut marked = Ok(true);
+        self.bitmap_cache
+            .modify(block_dev, cache_key, bitmap_block, |data| {
+                marked = match InodeBitmapMut::new(data, inodes_per_group).allocate(inode_in_group) {
+                    Ok(()) => Ok(true),
+                    Err(BitmapError::AlreadyAllocated) => Ok(false),
+                    Err(_) => Err(BlockDevError::Corrupted),
+                };
+            })?;
+        if !marked? {
+            return Ok(false);
+        }
+        self.update_bitmap_csum(cache_key);
+
+        let desc = self
+            .get_group_desc_mut(group_idx)
+            .ok_or(BlockDevError::Corrupted)?;
+        let new_count = desc.free_inodes_count().saturating_sub(1);
+        desc.bg_free_inodes_count_lo = (new_count & 0xFFFF) as u16;
+        desc.bg_free_inodes_count_hi = (new_count >> 16) as u16;
+        self.superblock.s_free_inodes_count = self.superblock.s_free_inodes_count.saturating_sub(1);
+        self.shrink_itable_unused(inode_num);
+        Ok(true)
+    }
+
     /// 根据 inode 号释放一个 inode
     /// 内部自动计算所属块组和位图位置，并更新块组/超级块计数
     pub fn free_inode<B: BlockDevice>(
@@ -915,6 +1753,7 @@ impl Ext4FileSystem {
             cache_key = CacheKey::new_inode(group_idx);
         }
 
+        self.verify_bitmap_csum(block_dev, cache_key, bitmap_block)?;
         let mut free_ok = Ok(());
         let mut did_free = true;
         self.bitmap_cache
@@ -931,6 +1770,7 @@ impl Ext4FileSystem {
                 };
             })?;
         free_ok?;
+        self.update_bitmap_csum(cache_key);
 
         if !did_free {
             return Ok(());
@@ -947,8 +1787,12 @@ impl Ext4FileSystem {
 
         // 更新超级块 free_inodes_count
         self.superblock.s_free_inodes_count = self.superblock.s_free_inodes_count.saturating_add(1);
+        // 扩展属性块是 inode 之外的引用，先释放（共享块只减少引用计数）
+        release_xattr_block(self, block_dev, inode_num)?;
+        self.extent_status.invalidate(inode_num);
+        self.mballoc.discard_inode(inode_num);
         // 真正清空inodetable 大坑....，free_inode必须清空inodetable。不然e2fsck会捣蛋
-        self.modify_inode(block_dev, inode_num, |td| *td = Ext4Inode::default())?;
+        self.modify_inode_raw(block_dev, inode_num, |raw| clear_inode_raw(raw, 0))?;
         Ok(())
     }
 
@@ -1032,11 +1876,27 @@ pub fn mount<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<Ext4F
         }
         Err(e) => {
             error!("Mount failed: {e}");
-            Err(BlockDevError::Corrupted)
+            Err(match e {
+                RSEXT4Error::IoError => BlockDevError::IoError,
+                RSEXT4Error::ChecksumError => BlockDevError::ChecksumError,
+                RSEXT4Error::UnsupportedFeature => BlockDevError::Unsupported,
+                _ => BlockDevError::Corrupted,
+            })
         }
     }
 }
 
+/// 带挂载选项（时钟、atime 策略）的挂载函数
+pub fn mount_with_options<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    options: MountOptions,
+) -> BlockDevResult<Ext4FileSystem> {
+    let mut fs = mount(block_dev)?;
+    fs.time_source = options.time_source;
+    fs.atime_policy = options.atime;
+    Ok(fs)
+}
+
 ///取消挂载函数
 pub fn umount<B: BlockDevice>(
     fs: Ext4FileSystem,
@@ -1047,6 +1907,13 @@ pub fn umount<B: BlockDevice>(
     Ok(())
 }
 
+/// 清零原始 inode，只保留 i_generation（并加上 bump）
+fn clear_inode_raw(raw: &mut [u8], bump: u32) {
+    let generation = read_u32_le(&raw[100..104]).wrapping_add(bump);
+    raw.fill(0);
+    write_u32_le(generation, &mut raw[100..104]);
+}
+
 /// 文件系统布局信息（仅用于 mkfs 阶段的计算）
 pub struct FsLayoutInfo {
     /// 逻辑块大小（字节）
@@ -1081,6 +1948,17 @@ pub struct FsLayoutInfo {
     group0_metadata_blocks: u32,
     /// 预留块总数（按比例预留给 root）
     reserved_blocks: u64,
+    /// 实际使用的总块数（末尾放不下元数据的残缺块组会被舍弃）
+    total_blocks: u64,
+}
+
+impl FsLayoutInfo {
+    /// 指定块组实际包含的块数（最后一个块组可能不满）
+    fn blocks_in_group(&self, group_id: u32) -> u32 {
+        let group_start =
+            self.first_data_block as u64 + group_id as u64 * self.blocks_per_group as u64;
+        (self.total_blocks - group_start).min(self.blocks_per_group as u64) as u32
+    }
 }
 
 /// block_group 布局信息，仅在 mkfs 阶段使用
@@ -1097,18 +1975,25 @@ pub struct BlcokGroupLayout {
     pub metadata_blocks_in_group: u32,
 }
 
-pub fn compute_fs_layout(inode_size:u16,total_blocks: u64) -> FsLayoutInfo {
-    let block_size: u32 = 1024u32 << LOG_BLOCK_SIZE;
+pub fn compute_fs_layout(inode_size: u16, total_blocks: u64, block_size: u32) -> FsLayoutInfo {
+    // 每组块数：8 * block_size（标准 ext4 默认），但不超过位图和 16 位计数能表示的上限
+    let blocks_per_group: u32 = (8 * block_size).min(MAX_BLOCKS_PER_GROUP);
 
-    // 每组块数：8 * block_size（标准 ext4 默认）
-    let blocks_per_group: u32 = 8 * block_size;
+    // 每组 inode 数：blocks_per_group / 4（简化策略），向上取整到整块 inode 表
+    let inodes_per_block: u32 = block_size / inode_size as u32;
+    let inodes_per_group: u32 = (blocks_per_group / 4)
+        .div_ceil(inodes_per_block)
+        .saturating_mul(inodes_per_block)
+        .min(8 * block_size);
 
-    // 每组 inode 数：blocks_per_group / 4（简化策略）
-    let inodes_per_group: u32 = blocks_per_group / 4;
+    // 第一个数据块：块大小 > 1024 时为 0，否则为 1（参考 lwext4 create_fs_aux_info）
+    let first_data_block: u32 = if block_size > 1024 { 0 } else { 1 };
 
     // 块组数：向上取整
-    let groups: u32 =
-        total_blocks.div_ceil(blocks_per_group as u64) as u32;
+    let mut total_blocks = total_blocks;
+    let mut groups: u32 = total_blocks
+        .saturating_sub(first_data_block as u64)
+        .div_ceil(blocks_per_group as u64) as u32;
 
     // 确定块组描述符大小，默认使用64位描述符大小，除非明确指定使用32位
     let desc_size: u16 = if DEFAULT_FEATURE_INCOMPAT & Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT != 0 {
@@ -1138,18 +2023,27 @@ pub fn compute_fs_layout(inode_size:u16,total_blocks: u64) -> FsLayoutInfo {
         (inodes_per_group * inode_size as u32).div_ceil(block_size)
     };
 
-    // 第一个数据块：块大小 > 1024 时为 0，否则为 1（参考 lwext4 create_fs_aux_info）
-    let first_data_block: u32 = if block_size > 1024 { 0 } else { 1 };
+    // 最后一个块组放不下自身元数据（备份 super+GDT+位图+inode 表）时直接舍弃
+    if groups > 1 {
+        let last_start =
+            first_data_block as u64 + (groups - 1) as u64 * blocks_per_group as u64;
+        let last_blocks = total_blocks - last_start;
+        let last_meta = 1 + gdt_blocks + 2 + inode_table_blocks;
+        if last_blocks <= last_meta as u64 + MIN_LAST_GROUP_FREE_BLOCKS {
+            groups -= 1;
+            total_blocks = last_start;
+        }
+    }
 
     // 预留的 GDT 块数（与 ext4 标准一致）
     let reserved_gdt_blocks: u32 = RESERVED_GDT_BLOCKS;
 
     // 组0布局：
-    // - 对于 4K：Primary superblock at 0, GDT at 1, Reserved GDT blocks at 2..(2+reserved_gdt_blocks-1)
+    // - 超级块所在块之后依次是 GDT、预留 GDT 块
     // - 我们在预留 GDT 区域之后顺序放置 block_bitmap、inode_bitmap、inode_table
     let group0_start: u32 = first_data_block;
-    let reserved_gdt_start: u32 = group0_start + 2; // 块0=引导/超级块，块1=GDT，块2.. 预留GDT
-    let group0_block_bitmap: u32 = reserved_gdt_start + reserved_gdt_blocks; // 2 + reserved
+    let reserved_gdt_start: u32 = group0_start + 1 + gdt_blocks;
+    let group0_block_bitmap: u32 = reserved_gdt_start + reserved_gdt_blocks;
     let group0_inode_bitmap: u32 = group0_block_bitmap + 1;
     let group0_inode_table: u32 = group0_inode_bitmap + 1;
     let group0_metadata_blocks: u32 = (group0_inode_table + inode_table_blocks) - group0_start;
@@ -1174,18 +2068,95 @@ pub fn compute_fs_layout(inode_size:u16,total_blocks: u64) -> FsLayoutInfo {
         group0_inode_table,
         group0_metadata_blocks,
         reserved_blocks,
+        total_blocks,
+    }
+}
+
+/// 挂载时读元数据出错：校验和错误说明盘上数据损坏，与设备 I/O 错误区分开
+fn mount_error(e: BlockDevError) -> RSEXT4Error {
+    match e {
+        BlockDevError::ChecksumError => RSEXT4Error::ChecksumError,
+        _ => RSEXT4Error::IoError,
+    }
+}
+
+/// 挂载前探测块大小：先以最小块大小读出超级块，再按 s_log_block_size 切换块设备
+fn probe_block_size<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> Result<(), RSEXT4Error> {
+    let probe_size = (MIN_BLOCK_SIZE as u32).max(block_dev.device_block_size()) as usize;
+    block_dev
+        .set_block_size(probe_size)
+        .map_err(|_| RSEXT4Error::IoError)?;
+    let sb = read_superblock(block_dev).map_err(mount_error)?;
+    if sb.s_magic != EXT4_SUPER_MAGIC {
+        error!(
+            "Invalid magic: {:#x}, expected: {:#x}",
+            sb.s_magic, EXT4_SUPER_MAGIC
+        );
+        return Err(RSEXT4Error::InvalidMagic);
+    }
+    if sb.s_log_block_size > MAX_LOG_BLOCK_SIZE {
+        error!("Unsupported s_log_block_size: {}", sb.s_log_block_size);
+        return Err(RSEXT4Error::InvalidSuperblock);
     }
+    let block_size = sb.block_size() as usize;
+    block_dev.set_block_size(block_size).map_err(|e| {
+        error!("Block size {block_size} not supported by device: {e}");
+        RSEXT4Error::UnsupportedFeature
+    })?;
+    debug!("Filesystem block size: {block_size} bytes");
+    Ok(())
+}
+
+/// mkfs 时内部日志的大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum JournalSize {
+    /// 按块大小取默认大小（16 MiB，1024..=4096 块）
+    #[default]
+    Default,
+    /// 指定日志块数（含 journal 超级块，至少 JBD2_MIN_JOURNAL_BLOCKS）
+    Blocks(u32),
+    /// 不创建日志
+    Disabled,
+}
+
+impl JournalSize {
+    /// 换算成日志块数，不建日志时返回 None
+    pub fn blocks(self, block_size: usize) -> Option<u32> {
+        match self {
+            JournalSize::Default => Some(journal_blocks_for(block_size)),
+            JournalSize::Blocks(n) => Some(n),
+            JournalSize::Disabled => None,
+        }
+    }
+}
+
+/// 格式化块设备，`block_size` 为文件系统块大小（1024/2048/4096/.../65536 字节），使用默认大小的日志
+pub fn mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>, block_size: usize) -> BlockDevResult<()> {
+    mkfs_with_journal(block_dev, block_size, JournalSize::Default)
 }
 
-pub fn mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
+/// 格式化块设备并按 `journal` 创建内部日志；接了外部日志设备时忽略 `journal`
+pub fn mkfs_with_journal<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    block_size: usize,
+    journal: JournalSize,
+) -> BlockDevResult<()> {
     debug!("Start initializing Ext4 filesystem...");
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(BlockDevError::BlockSizeOutOfRange {
+            size: block_size,
+            min: MIN_BLOCK_SIZE,
+            max: MAX_BLOCK_SIZE,
+        });
+    }
     // mkfs 阶段先强制关闭日志，避免还未初始化 journal superblock 时触发 JBD2 逻辑
     block_dev.set_journal_use(false);
     let old_jouranl_use = block_dev.is_use_journal();
+    block_dev.set_block_size(block_size)?;
 
     // 1. 计算布局参数
-    let total_blocks = block_dev.total_blocks();
-    let layout = compute_fs_layout(DEFAULT_INODE_SIZE,total_blocks);
+    let layout = compute_fs_layout(DEFAULT_INODE_SIZE, block_dev.total_blocks(), block_size as u32);
+    let total_blocks = layout.total_blocks;
     let total_groups = layout.groups;
 
     debug!("  Total blocks: {total_blocks}");
@@ -1195,7 +2166,18 @@ pub fn mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
     debug!("  Inodes per group: {}", layout.inodes_per_group);
 
     //构建并根据fearure写入到所有group超级块
-    let superblock = build_superblock(total_blocks, &layout);
+    let mut superblock = build_superblock(total_blocks, &layout);
+    // 接了外部日志设备：格式化日志设备并登记本文件系统，不再创建 journal inode
+    if block_dev.has_journal_device() {
+        block_dev.format_journal_device(generate_uuid_8())?;
+        superblock.s_journal_uuid = block_dev.attach_journal_device(superblock.s_uuid)?;
+        superblock.s_journal_inum = 0;
+    }
+    let journal_blocks = journal.blocks(block_size).filter(|_| !block_dev.has_journal_device());
+    if journal_blocks.is_none() && !block_dev.has_journal_device() {
+        superblock.s_feature_compat &= !Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL;
+        superblock.s_journal_inum = 0;
+    }
     write_superblock(block_dev, &superblock)?;
     debug!("Superblock written");
 
@@ -1204,27 +2186,32 @@ pub fn mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
 
     //注意顺序
     let mut descs: VecDeque<Ext4GroupDesc> = VecDeque::new();
-    //为superblock写入gdt（全部标记为UNINIT）
     for group_id in 0..total_groups {
-        let desc = build_uninit_group_desc(&superblock, group_id, &layout);
-        write_group_desc(block_dev, group_id, &desc)?;
-        descs.push_back(desc);
+        descs.push_back(build_uninit_group_desc(&superblock, group_id, &layout));
     }
-    //为其它块组选择性的写入冗余备份desc
-    write_gdt_redundant_backup(block_dev, &descs, &superblock, total_groups, &layout)?;
-    debug!("{total_groups} block group descriptors written");
 
     //实际初始化块组0（用于根目录）
-    initialize_group_0(block_dev, &layout)?;
+    initialize_group_0(block_dev, &layout, &superblock, &mut descs[0])?;
     debug!("Block group 0 initialized (for root directory)");
 
     // 初始化其它块组的位图（全部视为空闲）
-    initialize_other_groups_bitmaps(block_dev, &layout, &superblock)?;
+    initialize_other_groups_bitmaps(block_dev, &layout, &superblock, &mut descs)?;
+
+    //位图校验和已经填入描述符，再写入gdt
+    for (group_id, desc) in descs.iter().enumerate() {
+        write_group_desc(block_dev, group_id as u32, desc)?;
+    }
+    //为其它块组选择性的写入冗余备份desc
+    write_gdt_redundant_backup(block_dev, &descs, &superblock, total_groups, &layout)?;
+    debug!("{total_groups} block group descriptors written");
 
     //通过一次挂载/卸载流程，让根目录在 mkfs 阶段就被真正创建并写回磁盘
     // 注意：此时日志仍然关闭，等真正挂载时再开启 JBD2
     {
         let mut fs = Ext4FileSystem::mount(block_dev).expect("Mount Failed!");
+        if let Some(blocks) = journal_blocks {
+            create_journal_entry(&mut fs, block_dev, blocks)?;
+        }
         fs.umount(block_dev)?;
     }
 
@@ -1246,6 +2233,63 @@ pub fn mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
     }
 }
 
+/// 给未挂载的文件系统添加 `blocks` 块的内部日志（相当于 tune2fs -J size=）
+pub fn add_journal<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>, blocks: u32) -> BlockDevResult<()> {
+    with_unmounted_fs(block_dev, |fs, dev| {
+        if fs.superblock.has_journal() {
+            return Err(BlockDevError::AlreadyExists);
+        }
+        create_journal_entry(fs, dev, blocks)
+    })
+}
+
+/// 删除未挂载文件系统的内部日志（相当于 tune2fs -O ^has_journal），日志需要重放时拒绝
+pub fn remove_journal<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
+    with_unmounted_fs(block_dev, |fs, dev| remove_journal_entry(fs, dev))
+}
+
+/// 把未挂载文件系统的内部日志重建为 `blocks` 块；日志为空，直接删除后重新创建，fast commit 特性保持不变
+pub fn resize_journal<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>, blocks: u32) -> BlockDevResult<()> {
+    with_unmounted_fs(block_dev, |fs, dev| {
+        if blocks < JBD2_MIN_JOURNAL_BLOCKS {
+            return Err(BlockDevError::InvalidInput);
+        }
+        let fast_commit = fs
+            .superblock
+            .has_feature_compat(Ext4Superblock::EXT4_FEATURE_COMPAT_FAST_COMMIT);
+        remove_journal_entry(fs, dev)?;
+        create_journal_entry(fs, dev, blocks)?;
+        if fast_commit {
+            fs.superblock.s_feature_compat |= Ext4Superblock::EXT4_FEATURE_COMPAT_FAST_COMMIT;
+            fs.sync_superblock(dev)?;
+        }
+        Ok(())
+    })
+}
+
+/// 关闭 JBD2 挂载文件系统，执行日志维护操作后卸载；文件系统需要日志重放时不做任何修改
+fn with_unmounted_fs<B, F>(block_dev: &mut Jbd2Dev<B>, f: F) -> BlockDevResult<()>
+where
+    B: BlockDevice,
+    F: FnOnce(&mut Ext4FileSystem, &mut Jbd2Dev<B>) -> BlockDevResult<()>,
+{
+    let use_journal = block_dev.is_use_journal();
+    block_dev.set_journal_use(false);
+    let res = mount(block_dev).and_then(|mut fs| {
+        if fs
+            .superblock
+            .has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_RECOVER)
+        {
+            error!("Filesystem needs journal recovery, mount it with journaling first");
+            return Err(BlockDevError::DeviceBusy);
+        }
+        f(&mut fs, block_dev)?;
+        fs.umount(block_dev)
+    });
+    block_dev.set_journal_use(use_journal);
+    res
+}
+
 /// 构建超级块 不管字节序
 fn build_superblock(total_blocks: u64, layout: &FsLayoutInfo) -> Ext4Superblock {
     let mut sb = Ext4Superblock::default();
@@ -1258,9 +2302,10 @@ fn build_superblock(total_blocks: u64, layout: &FsLayoutInfo) -> Ext4Superblock
     sb.s_blocks_count_hi = (total_blocks >> 32) as u32;
 
     // Ext4 标准：块大小 = 1024 << s_log_block_size
-    sb.s_log_block_size = LOG_BLOCK_SIZE;
+    let log_block_size = layout.block_size.trailing_zeros() - MIN_BLOCK_SIZE.trailing_zeros();
+    sb.s_log_block_size = log_block_size;
     // 簇大小目前与块大小一致
-    sb.s_log_cluster_size = LOG_BLOCK_SIZE;
+    sb.s_log_cluster_size = log_block_size;
 
     // 每组块数 / inode 数量
     sb.s_blocks_per_group = layout.blocks_per_group;
@@ -1319,6 +2364,11 @@ fn build_superblock(total_blocks: u64, layout: &FsLayoutInfo) -> Ext4Superblock
     sb.s_feature_compat = DEFAULT_FEATURE_COMPAT;
     sb.s_feature_incompat = DEFAULT_FEATURE_INCOMPAT;
     sb.s_feature_ro_compat = DEFAULT_FEATURE_RO_COMPAT;
+    // 目录哈希按有符号 char 计算（与 x86 上的 mke2fs 一致）
+    sb.s_flags |= Ext4Superblock::EXT2_FLAGS_SIGNED_HASH;
+    if sb.has_metadata_csum() {
+        sb.s_checksum_type = EXT4_CRC32C_CHKSUM;
+    }
 
     // 块组描述符大小
     sb.s_desc_size = layout.desc_size;
@@ -1349,13 +2399,13 @@ fn build_uninit_group_desc(
     );
 
     // 位图和 inode 表块号
-    desc.bg_block_bitmap_lo = gl.group_blcok_bitmap_startblocks as u32;
-    desc.bg_inode_bitmap_lo = gl.group_inode_bitmap_startblocks as u32;
-    desc.bg_inode_table_lo = gl.group_inode_table_startblocks as u32;
+    desc.set_block_bitmap(gl.group_blcok_bitmap_startblocks);
+    desc.set_inode_bitmap(gl.group_inode_bitmap_startblocks);
+    desc.set_inode_table(gl.group_inode_table_startblocks);
 
     // 理论空闲块数：整组减去元数据块
     let used_meta = gl.metadata_blocks_in_group as u32;
-    let free_blocks = layout.blocks_per_group.saturating_sub(used_meta);
+    let free_blocks = layout.blocks_in_group(group_id).saturating_sub(used_meta);
 
     if group_id == 0 {
         // 组0 还需要扣掉保留 inode
@@ -1403,10 +2453,11 @@ fn write_superblock_redundant_backup<B: BlockDevice>(
             //需要超级块备份
             if need_redundant_backup(gid) {
                 let super_blocks = group_layout.group_start_block;
-                block_dev.read_block(super_blocks as u32).expect("Superblock read failed!");
+                block_dev.read_block(super_blocks).expect("Superblock read failed!");
                 let buffer = block_dev.buffer_mut();
                 sb.to_disk_bytes(&mut buffer[0..SUPERBLOCK_SIZE]);
-                block_dev.write_block(super_blocks as u32, true)?;
+                set_superblock_csum(sb, &mut buffer[0..SUPERBLOCK_SIZE]);
+                block_dev.write_block(super_blocks, true)?;
             }
         }
     }
@@ -1419,40 +2470,38 @@ fn write_superblock<B: BlockDevice>(
     sb: &Ext4Superblock,
 ) -> BlockDevResult<()> {
     // 超级块总是从分区偏移 1024 字节开始，占用 1024 字节
-    if BLOCK_SIZE == 1024 {
-        block_dev.read_block(1)?;
-        let buffer = block_dev.buffer_mut();
-        sb.to_disk_bytes(&mut buffer[0..SUPERBLOCK_SIZE]);
-        block_dev.write_block(1, true)?;
-    } else {
-        block_dev.read_block(0)?;
-        let buffer = block_dev.buffer_mut();
-        let offset = Ext4Superblock::SUPERBLOCK_OFFSET as usize; // 1024
-        let end = offset + Ext4Superblock::SUPERBLOCK_SIZE;
-        sb.to_disk_bytes(&mut buffer[offset..end]);
-        block_dev.write_block(0, false)?; //由于目前日志回放在超级块读取后，目前为了快速修复防止读取到旧的超级块。直接让超级块落盘写回
-    }
+    let (block, offset) = superblock_location(block_dev.block_size());
+    block_dev.read_block(block)?;
+    let buffer = block_dev.buffer_mut();
+    let end = offset + Ext4Superblock::SUPERBLOCK_SIZE;
+    sb.to_disk_bytes(&mut buffer[offset..end]);
+    set_superblock_csum(sb, &mut buffer[offset..end]);
+    block_dev.write_block(block, false)?; //由于目前日志回放在超级块读取后，目前为了快速修复防止读取到旧的超级块。直接让超级块落盘写回
 
     Ok(())
 }
 
+/// 主超级块所在的 (块号, 块内偏移)
+fn superblock_location(block_size: u32) -> (u64, usize) {
+    let block_size = block_size as u64;
+    (SUPERBLOCK_OFFSET / block_size, (SUPERBLOCK_OFFSET % block_size) as usize)
+}
+
 /// 读取超级块 管字节序
 fn read_superblock<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<Ext4Superblock> {
     // 超级块总是从分区偏移 1024 字节开始，占用 1024 字节
-    // 这里通过按 BLOCK_SIZE 读块，再在块内做 1024 字节切片来解析
-    if BLOCK_SIZE == 1024 {
-        block_dev.read_block(1)?;
-        let buffer = block_dev.buffer();
-        let sb = Ext4Superblock::from_disk_bytes(&buffer[0..SUPERBLOCK_SIZE]);
-        Ok(sb)
-    } else {
-        block_dev.read_block(0)?;
-        let buffer = block_dev.buffer();
-        let offset = Ext4Superblock::SUPERBLOCK_OFFSET as usize; // 1024
-        let end = offset + Ext4Superblock::SUPERBLOCK_SIZE;
-        let sb = Ext4Superblock::from_disk_bytes(&buffer[offset..end]);
-        Ok(sb)
+    // 这里按当前块大小读块，再在块内做 1024 字节切片来解析
+    let (block, offset) = superblock_location(block_dev.block_size());
+    block_dev.read_block(block)?;
+    let buffer = block_dev.buffer();
+    let end = offset + Ext4Superblock::SUPERBLOCK_SIZE;
+    let sb = Ext4Superblock::from_disk_bytes(&buffer[offset..end]);
+    // 魔数不对时不是 ext4 超级块，留给调用方报告 InvalidMagic
+    if sb.s_magic == EXT4_SUPER_MAGIC && !verify_superblock_csum(&sb, &buffer[offset..end]) {
+        error!("Superblock checksum mismatch");
+        return Err(BlockDevError::ChecksumError);
     }
+    Ok(sb)
 }
 
 ///写入所有组的冗余备份中 自动判断特性
@@ -1492,23 +2541,23 @@ fn write_gdt_redundant_backup<B: BlockDevice>(
                 );
                 let gdt_start = group_layout.group_start_block + 1; //跳过超级块
 
-                let mut desc_iter = descs.iter();
+                let mut desc_iter = descs.iter().enumerate();
                 //循环写入desc
                 for gdt_block_id in gdt_start..group_layout.group_blcok_bitmap_startblocks {
-                    block_dev.read_block(gdt_block_id as u32)?;
+                    block_dev.read_block(gdt_block_id)?;
                     let buffer = block_dev.buffer_mut();
                     let mut current_offset = 0_usize; //descoffset循环记录
                     for _ in 0..fs_layout.descs_per_block {
-                        if let Some(desc) = desc_iter.next() {
-                            desc.to_disk_bytes(
-                                &mut buffer
-                                    [current_offset..current_offset + desc_size as usize],
-                            );
+                        if let Some((desc_gid, desc)) = desc_iter.next() {
+                            let desc_bytes =
+                                &mut buffer[current_offset..current_offset + desc_size as usize];
+                            desc.to_disk_bytes(desc_bytes);
+                            set_group_desc_csum(sb, desc_gid as u32, desc_bytes);
                             current_offset += desc_size as usize;
                         }
                     }
                     //写回磁盘
-                    block_dev.write_block(gdt_block_id as u32, true)?;
+                    block_dev.write_block(gdt_block_id, true)?;
                 }
             }
         }
@@ -1527,22 +2576,23 @@ fn write_group_desc<B: BlockDevice>(
     let superblock = read_superblock(block_dev)?;
     let desc_size = superblock.get_desc_size() as usize;
     
-    // GDT 基地址统一为块号 1 的起始字节偏移：按字节偏移计算所在块和块内偏移
-    let gdt_base: u64 = BLOCK_SIZE as u64;
+    // GDT 紧跟超级块所在块：按字节偏移计算所在块和块内偏移
+    let block_size_u64 = superblock.block_size();
+    let gdt_base = superblock.gdt_start_block() * block_size_u64;
     let byte_offset = gdt_base + group_id as u64 * desc_size as u64;
-    let block_size_u64 = BLOCK_SIZE as u64;
     let block_num = byte_offset / block_size_u64;
     let in_block = (byte_offset % block_size_u64) as usize;
     let end = in_block + desc_size;
 
     // 读取目标块，修改对应 slice，再写回
-    block_dev.read_block(block_num as u32)?;
+    block_dev.read_block(block_num)?;
     let buffer = block_dev.buffer_mut();
     if end > buffer.len() {
         return Err(BlockDevError::Corrupted);
     }
     desc.to_disk_bytes(&mut buffer[in_block..end]);
-    block_dev.write_block(block_num as u32, true)?;
+    set_group_desc_csum(&superblock, group_id, &mut buffer[in_block..end]);
+    block_dev.write_block(block_num, true)?;
 
     Ok(())
 }
@@ -1551,24 +2601,40 @@ fn write_group_desc<B: BlockDevice>(
 fn initialize_group_0<B: BlockDevice>(
     block_dev: &mut Jbd2Dev<B>,
     layout: &FsLayoutInfo,
+    sb: &Ext4Superblock,
+    desc: &mut Ext4GroupDesc,
 ) -> BlockDevResult<()> {
     // 计算块组0的布局
-    let block_bitmap_blk = layout.group0_block_bitmap;
-    let inode_bitmap_blk = layout.group0_inode_bitmap;
-    let inode_table_blk = layout.group0_inode_table;
+    let block_bitmap_blk = layout.group0_block_bitmap as u64;
+    let inode_bitmap_blk = layout.group0_inode_bitmap as u64;
+    let inode_table_blk = layout.group0_inode_table as u64;
+
+    //  块组0的描述符（清除UNINIT标志），位图校验和在写位图时填入
+    *desc = Ext4GroupDesc::default();
+    desc.bg_flags = Ext4GroupDesc::EXT4_BG_INODE_ZEROED;
+    desc.bg_free_blocks_count_lo = layout
+        .blocks_in_group(0)
+        .saturating_sub(layout.group0_metadata_blocks) as u16;
+    desc.bg_free_inodes_count_lo = layout.inodes_per_group.saturating_sub(RESERVED_INODES) as u16;
+    desc.set_block_bitmap(block_bitmap_blk);
+    desc.set_inode_bitmap(inode_bitmap_blk);
+    desc.set_inode_table(inode_table_blk);
 
     {
         let buffer = block_dev.buffer_mut();
         buffer.fill(0);
-        // 标记元数据块为已使用：块0(引导) + 块1(超级块) + GDT + 块位图 + inode位图 + inode表
+        // 标记元数据块为已使用：超级块 + GDT + 块位图 + inode位图 + inode表
         let used_metadata_blocks = layout.group0_metadata_blocks as usize;
         for i in 0..used_metadata_blocks {
             let byte_idx = i / 8;
             let bit_idx = i % 8;
             buffer[byte_idx] |= 1 << bit_idx;
         }
+        // 组内不存在的块（末尾不满的块组 / 位图多余的位）标记为已用
+        mark_bitmap_padding(buffer, layout.blocks_in_group(0));
     }
     block_dev.write_block(block_bitmap_blk, true)?;
+    set_block_bitmap_csum(sb, desc, block_dev.buffer());
 
     {
         let buffer = block_dev.buffer_mut();
@@ -1581,14 +2647,10 @@ fn initialize_group_0<B: BlockDevice>(
         }
 
         // 2.5padding无效inode为1
-        let bits_per_group = BLOCK_SIZE_U32 * 8;
-        for i in layout.inodes_per_group..bits_per_group {
-            let byte_idx: usize = (i / 8) as usize;
-            let bit_idx = i % 8;
-            buffer[byte_idx] |= 1 << bit_idx;
-        }
+        mark_bitmap_padding(buffer, layout.inodes_per_group);
     }
     block_dev.write_block(inode_bitmap_blk, true)?;
+    set_inode_bitmap_csum(sb, desc, block_dev.buffer());
 
     //  清零inode表
     {
@@ -1596,22 +2658,9 @@ fn initialize_group_0<B: BlockDevice>(
         buffer.fill(0);
     }
     for i in 0..layout.inode_table_blocks {
-        block_dev.write_block(inode_table_blk + i, true)?;
+        block_dev.write_block(inode_table_blk + i as u64, true)?;
     }
 
-    //  更新块组0的描述符（清除UNINIT标志）
-    let mut desc = Ext4GroupDesc::default();
-    desc.bg_flags = Ext4GroupDesc::EXT4_BG_INODE_ZEROED;
-    desc.bg_free_blocks_count_lo = layout
-        .blocks_per_group
-        .saturating_sub(layout.group0_metadata_blocks) as u16;
-    desc.bg_free_inodes_count_lo = layout.inodes_per_group.saturating_sub(RESERVED_INODES) as u16;
-    desc.bg_block_bitmap_lo = block_bitmap_blk;
-    desc.bg_inode_bitmap_lo = inode_bitmap_blk;
-    desc.bg_inode_table_lo = inode_table_blk;
-
-    write_group_desc(block_dev, 0, &desc)?;
-
     Ok(())
 }
 
@@ -1621,6 +2670,7 @@ fn initialize_other_groups_bitmaps<B: BlockDevice>(
     block_dev: &mut Jbd2Dev<B>,
     layout: &FsLayoutInfo,
     sb: &Ext4Superblock,
+    descs: &mut VecDeque<Ext4GroupDesc>,
 ) -> BlockDevResult<()> {
     // 从块组1开始，逐组初始化
     for group_id in 1..layout.groups {
@@ -1636,8 +2686,8 @@ fn initialize_other_groups_bitmaps<B: BlockDevice>(
             layout.gdt_blocks,
         );
 
-        let block_bitmap_blk = gl.group_blcok_bitmap_startblocks as u32;
-        let inode_bitmap_blk = gl.group_inode_bitmap_startblocks as u32;
+        let block_bitmap_blk = gl.group_blcok_bitmap_startblocks;
+        let inode_bitmap_blk = gl.group_inode_bitmap_startblocks;
 
         //  初始化块位图：全0 → 所有块空闲
         {
@@ -1650,8 +2700,10 @@ fn initialize_other_groups_bitmaps<B: BlockDevice>(
                 let bit_idx = i % 8;
                 buffer[byte_idx] |= 1 << bit_idx;
             }
+            mark_bitmap_padding(buffer, layout.blocks_in_group(group_id));
         }
         block_dev.write_block(block_bitmap_blk, true)?;
+        set_block_bitmap_csum(sb, &mut descs[group_id as usize], block_dev.buffer());
 
         {
             //  初始化inode位图：全0 → 所有inode空闲
@@ -1659,15 +2711,113 @@ fn initialize_other_groups_bitmaps<B: BlockDevice>(
             buffer.fill(0);
 
             // padding无效inode
-            let bits_per_group = BLOCK_SIZE_U32 * 8;
-            for i in layout.inodes_per_group..bits_per_group {
-                let byte_idx: usize = (i / 8) as usize;
-                let bit_idx = i % 8;
-                buffer[byte_idx] |= 1 << bit_idx;
-            }
+            mark_bitmap_padding(buffer, layout.inodes_per_group);
         }
         block_dev.write_block(inode_bitmap_blk, true)?;
+        set_inode_bitmap_csum(sb, &mut descs[group_id as usize], block_dev.buffer());
     }
 
     Ok(())
 }
+
+/// 位图中从 `valid_bits` 开始直到块末尾的位全部置 1
+fn mark_bitmap_padding(bitmap: &mut [u8], valid_bits: u32) {
+    let bits_per_block = bitmap.len() as u32 * 8;
+    for i in valid_bits..bits_per_block {
+        bitmap[(i / 8) as usize] |= 1 << (i % 8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::test_support::*;
+
+    #[test]
+    fn test_alloc_lowers_itable_unused_and_inits_uninit_group() {
+        let mem = MemDev::new(32 << 20);
+        let (mut dev, mut fs) = setup_fs(mem.clone(), 1024);
+        let bs = fs.block_size();
+        let ipg = fs.superblock.inodes_per_group();
+        assert!(fs.group_descs.len() > 1);
+
+        // 模拟 mkfs.ext4 的 uninit_bg 布局：组 0 只用了前 11 个 inode，
+        // 组 1 的 inode 位图没有初始化（盘上是垃圾）
+        fs.group_descs[0].set_itable_unused(ipg - 11);
+        fs.group_descs[1].set_itable_unused(ipg);
+        fs.group_descs[1].bg_flags |= Ext4GroupDesc::EXT4_BG_INODE_UNINIT;
+        let bitmap = fs.group_descs[1].inode_bitmap() as usize * bs;
+        mem.0.borrow_mut()[bitmap..bitmap + bs].fill(0xff);
+
+        let (a, _) = mkfile_with_ino(&mut dev, &mut fs, "/a", Some(b"a"), None).unwrap();
+        assert!(a > 11 && a <= ipg);
+        assert_eq!(fs.group_descs[0].itable_unused(), ipg - a);
+
+        // 组 0 看起来已满时分配到组 1：位图先按全空初始化
+        let group0_free = fs.group_descs[0].free_inodes_count();
+        fs.group_descs[0].bg_free_inodes_count_lo = 0;
+        fs.group_descs[0].bg_free_inodes_count_hi = 0;
+        let (b, _) = mkfile_with_ino(&mut dev, &mut fs, "/b", Some(b"b"), None).unwrap();
+        assert!(b > ipg && b <= 2 * ipg);
+        assert!(!fs.group_descs[1].is_inode_bitmap_uninit());
+        assert_eq!(fs.group_descs[1].itable_unused(), 2 * ipg - b);
+        fs.group_descs[0].bg_free_inodes_count_lo = group0_free as u16;
+        fs.group_descs[0].bg_free_inodes_count_hi = (group0_free >> 16) as u16;
+        fs.umount(&mut dev).unwrap();
+
+        // 重新挂载后描述符和位图（含校验和）都保持一致
+        let mut fs = mount(&mut dev).unwrap();
+        assert_eq!(fs.group_descs[0].itable_unused(), ipg - a);
+        assert_eq!(fs.group_descs[1].itable_unused(), 2 * ipg - b);
+        assert!(fs.inode_num_already_allocted(&mut dev, b as u64));
+        assert_eq!(read_file(&mut dev, &mut fs, "/b").unwrap().unwrap(), b"b");
+    }
+
+    #[cfg(feature = "CONFIG_META_CSUM_ENABLE")]
+    #[test]
+    fn test_mount_verifies_group0_bitmaps() {
+        for inode_bitmap in [true, false] {
+            let mem = MemDev::new(8 << 20);
+            let (mut dev, mut fs) = setup_fs(mem.clone(), 1024);
+            let bs = fs.block_size();
+            let desc = fs.group_descs[0];
+            fs.umount(&mut dev).unwrap();
+
+            // 组 0 的位图正是挂载时预先载入的那两个
+            let blk = if inode_bitmap { desc.inode_bitmap() } else { desc.block_bitmap() };
+            mem.0.borrow_mut()[blk as usize * bs + 1] ^= 0x10;
+            assert_eq!(Ext4FileSystem::mount(&mut dev).err(), Some(RSEXT4Error::ChecksumError));
+        }
+    }
+
+    #[cfg(not(feature = "CONFIG_META_CSUM_ENABLE"))]
+    #[test]
+    fn test_metadata_csum_image_refused_without_csum_support() {
+        let mem = MemDev::new(8 << 20);
+        let (mut dev, mut fs) = setup_fs(mem.clone(), 1024);
+        fs.umount(&mut dev).unwrap();
+
+        // s_feature_ro_compat 在超级块内偏移 0x64
+        let off = SUPERBLOCK_OFFSET as usize + 0x64;
+        let mut ro_compat = u32::from_le_bytes(mem.0.borrow()[off..off + 4].try_into().unwrap());
+        ro_compat |= Ext4Superblock::EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+        mem.0.borrow_mut()[off..off + 4].copy_from_slice(&ro_compat.to_le_bytes());
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem, true);
+        assert_eq!(Ext4FileSystem::mount(&mut dev).err(), Some(RSEXT4Error::UnsupportedFeature));
+    }
+
+    #[cfg(feature = "CONFIG_META_CSUM_ENABLE")]
+    #[test]
+    fn test_corrupt_superblock_reported_as_checksum_error() {
+        let mem = MemDev::new(8 << 20);
+        let (mut dev, mut fs) = setup_fs(mem.clone(), 1024);
+        fs.umount(&mut dev).unwrap();
+
+        // 改动 s_volume_name（偏移 0x78），校验和不再匹配
+        mem.0.borrow_mut()[SUPERBLOCK_OFFSET as usize + 0x78] ^= 1;
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem.clone(), true);
+        assert_eq!(Ext4FileSystem::mount(&mut dev).err(), Some(RSEXT4Error::ChecksumError));
+        assert_eq!(mount(&mut dev).err(), Some(BlockDevError::ChecksumError));
+    }
+}
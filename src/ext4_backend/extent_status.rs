@@ -0,0 +1,337 @@
+//! Extent 状态缓存（对应内核 extent status tree）
+//!
+//! 按 inode 缓存"逻辑块范围 -> 已写入 / unwritten / 空洞"的映射，读写只解析自己访问的范围，
+//! 不再把整棵 extent 树展开成逐块映射。extent 树被修改时整个 inode 的缓存失效；
+//! 另外记录填充时 inode 的 extent 根，根被其它路径改写（截断、inode 复用等）时自动丢弃。
+//! 总条目数超过上限时按 inode 做 LRU 淘汰。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::blockmap::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::extents_tree::*;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// es_pblk 高位的状态标志（与内核相同）
+pub const EXTENT_STATUS_WRITTEN: u64 = 1 << 63;
+pub const EXTENT_STATUS_UNWRITTEN: u64 = 1 << 62;
+pub const EXTENT_STATUS_HOLE: u64 = 1 << 60;
+const EXTENT_STATUS_MASK: u64 = 0x1F << 59;
+
+impl Ext4ExtentStatus {
+    pub fn new(lblk: u64, len: u64, pblk: u64, status: u64) -> Self {
+        Self {
+            es_lblk: lblk,
+            es_len: len,
+            es_pblk: (pblk & !EXTENT_STATUS_MASK) | status,
+        }
+    }
+
+    /// 起始物理块（去掉状态标志），空洞为 0
+    pub fn pblk(&self) -> u64 {
+        self.es_pblk & !EXTENT_STATUS_MASK
+    }
+
+    pub fn is_written(&self) -> bool {
+        self.es_pblk & EXTENT_STATUS_WRITTEN != 0
+    }
+
+    pub fn is_unwritten(&self) -> bool {
+        self.es_pblk & EXTENT_STATUS_UNWRITTEN != 0
+    }
+
+    pub fn is_hole(&self) -> bool {
+        self.es_pblk & EXTENT_STATUS_HOLE != 0
+    }
+
+    /// 结束逻辑块（不含）
+    pub fn end(&self) -> u64 {
+        self.es_lblk + self.es_len
+    }
+
+    /// 截取与 [start, end) 相交的部分，物理块随起点平移
+    pub fn clip(&self, start: u64, end: u64) -> Self {
+        let lblk = self.es_lblk.max(start);
+        let pblk = if self.is_hole() { 0 } else { self.pblk() + (lblk - self.es_lblk) };
+        Self::new(lblk, self.end().min(end) - lblk, pblk, self.es_pblk & EXTENT_STATUS_MASK)
+    }
+
+    /// 逻辑块 lblk 对应的物理块，空洞返回 None
+    pub fn pblk_of(&self, lblk: u64) -> Option<u64> {
+        (!self.is_hole()).then(|| self.pblk() + (lblk - self.es_lblk))
+    }
+}
+
+/// 单个 inode 的缓存：按起始逻辑块排序、互不重叠
+struct InodeExtentStatus {
+    /// 填充时 inode 的 i_block（extent 根）
+    root: [u32; 15],
+    entries: BTreeMap<u64, Ext4ExtentStatus>,
+    last_access: u64,
+}
+
+/// Extent 状态缓存管理器
+pub struct ExtentStatusCache {
+    inodes: BTreeMap<u32, InodeExtentStatus>,
+    /// 所有 inode 合计的最大条目数
+    max_entries: usize,
+    /// 当前条目数
+    total: usize,
+    access_counter: u64,
+}
+
+impl ExtentStatusCache {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            inodes: BTreeMap::new(),
+            max_entries,
+            total: 0,
+            access_counter: 0,
+        }
+    }
+
+    /// 查找覆盖 lblk 的缓存项；inode 的 extent 根已变化时丢弃该 inode 的缓存
+    pub fn lookup(&mut self, inode_num: u32, inode: &Ext4Inode, lblk: u64) -> Option<Ext4ExtentStatus> {
+        if self.inodes.get(&inode_num)?.root != inode.i_block {
+            self.invalidate(inode_num);
+            return None;
+        }
+        self.access_counter += 1;
+        let tree = self.inodes.get_mut(&inode_num)?;
+        tree.last_access = self.access_counter;
+        tree.entries
+            .range(..=lblk)
+            .next_back()
+            .map(|(_, es)| *es)
+            .filter(|es| es.end() > lblk)
+    }
+
+    /// 插入一项，先移除与之重叠的旧项；超过上限时先淘汰最久未用的其它 inode
+    pub fn insert(&mut self, inode_num: u32, inode: &Ext4Inode, es: Ext4ExtentStatus) {
+        if inode_num == 0 || es.es_len == 0 || self.max_entries == 0 {
+            return;
+        }
+        if self.inodes.get(&inode_num).is_some_and(|t| t.root != inode.i_block) {
+            self.invalidate(inode_num);
+        }
+        if self.total >= self.max_entries {
+            self.shrink(inode_num);
+        }
+
+        self.access_counter += 1;
+        let tree = self.inodes.entry(inode_num).or_insert_with(|| InodeExtentStatus {
+            root: inode.i_block,
+            entries: BTreeMap::new(),
+            last_access: 0,
+        });
+        tree.last_access = self.access_counter;
+        let overlapping: Vec<u64> = tree
+            .entries
+            .range(..es.end())
+            .rev()
+            .take_while(|(_, old)| old.end() > es.es_lblk)
+            .map(|(&k, _)| k)
+            .collect();
+        for k in &overlapping {
+            tree.entries.remove(k);
+        }
+        tree.entries.insert(es.es_lblk, es);
+        self.total = self.total + 1 - overlapping.len();
+    }
+
+    /// 丢弃一个 inode 的全部缓存（extent 树被修改或 inode 被释放）
+    pub fn invalidate(&mut self, inode_num: u32) {
+        if let Some(tree) = self.inodes.remove(&inode_num) {
+            self.total -= tree.entries.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.inodes.clear();
+        self.total = 0;
+    }
+
+    /// 当前缓存的条目数
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// 淘汰 LRU inode 直到低于上限；只剩 keep 时清空它
+    fn shrink(&mut self, keep: u32) {
+        while self.total >= self.max_entries {
+            let victim = self
+                .inodes
+                .iter()
+                .filter(|(ino, _)| **ino != keep)
+                .min_by_key(|(_, t)| t.last_access)
+                .map(|(ino, _)| *ino)
+                .unwrap_or(keep);
+            self.invalidate(victim);
+            if victim == keep {
+                break;
+            }
+        }
+    }
+}
+
+/// 解析 inode 在 [lblk, lblk + len) 的映射，返回按逻辑块顺序、首尾相接覆盖整个范围的状态段（空洞也是一段）。
+/// extent 映射的 inode 先查缓存，未命中时只遍历树中与剩余范围相交的节点并写回缓存；
+/// 传统间接映射逐块查找，不缓存
+pub fn map_extent_range<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &mut Ext4Inode,
+    lblk: u32,
+    len: u32,
+) -> BlockDevResult<Vec<Ext4ExtentStatus>> {
+    let end = lblk as u64 + len as u64;
+    let mut out: Vec<Ext4ExtentStatus> = Vec::new();
+    if len == 0 {
+        return Ok(out);
+    }
+    if !inode.maps_data_blocks() {
+        out.push(Ext4ExtentStatus::new(lblk as u64, len as u64, 0, EXTENT_STATUS_HOLE));
+        return Ok(out);
+    }
+
+    if !inode.have_extend_header_and_use_extend() {
+        let map = BlockMap::new(inode);
+        for l in lblk as u64..end {
+            let es = match map.lookup(block_dev, l as u32)? {
+                Some(phys) => Ext4ExtentStatus::new(l, 1, phys, EXTENT_STATUS_WRITTEN),
+                None => Ext4ExtentStatus::new(l, 1, 0, EXTENT_STATUS_HOLE),
+            };
+            match out.last_mut() {
+                Some(last)
+                    if last.is_hole() == es.is_hole()
+                        && (es.is_hole() || last.pblk() + last.es_len == es.pblk()) =>
+                {
+                    last.es_len += 1
+                }
+                _ => out.push(es),
+            }
+        }
+        return Ok(out);
+    }
+
+    let mut pos = lblk as u64;
+    while pos < end {
+        if let Some(es) = fs.extent_status.lookup(inode_num, inode, pos) {
+            out.push(es.clip(pos, end));
+            pos = es.end();
+            continue;
+        }
+
+        // 未命中：一次取出剩余范围内的全部 extent，空隙记为空洞
+        let extents = ExtentTree::new_with_ino(fs, inode, inode_num).extents_in_range(
+            block_dev,
+            pos as u32,
+            (end - pos) as u32,
+        )?;
+        let mut found = Vec::with_capacity(extents.len() * 2 + 1);
+        let mut cur = pos;
+        for ext in extents {
+            let e_start = ext.ee_block as u64;
+            if e_start > cur {
+                found.push(Ext4ExtentStatus::new(cur, e_start - cur, 0, EXTENT_STATUS_HOLE));
+            }
+            let status = if ext.is_initialized() { EXTENT_STATUS_WRITTEN } else { EXTENT_STATUS_UNWRITTEN };
+            found.push(Ext4ExtentStatus::new(e_start, ext.actual_len() as u64, ext.start_block(), status));
+            cur = e_start + ext.actual_len() as u64;
+        }
+        if cur < end {
+            found.push(Ext4ExtentStatus::new(cur, end - cur, 0, EXTENT_STATUS_HOLE));
+        }
+        for es in found {
+            fs.extent_status.insert(inode_num, inode, es);
+            out.push(es.clip(pos, end));
+        }
+        pos = end;
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+    use crate::ext4_backend::fallocate::*;
+    use crate::ext4_backend::file::*;
+    use alloc::vec;
+
+    fn layout(es: &[Ext4ExtentStatus]) -> Vec<(u64, u64, bool, bool)> {
+        es.iter().map(|e| (e.es_lblk, e.es_len, e.is_written(), e.is_unwritten())).collect()
+    }
+
+    #[test]
+    fn test_map_range_caches_and_invalidates() {
+        let (mut dev, mut fs) = setup();
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/f", 0, &[1u8; 2048]).unwrap();
+        write_file(&mut dev, &mut fs, "/f", 8192, &[2u8; 1024]).unwrap();
+        fallocate(&mut dev, &mut fs, ino, 9216, 2048, 0).unwrap();
+
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        let map = map_extent_range(&mut fs, &mut dev, ino, &mut inode, 1, 12).unwrap();
+        assert_eq!(
+            layout(&map),
+            vec![(1, 1, true, false), (2, 6, false, false), (8, 1, true, false), (9, 2, false, true), (11, 2, false, false)]
+        );
+        // 第一块物理块随截取平移
+        let full = map_extent_range(&mut fs, &mut dev, ino, &mut inode, 0, 1).unwrap();
+        assert_eq!(full[0].pblk() + 1, map[0].pblk());
+        assert!(fs.extent_status.len() >= 5);
+
+        // 第二次查询完全命中缓存，结果相同
+        let again = map_extent_range(&mut fs, &mut dev, ino, &mut inode, 1, 12).unwrap();
+        assert_eq!(layout(&again), layout(&map));
+
+        // 写入 unwritten 区域会修改 extent 树并失效缓存
+        write_file(&mut dev, &mut fs, "/f", 9216, &[3u8; 1024]).unwrap();
+        assert!(fs.extent_status.lookup(ino, &inode, 9).is_none());
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        let map = map_extent_range(&mut fs, &mut dev, ino, &mut inode, 8, 3).unwrap();
+        assert_eq!(layout(&map), vec![(8, 1, true, false), (9, 1, true, false), (10, 1, false, true)]);
+
+        // 根被改写（如截断为 0）时旧缓存自动丢弃
+        let stale = inode;
+        truncate(&mut dev, &mut fs, "/f", 0).unwrap();
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert_ne!(stale.i_block, inode.i_block);
+        let map = map_extent_range(&mut fs, &mut dev, ino, &mut inode, 0, 12).unwrap();
+        assert_eq!(layout(&map), vec![(0, 12, false, false)]);
+    }
+
+    #[test]
+    fn test_cache_memory_cap() {
+        let mut cache = ExtentStatusCache::new(4);
+        let a = Ext4Inode::default();
+        let mut b = Ext4Inode::default();
+        b.i_block[0] = 1;
+        for i in 0..3 {
+            cache.insert(11, &a, Ext4ExtentStatus::new(i * 10, 5, 100 + i, EXTENT_STATUS_WRITTEN));
+        }
+        cache.insert(12, &b, Ext4ExtentStatus::new(0, 5, 500, EXTENT_STATUS_WRITTEN));
+        assert_eq!(cache.len(), 4);
+        // 超过上限：最久未用的 inode 11 整体被淘汰
+        cache.insert(12, &b, Ext4ExtentStatus::new(10, 5, 600, EXTENT_STATUS_WRITTEN));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.lookup(11, &a, 0).is_none());
+        assert_eq!(cache.lookup(12, &b, 12).unwrap().pblk_of(12), Some(602));
+        // 重叠插入替换旧项
+        cache.insert(12, &b, Ext4ExtentStatus::new(3, 10, 0, EXTENT_STATUS_HOLE));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.lookup(12, &b, 12).unwrap().is_hole());
+        // extent 根变化时查询失效
+        assert!(cache.lookup(12, &a, 5).is_none());
+        assert!(cache.is_empty());
+    }
+}
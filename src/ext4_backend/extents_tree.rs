@@ -1,7 +1,7 @@
 use log::{debug, error};
 
 use crate::ext4_backend::blockdev::*;
-use crate::ext4_backend::config::*;
+use crate::ext4_backend::checksum::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
 use crate::ext4_backend::ext4::*;
@@ -47,6 +47,10 @@ impl ExtentNode {
 /// 绑定到单个 inode 的 extent 树视图（不持有 BlockDev，按需传入）
 pub struct ExtentTree<'a> {
     pub inode: &'a mut Ext4Inode,
+    /// metadata_csum 开启时的 inode 校验种子，用于外部 extent 块尾部校验和
+    csum_seed: Option<u32>,
+    /// inode 号（只读遍历时为 0），修改树时据此失效 extent 状态缓存
+    inode_num: u32,
 }
 
 /// 用于在递归插入时向上冒泡分裂信息
@@ -60,19 +64,40 @@ struct SplitInfo {
 impl<'a> ExtentTree<'a> {
     /// 构造：从给定 inode 开始操作其 extent 树
     pub fn new(inode: &'a mut Ext4Inode) -> Self {
-        Self { inode }
+        Self {
+            inode,
+            csum_seed: None,
+            inode_num: 0,
+        }
+    }
+
+    /// 构造：带 inode 号，开启 metadata_csum 时维护 extent 块的尾部校验和
+    pub fn new_with_ino(fs: &Ext4FileSystem, inode: &'a mut Ext4Inode, inode_num: u32) -> Self {
+        let csum_seed = fs.inode_csum_seed(inode_num, inode.i_generation);
+        Self { inode, csum_seed, inode_num }
+    }
+
+    /// 校验刚从磁盘读入的 extent 块
+    fn verify_node_csum(&self, block: &[u8]) -> BlockDevResult<()> {
+        match self.csum_seed {
+            Some(seed) if !verify_extent_block_csum(seed, block) => {
+                error!("extent block checksum mismatch");
+                Err(BlockDevError::ChecksumError)
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn add_inode_sectors_for_block(&mut self) {
-        let add_sectors = (BLOCK_SIZE / 512) as u64;
+    fn add_inode_sectors_for_block(&mut self, block_size: usize) {
+        let add_sectors = (block_size / 512) as u64;
         let cur = ((self.inode.l_i_blocks_high as u64) << 32) | (self.inode.i_blocks_lo as u64);
         let newv = cur.saturating_add(add_sectors);
         self.inode.i_blocks_lo = (newv & 0xFFFF_FFFF) as u32;
         self.inode.l_i_blocks_high = ((newv >> 32) & 0xFFFF) as u16;
     }
 
-    fn sub_inode_sectors_for_block(&mut self) {
-        let sub_sectors = (BLOCK_SIZE / 512) as u64;
+    fn sub_inode_sectors_for_block(&mut self, block_size: usize) {
+        let sub_sectors = (block_size / 512) as u64;
         let cur = ((self.inode.l_i_blocks_high as u64) << 32) | (self.inode.i_blocks_lo as u64);
         let newv = cur.saturating_sub(sub_sectors);
         self.inode.i_blocks_lo = (newv & 0xFFFF_FFFF) as u32;
@@ -247,6 +272,87 @@ impl<'a> ExtentTree<'a> {
         self.find_in_node(dev, &root, lblock)
     }
 
+    /// 按逻辑块顺序返回与 [lblk, lblk + len) 相交的叶子 extent，只读取与范围相交的子树
+    pub fn extents_in_range<B: BlockDevice>(
+        &mut self,
+        dev: &mut Jbd2Dev<B>,
+        lblk: u32,
+        len: u32,
+    ) -> BlockDevResult<Vec<Ext4Extent>> {
+        let mut out = Vec::new();
+        if let Some(root) = self.load_root_from_inode() {
+            let end = lblk as u64 + len as u64;
+            self.range_in_node(dev, &root, lblk as u64, end, &mut out)?;
+        }
+        out.sort_unstable_by_key(|e| e.ee_block);
+        Ok(out)
+    }
+
+    /// 返回树在 i_block 之外的全部节点块（索引块和叶子块）
+    pub fn node_blocks<B: BlockDevice>(&mut self, dev: &mut Jbd2Dev<B>) -> BlockDevResult<Vec<u64>> {
+        let mut out = Vec::new();
+        if let Some(root) = self.load_root_from_inode() {
+            self.node_blocks_in(dev, &root, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    fn node_blocks_in<B: BlockDevice>(
+        &mut self,
+        dev: &mut Jbd2Dev<B>,
+        node: &ExtentNode,
+        out: &mut Vec<u64>,
+    ) -> BlockDevResult<()> {
+        if let ExtentNode::Index { entries, .. } = node {
+            for idx in entries {
+                let child_block = ((idx.ei_leaf_hi as u64) << 32) | idx.ei_leaf_lo as u64;
+                out.push(child_block);
+                dev.read_block(child_block)?;
+                self.verify_node_csum(dev.buffer())?;
+                let child = Self::parse_node_from_bytes(dev.buffer()).ok_or(BlockDevError::Corrupted)?;
+                self.node_blocks_in(dev, &child, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn range_in_node<B: BlockDevice>(
+        &mut self,
+        dev: &mut Jbd2Dev<B>,
+        node: &ExtentNode,
+        start: u64,
+        end: u64,
+        out: &mut Vec<Ext4Extent>,
+    ) -> BlockDevResult<()> {
+        match node {
+            ExtentNode::Leaf { entries, .. } => {
+                out.extend(entries.iter().filter(|e| {
+                    let e_start = e.ee_block as u64;
+                    e.actual_len() != 0 && e_start < end && e_start + e.actual_len() as u64 > start
+                }));
+            }
+            ExtentNode::Index { entries, .. } => {
+                for (i, idx) in entries.iter().enumerate() {
+                    // 子树覆盖 [ei_block, 下一个 key)；第一个子树向前覆盖到 0
+                    let child_start = if i == 0 { 0 } else { idx.ei_block as u64 };
+                    let child_end = entries.get(i + 1).map_or(u64::MAX, |next| next.ei_block as u64);
+                    if child_end <= start {
+                        continue;
+                    }
+                    if child_start >= end {
+                        break;
+                    }
+                    let child_block = ((idx.ei_leaf_hi as u64) << 32) | idx.ei_leaf_lo as u64;
+                    dev.read_block(child_block)?;
+                    self.verify_node_csum(dev.buffer())?;
+                    let child = Self::parse_node_from_bytes(dev.buffer()).ok_or(BlockDevError::Corrupted)?;
+                    self.range_in_node(dev, &child, start, end, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// 在给定节点下查找逻辑块对应的 extent
     fn find_in_node<B: BlockDevice>(
         &mut self,
@@ -258,7 +364,7 @@ impl<'a> ExtentTree<'a> {
             ExtentNode::Leaf { entries, .. } => {
                 for et in entries {
                     let start = et.ee_block; // 逻辑起始块
-                    let len = et.ee_len as u32; // 覆盖长度
+                    let len = et.actual_len(); // 覆盖长度（去掉 unwritten 标志）
                     let end = start.saturating_add(len); // 半开区间 [start, end)
                     if lblock >= start && lblock < end {
                         return Ok(Some(*et));
@@ -288,8 +394,9 @@ impl<'a> ExtentTree<'a> {
                 );
 
                 // 读取子节点所在的物理块，并从块开头解析 extent 节点
-                dev.read_block(child_block as u32)?;
+                dev.read_block(child_block)?;
                 let buf = dev.buffer();
+                self.verify_node_csum(buf)?;
                 let child = match Self::parse_node_from_bytes(buf) {
                     Some(n) => n,
                     None => return Ok(None),
@@ -306,6 +413,9 @@ impl<'a> ExtentTree<'a> {
         deleted_ext: Ext4Extent,
         block_dev: &mut Jbd2Dev<B>,
     ) -> BlockDevResult<()> {
+        fs.extent_status.invalidate(self.inode_num);
+        // 截断/打洞后预分配窗口的逻辑位置不再可信
+        fs.mballoc.discard_inode(self.inode_num);
         let del_start = deleted_ext.ee_block;
         let del_len = (deleted_ext.ee_len as u32) & 0x7FFF;
         if del_len == 0 {
@@ -421,7 +531,7 @@ impl<'a> ExtentTree<'a> {
                         while idx_pos < entries.len() {
                             let child_phy = ((entries[idx_pos].ei_leaf_hi as u64) << 32)
                                 | (entries[idx_pos].ei_leaf_lo as u64);
-                            dev.read_block(child_phy as u32)?;
+                            dev.read_block(child_phy)?;
                             let child = ExtentTree::parse_node_from_bytes(dev.buffer())
                                 .ok_or(BlockDevError::Corrupted)?;
 
@@ -535,7 +645,7 @@ impl<'a> ExtentTree<'a> {
             entries: &mut Vec<Ext4Extent>,
             cur_lbn: u32,
             remaining: u32,
-            phy_block: Option<u32>,
+            phy_block: Option<u64>,
         ) -> BlockDevResult<StepRes> {
             if entries.is_empty() {
                 return Ok(StepRes {
@@ -619,7 +729,7 @@ impl<'a> ExtentTree<'a> {
                 let off = within_off as u64;
                 for j in 0..(cut_len as u64) {
                     fs.free_block(dev, base + off + j)?;
-                    tree.sub_inode_sectors_for_block();
+                    tree.sub_inode_sectors_for_block(fs.block_size());
                 }
             }
 
@@ -666,7 +776,7 @@ impl<'a> ExtentTree<'a> {
                     header: *header,
                     entries: entries.clone(),
                 };
-                ExtentTree::write_node_to_block(dev, block_id, &disk_node, header.eh_max)?;
+                ExtentTree::write_node_to_block(dev, block_id, &disk_node, header.eh_max, tree.csum_seed)?;
             }
 
             Ok(StepRes {
@@ -685,7 +795,7 @@ impl<'a> ExtentTree<'a> {
             node: &mut ExtentNode,
             cur_lbn: u32,
             remaining: u32,
-            phy_block: Option<u32>,
+            phy_block: Option<u64>,
         ) -> BlockDevResult<StepRes> {
             match node {
                 ExtentNode::Leaf { header, entries } =>
@@ -710,8 +820,9 @@ impl<'a> ExtentTree<'a> {
                     while idx_pos < entries.len() {
                         let child_phy = ((entries[idx_pos].ei_leaf_hi as u64) << 32)
                             | (entries[idx_pos].ei_leaf_lo as u64);
-                        dev.read_block(child_phy as u32)?;
+                        dev.read_block(child_phy)?;
                         let child_bytes = dev.buffer();
+                        tree.verify_node_csum(child_bytes)?;
                         let mut child_node =
                             ExtentTree::parse_node_from_bytes(child_bytes).ok_or(BlockDevError::Corrupted)?;
 
@@ -722,7 +833,7 @@ impl<'a> ExtentTree<'a> {
                             &mut child_node,
                             search_lbn,
                             remaining,
-                            Some(child_phy as u32),
+                            Some(child_phy),
                         )?;
 
                         match child_res.kind {
@@ -731,7 +842,7 @@ impl<'a> ExtentTree<'a> {
                                     entries.remove(idx_pos);
                                     header.eh_entries = entries.len() as u16;
                                     fs.free_block(dev, child_phy)?;
-                                    tree.sub_inode_sectors_for_block();
+                                    tree.sub_inode_sectors_for_block(fs.block_size());
                                 } else {
                                     entries[idx_pos].ei_block = child_res.first_key;
                                 }
@@ -744,7 +855,7 @@ impl<'a> ExtentTree<'a> {
                                         header: *header,
                                         entries: entries.clone(),
                                     };
-                                    ExtentTree::write_node_to_block(dev, block_id, &disk_node, header.eh_max)?;
+                                    ExtentTree::write_node_to_block(dev, block_id, &disk_node, header.eh_max, tree.csum_seed)?;
                                 }
 
                                 return Ok(StepRes {
@@ -843,8 +954,9 @@ impl<'a> ExtentTree<'a> {
 
                 if entries.len() == 1 {
                     let child_phy = ((entries[0].ei_leaf_hi as u64) << 32) | (entries[0].ei_leaf_lo as u64);
-                    block_dev.read_block(child_phy as u32)?;
+                    block_dev.read_block(child_phy)?;
                     let child_bytes = block_dev.buffer();
+                    self.verify_node_csum(child_bytes)?;
                     let mut child_node =
                         ExtentTree::parse_node_from_bytes(child_bytes).ok_or(BlockDevError::Corrupted)?;
 
@@ -864,7 +976,7 @@ impl<'a> ExtentTree<'a> {
                         self.store_root_to_inode(&child_node);
 
                         fs.free_block(block_dev, child_phy)?;
-                        self.sub_inode_sectors_for_block();
+                        self.sub_inode_sectors_for_block(fs.block_size());
                         return Ok(());
                     }
                 }
@@ -892,6 +1004,7 @@ impl<'a> ExtentTree<'a> {
             new_ext.ee_len & 0x7FFF,
             new_ext.start_block()
         );
+        fs.extent_status.invalidate(self.inode_num);
 
         let mut root = match self.load_root_from_inode() {
             Some(node) => node,
@@ -947,18 +1060,18 @@ impl<'a> ExtentTree<'a> {
 
                 // 分配一个新的块，将“左半部分”（即原本在 Root 里的数据）移到这个新块中
                 let new_left_block = fs.alloc_block(block_dev)?;
-                self.add_inode_sectors_for_block();
+                self.add_inode_sectors_for_block(fs.block_size());
                 debug!(
                     "ExtentTree::insert_extent: root split occurred, new_left_block={} split_info={{start_block={}, phy_block={}}}",
                     new_left_block, split_info.start_block, split_info.phy_block
                 );
 
                 // 计算普通块的 eh_max (通常 340)
-                let block_eh_max = Self::calc_block_eh_max();
+                let block_eh_max = Self::calc_block_eh_max(fs.block_size());
 
                 // 将当前的 root (左半部分) 写入新分配的物理块
                 // 注意：写入磁盘时要更新 eh_max，因为从 inode (max~4) 移到了 block (max~340)
-                Self::write_node_to_block(block_dev, new_left_block as u32, &root, block_eh_max)?;
+                Self::write_node_to_block(block_dev, new_left_block, &root, block_eh_max, self.csum_seed)?;
 
                 // 在 Inode 中构建新的 Root Index
                 let inline_bytes = self.inode.i_block.len() * 4;
@@ -1011,7 +1124,7 @@ impl<'a> ExtentTree<'a> {
         block_dev: &mut Jbd2Dev<B>,
         node: &mut ExtentNode,
         new_ext: Ext4Extent,
-        phy_block: Option<u32>,
+        phy_block: Option<u64>,
     ) -> BlockDevResult<Option<SplitInfo>> {
         match node {
             ExtentNode::Leaf { header, entries } => {
@@ -1029,94 +1142,65 @@ impl<'a> ExtentTree<'a> {
                     .binary_search_by_key(&new_ext.ee_block, |e| e.ee_block)
                     .unwrap_or_else(|i| i);
 
-                const MAX_LEN: u32 = 32768;
+                let mut new_ext = new_ext;
 
-                if pos > 0 {
+                // 只与前一个同类（都已初始化或都是 unwritten）且物理连续的 extent 合并
+                if pos > 0 && entries[pos - 1].is_initialized() == new_ext.is_initialized() {
                     let prev = &mut entries[pos - 1];
 
                     let prev_logical = prev.ee_block;
-                    let prev_len = prev.ee_len as u32 & 0x7FFF;
+                    let prev_len = prev.actual_len();
                     let new_logical = new_ext.ee_block;
-                    let new_len = new_ext.ee_len as u32 & 0x7FFF;
+                    let new_len = new_ext.actual_len();
+                    let max_len = prev.max_len();
+                    let unwritten = !prev.is_initialized();
 
                     if prev_len != 0 && new_len != 0 {
                         let prev_end = prev_logical.saturating_add(prev_len);
 
                         if new_logical == prev_end {
-                            let prev_phys_start =
-                                ((prev.ee_start_hi as u64) << 32) | prev.ee_start_lo as u64;
-                            let new_phys_start =
-                                ((new_ext.ee_start_hi as u64) << 32) | new_ext.ee_start_lo as u64;
+                            let prev_phys_start = prev.start_block();
+                            let new_phys_start = new_ext.start_block();
 
                             if new_phys_start == prev_phys_start + prev_len as u64 {
                                 let total = prev_len + new_len;
-                                let hi_flag = prev.ee_len & 0x8000; // 保留原高位标志
 
-                                if total <= MAX_LEN {
-                                    prev.ee_len = (total as u16 & 0x7FFF) | hi_flag;
+                                if total <= max_len {
+                                    prev.set_len(total, unwritten);
                                     debug!(
                                         "insert_recursive: merged with previous extent -> new_len={total} (no split yet)"
                                     );
 
-                                    if entries.len() <= header.eh_max as usize {
-                                        if let Some(block_id) = phy_block {
-                                            // 为当前叶子节点构造一个临时 ExtentNode 写回磁盘
-                                            let disk_node = ExtentNode::Leaf {
-                                                header: *header,
-                                                entries: entries.clone(),
-                                            };
-                                            Self::write_node_to_block(
-                                                block_dev,
-                                                block_id,
-                                                &disk_node,
-                                                header.eh_max,
-                                            )?;
-                                        }
-                                        return Ok(None);
-                                    }
-                                } else {
-                                    prev.ee_len = (MAX_LEN as u16 & 0x7FFF) | hi_flag;
-
-                                    let remain = total - MAX_LEN;
-                                    if remain > 0 {
-                                        let tail_logical = prev_logical + MAX_LEN;
-                                        let tail_phys = prev_phys_start + MAX_LEN as u64;
-
-                                        let tail = Ext4Extent {
-                                            ee_block: tail_logical,
-                                            ee_len: (remain as u16 & 0x7FFF)
-                                                | (new_ext.ee_len & 0x8000),
-                                            ee_start_hi: (tail_phys >> 32) as u16,
-                                            ee_start_lo: (tail_phys & 0xFFFF_FFFF) as u32,
+                                    if let Some(block_id) = phy_block {
+                                        // 为当前叶子节点构造一个临时 ExtentNode 写回磁盘
+                                        let disk_node = ExtentNode::Leaf {
+                                            header: *header,
+                                            entries: entries.clone(),
                                         };
-
-                                        let insert_pos = pos; // 在 pos 处插入新 extent
-                                        entries.insert(insert_pos, tail);
-                                        header.eh_entries = entries.len() as u16;
-                                        debug!(
-                                            "insert_recursive: previous extent saturated MAX_LEN, inserted tail extent (lbn={}, len={}, phys_start={}) now entries_len={}",
-                                            tail.ee_block,
-                                            tail.ee_len & 0x7FFF,
-                                            tail.start_block(),
-                                            header.eh_entries
-                                        );
-
-                                        if entries.len() <= header.eh_max as usize {
-                                            if let Some(block_id) = phy_block {
-                                                let disk_node = ExtentNode::Leaf {
-                                                    header: *header,
-                                                    entries: entries.clone(),
-                                                };
-                                                Self::write_node_to_block(
-                                                    block_dev,
-                                                    block_id,
-                                                    &disk_node,
-                                                    header.eh_max,
-                                                )?;
-                                            }
-                                            return Ok(None);
-                                        }
+                                        Self::write_node_to_block(
+                                            block_dev,
+                                            block_id,
+                                            &disk_node,
+                                            header.eh_max,
+                                            self.csum_seed,
+                                        )?;
                                     }
+                                    return Ok(None);
+                                } else if prev_len < max_len {
+                                    // 前一个 extent 填满，剩余部分作为新的 extent 插入
+                                    prev.set_len(max_len, unwritten);
+                                    let take = max_len - prev_len;
+                                    new_ext.ee_block += take;
+                                    let tail_phys = new_phys_start + take as u64;
+                                    new_ext.ee_start_hi = (tail_phys >> 32) as u16;
+                                    new_ext.ee_start_lo = tail_phys as u32;
+                                    new_ext.set_len(new_len - take, unwritten);
+                                    debug!(
+                                        "insert_recursive: previous extent saturated, inserting tail extent (lbn={}, len={}, phys_start={})",
+                                        new_ext.ee_block,
+                                        new_ext.actual_len(),
+                                        new_ext.start_block()
+                                    );
                                 }
                             }
                         }
@@ -1144,7 +1228,7 @@ impl<'a> ExtentTree<'a> {
                             header: *header,
                             entries: entries.clone(),
                         };
-                        Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max)?;
+                        Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max, self.csum_seed)?;
                     }
                     // Root 节点由调用方负责写回 Inode，这里返回 None
                     return Ok(None);
@@ -1164,7 +1248,7 @@ impl<'a> ExtentTree<'a> {
 
                 // 分配新块用于存储右半部分
                 let new_phy_block = fs.alloc_block(block_dev)?;
-                self.add_inode_sectors_for_block();
+                self.add_inode_sectors_for_block(fs.block_size());
                 debug!(
                     "insert_recursive: allocated new block for right leaf node: {new_phy_block}"
                 );
@@ -1173,7 +1257,7 @@ impl<'a> ExtentTree<'a> {
                 let right_header = Ext4ExtentHeader {
                     eh_magic: Ext4ExtentHeader::EXT4_EXT_MAGIC,
                     eh_entries: right_entries.len() as u16,
-                    eh_max: Self::calc_block_eh_max(), // 新块一定是在磁盘上的，使用标准容量
+                    eh_max: Self::calc_block_eh_max(fs.block_size()), // 新块一定是在磁盘上的，使用标准容量
                     eh_depth: 0,                       // 依然是 Leaf
                     eh_generation: 0,
                 };
@@ -1186,9 +1270,10 @@ impl<'a> ExtentTree<'a> {
                 // 写右节点（新块）
                 Self::write_node_to_block(
                     block_dev,
-                    new_phy_block as u32,
+                    new_phy_block,
                     &right_node,
                     right_header.eh_max,
+                    self.csum_seed,
                 )?;
                 // 写左节点（当前节点）
                 // 如果当前节点是普通块，写回磁盘；如果是 Root，调用方会处理，但这里我们要在内存中保持正确状态
@@ -1197,7 +1282,7 @@ impl<'a> ExtentTree<'a> {
                         header: *header,
                         entries: entries.clone(),
                     };
-                    Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max)?;
+                    Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max, self.csum_seed)?;
                 }
 
                 //返回分裂信息
@@ -1239,8 +1324,9 @@ impl<'a> ExtentTree<'a> {
                 let child_phy_block = ((entries[idx_pos].ei_leaf_hi as u64) << 32)
                     | (entries[idx_pos].ei_leaf_lo as u64);
                 // 读取子节点
-                block_dev.read_block(child_phy_block as u32)?;
+                block_dev.read_block(child_phy_block)?;
                 let child_bytes = block_dev.buffer();
+                self.verify_node_csum(child_bytes)?;
                 let mut child_node =
                     Self::parse_node_from_bytes(child_bytes).expect("Can't parse node from bytes!");
 
@@ -1250,7 +1336,7 @@ impl<'a> ExtentTree<'a> {
                     block_dev,
                     &mut child_node,
                     new_ext,
-                    Some(child_phy_block as u32),
+                    Some(child_phy_block),
                 )?;
 
                 //  处理子节点返回的结果
@@ -1284,6 +1370,7 @@ impl<'a> ExtentTree<'a> {
                                 block_id,
                                 &disk_node,
                                 header.eh_max,
+                                self.csum_seed,
                             )?;
                         }
                         return Ok(None);
@@ -1304,7 +1391,7 @@ impl<'a> ExtentTree<'a> {
 
                     // 分配新块
                     let new_phy_block = fs.alloc_block(block_dev)?;
-                    self.add_inode_sectors_for_block();
+                    self.add_inode_sectors_for_block(fs.block_size());
                     debug!(
                         "insert_recursive: allocated new block for right index node: {new_phy_block}"
                     );
@@ -1312,7 +1399,7 @@ impl<'a> ExtentTree<'a> {
                     let right_header = Ext4ExtentHeader {
                         eh_magic: Ext4ExtentHeader::EXT4_EXT_MAGIC,
                         eh_entries: right_entries.len() as u16,
-                        eh_max: Self::calc_block_eh_max(),
+                        eh_max: Self::calc_block_eh_max(fs.block_size()),
                         eh_depth: header.eh_depth, // 保持相同的 depth
                         eh_generation: 0,
                     };
@@ -1325,16 +1412,17 @@ impl<'a> ExtentTree<'a> {
                     // 写回
                     Self::write_node_to_block(
                         block_dev,
-                        new_phy_block as u32,
+                        new_phy_block,
                         &right_node,
                         right_header.eh_max,
+                        self.csum_seed,
                     )?;
                     if let Some(block_id) = phy_block {
                         let disk_node = ExtentNode::Index {
                             header: *header,
                             entries: entries.clone(),
                         };
-                        Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max)?;
+                        Self::write_node_to_block(block_dev, block_id, &disk_node, header.eh_max, self.csum_seed)?;
                     }
 
                     // 返回分裂信息
@@ -1356,12 +1444,196 @@ impl<'a> ExtentTree<'a> {
         }
     }
 
+    /// 把 [lblk, lblk + len) 中 unwritten extent 覆盖的部分标记为已写入
+    pub fn convert_to_written<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        lblk: u32,
+        len: u32,
+    ) -> BlockDevResult<()> {
+        self.convert_range(fs, block_dev, lblk, len, false)
+    }
+
+    /// 把 [lblk, lblk + len) 中已写入 extent 覆盖的部分标记为 unwritten（内容按 0 读出）
+    pub fn convert_to_unwritten<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        lblk: u32,
+        len: u32,
+    ) -> BlockDevResult<()> {
+        self.convert_range(fs, block_dev, lblk, len, true)
+    }
+
+    /// 原 extent 按需拆成保持原状态的前段、转换后的中段和保持原状态的后段；
+    /// 已是目标状态的 extent 和空洞跳过
+    fn convert_range<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        lblk: u32,
+        len: u32,
+        unwritten: bool,
+    ) -> BlockDevResult<()> {
+        fs.extent_status.invalidate(self.inode_num);
+        let end = lblk.saturating_add(len);
+        let mut cur = lblk;
+        while cur < end {
+            let Some(ext) = self.find_extent(block_dev, cur)? else {
+                cur += 1;
+                continue;
+            };
+            let e_start = ext.ee_block;
+            let e_end = e_start + ext.actual_len();
+            let mid_end = end.min(e_end);
+            if ext.is_initialized() != unwritten {
+                cur = mid_end;
+                continue;
+            }
+            if unwritten && mid_end - cur > Ext4Extent::EXT_UNINIT_MAX_LEN as u32 {
+                // 满长的已初始化 extent 装不进一个 unwritten extent
+                return Err(BlockDevError::Unsupported);
+            }
+
+            let piece = |start: u32, stop: u32, unwritten: bool| {
+                let mut e = Ext4Extent::new(start, ext.start_block() + (start - e_start) as u64, 0);
+                e.set_len(stop - start, unwritten);
+                e
+            };
+            let mid = piece(cur, mid_end, unwritten);
+            // 起始逻辑块不变，原地替换不影响上层索引的 key
+            if cur > e_start {
+                self.replace_extent(fs, block_dev, e_start, piece(e_start, cur, !unwritten))?;
+                self.insert_extent(fs, mid, block_dev)?;
+            } else {
+                self.replace_extent(fs, block_dev, e_start, mid)?;
+            }
+            if mid_end < e_end {
+                self.insert_extent(fs, piece(mid_end, e_end, !unwritten), block_dev)?;
+            }
+            debug!("convert_range: lbn {cur}..{mid_end} of extent {e_start}..{e_end} unwritten={unwritten}");
+            cur = mid_end;
+        }
+        Ok(())
+    }
+
+    /// 把起始逻辑块不小于 `start` 的 extent 整体平移 `delta` 个逻辑块，并同步各级索引的 key。
+    /// 调用方保证平移后不与其它 extent 重叠
+    pub fn shift_extents<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        start: u32,
+        delta: i64,
+    ) -> BlockDevResult<()> {
+        fs.extent_status.invalidate(self.inode_num);
+        let mut root = self.load_root_from_inode().ok_or(BlockDevError::Corrupted)?;
+        self.shift_in_node(block_dev, &mut root, None, start, delta)?;
+        self.store_root_to_inode(&root);
+        Ok(())
+    }
+
+    fn shift_in_node<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        node: &mut ExtentNode,
+        phy_block: Option<u64>,
+        start: u32,
+        delta: i64,
+    ) -> BlockDevResult<()> {
+        match node {
+            ExtentNode::Leaf { entries, .. } => {
+                let mut changed = false;
+                for e in entries.iter_mut().filter(|e| e.ee_block >= start) {
+                    e.ee_block = (e.ee_block as i64 + delta) as u32;
+                    changed = true;
+                }
+                if !changed {
+                    return Ok(());
+                }
+            }
+            ExtentNode::Index { entries, .. } => {
+                for i in 0..entries.len() {
+                    // 子树覆盖 [ei_block, 下一个 key)，整体在 start 之前的不用动
+                    if entries.get(i + 1).is_some_and(|next| next.ei_block <= start) {
+                        continue;
+                    }
+                    let child_block = ((entries[i].ei_leaf_hi as u64) << 32) | entries[i].ei_leaf_lo as u64;
+                    block_dev.read_block(child_block)?;
+                    self.verify_node_csum(block_dev.buffer())?;
+                    let mut child =
+                        Self::parse_node_from_bytes(block_dev.buffer()).ok_or(BlockDevError::Corrupted)?;
+                    self.shift_in_node(block_dev, &mut child, Some(child_block), start, delta)?;
+                    entries[i].ei_block = Self::get_node_start_block(&child);
+                }
+            }
+        }
+        if let Some(block_id) = phy_block {
+            let eh_max = node.header().eh_max;
+            Self::write_node_to_block(block_dev, block_id, node, eh_max, self.csum_seed)?;
+        }
+        Ok(())
+    }
+
+    /// 把起始逻辑块为 `start` 的叶子 extent 原地替换为 `new_ext`（两者起始块必须相同）
+    pub fn replace_extent<B: BlockDevice>(
+        &mut self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        start: u32,
+        new_ext: Ext4Extent,
+    ) -> BlockDevResult<()> {
+        fs.extent_status.invalidate(self.inode_num);
+        let mut root = self.load_root_from_inode().ok_or(BlockDevError::Corrupted)?;
+        self.replace_in_node(block_dev, &mut root, None, start, new_ext)?;
+        if root.is_leaf() {
+            self.store_root_to_inode(&root);
+        }
+        Ok(())
+    }
+
+    fn replace_in_node<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        node: &mut ExtentNode,
+        phy_block: Option<u64>,
+        start: u32,
+        new_ext: Ext4Extent,
+    ) -> BlockDevResult<()> {
+        match node {
+            ExtentNode::Leaf { header, entries } => {
+                let slot = entries
+                    .iter_mut()
+                    .find(|e| e.ee_block == start)
+                    .ok_or(BlockDevError::Corrupted)?;
+                *slot = new_ext;
+                if let Some(block_id) = phy_block {
+                    let eh_max = header.eh_max;
+                    Self::write_node_to_block(block_dev, block_id, node, eh_max, self.csum_seed)?;
+                }
+                Ok(())
+            }
+            ExtentNode::Index { entries, .. } => {
+                let pp = entries.partition_point(|idx| idx.ei_block <= start);
+                let idx = entries.get(pp.saturating_sub(1)).ok_or(BlockDevError::Corrupted)?;
+                let child_block = ((idx.ei_leaf_hi as u64) << 32) | idx.ei_leaf_lo as u64;
+                block_dev.read_block(child_block)?;
+                self.verify_node_csum(block_dev.buffer())?;
+                let mut child =
+                    Self::parse_node_from_bytes(block_dev.buffer()).ok_or(BlockDevError::Corrupted)?;
+                self.replace_in_node(block_dev, &mut child, Some(child_block), start, new_ext)
+            }
+        }
+    }
+
     /// 通用的写节点到物理块函数
     fn write_node_to_block<B: BlockDevice>(
         dev: &mut Jbd2Dev<B>,
-        block_id: u32,
+        block_id: u64,
         node: &ExtentNode,
         eh_max: u16,
+        csum_seed: Option<u32>,
     ) -> BlockDevResult<()> {
         let hdr_size = Ext4ExtentHeader::disk_size();
         // 读取块
@@ -1402,16 +1674,19 @@ impl<'a> ExtentTree<'a> {
                 }
             }
         }
+        if let Some(seed) = csum_seed {
+            set_extent_block_csum(seed, buf);
+        }
         // 标记脏并写回
         dev.write_block(block_id, true)?;
         Ok(())
     }
 
     /// 计算标准数据块能容纳的条目数
-    fn calc_block_eh_max() -> u16 {
+    fn calc_block_eh_max(block_size: usize) -> u16 {
         let hdr_size = Ext4ExtentHeader::disk_size();
         let entry_size = Ext4Extent::disk_size(); // Index 和 Extent 大小一样，都是 12
-        (BLOCK_SIZE.saturating_sub(hdr_size) / entry_size) as u16
+        (block_size.saturating_sub(hdr_size) / entry_size) as u16
     }
 
     /// 辅助：获取节点的起始逻辑块号
@@ -1442,29 +1717,32 @@ mod tests {
     use super::*;
     use crate::ext4_backend::blockdev::{BlockDevice, Jbd2Dev};
     use crate::ext4_backend::bitmap_cache::CacheKey;
+    use crate::ext4_backend::config::BLOCK_SIZE;
     use crate::ext4_backend::ext4::{mkfs, mount};
     use crate::ext4_backend::error::{BlockDevError, BlockDevResult};
     use alloc::vec;
     use alloc::vec::Vec;
 
+    /// 以 512 字节扇区为单位的内存块设备
     struct MemBlockDev {
         data: Vec<u8>,
         total_blocks: u64,
     }
 
+    const SECTOR_SIZE: usize = 512;
+
     impl MemBlockDev {
-        fn new(total_blocks: u64) -> Self {
-            let size = total_blocks as usize * BLOCK_SIZE;
+        fn new(size: usize) -> Self {
             Self {
                 data: vec![0u8; size],
-                total_blocks,
+                total_blocks: (size / SECTOR_SIZE) as u64,
             }
         }
     }
 
     impl BlockDevice for MemBlockDev {
-        fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-            let block_size = BLOCK_SIZE;
+        fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+            let block_size = SECTOR_SIZE;
             let required = block_size * count as usize;
             if buffer.len() < required {
                 return Err(BlockDevError::BufferTooSmall {
@@ -1484,8 +1762,8 @@ mod tests {
             Ok(())
         }
 
-        fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
-            let block_size = BLOCK_SIZE;
+        fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+            let block_size = SECTOR_SIZE;
             let required = block_size * count as usize;
             if buffer.len() < required {
                 return Err(BlockDevError::BufferTooSmall {
@@ -1518,14 +1796,21 @@ mod tests {
         }
 
         fn block_size(&self) -> u32 {
-            BLOCK_SIZE as u32
+            SECTOR_SIZE as u32
         }
     }
 
     fn setup_fs(total_blocks: u64) -> (Jbd2Dev<MemBlockDev>, Ext4FileSystem) {
-        let dev = MemBlockDev::new(total_blocks);
-        let mut jbd = Jbd2Dev::initial_jbd2dev(0, dev, false);
-        mkfs(&mut jbd).unwrap();
+        setup_fs_with_block_size(total_blocks, BLOCK_SIZE)
+    }
+
+    fn setup_fs_with_block_size(
+        total_blocks: u64,
+        block_size: usize,
+    ) -> (Jbd2Dev<MemBlockDev>, Ext4FileSystem) {
+        let dev = MemBlockDev::new(total_blocks as usize * block_size);
+        let mut jbd = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, dev, false);
+        mkfs(&mut jbd, block_size).unwrap();
         let fs = mount(&mut jbd).unwrap();
         (jbd, fs)
     }
@@ -1612,7 +1897,7 @@ mod tests {
                 ExtentNode::Index { entries, .. } => {
                     for idx in entries {
                         let child_phy = ((idx.ei_leaf_hi as u64) << 32) | (idx.ei_leaf_lo as u64);
-                        dev.read_block(child_phy as u32).unwrap();
+                        dev.read_block(child_phy).unwrap();
                         let child =
                             ExtentTree::parse_node_from_bytes(dev.buffer()).expect("parse child");
                         walk(dev, &child, out);
@@ -1629,6 +1914,30 @@ mod tests {
         out
     }
 
+    #[test]
+    fn insert_and_remove_extents_on_1k_block_fs() {
+        let (mut dev, mut fs) = setup_fs_with_block_size(64 * 1024, 1024);
+        assert_eq!(fs.block_size(), 1024);
+        assert_eq!(fs.superblock.s_first_data_block, 1);
+        let mut inode = new_extent_inode();
+
+        // 1K 块的叶子节点最多 84 个条目，插入更多以触发块内节点分裂
+        let exts = insert_n_extents_with_phys_gaps(&mut fs, &mut dev, &mut inode, 200);
+        let got = collect_extents_from_inode(&mut inode, &mut dev);
+        assert_eq!(got.len(), exts.len());
+        for (a, b) in got.iter().zip(exts.iter()) {
+            assert_eq!(a.ee_block, b.ee_block);
+            assert_eq!(a.start_block(), b.start_block());
+        }
+
+        for ext in exts {
+            let mut tree = ExtentTree::new(&mut inode);
+            tree.remove_extend(&mut fs, ext, &mut dev).unwrap();
+            assert!(!bitmap_block_is_allocated(&mut fs, &mut dev, ext.start_block()));
+        }
+        assert!(collect_extents_from_inode(&mut inode, &mut dev).is_empty());
+    }
+
     #[test]
     fn remove_extend_root_leaf_no_degeneration() {
         let (mut dev, mut fs) = setup_fs(16 * 1024);
@@ -0,0 +1,665 @@
+//! 预分配（fallocate）与文件中间范围的操作
+//!
+//! 预分配为文件的一段范围分配尽量连续的物理块，记录为 unwritten extent：
+//! 读取时按空洞返回 0，写入时由 [`write_file_blocks`] 清零整块并转换为已写入。
+//! 另外提供 punch_hole / zero_range / collapse_range / insert_range，
+//! 在文件中间释放、清零、删除或插入一段范围。这些操作都只支持 extent 映射的普通文件。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::config::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::extent_status::*;
+use crate::ext4_backend::extents_tree::*;
+use crate::ext4_backend::file::*;
+use crate::ext4_backend::inline_data::*;
+use crate::ext4_backend::loopfile::*;
+use alloc::vec::Vec;
+use log::debug;
+
+/// 不修改文件大小，超出 EOF 的部分只分配块
+pub const FALLOC_FL_KEEP_SIZE: u32 = 0x01;
+/// 释放范围内的块，必须与 KEEP_SIZE 一起使用
+pub const FALLOC_FL_PUNCH_HOLE: u32 = 0x02;
+/// 删除范围并把后面的内容前移，范围必须按块对齐
+pub const FALLOC_FL_COLLAPSE_RANGE: u32 = 0x08;
+/// 把范围清零（整块转为 unwritten）并保证已分配
+pub const FALLOC_FL_ZERO_RANGE: u32 = 0x10;
+/// 在 offset 处插入空洞并把后面的内容后移，范围必须按块对齐
+pub const FALLOC_FL_INSERT_RANGE: u32 = 0x20;
+
+/// 释放或转换块时每个事务处理的最大块数
+const EXTENT_OP_CHUNK: u32 = 1024;
+
+/// 按 mode 分派：0 或 KEEP_SIZE 为预分配，其余对应 punch_hole / zero_range / collapse_range / insert_range
+pub fn fallocate<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+    mode: u32,
+) -> BlockDevResult<()> {
+    let keep_size = mode & FALLOC_FL_KEEP_SIZE != 0;
+    match mode & !FALLOC_FL_KEEP_SIZE {
+        0 => preallocate(block_dev, fs, inode_num, offset, len, keep_size),
+        FALLOC_FL_PUNCH_HOLE if keep_size => punch_hole(block_dev, fs, inode_num, offset, len),
+        FALLOC_FL_ZERO_RANGE => zero_range(block_dev, fs, inode_num, offset, len, keep_size),
+        FALLOC_FL_COLLAPSE_RANGE if !keep_size => collapse_range(block_dev, fs, inode_num, offset, len),
+        FALLOC_FL_INSERT_RANGE if !keep_size => insert_range(block_dev, fs, inode_num, offset, len),
+        FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_INSERT_RANGE => Err(BlockDevError::InvalidInput),
+        _ => Err(BlockDevError::Unsupported),
+    }
+}
+
+/// 为 inode 的 [offset, offset + len) 预分配数据块。已映射的块保持不变；
+/// 未指定 keep_size 时文件大小至少扩展到 offset + len。
+/// 空间不足时已分配的部分保留，返回 NoSpace
+pub fn preallocate<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> BlockDevResult<()> {
+    let end = check_range(fs, offset, len)?;
+    prepare_extent_inode(fs, block_dev, inode_num)?;
+    let block_bytes = fs.block_size() as u64;
+    alloc_range(fs, block_dev, inode_num, (offset / block_bytes) as u32, end.div_ceil(block_bytes) as u32)?;
+    finish(fs, block_dev, inode_num, |inode| {
+        if !keep_size && end > inode.size() {
+            inode.i_size_lo = end as u32;
+            inode.i_size_high = (end >> 32) as u32;
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// 释放 [offset, offset + len) 内的整块，两端不满一块的部分清零；文件大小不变
+pub fn punch_hole<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+) -> BlockDevResult<()> {
+    let end = check_range(fs, offset, len)?;
+    prepare_extent_inode(fs, block_dev, inode_num)?;
+    let size = fs.get_inode_by_num(block_dev, inode_num)?.size();
+    if offset >= size {
+        return Ok(());
+    }
+    let block_bytes = fs.block_size() as u64;
+    // 打到 EOF 时最后一个块整体释放，EOF 之后的预分配块保留
+    let end = if end >= size { size.next_multiple_of(block_bytes) } else { end };
+    zero_partial_blocks(fs, block_dev, inode_num, offset, end)?;
+    free_range(fs, block_dev, inode_num, offset.div_ceil(block_bytes) as u32, (end / block_bytes) as u32)?;
+    finish(fs, block_dev, inode_num, |_| true)
+}
+
+/// 把 [offset, offset + len) 清零：整块转为 unwritten，空洞补上 unwritten 块，两端不满一块的部分写 0。
+/// 未指定 keep_size 时文件大小至少扩展到 offset + len
+pub fn zero_range<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+    keep_size: bool,
+) -> BlockDevResult<()> {
+    let end = check_range(fs, offset, len)?;
+    prepare_extent_inode(fs, block_dev, inode_num)?;
+    let block_bytes = fs.block_size() as u64;
+    zero_partial_blocks(fs, block_dev, inode_num, offset, end)?;
+
+    let first_full = offset.div_ceil(block_bytes) as u32;
+    let last_full = (end / block_bytes) as u32;
+    let mut lbn = first_full;
+    while lbn < last_full {
+        let count = (last_full - lbn).min(EXTENT_OP_CHUNK);
+        fs.journaled(block_dev, extent_trans_blocks(fs, count), |fs, block_dev| {
+            let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+            ExtentTree::new_with_ino(fs, &mut inode, inode_num).convert_to_unwritten(fs, block_dev, lbn, count)?;
+            fs.modify_inode(block_dev, inode_num, |td| *td = inode)?;
+            fs.fast_commit.mark_ineligible();
+            Ok(())
+        })??;
+        lbn += count;
+    }
+
+    alloc_range(fs, block_dev, inode_num, (offset / block_bytes) as u32, end.div_ceil(block_bytes) as u32)?;
+    finish(fs, block_dev, inode_num, |inode| {
+        if !keep_size && end > inode.size() {
+            inode.i_size_lo = end as u32;
+            inode.i_size_high = (end >> 32) as u32;
+        }
+        true
+    })
+}
+
+/// 删除 [offset, offset + len) 并把后面的内容前移，文件大小减少 len。
+/// offset 和 len 必须按块对齐，且范围必须在 EOF 之前结束
+pub fn collapse_range<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+) -> BlockDevResult<()> {
+    let end = check_range(fs, offset, len)?;
+    let block_bytes = fs.block_size() as u64;
+    if offset % block_bytes != 0 || len % block_bytes != 0 {
+        return Err(BlockDevError::InvalidInput);
+    }
+    prepare_extent_inode(fs, block_dev, inode_num)?;
+    if end >= fs.get_inode_by_num(block_dev, inode_num)?.size() {
+        return Err(BlockDevError::InvalidInput);
+    }
+    let start_lbn = (offset / block_bytes) as u32;
+    let end_lbn = (end / block_bytes) as u32;
+    free_range(fs, block_dev, inode_num, start_lbn, end_lbn)?;
+
+    let credits = shift_trans_blocks(fs, block_dev, inode_num)?;
+    fs.journaled(block_dev, credits, |fs, block_dev| {
+        let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+        ExtentTree::new_with_ino(fs, &mut inode, inode_num).shift_extents(
+            fs,
+            block_dev,
+            end_lbn,
+            -((end_lbn - start_lbn) as i64),
+        )?;
+        let size = inode.size() - len;
+        inode.i_size_lo = size as u32;
+        inode.i_size_high = (size >> 32) as u32;
+        fs.modify_inode(block_dev, inode_num, |td| *td = inode)?;
+        fs.fast_commit.mark_ineligible();
+        Ok(())
+    })??;
+    finish(fs, block_dev, inode_num, |_| true)
+}
+
+/// 在 offset 处插入 len 字节的空洞，原来从 offset 开始的内容后移，文件大小增加 len。
+/// offset 和 len 必须按块对齐，且 offset 必须在 EOF 之前
+pub fn insert_range<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    len: u64,
+) -> BlockDevResult<()> {
+    check_range(fs, offset, len)?;
+    let block_bytes = fs.block_size() as u64;
+    if offset % block_bytes != 0 || len % block_bytes != 0 {
+        return Err(BlockDevError::InvalidInput);
+    }
+    prepare_extent_inode(fs, block_dev, inode_num)?;
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let size = inode.size();
+    if offset >= size {
+        return Err(BlockDevError::InvalidInput);
+    }
+    let lbn = (offset / block_bytes) as u32;
+    let delta = (len / block_bytes) as u32;
+    // 后移后的最后一个块和新的文件大小都必须还能表示
+    let last_end = resolve_inode_extents(fs, block_dev, inode_num, &mut inode)?
+        .last()
+        .map_or(0, |e| e.ee_block as u64 + e.actual_len() as u64);
+    let new_size = size.checked_add(len).ok_or(BlockDevError::InvalidInput)?;
+    if last_end + delta as u64 > u32::MAX as u64 || new_size.div_ceil(block_bytes) > u32::MAX as u64 {
+        return Err(BlockDevError::InvalidInput);
+    }
+
+    let credits = shift_trans_blocks(fs, block_dev, inode_num)?;
+    fs.journaled(block_dev, credits, |fs, block_dev| {
+        let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+        let mut tree = ExtentTree::new_with_ino(fs, &mut inode, inode_num);
+        // 跨过 offset 的 extent 拆成两段，后段随其它 extent 一起后移
+        let straddle = tree.find_extent(block_dev, lbn)?.filter(|e| e.ee_block < lbn);
+        tree.shift_extents(fs, block_dev, lbn, delta as i64)?;
+        if let Some(ext) = straddle {
+            let unwritten = !ext.is_initialized();
+            let head_len = lbn - ext.ee_block;
+            let mut head = ext;
+            head.set_len(head_len, unwritten);
+            tree.replace_extent(fs, block_dev, ext.ee_block, head)?;
+            let mut tail = Ext4Extent::new(lbn + delta, ext.start_block() + head_len as u64, 0);
+            tail.set_len(ext.actual_len() - head_len, unwritten);
+            tree.insert_extent(fs, tail, block_dev)?;
+        }
+        inode.i_size_lo = new_size as u32;
+        inode.i_size_high = (new_size >> 32) as u32;
+        fs.modify_inode(block_dev, inode_num, |td| *td = inode)?;
+        fs.fast_commit.mark_ineligible();
+        Ok(())
+    })??;
+    finish(fs, block_dev, inode_num, |_| true)
+}
+
+/// 检查范围，返回结束偏移
+fn check_range(fs: &Ext4FileSystem, offset: u64, len: u64) -> BlockDevResult<u64> {
+    let end = offset.checked_add(len).ok_or(BlockDevError::InvalidInput)?;
+    if len == 0 || end.div_ceil(fs.block_size() as u64) > u32::MAX as u64 {
+        return Err(BlockDevError::InvalidInput);
+    }
+    Ok(end)
+}
+
+/// 保证 inode 是使用 extent 映射的普通文件：内联数据搬到数据块，空 inode 写入 extent 头
+fn prepare_extent_inode<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<()> {
+    let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    if !inode.is_file() {
+        return Err(BlockDevError::InvalidInput);
+    }
+    if inode.have_extend_header_and_use_extend() {
+        return Ok(());
+    }
+    fs.journaled(block_dev, EXT4_DATA_TRANS_BLOCKS, |fs, block_dev| {
+        let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+        if inode.has_inline_data() {
+            let old = uninline_data(fs, block_dev, inode_num)?;
+            if !old.is_empty() {
+                write_file_blocks(block_dev, fs, inode_num, 0, &old)?;
+            }
+        }
+        let has_extents = fs.superblock.has_extents();
+        let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+        if inode.have_extend_header_and_use_extend() {
+            return Ok(());
+        }
+        if !has_extents || inode.i_block.iter().any(|&w| w != 0) {
+            // 传统间接映射无法表示 unwritten 块
+            return Err(BlockDevError::Unsupported);
+        }
+        fs.modify_inode(block_dev, inode_num, |inode| {
+            inode.i_flags |= Ext4Inode::EXT4_EXTENTS_FL;
+            inode.write_extend_header();
+        })
+    })?
+}
+
+/// 最后一个事务：按 `update` 修改 inode（返回是否改动了内容），内容改动时更新 mtime，总是更新 ctime
+fn finish<B, F>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>, inode_num: u32, update: F) -> BlockDevResult<()>
+where
+    B: BlockDevice,
+    F: FnOnce(&mut Ext4Inode) -> bool,
+{
+    fs.journaled(block_dev, EXT4_INODE_TRANS_BLOCKS, |fs, block_dev| {
+        let now = fs.now();
+        fs.modify_inode(block_dev, inode_num, |inode| {
+            if update(inode) {
+                inode.set_mtime_ts(now);
+            }
+            inode.set_ctime_ts(now);
+        })?;
+        fs.fast_commit.mark_ineligible();
+        Ok(())
+    })?
+}
+
+/// 为 [start_lbn, end_lbn) 中的空洞分配 unwritten extent，每段连续分配单独一个事务
+fn alloc_range<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    start_lbn: u32,
+    end_lbn: u32,
+) -> BlockDevResult<()> {
+    // 空洞就是范围内各 extent 之间的间隙
+    let mut holes = Vec::new();
+    let mut cursor = start_lbn;
+    for (lbn, len) in mapped_runs(fs, block_dev, inode_num, start_lbn, end_lbn)? {
+        if lbn > cursor {
+            holes.push((cursor, lbn));
+        }
+        cursor = lbn + len;
+    }
+    if cursor < end_lbn {
+        holes.push((cursor, end_lbn));
+    }
+    let total: u64 = holes.iter().map(|&(from, to)| (to - from) as u64).sum();
+    if total > fs.superblock.free_blocks_count() {
+        return Err(BlockDevError::NoSpace);
+    }
+
+    for (mut lbn, to) in holes {
+        while lbn < to {
+            let run = (to - lbn).min(Ext4Extent::EXT_UNINIT_MAX_LEN as u32);
+            let got = fs.journaled(block_dev, EXT4_DATA_TRANS_BLOCKS, |fs, block_dev| {
+                alloc_unwritten(fs, block_dev, inode_num, lbn, run)
+            })??;
+            lbn += got;
+        }
+    }
+    Ok(())
+}
+
+/// [start_lbn, end_lbn) 内已映射（含 unwritten）的区段，裁剪到范围内，按逻辑块排序
+fn mapped_runs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    start_lbn: u32,
+    end_lbn: u32,
+) -> BlockDevResult<Vec<(u32, u32)>> {
+    if start_lbn >= end_lbn {
+        return Ok(Vec::new());
+    }
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let extents = ExtentTree::new_with_ino(fs, &mut inode, inode_num).extents_in_range(
+        block_dev,
+        start_lbn,
+        end_lbn - start_lbn,
+    )?;
+    Ok(extents
+        .iter()
+        .map(|e| {
+            let from = e.ee_block.max(start_lbn);
+            let to = (e.ee_block as u64 + e.actual_len() as u64).min(end_lbn as u64) as u32;
+            (from, to - from)
+        })
+        .collect())
+}
+
+/// 在 lbn 处分配最多 `count` 个连续块并插入 unwritten extent，返回实际分配的块数。
+/// 没有足够大的连续空闲区时逐次减半
+fn alloc_unwritten<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    lbn: u32,
+    count: u32,
+) -> BlockDevResult<u32> {
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    // 紧跟前一个逻辑块的物理位置分配；空间碎片化时能拿多少拿多少
+    let goal = match lbn.checked_sub(1) {
+        Some(prev) => map_extent_range(fs, block_dev, inode_num, &mut inode, prev, 1)?
+            .first()
+            .and_then(|es| es.pblk_of(prev as u64))
+            .map(|p| p + 1),
+        None => None,
+    };
+    let (start, want) = fs.mb_alloc(block_dev, goal, count.min(fs.superblock.s_blocks_per_group), 1)?;
+    let ext = Ext4Extent::new_unwritten(lbn, start, want as u16);
+    ExtentTree::new_with_ino(fs, &mut inode, inode_num).insert_extent(fs, ext, block_dev)?;
+    let sectors = inode.blocks_count() + want as u64 * (fs.block_size() / 512) as u64;
+    inode.i_blocks_lo = sectors as u32;
+    inode.l_i_blocks_high = (sectors >> 32) as u16;
+    fs.modify_inode(block_dev, inode_num, |td| *td = inode)?;
+    fs.fast_commit.mark_ineligible();
+    debug!("fallocate: inode {inode_num} lbn {lbn} -> {start} x{want} (unwritten)");
+    Ok(want)
+}
+
+/// 释放 [start_lbn, end_lbn) 内已映射的块（含 unwritten），i_blocks 由 extent 树维护。
+/// 每个 extent 在范围内的部分单独一个事务
+fn free_range<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    start_lbn: u32,
+    end_lbn: u32,
+) -> BlockDevResult<()> {
+    // 删除只会拆分或移除 extent，不改变其它 extent 的逻辑位置，所以先取一次列表即可
+    for (mut first, len) in mapped_runs(fs, block_dev, inode_num, start_lbn, end_lbn)? {
+        let end = first + len;
+        while first < end {
+            // remove_extend 的长度按 15 位解释
+            let count = (end - first).min(Ext4Extent::EXT_UNINIT_MAX_LEN as u32);
+            fs.journaled(block_dev, extent_trans_blocks(fs, count), |fs, block_dev| {
+                let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+                ExtentTree::new_with_ino(fs, &mut inode, inode_num).remove_extend(
+                    fs,
+                    Ext4Extent::new(first, 0, count as u16),
+                    block_dev,
+                )?;
+                fs.modify_inode(block_dev, inode_num, |td| *td = inode)?;
+                fs.fast_commit.mark_ineligible();
+                debug!("punch: inode {inode_num} freed {count} blocks from lbn {first}");
+                Ok(())
+            })??;
+            first += count;
+        }
+    }
+    Ok(())
+}
+
+/// 把 [from, to) 两端不满一块的部分清零；只处理已写入的块，空洞和 unwritten 块本来就读出为 0
+fn zero_partial_blocks<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    from: u64,
+    to: u64,
+) -> BlockDevResult<()> {
+    let block_bytes = fs.block_size() as u64;
+    let head_end = to.min((from / block_bytes + 1) * block_bytes);
+    let mut parts = Vec::new();
+    if head_end - from < block_bytes {
+        parts.push((from, head_end));
+    }
+    let tail_start = (to / block_bytes * block_bytes).max(head_end);
+    if tail_start < to {
+        parts.push((tail_start, to));
+    }
+    if parts.is_empty() {
+        return Ok(());
+    }
+    fs.journaled(block_dev, EXT4_DATA_TRANS_BLOCKS, |fs, block_dev| {
+        let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+        for (start, stop) in parts {
+            let lbn = (start / block_bytes) as u32;
+            let Some(ext) = ExtentTree::new_with_ino(fs, &mut inode, inode_num).find_extent(block_dev, lbn)? else {
+                continue;
+            };
+            if !ext.is_initialized() {
+                continue;
+            }
+            let phys = ext.start_block() + (lbn - ext.ee_block) as u64;
+            let off = (start % block_bytes) as usize;
+            let len = (stop - start) as usize;
+            fs.datablock_cache.modify_data(block_dev, phys, |blk| blk[off..off + len].fill(0))?;
+        }
+        Ok(())
+    })?
+}
+
+/// 释放或转换 `blocks` 个块的事务额度：涉及的块组位图和描述符，加上 extent 树的修改
+fn extent_trans_blocks(fs: &Ext4FileSystem, blocks: u32) -> u32 {
+    let groups = (blocks.div_ceil(fs.superblock.s_blocks_per_group.max(1)) + 1).min(fs.group_count);
+    EXT4_DATA_TRANS_BLOCKS + 2 * groups
+}
+
+/// 平移 extent 的事务额度：最坏情况下改写 extent 树的所有节点块
+fn shift_trans_blocks<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<u32> {
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    Ok(EXT4_DATA_TRANS_BLOCKS + resolve_extent_node_blocks(fs, block_dev, inode_num, &mut inode)?.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn extents(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, ino: u32) -> Vec<(u32, u32, bool)> {
+        let mut inode = fs.get_inode_by_num(dev, ino).unwrap();
+        resolve_inode_extents(fs, dev, ino, &mut inode)
+            .unwrap()
+            .iter()
+            .map(|e| (e.ee_block, e.actual_len(), e.is_initialized()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fallocate_reads_zero_and_converts_on_write() {
+        // 先把设备填成非 0，确认 unwritten 块读出的 0 不是来自磁盘
+        let (mut dev, mut fs) = setup_fs(MemDev::filled(8 << 20, 0xAA), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/video", None, None).unwrap();
+        fallocate(&mut dev, &mut fs, ino, 0, 100 * 1024, 0).unwrap();
+
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert_eq!(inode.size(), 100 * 1024);
+        assert_eq!(inode.blocks_count(), 200);
+        assert_eq!(extents(&mut fs, &mut dev, ino), [(0, 100, false)]);
+        assert!(read_file(&mut dev, &mut fs, "/video").unwrap().unwrap().iter().all(|&b| b == 0));
+
+        // 写入中间的部分块：块内其余字节为 0，extent 拆成三段
+        write_file(&mut dev, &mut fs, "/video", 10 * 1024 + 100, &[7u8; 2048]).unwrap();
+        assert_eq!(extents(&mut fs, &mut dev, ino), [(0, 10, false), (10, 3, true), (13, 87, false)]);
+        let data = read_file(&mut dev, &mut fs, "/video").unwrap().unwrap();
+        assert_eq!(data.len(), 100 * 1024);
+        let written = 10 * 1024 + 100..10 * 1024 + 2148;
+        for (i, &b) in data.iter().enumerate() {
+            assert_eq!(b, if written.contains(&i) { 7 } else { 0 }, "byte {i}");
+        }
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().blocks_count(), 200);
+
+        // 从头写：第一段整体转换，与后面已写入的段相邻
+        write_file(&mut dev, &mut fs, "/video", 0, &[1u8; 10 * 1024]).unwrap();
+        assert_eq!(extents(&mut fs, &mut dev, ino), [(0, 10, true), (10, 3, true), (13, 87, false)]);
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_fallocate_keep_size_and_existing_blocks() {
+        let (mut dev, mut fs) = setup_fs(MemDev::filled(8 << 20, 0xAA), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/f", 0, &[5u8; 3000]).unwrap();
+
+        // 已映射的块不变，只补上空洞；KEEP_SIZE 不改大小
+        fallocate(&mut dev, &mut fs, ino, 1024, 9 * 1024, FALLOC_FL_KEEP_SIZE).unwrap();
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().size(), 3000);
+        assert_eq!(extents(&mut fs, &mut dev, ino)[..2], [(0, 3, true), (3, 7, false)]);
+        assert_eq!(read_file(&mut dev, &mut fs, "/f").unwrap().unwrap(), vec![5u8; 3000]);
+
+        // 在 EOF 之后的预分配区写入
+        write_file(&mut dev, &mut fs, "/f", 5000, b"tail").unwrap();
+        let data = read_file(&mut dev, &mut fs, "/f").unwrap().unwrap();
+        assert_eq!(data.len(), 5004);
+        assert!(data[3000..5000].iter().all(|&b| b == 0));
+        assert_eq!(&data[5000..], b"tail");
+
+        assert_eq!(fallocate(&mut dev, &mut fs, ino, 0, 0, 0), Err(BlockDevError::InvalidInput));
+        assert_eq!(fallocate(&mut dev, &mut fs, ino, 0, 1, 0x40), Err(BlockDevError::Unsupported));
+        assert_eq!(
+            fallocate(&mut dev, &mut fs, ino, 0, 64 << 20, 0),
+            Err(BlockDevError::NoSpace)
+        );
+        fs.umount(&mut dev).unwrap();
+    }
+
+    /// 第 i 块的内容全是 i + 1
+    fn pattern(blocks: usize) -> Vec<u8> {
+        (0..blocks).flat_map(|i| [(i + 1) as u8; 1024]).collect()
+    }
+
+    #[test]
+    fn test_punch_hole_and_zero_range() {
+        let (mut dev, mut fs) = setup_fs(MemDev::filled(8 << 20, 0xAA), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/log", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/log", 0, &pattern(20)).unwrap();
+        let free_before = fs.superblock.free_blocks_count();
+
+        // 中间 2.5 块到 7.5 块：整块 3..7 释放，两端清零
+        punch_hole(&mut dev, &mut fs, ino, 2560, 5120).unwrap();
+        let mut expect = pattern(20);
+        expect[2560..7680].fill(0);
+        assert_eq!(read_file(&mut dev, &mut fs, "/log").unwrap().unwrap(), expect);
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert_eq!((inode.size(), inode.blocks_count()), (20 * 1024, 32));
+        assert_eq!(fs.superblock.free_blocks_count(), free_before + 4);
+        assert_eq!(extents(&mut fs, &mut dev, ino), [(0, 3, true), (7, 13, true)]);
+        // 超出 EOF 的部分不改变大小
+        punch_hole(&mut dev, &mut fs, ino, 19 * 1024, 1 << 20).unwrap();
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().size(), 20 * 1024);
+        expect[19 * 1024..].fill(0);
+
+        // zero_range：整块转为 unwritten，空洞补上 unwritten 块
+        zero_range(&mut dev, &mut fs, ino, 1536, 9 * 1024, false).unwrap();
+        expect[1536..1536 + 9 * 1024].fill(0);
+        assert_eq!(read_file(&mut dev, &mut fs, "/log").unwrap().unwrap(), expect);
+        let ext = extents(&mut fs, &mut dev, ino);
+        assert_eq!(ext[0], (0, 2, true));
+        assert!(ext.iter().filter(|e| e.0 >= 2 && e.0 < 10).all(|e| !e.2));
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().blocks_count(), 40 - 2);
+
+        // 超出 EOF 且不带 KEEP_SIZE 时扩大文件
+        zero_range(&mut dev, &mut fs, ino, 20 * 1024, 2048, false).unwrap();
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().size(), 22 * 1024);
+        assert_eq!(
+            fallocate(&mut dev, &mut fs, ino, 0, 1024, FALLOC_FL_PUNCH_HOLE),
+            Err(BlockDevError::Unsupported)
+        );
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_collapse_and_insert_range() {
+        let (mut dev, mut fs) = setup_fs(MemDev::filled(8 << 20, 0xAA), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/img", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/img", 0, &pattern(10)).unwrap();
+        write_file(&mut dev, &mut fs, "/img", 10 * 1024, b"end").unwrap();
+        let free_before = fs.superblock.free_blocks_count();
+
+        collapse_range(&mut dev, &mut fs, ino, 2048, 3072).unwrap();
+        let mut expect = pattern(10);
+        expect.drain(2048..5120);
+        expect.extend_from_slice(b"end");
+        assert_eq!(read_file(&mut dev, &mut fs, "/img").unwrap().unwrap(), expect);
+        assert_eq!(fs.superblock.free_blocks_count(), free_before + 3);
+
+        // 在第 4 块处插入 3 块空洞，跨过 offset 的 extent 被拆开
+        insert_range(&mut dev, &mut fs, ino, 4096, 3072).unwrap();
+        expect.splice(4096..4096, [0u8; 3072]);
+        assert_eq!(read_file(&mut dev, &mut fs, "/img").unwrap().unwrap(), expect);
+        assert_eq!(fs.get_inode_by_num(&mut dev, ino).unwrap().size(), expect.len() as u64);
+        assert_eq!(extents(&mut fs, &mut dev, ino).iter().map(|e| e.0).collect::<Vec<_>>(), [0, 2, 7]);
+
+        assert_eq!(collapse_range(&mut dev, &mut fs, ino, 1000, 1024), Err(BlockDevError::InvalidInput));
+        assert_eq!(collapse_range(&mut dev, &mut fs, ino, 0, 11 * 1024), Err(BlockDevError::InvalidInput));
+        assert_eq!(insert_range(&mut dev, &mut fs, ino, 11 * 1024, 1024), Err(BlockDevError::InvalidInput));
+        assert_eq!(
+            fallocate(&mut dev, &mut fs, ino, 0, 1024, FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_KEEP_SIZE),
+            Err(BlockDevError::InvalidInput)
+        );
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_shift_multilevel_tree() {
+        let (mut dev, mut fs) = setup_fs(MemDev::filled(8 << 20, 0xAA), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/sparse", None, None).unwrap();
+        // 隔块写入，得到几百个单块 extent，树至少两层
+        for i in 0..300u64 {
+            write_file(&mut dev, &mut fs, "/sparse", i * 2048, &[(i % 250 + 1) as u8; 1024]).unwrap();
+        }
+        let before = read_file(&mut dev, &mut fs, "/sparse").unwrap().unwrap();
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(!resolve_extent_node_blocks(&fs, &mut dev, ino, &mut inode).unwrap().is_empty());
+
+        insert_range(&mut dev, &mut fs, ino, 100 * 1024, 50 * 1024).unwrap();
+        let mut expect = before.clone();
+        expect.splice(100 * 1024..100 * 1024, vec![0u8; 50 * 1024]);
+        assert_eq!(read_file(&mut dev, &mut fs, "/sparse").unwrap().unwrap(), expect);
+
+        collapse_range(&mut dev, &mut fs, ino, 100 * 1024, 50 * 1024).unwrap();
+        assert_eq!(read_file(&mut dev, &mut fs, "/sparse").unwrap().unwrap(), before);
+        let ext = extents(&mut fs, &mut dev, ino);
+        assert_eq!(ext.len(), 300);
+        assert!(ext.iter().enumerate().all(|(i, e)| e.0 == 2 * i as u32));
+        fs.umount(&mut dev).unwrap();
+    }
+}
@@ -0,0 +1,887 @@
+//! 快速提交（fast commit）模块
+//!
+//! 与内核 ext4 的 fast commit 格式兼容：fsync 时不提交整个 JBD2 事务，
+//! 只把本事务里被跟踪的修改编码成紧凑的逻辑记录（inode、目录项增删、extent 范围），
+//! 写进日志末尾的 fast commit 区。记录是 TLV 格式：小端 u16 tag + u16 长度，后跟值。
+//! 每次 fast commit 以 TAIL 结束，TAIL 带事务号和此前所有字节的 crc32c；
+//! 区内第一个块以 HEAD 开头。挂载时 JBD2 重放完完整事务后扫描 fast commit 区，
+//! 这里再把记录应用到文件系统上。无法用逻辑记录描述的操作（mkdir、xattr、截断等）
+//! 让运行中的事务失去 fast commit 资格，fsync 时退回完整提交。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::checksum::*;
+use crate::ext4_backend::dir::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::endian::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::extents_tree::*;
+use crate::ext4_backend::file::*;
+use crate::ext4_backend::loopfile::*;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{debug, warn};
+
+/// 为 inode 增加一段逻辑块到物理块的映射
+pub const EXT4_FC_TAG_ADD_RANGE: u16 = 0x0001;
+/// 删除 inode 的一段逻辑块映射
+pub const EXT4_FC_TAG_DEL_RANGE: u16 = 0x0002;
+/// 新建文件的目录项
+pub const EXT4_FC_TAG_CREAT: u16 = 0x0003;
+/// 硬链接目录项
+pub const EXT4_FC_TAG_LINK: u16 = 0x0004;
+/// 删除目录项
+pub const EXT4_FC_TAG_UNLINK: u16 = 0x0005;
+/// inode 原始内容
+pub const EXT4_FC_TAG_INODE: u16 = 0x0006;
+/// 填充到块尾
+pub const EXT4_FC_TAG_PAD: u16 = 0x0007;
+/// 一次 fast commit 的结束标记
+pub const EXT4_FC_TAG_TAIL: u16 = 0x0008;
+/// fast commit 区的开头
+pub const EXT4_FC_TAG_HEAD: u16 = 0x0009;
+
+/// TLV 头长度（tag + len）
+const FC_TL_SIZE: usize = 4;
+/// ADD_RANGE 值长度：inode 号 + 一个 extent
+const FC_ADD_RANGE_LEN: usize = 16;
+/// DEL_RANGE 值长度：inode 号 + 起始逻辑块 + 长度
+const FC_DEL_RANGE_LEN: usize = 12;
+/// 目录项记录值中名字之前的部分：父目录 inode 号 + inode 号
+const FC_DENTRY_LEN: usize = 8;
+/// 目录项名字最大长度
+const EXT4_NAME_LEN: usize = 255;
+/// HEAD 值长度：特性位 + 事务号
+const FC_HEAD_LEN: usize = 8;
+/// TAIL 值长度：事务号 + crc
+const FC_TAIL_LEN: usize = 8;
+/// inode 原始字节里 i_block 的范围
+const I_BLOCK_RANGE: core::ops::Range<usize> = 40..100;
+/// 旧版 inode 大小，i_extra_isize 之前的部分
+const EXT4_GOOD_OLD_INODE_SIZE: usize = 128;
+/// 大 inode 记录里 i_extra_isize 的下限：i_extra_isize + i_checksum_hi
+const FC_MIN_EXTRA_ISIZE: usize = 4;
+
+/// fast commit 目录项记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FcDentry {
+    pub parent: u32,
+    pub ino: u32,
+    pub name: Vec<u8>,
+}
+
+/// fast commit 区里的一条逻辑记录（HEAD/TAIL/PAD 只在编码时出现）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FcRecord {
+    AddRange { ino: u32, ext: Ext4Extent },
+    DelRange { ino: u32, lblk: u32, len: u32 },
+    Create(FcDentry),
+    Link(FcDentry),
+    Unlink(FcDentry),
+    Inode { ino: u32, raw: Vec<u8> },
+}
+
+impl FcRecord {
+    fn tag(&self) -> u16 {
+        match self {
+            FcRecord::AddRange { .. } => EXT4_FC_TAG_ADD_RANGE,
+            FcRecord::DelRange { .. } => EXT4_FC_TAG_DEL_RANGE,
+            FcRecord::Create(_) => EXT4_FC_TAG_CREAT,
+            FcRecord::Link(_) => EXT4_FC_TAG_LINK,
+            FcRecord::Unlink(_) => EXT4_FC_TAG_UNLINK,
+            FcRecord::Inode { .. } => EXT4_FC_TAG_INODE,
+        }
+    }
+
+    /// 编码记录的值部分
+    fn value(&self) -> Vec<u8> {
+        let mut val = Vec::new();
+        match self {
+            FcRecord::AddRange { ino, ext } => {
+                val.resize(FC_ADD_RANGE_LEN, 0);
+                write_u32_le(*ino, &mut val[0..4]);
+                ext.to_disk_bytes(&mut val[4..FC_ADD_RANGE_LEN]);
+            }
+            FcRecord::DelRange { ino, lblk, len } => {
+                val.extend_from_slice(&ino.to_le_bytes());
+                val.extend_from_slice(&lblk.to_le_bytes());
+                val.extend_from_slice(&len.to_le_bytes());
+            }
+            FcRecord::Create(d) | FcRecord::Link(d) | FcRecord::Unlink(d) => {
+                val.extend_from_slice(&d.parent.to_le_bytes());
+                val.extend_from_slice(&d.ino.to_le_bytes());
+                val.extend_from_slice(&d.name);
+            }
+            FcRecord::Inode { ino, raw } => {
+                val.extend_from_slice(&ino.to_le_bytes());
+                val.extend_from_slice(raw);
+            }
+        }
+        val
+    }
+
+    /// 解析一条记录，值长度不合法时返回 None（与内核 ext4_fc_value_len_isvalid 相同）
+    fn decode(tag: u16, val: &[u8]) -> Option<FcRecord> {
+        let dentry = |val: &[u8]| {
+            (val.len() > FC_DENTRY_LEN && val.len() <= FC_DENTRY_LEN + EXT4_NAME_LEN).then(|| FcDentry {
+                parent: read_u32_le(&val[0..4]),
+                ino: read_u32_le(&val[4..8]),
+                name: val[FC_DENTRY_LEN..].to_vec(),
+            })
+        };
+        match tag {
+            EXT4_FC_TAG_ADD_RANGE if val.len() == FC_ADD_RANGE_LEN => Some(FcRecord::AddRange {
+                ino: read_u32_le(&val[0..4]),
+                ext: Ext4Extent::from_disk_bytes(&val[4..FC_ADD_RANGE_LEN]),
+            }),
+            EXT4_FC_TAG_DEL_RANGE if val.len() == FC_DEL_RANGE_LEN => Some(FcRecord::DelRange {
+                ino: read_u32_le(&val[0..4]),
+                lblk: read_u32_le(&val[4..8]),
+                len: read_u32_le(&val[8..12]),
+            }),
+            EXT4_FC_TAG_CREAT => dentry(val).map(FcRecord::Create),
+            EXT4_FC_TAG_LINK => dentry(val).map(FcRecord::Link),
+            EXT4_FC_TAG_UNLINK => dentry(val).map(FcRecord::Unlink),
+            EXT4_FC_TAG_INODE if val.len() > 4 => Some(FcRecord::Inode {
+                ino: read_u32_le(&val[0..4]),
+                raw: val[4..].to_vec(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// 把 TLV 依次排进块里：当前块放不下时用 PAD 填满块尾再换块，crc 覆盖 TAIL 之前的全部字节
+struct FcWriter {
+    block_size: usize,
+    blocks: Vec<Vec<u8>>,
+    off: usize,
+    crc: u32,
+}
+
+impl FcWriter {
+    fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            blocks: Vec::new(),
+            off: block_size,
+            crc: 0,
+        }
+    }
+
+    /// 为总长 `len` 的 TLV 预留空间，块尾至少留下一个 TLV 头的位置给 PAD
+    fn reserve(&mut self, len: usize) -> BlockDevResult<usize> {
+        if len + FC_TL_SIZE > self.block_size {
+            return Err(BlockDevError::InvalidInput);
+        }
+        if self.off < self.block_size {
+            let remaining = self.block_size - FC_TL_SIZE - self.off;
+            if len <= remaining {
+                let off = self.off;
+                self.off += len;
+                return Ok(off);
+            }
+            let off = self.off;
+            let blk = self.blocks.last_mut().ok_or(BlockDevError::Corrupted)?;
+            write_u16_le(EXT4_FC_TAG_PAD, &mut blk[off..off + 2]);
+            write_u16_le(remaining as u16, &mut blk[off + 2..off + 4]);
+            self.crc = crc32c(self.crc, &blk[off..]);
+        }
+        self.blocks.push(vec![0u8; self.block_size]);
+        self.off = len;
+        Ok(0)
+    }
+
+    fn push(&mut self, tag: u16, val: &[u8]) -> BlockDevResult<()> {
+        let off = self.reserve(FC_TL_SIZE + val.len())?;
+        let blk = self.blocks.last_mut().ok_or(BlockDevError::Corrupted)?;
+        write_u16_le(tag, &mut blk[off..off + 2]);
+        write_u16_le(val.len() as u16, &mut blk[off + 2..off + 4]);
+        blk[off + FC_TL_SIZE..off + FC_TL_SIZE + val.len()].copy_from_slice(val);
+        self.crc = crc32c(self.crc, &blk[off..off + FC_TL_SIZE + val.len()]);
+        Ok(())
+    }
+
+    /// TAIL 的长度字段覆盖到块尾，之后的记录从新块开始
+    fn tail(mut self, tid: u32) -> BlockDevResult<Vec<Vec<u8>>> {
+        let off = self.reserve(FC_TL_SIZE + FC_TAIL_LEN)?;
+        let bsize = self.block_size;
+        let blk = self.blocks.last_mut().ok_or(BlockDevError::Corrupted)?;
+        write_u16_le(EXT4_FC_TAG_TAIL, &mut blk[off..off + 2]);
+        write_u16_le((bsize - off - FC_TL_SIZE) as u16, &mut blk[off + 2..off + 4]);
+        write_u32_le(tid, &mut blk[off + 4..off + 8]);
+        let crc = crc32c(self.crc, &blk[off..off + 8]);
+        write_u32_le(crc, &mut blk[off + 8..off + 12]);
+        Ok(self.blocks)
+    }
+}
+
+/// 把一次 fast commit 的记录编码成若干日志块；`with_head` 为真时先写 HEAD（fast commit 区的第一次提交）
+pub fn encode_fast_commit(
+    records: &[FcRecord],
+    tid: u32,
+    with_head: bool,
+    block_size: usize,
+) -> BlockDevResult<Vec<Vec<u8>>> {
+    let mut writer = FcWriter::new(block_size);
+    if with_head {
+        let mut head = [0u8; FC_HEAD_LEN];
+        write_u32_le(tid, &mut head[4..8]);
+        writer.push(EXT4_FC_TAG_HEAD, &head)?;
+    }
+    for rec in records {
+        writer.push(rec.tag(), &rec.value())?;
+    }
+    writer.tail(tid)
+}
+
+/// 重放时逐块扫描 fast commit 区，只接受最后一个校验通过的 TAIL 之前的记录
+pub struct FcScanner {
+    expected_tid: u32,
+    blocks: u32,
+    crc: u32,
+    pending: Vec<FcRecord>,
+    records: Vec<FcRecord>,
+}
+
+impl FcScanner {
+    pub fn new(expected_tid: u32) -> Self {
+        Self {
+            expected_tid,
+            blocks: 0,
+            crc: 0,
+            pending: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    /// 扫描一个块，返回是否继续扫描下一个块
+    pub fn scan_block(&mut self, block: &[u8]) -> bool {
+        if self.blocks == 0 && (block.len() < FC_TL_SIZE || read_u16_le(&block[0..2]) != EXT4_FC_TAG_HEAD) {
+            return false;
+        }
+        self.blocks += 1;
+        let mut cur = 0usize;
+        while cur + FC_TL_SIZE <= block.len() {
+            let tag = read_u16_le(&block[cur..cur + 2]);
+            let len = read_u16_le(&block[cur + 2..cur + 4]) as usize;
+            let val_off = cur + FC_TL_SIZE;
+            if len > block.len() - val_off {
+                return false;
+            }
+            let val = &block[val_off..val_off + len];
+            match tag {
+                EXT4_FC_TAG_HEAD => {
+                    if len != FC_HEAD_LEN || read_u32_le(&val[0..4]) != 0 || read_u32_le(&val[4..8]) != self.expected_tid {
+                        return false;
+                    }
+                    self.crc = crc32c(self.crc, &block[cur..val_off + len]);
+                }
+                EXT4_FC_TAG_TAIL => {
+                    if len < FC_TAIL_LEN {
+                        return false;
+                    }
+                    let crc = crc32c(self.crc, &block[cur..val_off + 4]);
+                    if read_u32_le(&val[0..4]) != self.expected_tid || read_u32_le(&val[4..8]) != crc {
+                        return false;
+                    }
+                    self.records.append(&mut self.pending);
+                    self.crc = 0;
+                }
+                EXT4_FC_TAG_PAD => self.crc = crc32c(self.crc, &block[cur..val_off + len]),
+                _ => {
+                    let Some(rec) = FcRecord::decode(tag, val) else {
+                        return false;
+                    };
+                    self.pending.push(rec);
+                    self.crc = crc32c(self.crc, &block[cur..val_off + len]);
+                }
+            }
+            cur = val_off + len;
+        }
+        true
+    }
+
+    /// 扫描结束，返回所有已完整提交的记录
+    pub fn finish(self) -> Vec<FcRecord> {
+        self.records
+    }
+}
+
+/// 运行中事务的 fast commit 跟踪状态
+/// 每个操作（最外层事务句柄）都要用记录描述自己的全部修改，否则整个事务失去 fast commit 资格
+#[derive(Debug, Default)]
+pub struct FastCommitState {
+    enabled: bool,
+    /// 跟踪记录所属的事务号
+    tid: Option<u32>,
+    /// 被修改的 inode 及其被写过的逻辑块范围
+    inodes: BTreeMap<u32, Option<(u32, u32)>>,
+    /// 按发生顺序记录的目录项修改
+    dentries: Vec<FcRecord>,
+    /// 当前操作是否可以用记录描述：None 表示操作没有留下任何记录
+    op_eligible: Option<bool>,
+}
+
+impl FastCommitState {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    fn clear(&mut self) {
+        self.tid = None;
+        self.inodes.clear();
+        self.dentries.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inodes.is_empty() && self.dentries.is_empty()
+    }
+
+    fn note_op(&mut self) {
+        if self.op_eligible.is_none() {
+            self.op_eligible = Some(true);
+        }
+    }
+
+    /// 最外层事务句柄开始：事务号变了说明之前的事务已完整提交，跟踪记录清空
+    pub fn begin_op(&mut self, tid: u32) {
+        if !self.enabled {
+            return;
+        }
+        if self.tid != Some(tid) {
+            self.clear();
+            self.tid = Some(tid);
+        }
+        self.op_eligible = None;
+    }
+
+    /// 记录 inode 被修改，`range` 是写过的逻辑块范围（闭区间）
+    pub fn track_inode(&mut self, ino: u32, range: Option<(u32, u32)>) {
+        if !self.enabled {
+            return;
+        }
+        let slot = self.inodes.entry(ino).or_insert(None);
+        *slot = match (*slot, range) {
+            (Some((s0, e0)), Some((s1, e1))) => Some((s0.min(s1), e0.max(e1))),
+            (old, new) => old.or(new),
+        };
+        self.note_op();
+    }
+
+    /// 记录一个目录项修改（CREAT/LINK/UNLINK）
+    pub fn track_dentry(&mut self, rec: FcRecord) {
+        if !self.enabled {
+            return;
+        }
+        self.dentries.push(rec);
+        self.note_op();
+    }
+
+    /// 当前操作无法用 fast commit 记录描述
+    pub fn mark_ineligible(&mut self) {
+        if self.enabled {
+            self.op_eligible = Some(false);
+        }
+    }
+
+    /// 最外层事务句柄结束：返回事务是否可以推迟到 fsync 时用 fast commit 落盘
+    /// 事务已经很大时也不再推迟，避免 fsync 前积累过多未提交的元数据
+    pub fn end_op<B: BlockDevice>(&mut self, block_dev: &Jbd2Dev<B>) -> bool {
+        let eligible = self.enabled
+            && self.op_eligible == Some(true)
+            && block_dev.fast_commit_tid().is_some_and(|(tid, _)| self.tid == Some(tid))
+            && block_dev
+                .max_transaction_blocks()
+                .is_some_and(|max| block_dev.running_transaction_blocks() < max / 2);
+        self.op_eligible = None;
+        if !eligible {
+            self.clear();
+        }
+        eligible
+    }
+}
+
+/// fsync：把运行中事务的修改持久化
+/// 事务里只有被跟踪的操作时写 fast commit，否则（或 fast commit 失败时）做一次完整提交
+pub fn fsync<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<()> {
+    if fs.read_only {
+        return Err(BlockDevError::ReadOnly);
+    }
+    if block_dev.transaction_depth() > 0 {
+        return Err(BlockDevError::DeviceBusy);
+    }
+    // 元数据已在操作结束时进入事务，这里只剩 data=writeback 留在缓存里的数据块
+    fs.datablock_cache.flush_all(block_dev)?;
+    if !block_dev.is_use_journal() {
+        fs.bitmap_cache.flush_all(block_dev)?;
+        fs.inodetable_cahce.flush_all(block_dev)?;
+        fs.sync_group_descriptors(block_dev)?;
+        fs.sync_superblock(block_dev)?;
+        return block_dev.cantflush();
+    }
+    let done = try_fast_commit(fs, block_dev).unwrap_or_else(|e| {
+        warn!("fast commit failed, fall back to a full commit: {e}");
+        false
+    });
+    fs.fast_commit.clear();
+    if done {
+        return Ok(());
+    }
+    if let Err(e) = block_dev.commit_running() {
+        fs.handle_error(block_dev, e);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// 尝试用 fast commit 持久化运行中的事务，返回是否已经持久化
+fn try_fast_commit<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>) -> BlockDevResult<bool> {
+    let Some((tid, with_head)) = block_dev.fast_commit_tid() else {
+        return Ok(false);
+    };
+    if !fs.fast_commit.enabled || fs.fast_commit.tid != Some(tid) || fs.fast_commit.is_empty() {
+        // 没有跟踪记录：事务为空时没有需要持久化的内容
+        if block_dev.running_transaction_blocks() == 0 {
+            block_dev.cantflush()?;
+            return Ok(true);
+        }
+        return Ok(false);
+    }
+    let records = collect_records(fs, block_dev)?;
+    let blocks = encode_fast_commit(&records, tid, with_head, fs.block_size())?;
+    block_dev.write_fast_commit(&blocks)?;
+    debug!("fast commit: tid={tid} records={} blocks={}", records.len(), blocks.len());
+    Ok(true)
+}
+
+/// 按内核的顺序生成记录：新建文件先写 inode 和数据范围再写 CREAT，其余目录项按发生顺序，最后是其它 inode
+fn collect_records<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+) -> BlockDevResult<Vec<FcRecord>> {
+    let dentries = fs.fast_commit.dentries.clone();
+    let inodes = fs.fast_commit.inodes.clone();
+    let mut out = Vec::new();
+    let mut written = BTreeSet::new();
+    for rec in dentries {
+        if let FcRecord::Create(d) = &rec
+            && let Some(range) = inodes.get(&d.ino)
+            && written.insert(d.ino)
+        {
+            out.push(inode_record(fs, block_dev, d.ino)?);
+            range_records(fs, block_dev, d.ino, *range, &mut out)?;
+        }
+        out.push(rec);
+    }
+    for (ino, range) in inodes {
+        if written.contains(&ino) {
+            continue;
+        }
+        range_records(fs, block_dev, ino, range, &mut out)?;
+        out.push(inode_record(fs, block_dev, ino)?);
+    }
+    Ok(out)
+}
+
+/// inode 记录：前 128 字节加上 i_extra_isize 部分，inode 内扩展属性区不记录
+fn inode_record<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+) -> BlockDevResult<FcRecord> {
+    let inode = fs.get_inode_by_num(block_dev, ino)?;
+    if inode.has_inline_data() {
+        // 内联数据有一部分在扩展属性区里，记录不下
+        return Err(BlockDevError::Unsupported);
+    }
+    let mut raw = fs.get_inode_raw(block_dev, ino)?;
+    if raw.len() > EXT4_GOOD_OLD_INODE_SIZE {
+        // 内核与 e2fsck 要求大 inode 的记录至少带上 i_extra_isize 和 i_checksum_hi
+        let extra = (inode.i_extra_isize as usize).max(FC_MIN_EXTRA_ISIZE);
+        write_u16_le(extra as u16, &mut raw[EXT4_GOOD_OLD_INODE_SIZE..EXT4_GOOD_OLD_INODE_SIZE + 2]);
+        raw.truncate((EXT4_GOOD_OLD_INODE_SIZE + extra).min(raw.len()));
+    }
+    Ok(FcRecord::Inode { ino, raw })
+}
+
+/// 把写过的逻辑块范围转换成 ADD_RANGE（已映射）和 DEL_RANGE（空洞）记录
+fn range_records<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+    range: Option<(u32, u32)>,
+    out: &mut Vec<FcRecord>,
+) -> BlockDevResult<()> {
+    let Some((start, end)) = range else {
+        return Ok(());
+    };
+    let mut inode = fs.get_inode_by_num(block_dev, ino)?;
+    if inode.has_inline_data() || !inode.have_extend_header_and_use_extend() {
+        return Err(BlockDevError::Unsupported);
+    }
+    let mut tree = ExtentTree::new_with_ino(fs, &mut inode, ino);
+    let mut lblk = start as u64;
+    while lblk <= end as u64 {
+        let cur = lblk as u32;
+        match tree.find_extent(block_dev, cur)? {
+            Some(ext) => {
+                if !ext.is_initialized() {
+                    // 未初始化 extent 的重放不在支持范围内
+                    return Err(BlockDevError::Unsupported);
+                }
+                let skip = cur - ext.ee_block;
+                let len = (ext.ee_len as u64 - skip as u64).min(end as u64 - lblk + 1);
+                out.push(FcRecord::AddRange {
+                    ino,
+                    ext: Ext4Extent::new(cur, ext.start_block() + skip as u64, len as u16),
+                });
+                lblk += len;
+            }
+            None => {
+                let mut next = lblk + 1;
+                while next <= end as u64 && tree.find_extent(block_dev, next as u32)?.is_none() {
+                    next += 1;
+                }
+                out.push(FcRecord::DelRange {
+                    ino,
+                    lblk: cur,
+                    len: (next - lblk) as u32,
+                });
+                lblk = next;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 挂载时应用 fast commit 记录，调用方负责关闭日志（记录直接写回原位置，中途崩溃可以重新应用）
+pub fn replay<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    records: &[FcRecord],
+) -> BlockDevResult<()> {
+    debug!("fast commit replay: {} records", records.len());
+    // ADD_RANGE 引用的块先在位图里占住，重建 extent 树时不会被分配出去
+    let mut claimed = BTreeSet::new();
+    let mut touched = BTreeSet::new();
+    for rec in records {
+        match rec {
+            FcRecord::AddRange { ino, ext } => {
+                touched.insert(*ino);
+                if ext.is_initialized() {
+                    for blk in ext.start_block()..ext.start_block() + ext.ee_len as u64 {
+                        if fs.mark_block_used(block_dev, blk)? {
+                            claimed.insert(blk);
+                        }
+                    }
+                }
+            }
+            FcRecord::DelRange { ino, .. } | FcRecord::Inode { ino, .. } => {
+                touched.insert(*ino);
+            }
+            _ => {}
+        }
+    }
+    for rec in records {
+        match rec {
+            FcRecord::Inode { ino, raw } => replay_inode(fs, block_dev, *ino, raw)?,
+            FcRecord::AddRange { ino, ext } => replay_add_range(fs, block_dev, *ino, *ext)?,
+            FcRecord::DelRange { ino, lblk, len } => replay_del_range(fs, block_dev, *ino, *lblk, *len)?,
+            FcRecord::Create(d) | FcRecord::Link(d) => replay_link(fs, block_dev, d)?,
+            FcRecord::Unlink(d) => replay_unlink(fs, block_dev, d)?,
+        }
+    }
+    // 与内核相同：最后按 inode 的实际映射重新标记占用的块，没有被引用的预占块放回
+    for ino in touched {
+        let mut inode = fs.get_inode_by_num(block_dev, ino)?;
+        if inode.i_mode == 0 || inode.has_inline_data() {
+            continue;
+        }
+        let mut used: Vec<u64> = resolve_inode_block_allextend(fs, block_dev, ino, &mut inode)?
+            .into_values()
+            .collect();
+        used.extend(resolve_extent_node_blocks(fs, block_dev, ino, &mut inode)?);
+        for blk in used {
+            claimed.remove(&blk);
+            fs.mark_block_used(block_dev, blk)?;
+        }
+    }
+    for blk in claimed {
+        fs.free_block(block_dev, blk)?;
+    }
+    fs.bitmap_cache.flush_all(block_dev)?;
+    fs.inodetable_cahce.flush_all(block_dev)?;
+    fs.datablock_cache.flush_all(block_dev)?;
+    fs.sync_group_descriptors(block_dev)?;
+    fs.sync_superblock(block_dev)?;
+    block_dev.cantflush()
+}
+
+/// INODE：除 i_block 外按记录覆盖（extent 树由 ADD_RANGE/DEL_RANGE 重建），内联数据 inode 连 i_block 一起覆盖
+fn replay_inode<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+    raw: &[u8],
+) -> BlockDevResult<()> {
+    let was_free = fs.mark_inode_used(block_dev, ino)?;
+    fs.modify_inode_raw(block_dev, ino, |buf| {
+        let mut i_block = [0u8; 60];
+        if !was_free {
+            i_block.copy_from_slice(&buf[I_BLOCK_RANGE]);
+        }
+        let n = raw.len().min(buf.len());
+        buf[..n].copy_from_slice(&raw[..n]);
+        let flags = read_u32_le(&buf[32..36]);
+        if flags & Ext4Inode::EXT4_INLINE_DATA_FL == 0 {
+            buf[I_BLOCK_RANGE].copy_from_slice(&i_block);
+        }
+        if flags & Ext4Inode::EXT4_EXTENTS_FL != 0
+            && read_u16_le(&buf[I_BLOCK_RANGE][0..2]) != Ext4ExtentHeader::EXT4_EXT_MAGIC
+        {
+            Ext4ExtentHeader::new().to_disk_bytes(&mut buf[I_BLOCK_RANGE]);
+        }
+    })?;
+    update_iblocks(fs, block_dev, ino)
+}
+
+/// ADD_RANGE：映射到别处的逻辑块先删掉，再插入记录里的映射
+fn replay_add_range<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+    ext: Ext4Extent,
+) -> BlockDevResult<()> {
+    let mut inode = fs.get_inode_by_num(block_dev, ino)?;
+    if inode.i_mode == 0 || !ext.is_initialized() || !inode.have_extend_header_and_use_extend() {
+        warn!("fast commit replay: skip range lblk={} of inode {ino}", ext.ee_block);
+        return Ok(());
+    }
+    let map = resolve_inode_block_allextend(fs, block_dev, ino, &mut inode)?;
+    let mut stale = Vec::new();
+    let mut missing = Vec::new();
+    for i in 0..ext.ee_len as u32 {
+        let lblk = ext.ee_block + i;
+        match map.get(&lblk) {
+            Some(&phys) if phys == ext.start_block() + i as u64 => {}
+            Some(_) => stale.push(lblk),
+            None => missing.push(lblk),
+        }
+    }
+    for (lblk, len) in runs(&stale) {
+        ExtentTree::new_with_ino(fs, &mut inode, ino).remove_extend(fs, Ext4Extent::new(lblk, 0, len), block_dev)?;
+    }
+    for (lblk, len) in runs(&stale).into_iter().chain(runs(&missing)) {
+        let phys = ext.start_block() + (lblk - ext.ee_block) as u64;
+        ExtentTree::new_with_ino(fs, &mut inode, ino).insert_extent(fs, Ext4Extent::new(lblk, phys, len), block_dev)?;
+    }
+    fs.modify_inode(block_dev, ino, |td| *td = inode)?;
+    update_iblocks(fs, block_dev, ino)
+}
+
+/// DEL_RANGE：删除范围内所有已映射的块
+fn replay_del_range<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+    lblk: u32,
+    len: u32,
+) -> BlockDevResult<()> {
+    let mut inode = fs.get_inode_by_num(block_dev, ino)?;
+    if inode.i_mode == 0 || !inode.have_extend_header_and_use_extend() {
+        return Ok(());
+    }
+    let end = lblk.saturating_add(len);
+    let mapped: Vec<u32> = resolve_inode_block_allextend(fs, block_dev, ino, &mut inode)?
+        .range(lblk..end)
+        .map(|(l, _)| *l)
+        .collect();
+    if mapped.is_empty() {
+        return Ok(());
+    }
+    for (start, n) in runs(&mapped) {
+        ExtentTree::new_with_ino(fs, &mut inode, ino).remove_extend(fs, Ext4Extent::new(start, 0, n), block_dev)?;
+    }
+    fs.modify_inode(block_dev, ino, |td| *td = inode)?;
+    update_iblocks(fs, block_dev, ino)
+}
+
+/// CREAT/LINK：目录项不存在时插入；链接计数由之后的 INODE 记录恢复
+fn replay_link<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    d: &FcDentry,
+) -> BlockDevResult<()> {
+    let mut parent = fs.get_inode_by_num(block_dev, d.parent)?;
+    let child = fs.get_inode_by_num(block_dev, d.ino)?;
+    if parent.i_mode == 0 || !parent.is_dir() || child.i_mode == 0 {
+        warn!("fast commit replay: skip dentry of inode {} in {}", d.ino, d.parent);
+        return Ok(());
+    }
+    if child.is_dir() {
+        warn!("fast commit replay: directory dentry of inode {} not supported", d.ino);
+        return Ok(());
+    }
+    if lookup_dir_entry(fs, block_dev, d.parent, &mut parent, &d.name)?.is_some() {
+        return Ok(());
+    }
+    let name = core::str::from_utf8(&d.name).map_err(|_| BlockDevError::InvalidInput)?;
+    insert_dir_entry(fs, block_dev, d.parent, &mut parent, d.ino, name, dir_file_type(child.i_mode))
+}
+
+/// UNLINK：目录项仍指向同一个 inode 时删除
+fn replay_unlink<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    d: &FcDentry,
+) -> BlockDevResult<()> {
+    let mut parent = fs.get_inode_by_num(block_dev, d.parent)?;
+    if parent.i_mode == 0 || !parent.is_dir() {
+        return Ok(());
+    }
+    if lookup_dir_entry(fs, block_dev, d.parent, &mut parent, &d.name)? == Some(d.ino) {
+        remove_dir_entry(fs, block_dev, d.parent, &mut parent, &d.name);
+    }
+    Ok(())
+}
+
+/// 按 inode 的实际映射重新计算 i_blocks：数据块 + extent 树节点块 + 扩展属性块
+fn update_iblocks<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>, ino: u32) -> BlockDevResult<()> {
+    let mut inode = fs.get_inode_by_num(block_dev, ino)?;
+    if inode.has_inline_data() || !inode.have_extend_header_and_use_extend() {
+        return Ok(());
+    }
+    let mut blocks = resolve_inode_block_allextend(fs, block_dev, ino, &mut inode)?.len() as u64;
+    blocks += resolve_extent_node_blocks(fs, block_dev, ino, &mut inode)?.len() as u64;
+    if inode.file_acl() != 0 {
+        blocks += 1;
+    }
+    let sectors = blocks * (fs.block_size() / 512) as u64;
+    fs.modify_inode(block_dev, ino, |td| {
+        td.i_blocks_lo = sectors as u32;
+        td.l_i_blocks_high = (sectors >> 32) as u16;
+    })
+}
+
+/// 把升序的逻辑块号合并成 (起始, 长度) 的连续段
+fn runs(lblks: &[u32]) -> Vec<(u32, u16)> {
+    let mut out: Vec<(u32, u16)> = Vec::new();
+    for &l in lblks {
+        match out.last_mut() {
+            Some((start, len)) if *start + *len as u32 == l && *len < Ext4Extent::EXT_INIT_MAX_LEN => *len += 1,
+            _ => out.push((l, 1)),
+        }
+    }
+    out
+}
+
+/// 目录项里的文件类型
+fn dir_file_type(mode: u16) -> u8 {
+    match mode & Ext4Inode::S_IFMT {
+        Ext4Inode::S_IFREG => Ext4DirEntry2::EXT4_FT_REG_FILE,
+        Ext4Inode::S_IFDIR => Ext4DirEntry2::EXT4_FT_DIR,
+        Ext4Inode::S_IFCHR => Ext4DirEntry2::EXT4_FT_CHRDEV,
+        Ext4Inode::S_IFBLK => Ext4DirEntry2::EXT4_FT_BLKDEV,
+        Ext4Inode::S_IFIFO => Ext4DirEntry2::EXT4_FT_FIFO,
+        Ext4Inode::S_IFSOCK => Ext4DirEntry2::EXT4_FT_SOCK,
+        Ext4Inode::S_IFLNK => Ext4DirEntry2::EXT4_FT_SYMLINK,
+        _ => Ext4DirEntry2::EXT4_FT_UNKNOWN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::ext4::mount;
+    use crate::ext4_backend::test_support::*;
+
+    const BS: usize = 1024;
+    fn dentry(parent: u32, ino: u32, name: &[u8]) -> FcDentry {
+        FcDentry {
+            parent,
+            ino,
+            name: name.to_vec(),
+        }
+    }
+
+    fn scan(blocks: &[Vec<u8>], tid: u32) -> Vec<FcRecord> {
+        let mut scanner = FcScanner::new(tid);
+        for blk in blocks {
+            if !scanner.scan_block(blk) {
+                break;
+            }
+        }
+        scanner.finish()
+    }
+
+    #[test]
+    fn test_encode_scan_roundtrip_with_padding() {
+        let mut records = Vec::new();
+        for i in 0..20u32 {
+            records.push(FcRecord::Inode {
+                ino: 12 + i,
+                raw: vec![i as u8; 160],
+            });
+            records.push(FcRecord::Create(dentry(2, 12 + i, b"file")));
+        }
+        records.push(FcRecord::AddRange {
+            ino: 12,
+            ext: Ext4Extent::new(0, 5000, 8),
+        });
+        records.push(FcRecord::DelRange { ino: 13, lblk: 4, len: 2 });
+        let blocks = encode_fast_commit(&records, 7, true, BS).unwrap();
+        assert!(blocks.len() > 1);
+        assert_eq!(read_u16_le(&blocks[0][0..2]), EXT4_FC_TAG_HEAD);
+        assert_eq!(scan(&blocks, 7), records);
+        // 事务号不符的 fast commit 区整个被忽略
+        assert!(scan(&blocks, 8).is_empty());
+    }
+
+    #[test]
+    fn test_scan_keeps_commits_before_bad_crc() {
+        let first = vec![FcRecord::Link(dentry(2, 12, b"a"))];
+        let second = vec![FcRecord::Unlink(dentry(2, 12, b"a"))];
+        let mut blocks = encode_fast_commit(&first, 3, true, BS).unwrap();
+        let mut tail = encode_fast_commit(&second, 3, false, BS).unwrap();
+        tail[0][FC_TL_SIZE + FC_DENTRY_LEN] ^= 0xff;
+        blocks.append(&mut tail);
+        assert_eq!(scan(&blocks, 3), first);
+    }
+
+    #[test]
+    fn test_fsync_survives_crash() {
+        let mem = MemDev::new(16 << 20);
+        format(mem.clone(), BS);
+
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem.clone(), true);
+        let mut fs = mount(&mut dev).unwrap();
+        assert_eq!(fs.enable_fast_commit(&mut dev), Ok(true));
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        mkfile_with_ino(&mut dev, &mut fs, "/a", Some(b"hello"), None).unwrap();
+        write_file(&mut dev, &mut fs, "/a", 0, &data).unwrap();
+        link(&mut fs, &mut dev, "/b", "/a");
+        fsync(&mut fs, &mut dev).unwrap();
+        // 修改只写进了 fast commit 区，完整事务仍在运行
+        assert!(dev.running_transaction_blocks() > 0);
+
+        // 不卸载直接丢弃，模拟崩溃后重新挂载
+        drop(fs);
+        drop(dev);
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem, true);
+        let mut fs = mount(&mut dev).unwrap();
+        assert_eq!(read_file(&mut dev, &mut fs, "/a").unwrap(), Some(data.clone()));
+        assert_eq!(read_file(&mut dev, &mut fs, "/b").unwrap(), Some(data));
+        let (_, inode) = get_file_inode(&mut fs, &mut dev, "/a").unwrap().unwrap();
+        assert_eq!(inode.i_links_count, 2);
+        assert!(fs.fast_commit.is_enabled());
+        fs.umount(&mut dev).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+//! 稀疏文件的映射查询：SEEK_DATA / SEEK_HOLE 与 FIEMAP 风格的 extent 报告
+//!
+//! 直接遍历 extent 树（传统间接映射的 inode 把连续块合并成段），不按块展开。
+//! unwritten extent 读出为 0，SEEK_DATA / SEEK_HOLE 把它当作空洞；
+//! fiemap 则如实报告并带上 [`FIEMAP_EXTENT_UNWRITTEN`]。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::loopfile::*;
+use alloc::vec::Vec;
+
+/// 文件最后一段映射
+pub const FIEMAP_EXTENT_LAST: u32 = 0x0001;
+/// 数据没有按块对齐（内联数据）
+pub const FIEMAP_EXTENT_NOT_ALIGNED: u32 = 0x0100;
+/// 数据存放在 inode 内部
+pub const FIEMAP_EXTENT_DATA_INLINE: u32 = 0x0200;
+/// 已分配但未写入，读出为 0
+pub const FIEMAP_EXTENT_UNWRITTEN: u32 = 0x0800;
+/// 空洞（非 Linux 标准标志），physical 为 0
+pub const FIEMAP_EXTENT_HOLE: u32 = 0x0001_0000;
+
+/// lseek 的 whence
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+/// 从 offset 起的下一段数据
+pub const SEEK_DATA: u32 = 3;
+/// 从 offset 起的下一个空洞（EOF 视为空洞）
+pub const SEEK_HOLE: u32 = 4;
+
+/// fiemap 返回的一段映射，均以字节为单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FiemapExtent {
+    pub logical: u64,
+    pub physical: u64,
+    pub length: u64,
+    pub flags: u32,
+}
+
+/// 一段连续映射（逻辑块、物理块、块数、是否 unwritten）
+#[derive(Clone, Copy)]
+struct MappedRun {
+    lblk: u32,
+    pblk: u64,
+    len: u32,
+    unwritten: bool,
+}
+
+impl MappedRun {
+    fn end(&self) -> u64 {
+        self.lblk as u64 + self.len as u64
+    }
+}
+
+/// 按逻辑块顺序返回 inode 的全部映射段
+fn mapped_runs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<Vec<MappedRun>> {
+    if inode.have_extend_header_and_use_extend() {
+        return Ok(resolve_inode_extents(fs, block_dev, inode_num, inode)?
+            .iter()
+            .map(|e| MappedRun {
+                lblk: e.ee_block,
+                pblk: e.start_block(),
+                len: e.actual_len(),
+                unwritten: !e.is_initialized(),
+            })
+            .collect());
+    }
+
+    let mut runs: Vec<MappedRun> = Vec::new();
+    for (lbn, phys) in resolve_inode_block_allextend(fs, block_dev, inode_num, inode)? {
+        if let Some(last) = runs.last_mut()
+            && last.end() == lbn as u64
+            && last.pblk + last.len as u64 == phys
+        {
+            last.len += 1;
+            continue;
+        }
+        runs.push(MappedRun { lblk: lbn, pblk: phys, len: 1, unwritten: false });
+    }
+    Ok(runs)
+}
+
+/// 报告 inode 在 [start, start + len) 内的映射：与范围相交的每个 extent 整段返回，
+/// 范围内（且在 EOF 之前）的空洞以 [`FIEMAP_EXTENT_HOLE`] 单独返回。
+/// 文件最后一段带 [`FIEMAP_EXTENT_LAST`]；内联数据作为一段 physical 为 0 的 DATA_INLINE 返回
+pub fn fiemap<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    start: u64,
+    len: u64,
+) -> BlockDevResult<Vec<FiemapExtent>> {
+    if len == 0 {
+        return Err(BlockDevError::InvalidInput);
+    }
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let size = inode.size();
+    let end = start.saturating_add(len);
+    let mut out = Vec::new();
+
+    if inode.has_inline_data() {
+        if size > 0 && start < size {
+            out.push(FiemapExtent {
+                logical: 0,
+                physical: 0,
+                length: size,
+                flags: FIEMAP_EXTENT_DATA_INLINE | FIEMAP_EXTENT_NOT_ALIGNED | FIEMAP_EXTENT_LAST,
+            });
+        }
+        return Ok(out);
+    }
+
+    let bs = fs.block_size() as u64;
+    let runs = mapped_runs(fs, block_dev, inode_num, &mut inode)?;
+    let file_end = runs.last().map_or(size, |r| size.max(r.end() * bs));
+    let hole_end = end.min(size);
+    let mut pos = start;
+
+    for run in &runs {
+        let run_start = run.lblk as u64 * bs;
+        let run_end = run.end() * bs;
+        if run_end <= start {
+            continue;
+        }
+        if run_start >= end {
+            break;
+        }
+        if pos < run_start.min(hole_end) {
+            out.push(FiemapExtent {
+                logical: pos,
+                physical: 0,
+                length: run_start.min(hole_end) - pos,
+                flags: FIEMAP_EXTENT_HOLE,
+            });
+        }
+        out.push(FiemapExtent {
+            logical: run_start,
+            physical: run.pblk * bs,
+            length: run_end - run_start,
+            flags: if run.unwritten { FIEMAP_EXTENT_UNWRITTEN } else { 0 },
+        });
+        pos = run_end;
+    }
+    if pos < hole_end {
+        out.push(FiemapExtent {
+            logical: pos,
+            physical: 0,
+            length: hole_end - pos,
+            flags: FIEMAP_EXTENT_HOLE,
+        });
+    }
+
+    if let Some(last) = out.last_mut()
+        && last.logical + last.length >= file_end
+    {
+        last.flags |= FIEMAP_EXTENT_LAST;
+    }
+    Ok(out)
+}
+
+/// SEEK_DATA：返回 offset 之后（含）第一个数据所在的偏移。
+/// offset 不在文件内或之后没有数据时返回 NotFound（对应 ENXIO）
+pub fn seek_data<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+) -> BlockDevResult<u64> {
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let size = inode.size();
+    if offset >= size {
+        return Err(BlockDevError::NotFound);
+    }
+    if inode.has_inline_data() {
+        return Ok(offset);
+    }
+
+    let bs = fs.block_size() as u64;
+    for run in mapped_runs(fs, block_dev, inode_num, &mut inode)? {
+        if run.unwritten || run.end() * bs <= offset {
+            continue;
+        }
+        let data = offset.max(run.lblk as u64 * bs);
+        return if data < size { Ok(data) } else { Err(BlockDevError::NotFound) };
+    }
+    Err(BlockDevError::NotFound)
+}
+
+/// SEEK_HOLE：返回 offset 之后（含）第一个空洞的偏移，没有空洞时返回文件大小。
+/// offset 不在文件内时返回 NotFound（对应 ENXIO）
+pub fn seek_hole<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+) -> BlockDevResult<u64> {
+    let mut inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let size = inode.size();
+    if offset >= size {
+        return Err(BlockDevError::NotFound);
+    }
+    if inode.has_inline_data() {
+        return Ok(size);
+    }
+
+    let bs = fs.block_size() as u64;
+    let mut pos = offset;
+    for run in mapped_runs(fs, block_dev, inode_num, &mut inode)? {
+        if run.unwritten || run.end() * bs <= pos {
+            continue;
+        }
+        if run.lblk as u64 * bs > pos {
+            break;
+        }
+        pos = run.end() * bs;
+    }
+    Ok(pos.min(size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+    use crate::ext4_backend::api::*;
+    use crate::ext4_backend::fallocate::*;
+    use crate::ext4_backend::file::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_seek_data_hole() {
+        let (mut dev, mut fs) = setup();
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/sparse", None, None).unwrap();
+        // 数据：[0, 2K) 与 [8K, 9K)，中间是空洞，之后 [9K, 12K) 为 unwritten
+        write_file(&mut dev, &mut fs, "/sparse", 0, &[1u8; 2048]).unwrap();
+        write_file(&mut dev, &mut fs, "/sparse", 8192, &[2u8; 1024]).unwrap();
+        fallocate(&mut dev, &mut fs, ino, 9216, 3072, 0).unwrap();
+
+        assert_eq!(seek_data(&mut dev, &mut fs, ino, 0).unwrap(), 0);
+        assert_eq!(seek_hole(&mut dev, &mut fs, ino, 0).unwrap(), 2048);
+        assert_eq!(seek_data(&mut dev, &mut fs, ino, 2048).unwrap(), 8192);
+        assert_eq!(seek_data(&mut dev, &mut fs, ino, 8500).unwrap(), 8500);
+        assert_eq!(seek_hole(&mut dev, &mut fs, ino, 8192).unwrap(), 9216);
+        assert_eq!(seek_hole(&mut dev, &mut fs, ino, 10000).unwrap(), 10000);
+        assert_eq!(seek_data(&mut dev, &mut fs, ino, 9216), Err(BlockDevError::NotFound));
+        assert_eq!(seek_data(&mut dev, &mut fs, ino, 12288), Err(BlockDevError::NotFound));
+        assert_eq!(seek_hole(&mut dev, &mut fs, ino, 12288), Err(BlockDevError::NotFound));
+
+        let mut file = open(&mut dev, &mut fs, "/sparse", false).unwrap();
+        assert_eq!(lseek_whence(&mut dev, &mut fs, &mut file, 2048, SEEK_DATA).unwrap(), 8192);
+        assert_eq!(file.offset, 8192);
+        assert_eq!(lseek_whence(&mut dev, &mut fs, &mut file, 100, SEEK_CUR).unwrap(), 8292);
+        assert_eq!(lseek_whence(&mut dev, &mut fs, &mut file, -12288, SEEK_END).unwrap(), 0);
+        assert_eq!(lseek_whence(&mut dev, &mut fs, &mut file, -1, SEEK_SET), Err(BlockDevError::InvalidInput));
+    }
+
+    #[test]
+    fn test_fiemap_reports_extents_and_holes() {
+        let (mut dev, mut fs) = setup();
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/sparse", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/sparse", 0, &[1u8; 2048]).unwrap();
+        write_file(&mut dev, &mut fs, "/sparse", 8192, &[2u8; 1024]).unwrap();
+        fallocate(&mut dev, &mut fs, ino, 9216, 3072, 0).unwrap();
+
+        let map = fiemap(&mut dev, &mut fs, ino, 0, u64::MAX).unwrap();
+        let layout: Vec<(u64, u64, u32)> = map.iter().map(|e| (e.logical, e.length, e.flags)).collect();
+        assert_eq!(
+            layout,
+            vec![
+                (0, 2048, 0),
+                (2048, 6144, FIEMAP_EXTENT_HOLE),
+                (8192, 1024, 0),
+                (9216, 3072, FIEMAP_EXTENT_UNWRITTEN | FIEMAP_EXTENT_LAST),
+            ]
+        );
+        assert!(map.iter().filter(|e| e.flags & FIEMAP_EXTENT_HOLE == 0).all(|e| e.physical != 0));
+
+        // 范围只覆盖空洞中间：空洞被裁剪到范围内，且不是最后一段
+        let map = fiemap(&mut dev, &mut fs, ino, 3000, 1000).unwrap();
+        assert_eq!(map, vec![FiemapExtent { logical: 3000, physical: 0, length: 1000, flags: FIEMAP_EXTENT_HOLE }]);
+        assert_eq!(fiemap(&mut dev, &mut fs, ino, 0, 0), Err(BlockDevError::InvalidInput));
+    }
+}
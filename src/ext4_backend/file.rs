@@ -1,17 +1,24 @@
 use core::u32;
 
 use alloc::string::ToString;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use log::{error, info};
 use log::{debug, warn};
 
+use crate::ext4_backend::acl::*;
 use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::blockmap::*;
 use crate::ext4_backend::config::*;
 use crate::ext4_backend::dir::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::entries::*;
 use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::extent_status::*;
 use crate::ext4_backend::extents_tree::*;
+use crate::ext4_backend::fast_commit::*;
+use crate::ext4_backend::hashtree::*;
+use crate::ext4_backend::inline_data::*;
 use crate::ext4_backend::loopfile::*;
 use crate::ext4_backend::error::*;
 use alloc::string::String;
@@ -24,6 +31,17 @@ pub fn rename<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     old_path: &str,
     new_path: &str,
+) -> BlockDevResult<()> {
+    fs.journaled(device, EXT4_RENAME_TRANS_BLOCKS, |fs, device| {
+        do_rename(device, fs, old_path, new_path)
+    })?
+}
+
+fn do_rename<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    old_path: &str,
+    new_path: &str,
 ) -> BlockDevResult<()> {
     let old_norm = split_paren_child_and_tranlatevalid(old_path);
     let new_norm = split_paren_child_and_tranlatevalid(new_path);
@@ -33,7 +51,7 @@ pub fn rename<B: BlockDevice>(
         if inod.is_dir() {
             delete_dir(fs, device, new_path);
         } else {
-            delete_file(fs, device, new_path);
+            delete_file(fs, device, new_path)?;
         }
     }
     //删除了还存在？错误!
@@ -76,6 +94,18 @@ pub fn truncate_with_ino<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     inode_num: u32,
     truncate_size: u64,
+) -> BlockDevResult<()> {
+    fs.journaled(device, EXT4_DATA_TRANS_BLOCKS, |fs, device| {
+        do_truncate_with_ino(device, fs, inode_num, truncate_size)?;
+        fs.touch_mtime_ctime(device, inode_num)
+    })?
+}
+
+fn do_truncate_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    truncate_size: u64,
 ) -> BlockDevResult<()> {
     let mut inode = fs.get_inode_by_num(device, inode_num)?;
     
@@ -86,12 +116,24 @@ pub fn truncate_with_ino<B: BlockDevice>(
         return Err(BlockDevError::Unsupported);
     }
 
+    // 内联文件：新长度仍放得下就在 inode 内截断/扩展，否则先转为块存储
+    if inode.has_inline_data() {
+        if try_truncate_inline_data(fs, device, inode_num, truncate_size)? {
+            return Ok(());
+        }
+        let old = uninline_data(fs, device, inode_num)?;
+        if !old.is_empty() {
+            write_file_blocks(device, fs, inode_num, 0, &old)?;
+        }
+        inode = fs.get_inode_by_num(device, inode_num)?;
+    }
+
     let old_size = inode.size();
     if truncate_size == old_size {
         return Ok(());
     }
 
-    let block_bytes = BLOCK_SIZE as u64;
+    let block_bytes = fs.block_size() as u64;
     let old_blocks = if old_size == 0 {
         0u64
     } else {
@@ -111,7 +153,7 @@ pub fn truncate_with_ino<B: BlockDevice>(
             let del_start_lbn = new_blocks as u32;
 
             loop {
-                let blocks_map = resolve_inode_block_allextend(fs, device, &mut inode)?;
+                let blocks_map = resolve_inode_block_allextend(fs, device, inode_num, &mut inode)?;
                 let del_len = if truncate_size == 0 {
                     blocks_map.len() as u32
                 } else {
@@ -134,7 +176,7 @@ pub fn truncate_with_ino<B: BlockDevice>(
 
                 let chunk = core::cmp::min(del_len, 0x7FFF);
                 {
-                    let mut tree = ExtentTree::new(&mut inode);
+                    let mut tree = ExtentTree::new_with_ino(fs, &mut inode, inode_num);
                     tree.remove_extend(fs, Ext4Extent::new(start_lbn, 0, chunk as u16), device)?;
                 }
             }
@@ -146,7 +188,7 @@ pub fn truncate_with_ino<B: BlockDevice>(
             let mut new_blocks_map: Vec<(u32, u64)> = Vec::new();
             for lbn in old_blocks as u32..new_blocks as u32 {
                 let phys = fs.alloc_block(device)?;
-                fs.datablock_cache.modify_new(phys, |data| {
+                fs.datablock_cache.modify_new_data(phys, |data| {
                     for b in data.iter_mut() {
                         *b = 0;
                     }
@@ -154,7 +196,7 @@ pub fn truncate_with_ino<B: BlockDevice>(
                 new_blocks_map.push((lbn, phys));
             }
 
-            let mut tree = ExtentTree::new(&mut inode);
+            let mut tree = ExtentTree::new_with_ino(fs, &mut inode, inode_num);
             if !new_blocks_map.is_empty() {
                 let mut idx = 0usize;
                 while idx < new_blocks_map.len() {
@@ -183,8 +225,8 @@ pub fn truncate_with_ino<B: BlockDevice>(
         inode.i_size_lo = (truncate_size & 0xffff_ffff) as u32;
         inode.i_size_high = (truncate_size >> 32) as u32;
         // i_blocks reflects number of allocated blocks, not logical length. Recompute after edits.
-        let alloc_blocks = resolve_inode_block_allextend(fs, device, &mut inode)?.len() as u64;
-        let iblocks_used = alloc_blocks.saturating_mul(BLOCK_SIZE as u64 / 512);
+        let alloc_blocks = resolve_inode_block_allextend(fs, device, inode_num, &mut inode)?.len() as u64;
+        let iblocks_used = alloc_blocks.saturating_mul(fs.block_size() as u64 / 512);
         inode.i_blocks_lo = (iblocks_used & 0xffff_ffff) as u32;
         inode.l_i_blocks_high = ((iblocks_used >> 32) & 0xffff) as u16;
 
@@ -194,41 +236,26 @@ pub fn truncate_with_ino<B: BlockDevice>(
         return Ok(());
     }
 
-    //todo:
-    // 非 extent：仅支持 12 个直接块（现有实现本来就不支持间接块）
-    if new_blocks > 12 {
-        return Err(BlockDevError::Unsupported);
-    }
-
-    // grow：分配新块并填 0，写入 i_block
+    // 非 extent：传统直接/间接块映射，i_blocks 由 BlockMap 维护
+    let mut map = BlockMap::new(&mut inode);
     if new_blocks > old_blocks {
         for lbn in old_blocks as u32..new_blocks as u32 {
-            let phys = fs.alloc_block(device)?;
-            fs.datablock_cache.modify_new(phys, |data| {
-                for b in data.iter_mut() {
-                    *b = 0;
-                }
-            });
-            inode.i_block[lbn as usize] = phys as u32;
+            let (phys, fresh) = map.map_or_alloc(fs, device, lbn)?;
+            if fresh {
+                fs.datablock_cache.modify_new_data(phys, |data| {
+                    for b in data.iter_mut() {
+                        *b = 0;
+                    }
+                });
+            }
         }
     }
-
-    // shrink：释放尾部块，并清空 i_block
     if new_blocks < old_blocks {
-        for lbn in new_blocks as u32..old_blocks as u32 {
-            let phys = inode.i_block[lbn as usize] as u64;
-            if phys != 0 {
-                fs.free_block(device, phys)?;
-            }
-            inode.i_block[lbn as usize] = 0;
-        }
+        map.truncate_from(fs, device, new_blocks as u32)?;
     }
 
     inode.i_size_lo = (truncate_size & 0xffff_ffff) as u32;
     inode.i_size_high = (truncate_size >> 32) as u32;
-    let iblocks_used = (new_blocks.saturating_mul(BLOCK_SIZE as u64 / 512)) as u64;
-    inode.i_blocks_lo = (iblocks_used & 0xffff_ffff) as u32;
-    inode.l_i_blocks_high = ((iblocks_used >> 32) & 0xffff) as u16;
 
     fs.modify_inode(device, inode_num, |td| {
         *td = inode;
@@ -241,6 +268,17 @@ pub fn create_symbol_link<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     src_path: &str,
     dst_path: &str,
+) -> BlockDevResult<()> {
+    fs.journaled(device, EXT4_DIR_TRANS_BLOCKS, |fs, device| {
+        do_create_symbol_link(device, fs, src_path, dst_path)
+    })?
+}
+
+fn do_create_symbol_link<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    src_path: &str,
+    dst_path: &str,
 ) -> BlockDevResult<()> {
     // 首先判断两个目标文件是否存在，被链接不存在报错，链接文件存在报错。
     let src_norm = split_paren_child_and_tranlatevalid(src_path);
@@ -283,7 +321,8 @@ pub fn create_symbol_link<B: BlockDevice>(
     let size_lo = (target_len as u64 & 0xffffffff) as u32;
     let size_hi = ((target_len as u64) >> 32) as u32;
 
-    let mut new_inode = Ext4Inode::default();
+    // 从分配时初始化好的 inode（i_extra_isize、时间戳）开始填写
+    let mut new_inode = fs.get_inode_by_num(device, new_ino)?;
     new_inode.i_mode = Ext4Inode::S_IFLNK | 0o777;
     new_inode.i_links_count = 1;
     new_inode.i_size_lo = size_lo;
@@ -320,7 +359,7 @@ pub fn create_symbol_link<B: BlockDevice>(
             }
 
             let blk = fs.alloc_block(device)?;
-            let write_len = core::cmp::min(remaining, BLOCK_SIZE);
+            let write_len = core::cmp::min(remaining, fs.block_size());
             fs.datablock_cache.modify_new(blk, |data| {
                 for b in data.iter_mut() {
                     *b = 0;
@@ -335,11 +374,11 @@ pub fn create_symbol_link<B: BlockDevice>(
         }
 
         let used_datablocks = data_blocks.len() as u64;
-        let iblocks_used = used_datablocks.saturating_mul(BLOCK_SIZE as u64 / 512) as u32;
+        let iblocks_used = used_datablocks.saturating_mul(fs.block_size() as u64 / 512) as u32;
         new_inode.i_blocks_lo = iblocks_used as u32;
         new_inode.l_i_blocks_high = (iblocks_used as u64 >> 32) as u16;
 
-        build_file_block_mapping(fs, &mut new_inode, &data_blocks, device);
+        build_file_block_mapping(fs, &mut new_inode, new_ino, &data_blocks, device)?;
     }
 
     fs.modify_inode(device, new_ino, |on_disk| {
@@ -364,9 +403,11 @@ pub fn create_symbol_link<B: BlockDevice>(
 
 
 
-fn read_symlink_target<B: BlockDevice>(
+/// 读取符号链接的目标（快速链接、内联数据或数据块）
+pub fn read_symlink_target<B: BlockDevice>(
     device: &mut Jbd2Dev<B>,
     fs: &mut Ext4FileSystem,
+    inode_num: u32,
     inode: &mut Ext4Inode,
 ) -> BlockDevResult<Vec<u8>> {
 
@@ -376,6 +417,10 @@ fn read_symlink_target<B: BlockDevice>(
         return Ok(Vec::new());
     }
 
+    if inode.has_inline_data() {
+        return read_inline_data(fs, device, inode_num);
+    }
+
     if size <= 60 {
         let mut raw = [0u8; 60];
         for (i, word) in inode.i_block.iter().take(15).enumerate() {
@@ -384,12 +429,12 @@ fn read_symlink_target<B: BlockDevice>(
         return Ok(raw[..size].to_vec());
     }
 
-    let block_bytes = BLOCK_SIZE;
+    let block_bytes = fs.block_size();
     let total_blocks = size.div_ceil(block_bytes);
     let mut buf = Vec::with_capacity(size);
 
     if inode.have_extend_header_and_use_extend() {
-        let blocks = resolve_inode_block_allextend(fs, device, inode)?;
+        let blocks = resolve_inode_block_allextend(fs, device, inode_num, inode)?;
         for &phys in blocks.values() {
             let cached = fs.datablock_cache.get_or_load(device, phys)?;
             let data = &cached.data[..block_bytes];
@@ -400,7 +445,7 @@ fn read_symlink_target<B: BlockDevice>(
         }
     } else {
         for lbn in 0..total_blocks {
-            let phys = match resolve_inode_block( device, inode, lbn as u32)? {
+            let phys = match resolve_inode_block(fs, device, inode_num, inode, lbn as u32)? {
                 Some(b) => b,
                 None => break,
             };
@@ -417,7 +462,8 @@ fn read_symlink_target<B: BlockDevice>(
     Ok(buf)
 }
 
-fn resolve_symlink_path(current_path: &str, target: &str) -> String {
+/// 把 `current_path` 处符号链接的目标解析成规范化的绝对路径
+pub fn resolve_symlink_path(current_path: &str, target: &str) -> String {
     if target.starts_with('/') {
         return split_paren_child_and_tranlatevalid(target);
     }
@@ -444,18 +490,18 @@ fn read_file_follow<B: BlockDevice>(
     depth: usize,
 ) -> BlockDevResult<Option<Vec<u8>>> {
   
-    if depth > 8 {
+    if depth > MAX_SYMLINK_FOLLOW {
         return Err(BlockDevError::InvalidInput);
     }
 
-    let mut inode = match get_file_inode(fs, device, path) {
-        Ok(Some((_ino_num, ino))) => ino,
+    let (inode_num, mut inode) = match get_file_inode(fs, device, path) {
+        Ok(Some(v)) => v,
         Ok(None) => return Ok(None),
         Err(e) => return Err(e),
     };
 
     if inode.is_symlink() {
-        let target_bytes = read_symlink_target(device, fs, &mut inode)?;
+        let target_bytes = read_symlink_target(device, fs, inode_num, &mut inode)?;
         let target = match core::str::from_utf8(&target_bytes) {
             Ok(s) => s,
             Err(_) => return Err(BlockDevError::Corrupted),
@@ -468,37 +514,31 @@ fn read_file_follow<B: BlockDevice>(
         error!("Entry:{path} not aa file");
         return BlockDevResult::Err(BlockDevError::ReadError);
     }
+    fs.touch_atime(device, inode_num);
 
     let size = inode.size() as usize;
     if size == 0 {
         return Ok(Some(Vec::new()));
     }
 
-    let block_bytes = BLOCK_SIZE;
+    if inode.has_inline_data() {
+        return read_inline_data(fs, device, inode_num).map(Some);
+    }
+
+    let block_bytes = fs.block_size();
     let total_blocks = size.div_ceil(block_bytes);
 
     let mut buf = Vec::with_capacity(size);
 
-    if inode.have_extend_header_and_use_extend() {
-        let blocks = resolve_inode_block_allextend(fs, device, &mut inode)?;
-        for &phys in blocks.values() {
-            let cached = fs.datablock_cache.get_or_load(device, phys)?;
-            let data = &cached.data[..block_bytes];
-            buf.extend_from_slice(data);
-            if buf.len() >= size {
-                break;
+    // extent 与传统间接映射统一解析；未映射的逻辑块和 unwritten extent 读出为 0
+    let blocks = resolve_inode_written_blocks(fs, device, inode_num, &mut inode)?;
+    for lbn in 0..total_blocks {
+        match blocks.get(&(lbn as u32)) {
+            Some(&phys) => {
+                let cached = fs.datablock_cache.get_or_load(device, phys)?;
+                buf.extend_from_slice(&cached.data[..block_bytes]);
             }
-        }
-    } else {
-        for lbn in 0..total_blocks {
-            let phys = match resolve_inode_block( device, &mut inode, lbn as u32)? {
-                Some(b) => b,
-                None => break,
-            };
-
-            let cached = fs.datablock_cache.get_or_load(device, phys as u64)?;
-            let data = &cached.data[..block_bytes];
-            buf.extend_from_slice(data);
+            None => buf.resize(buf.len() + block_bytes, 0),
         }
     }
 
@@ -515,6 +555,17 @@ pub fn mv<B: BlockDevice>(
     block_dev: &mut Jbd2Dev<B>,
     old_path: &str,
     new_path: &str,
+) -> BlockDevResult<()> {
+    fs.journaled(block_dev, EXT4_RENAME_TRANS_BLOCKS, |fs, block_dev| {
+        do_mv(fs, block_dev, old_path, new_path)
+    })?
+}
+
+fn do_mv<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    old_path: &str,
+    new_path: &str,
 ) -> BlockDevResult<()> {
     //找到对应entry，找不到就返回。
     //判断new_path的父目录是否已经存在不存在就返回，存在继续判断new_path是否有对应的entry，存在就返回
@@ -560,7 +611,7 @@ pub fn mv<B: BlockDevice>(
     };
 
     // 找到 old entry（inode + file_type），找不到就返回
-    let (_old_pino, mut old_parent_inode) = match get_inode_with_num(fs, block_dev, &old_parent)
+    let (old_pino, mut old_parent_inode) = match get_inode_with_num(fs, block_dev, &old_parent)
         .ok()
         .flatten()
     {
@@ -573,13 +624,19 @@ pub fn mv<B: BlockDevice>(
 
     let mut src_ino: Option<u32> = None;
     let mut src_ft: Option<u8> = None;
-    if let Ok(blocks) = resolve_inode_block_allextend(fs, block_dev, &mut old_parent_inode) {
+    if old_parent_inode.has_inline_data()
+        && let Some(entry) = find_inline_dir_entry(fs, block_dev, old_pino, old_name.as_bytes())?
+    {
+        src_ino = Some(entry.inode);
+        src_ft = Some(entry.file_type);
+    }
+    if let Ok(blocks) = resolve_inode_block_allextend(fs, block_dev, old_pino, &mut old_parent_inode) {
         for phys in blocks {
             let cached = match fs.datablock_cache.get_or_load(block_dev, phys.1) {
                 Ok(v) => v,
                 Err(_) => continue,
             };
-            let data = &cached.data[..BLOCK_SIZE];
+            let data = &cached.data[..];
             let iter = DirEntryIterator::new(data);
             for (entry, _) in iter {
                 if entry.inode == 0 {
@@ -602,10 +659,10 @@ pub fn mv<B: BlockDevice>(
         let total_blocks = if total_size == 0 {
             0
         } else {
-            total_size.div_ceil(BLOCK_SIZE)
+            total_size.div_ceil(fs.block_size())
         };
         for lbn in 0..total_blocks {
-            let phys = match resolve_inode_block( block_dev, &mut old_parent_inode, lbn as u32) {
+            let phys = match resolve_inode_block(fs, block_dev, old_pino, &mut old_parent_inode, lbn as u32) {
                 Ok(Some(b)) => b,
                 _ => continue,
             };
@@ -613,7 +670,7 @@ pub fn mv<B: BlockDevice>(
                 Ok(v) => v,
                 Err(_) => continue,
             };
-            let data = &cached.data[..BLOCK_SIZE];
+            let data = &cached.data[..];
             let iter = DirEntryIterator::new(data);
             for (entry, _) in iter {
                 if entry.inode == 0 {
@@ -706,6 +763,7 @@ pub fn mv<B: BlockDevice>(
         );
         return Err(BlockDevError::WriteError);
     }
+    fs.touch_ctime(block_dev, src_ino)?;
 
     // 目录跨父目录移动：更新 link 以及 '..'
     let mut moved_inode = match fs.get_inode_by_num(block_dev, src_ino) {
@@ -735,18 +793,22 @@ pub fn mv<B: BlockDevice>(
                 td.i_links_count = td.i_links_count.saturating_add(1);
             });
 
-            // 更新被移动目录的 ".." 指向新父目录 inode
-            let first_blk = match resolve_inode_block( block_dev, &mut moved_inode, 0) {
+            // 更新被移动目录的 ".." 指向新父目录 inode（内联目录记录在 i_block 开头）
+            if moved_inode.has_inline_data() {
+                set_inline_dir_parent(fs, block_dev, src_ino, new_pino)?;
+                return Ok(());
+            }
+            let first_blk = match resolve_inode_block(fs, block_dev, src_ino, &mut moved_inode, 0) {
                 Ok(Some(b)) => b,
                 _ => {
                     error!("mv resolve_inode_block failed for moved dir ino={}", src_ino);
                     return Err(BlockDevError::Corrupted);
                 }
             };
+            let block_bytes = fs.block_size();
             let _ = fs
                 .datablock_cache
                 .modify(block_dev, first_blk as u64, |data| {
-                    let block_bytes = BLOCK_SIZE;
                     if block_bytes < 24 {
                         return;
                     }
@@ -765,6 +827,7 @@ pub fn mv<B: BlockDevice>(
                     data[off1 + 2] = bytes[2];
                     data[off1 + 3] = bytes[3];
                 });
+            fs.update_dir_block_csum(block_dev, src_ino, first_blk as u64)?;
         }
     }
 
@@ -776,6 +839,18 @@ pub fn unlink<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
     link_path: &str,
+) {
+    if let Err(e) = fs.journaled(block_dev, EXT4_DIR_TRANS_BLOCKS, |fs, block_dev| {
+        do_unlink(fs, block_dev, link_path)
+    }) {
+        error!("unlink journal failed: path={link_path} err={e:?}");
+    }
+}
+
+fn do_unlink<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    link_path: &str,
 ) {
     //首先逐级扫描entry找到对应linkentry。
     let norm_path = split_paren_child_and_tranlatevalid(link_path);
@@ -791,7 +866,7 @@ pub fn unlink<B: BlockDevice>(
         ("/".to_string(), norm_path)
     };
 
-    let (_pino, mut parent_inode) = match get_inode_with_num(fs, block_dev, &parent_path)
+    let (pino, mut parent_inode) = match get_inode_with_num(fs, block_dev, &parent_path)
         .ok()
         .flatten()
     {
@@ -803,7 +878,16 @@ pub fn unlink<B: BlockDevice>(
     };
 
     let mut target_ino: Option<u32> = None;
-    let blocks = match resolve_inode_block_allextend(fs, block_dev, &mut parent_inode) {
+    if parent_inode.has_inline_data() {
+        match find_inline_dir_entry(fs, block_dev, pino, child_name.as_bytes()) {
+            Ok(entry) => target_ino = entry.map(|e| e.inode),
+            Err(e) => {
+                warn!("Parse inline parent dir failed, unlink failed: {e:?} parent={parent_path}");
+                return;
+            }
+        }
+    }
+    let blocks = match resolve_inode_block_allextend(fs, block_dev, pino, &mut parent_inode) {
         Ok(v) => v,
         Err(e) => {
             warn!(
@@ -818,7 +902,7 @@ pub fn unlink<B: BlockDevice>(
             Ok(v) => v,
             Err(_) => continue,
         };
-        let data = &cached.data[..BLOCK_SIZE];
+        let data = &cached.data[..];
         let iter = DirEntryIterator::new(data);
         for (entry, _) in iter {
             if entry.inode == 0 {
@@ -853,9 +937,11 @@ pub fn unlink<B: BlockDevice>(
     //首先对指向inode 的link -1。
     let new_links = target_inode.i_links_count.saturating_sub(1);
     target_inode.i_links_count = new_links;
+    let now = fs.now();
     if fs
         .modify_inode(block_dev, target_ino, |td| {
             td.i_links_count = new_links;
+            td.set_ctime_ts(now);
         })
         .is_err()
     {
@@ -865,14 +951,23 @@ pub fn unlink<B: BlockDevice>(
 
     //如果此时link数为0就调用deletefile删除对应文件.   这里不复用deletefile，因为需要额外的定位
     if new_links == 0 {
+        // 释放 inode 和数据块没有对应的 fast commit 记录
+        fs.fast_commit.mark_ineligible();
         let mut used_blocks: Vec<u64> =
-            match resolve_inode_block_allextend(fs, block_dev, &mut target_inode) {
+            match resolve_inode_block_allextend(fs, block_dev, target_ino, &mut target_inode) {
                 Ok(v) => v.into_values().collect(),
                 Err(e) => {
                     warn!("Parse inode blocks failed (unlink free): {e:?}");
                     return;
                 }
             };
+        match resolve_inode_index_blocks(block_dev, &mut target_inode) {
+            Ok(v) => used_blocks.extend(v),
+            Err(e) => {
+                warn!("Parse inode index blocks failed (unlink free): {e:?}");
+                return;
+            }
+        }
         used_blocks.sort();
         for blk in used_blocks {
             if let Err(e) = fs.free_block(block_dev, blk) {
@@ -895,7 +990,14 @@ pub fn unlink<B: BlockDevice>(
         warn!(
             "Dir entry '{child_name}' not found under parent {parent_path} in unlink"
         );
+        return;
     }
+    fs.fast_commit.track_dentry(FcRecord::Unlink(FcDentry {
+        parent: pino,
+        ino: target_ino,
+        name: child_name.into_bytes(),
+    }));
+    fs.fast_commit.track_inode(target_ino, None);
 }
 
 ///Link
@@ -904,6 +1006,19 @@ pub fn link<B: BlockDevice>(
     block_dev: &mut Jbd2Dev<B>,
     link_path: &str,
     linked_path: &str,
+) {
+    if let Err(e) = fs.journaled(block_dev, EXT4_DIR_TRANS_BLOCKS, |fs, block_dev| {
+        do_link(fs, block_dev, link_path, linked_path)
+    }) {
+        error!("link journal failed: path={link_path} err={e:?}");
+    }
+}
+
+fn do_link<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    link_path: &str,
+    linked_path: &str,
 ) {
     let link_norm = split_paren_child_and_tranlatevalid(link_path);
     let linked_norm = split_paren_child_and_tranlatevalid(linked_path);
@@ -965,16 +1080,16 @@ pub fn link<B: BlockDevice>(
     };
 
     let mut copied_ft: Option<u8> = None;
-    if let Some((_lpino, mut lp_inode)) = get_inode_with_num(fs, block_dev, &linked_parent_path)
+    if let Some((lpino, mut lp_inode)) = get_inode_with_num(fs, block_dev, &linked_parent_path)
         .ok()
         .flatten()
-        && let Ok(blocks) = resolve_inode_block_allextend(fs, block_dev, &mut lp_inode) {
+        && let Ok(blocks) = resolve_inode_block_allextend(fs, block_dev, lpino, &mut lp_inode) {
             for &phys in blocks.values() {
                 let cached = match fs.datablock_cache.get_or_load(block_dev, phys) {
                     Ok(v) => v,
                     Err(_) => continue,
                 };
-                let data = &cached.data[..BLOCK_SIZE];
+                let data = &cached.data[..];
                 let iter = DirEntryIterator::new(data);
                 for (entry, _) in iter {
                     if entry.inode == 0 {
@@ -1017,14 +1132,23 @@ pub fn link<B: BlockDevice>(
     }
 
     // 4.更新目标inode的link+1，失败则回滚刚插入的目录项
+    let now = fs.now();
     if fs
         .modify_inode(block_dev, target_ino, |td| {
             td.i_links_count = td.i_links_count.saturating_add(1);
+            td.set_ctime_ts(now);
         })
         .is_err()
     {
         let _ = remove_inodeentry_from_parentdir(fs, block_dev, &parent_path, &child_name);
+        return;
     }
+    fs.fast_commit.track_dentry(FcRecord::Link(FcDentry {
+        parent: parent_ino,
+        ino: target_ino,
+        name: child_name.into_bytes(),
+    }));
+    fs.fast_commit.track_inode(target_ino, None);
 }
 
 pub fn remove_inodeentry_from_parentdir<B: BlockDevice>(
@@ -1045,88 +1169,83 @@ pub fn remove_inodeentry_from_parentdir<B: BlockDevice>(
             return false;
         }
     };
-    let (_parent_ino_num, mut parent_inode) = parent_info;
+    let (parent_ino_num, mut parent_inode) = parent_info;
+
+    remove_dir_entry(fs, block_dev, parent_ino_num, &mut parent_inode, child_name.as_bytes())
+}
+
+/// 从目录中删除名为 `name_bytes` 的目录项，返回是否找到并删除；删除后更新目录的 mtime/ctime
+pub fn remove_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    parent_ino_num: u32,
+    parent_inode: &mut Ext4Inode,
+    name_bytes: &[u8],
+) -> bool {
+    let removed = do_remove_dir_entry(fs, block_dev, parent_ino_num, parent_inode, name_bytes);
+    if removed && fs.touch_mtime_ctime(block_dev, parent_ino_num).is_err() {
+        warn!("Failed to update times of parent inode {parent_ino_num}");
+    }
+    removed
+}
+
+fn do_remove_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    parent_ino_num: u32,
+    parent_inode: &mut Ext4Inode,
+    name_bytes: &[u8],
+) -> bool {
+    let child_name = String::from_utf8_lossy(name_bytes);
+
+    if parent_inode.has_inline_data() {
+        return match remove_inline_dir_entry(fs, block_dev, parent_ino_num, name_bytes) {
+            Ok(removed) => removed,
+            Err(e) => {
+                warn!("Failed to remove {child_name} from inline dir {parent_ino_num}: {e}");
+                false
+            }
+        };
+    }
+
+    // 哈希索引目录：按哈希直接定位叶子块；索引不可用时退回线性扫描
+    if parent_inode.is_htree_indexed() {
+        let manager = create_hash_tree_manager(fs);
+        match manager.remove_entry(fs, block_dev, parent_ino_num, parent_inode, name_bytes) {
+            Ok(Some(removed)) => return removed,
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Failed to remove {child_name} from indexed dir {parent_ino_num}: {e}");
+                return false;
+            }
+        }
+    }
 
     let total_size = parent_inode.size() as usize;
-    let block_bytes = BLOCK_SIZE;
+    let block_bytes = fs.block_size();
     let total_blocks = if total_size == 0 {
         0
     } else {
         total_size.div_ceil(block_bytes)
     };
+    let usable_len = fs.dir_block_usable_len();
 
     let mut removed = false;
-    let name_bytes = child_name.as_bytes();
 
     for lbn in 0..total_blocks {
         if removed {
             break;
         }
-        let phys = match resolve_inode_block( block_dev, &mut parent_inode, lbn as u32) {
+        let phys = match resolve_inode_block(fs, block_dev, parent_ino_num, parent_inode, lbn as u32) {
             Ok(Some(b)) => b,
             _ => continue,
         };
         let _ = fs.datablock_cache.modify(block_dev, phys as u64, |data| {
-            if removed {
-                return;
-            }
-            let mut offset: usize = 0;
-            let mut prev_off: Option<usize> = None;
-            let mut prev_rec_len: u16 = 0;
-            while offset + 8 <= block_bytes {
-                let inode = u32::from_le_bytes([
-                    data[offset],
-                    data[offset + 1],
-                    data[offset + 2],
-                    data[offset + 3],
-                ]);
-                let rec_len = u16::from_le_bytes([data[offset + 4], data[offset + 5]]);
-                if rec_len < 8 {
-                    break;
-                }
-                let name_len = data[offset + 6] as usize;
-                let entry_end = offset + rec_len as usize;
-                if entry_end > block_bytes {
-                    break;
-                }
-
-                // Only compare name bytes within the current entry's rec_len.
-                if name_len > 0 && offset + 8 + name_len <= entry_end {
-                    let name = &data[offset + 8..offset + 8 + name_len];
-                    if inode != 0 && name == name_bytes {
-                        if let Some(poff) = prev_off {
-                            // Merge current entry's space into previous entry.
-                            let new_len = prev_rec_len.saturating_add(rec_len);
-                            let bytes = new_len.to_le_bytes();
-                            data[poff + 4] = bytes[0];
-                            data[poff + 5] = bytes[1];
-
-                            // Clear current entry inode so it will be treated as free.
-                            let zero = 0u32.to_le_bytes();
-                            data[offset] = zero[0];
-                            data[offset + 1] = zero[1];
-                            data[offset + 2] = zero[2];
-                            data[offset + 3] = zero[3];
-                        } else {
-                            // No previous entry in this block: mark this entry free.
-                            let zero = 0u32.to_le_bytes();
-                            data[offset] = zero[0];
-                            data[offset + 1] = zero[1];
-                            data[offset + 2] = zero[2];
-                            data[offset + 3] = zero[3];
-                        }
-                        removed = true;
-                        break;
-                    }
-                }
-                if entry_end >= block_bytes {
-                    break;
-                }
-                prev_off = Some(offset);
-                prev_rec_len = rec_len;
-                offset = entry_end;
-            }
+            removed = classic_dir::remove_entry(data, usable_len, name_bytes);
         });
+        if removed && fs.update_dir_block_csum(block_dev, parent_ino_num, phys as u64).is_err() {
+            warn!("Failed to update dir block checksum for parent inode {parent_ino_num}");
+        }
     }
 
     removed
@@ -1134,6 +1253,14 @@ pub fn remove_inodeentry_from_parentdir<B: BlockDevice>(
 
 ///删除目录
 pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>, path: &str) {
+    if let Err(e) = fs.journaled(block_dev, EXT4_DIR_TRANS_BLOCKS, |fs, block_dev| {
+        do_delete_dir(fs, block_dev, path)
+    }) {
+        error!("delete_dir journal failed: path={path} err={e:?}");
+    }
+}
+
+fn do_delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>, path: &str) {
     #[derive(Clone)]
     struct DirFrame {
         path: alloc::string::String,
@@ -1189,10 +1316,10 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
     while let Some(mut frame) = stack.pop() {
         // 1.首先遍历对应目录块。DirEntryIterator遍历所有entry（跳过. ..）。
         if frame.stage == 0 {
-            let block_bytes = BLOCK_SIZE;
+            let block_bytes = fs.block_size();
 
             let dir_blocks =
-                match resolve_inode_block_allextend(fs, block_dev, &mut frame.inode) {
+                match resolve_inode_block_allextend(fs, block_dev, frame.ino_num, &mut frame.inode) {
                     Ok(v) => v,
                     Err(e) => {
                         warn!("Parse dir blocks failed: {:?} path={}", e, frame.path);
@@ -1207,10 +1334,17 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
                 alloc::string::String,
             )> = Vec::new();
 
-            for &phys in dir_blocks.values() {
+            // 内联目录没有数据块，用 None 表示 inode 内的目录项
+            let sources: Vec<Option<u64>> = if frame.inode.has_inline_data() {
+                alloc::vec![None]
+            } else {
+                dir_blocks.values().map(|&phys| Some(phys)).collect()
+            };
+
+            for phys in sources {
                 // 先收集 entry，避免在持有 datablock_cache 借用时再次可变借用 fs
                 let mut child_entries: Vec<(u32, alloc::string::String)> = Vec::new();
-                {
+                if let Some(phys) = phys {
                     let cached = match fs.datablock_cache.get_or_load(block_dev, phys) {
                         Ok(v) => v,
                         Err(e) => {
@@ -1237,6 +1371,20 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
                         };
                         child_entries.push((entry.inode, child_name_str.to_string()));
                     }
+                } else {
+                    let entries = match inline_dir_entries(fs, block_dev, frame.ino_num) {
+                        Ok(v) => v,
+                        Err(e) => {
+                            warn!("load inline dir failed: {:?} path={}", e, frame.path);
+                            return;
+                        }
+                    };
+                    for entry in entries {
+                        match core::str::from_utf8(&entry.name[..entry.name_len as usize]) {
+                            Ok(s) => child_entries.push((entry.inode, s.to_string())),
+                            Err(_) => warn!("invalid child name utf8 under dir {}", frame.path),
+                        }
+                    }
                 }
 
                 for (child_ino, child_name) in child_entries {
@@ -1262,7 +1410,9 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
 
                     // 是普通文件或者是链接，调用deletefile删除对应文件。
                     if !child_inode.is_dir() {
-                        delete_file(fs, block_dev, &child_path);
+                        if let Err(e) = delete_file(fs, block_dev, &child_path) {
+                            warn!("delete child file failed: {e:?} path={child_path}");
+                        }
                         continue;
                     }
 
@@ -1341,8 +1491,8 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
         }
 
         // 然后仿照deletefile的逻辑释放entry对应的inode的blocks和inode。
-        let used_blocks: Vec<u64> =
-            match resolve_inode_block_allextend(fs, block_dev, &mut cur_inode) {
+        let mut used_blocks: Vec<u64> =
+            match resolve_inode_block_allextend(fs, block_dev, frame.ino_num, &mut cur_inode) {
                 Ok(v) => v.into_values().collect(),
                 Err(e) => {
                     warn!(
@@ -1352,6 +1502,16 @@ pub fn delete_dir<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2D
                     return;
                 }
             };
+        match resolve_inode_index_blocks(block_dev, &mut cur_inode) {
+            Ok(v) => used_blocks.extend(v),
+            Err(e) => {
+                warn!(
+                    "Parse dir index blocks failed (freeing): {:?} path={}",
+                    e, frame.path
+                );
+                return;
+            }
+        }
 
         for blk in used_blocks {
             if let Err(e) = fs.free_block(block_dev, blk) {
@@ -1386,33 +1546,44 @@ pub fn delete_file<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
     path: &str,
-) {
+) -> BlockDevResult<()> {
+    fs.journaled(block_dev, EXT4_DIR_TRANS_BLOCKS, |fs, block_dev| {
+        do_delete_file(fs, block_dev, path)
+    })?
+}
+
+fn do_delete_file<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    path: &str,
+) -> BlockDevResult<()> {
     //find inode
     let norm_path = split_paren_child_and_tranlatevalid(path);
     let target = match get_file_inode(fs, block_dev, &norm_path) {
         Ok(Some((ino_num, inode))) => (ino_num, inode),
         Ok(None) => {
             warn!("File not exist, delete failed!");
-            return;
+            return Ok(());
         }
         Err(e) => {
             warn!("File lookup error, delete failed: {e:?}");
-            return;
+            return Err(e);
         }
     };
     let (ino_num, mut target_inode) = target;
 
     if target_inode.is_dir() {
         error!("file:{path} is a dir!");
-        return;
+        return Ok(());
     }
 
     //统计block（i_blocks 以 512 字节为单位，换算成数据块个数）
     let mut inode_used_blocks: Vec<u64> =
-        resolve_inode_block_allextend(fs, block_dev, &mut target_inode)
-            .expect("Parse inode extend failed")
+        resolve_inode_block_allextend(fs, block_dev, ino_num, &mut target_inode)?
             .into_values()
             .collect();
+    // 传统间接映射的 inode 还需释放间接块本身
+    inode_used_blocks.extend(resolve_inode_index_blocks(block_dev, &mut target_inode)?);
     inode_used_blocks.sort(); //排序block
     //link-1
     target_inode.i_links_count = target_inode.i_links_count.saturating_sub(1);
@@ -1433,13 +1604,13 @@ pub fn delete_file<B: BlockDevice>(
         for blk in inode_used_blocks {
             if let Err(e) = fs.free_block(block_dev, blk) {
                 warn!("free_block failed for blk {blk}: {e:?}");
-                return;
+                return Err(e);
             }
         }
         //释放inode
         if let Err(e) = fs.free_inode(block_dev, ino_num) {
             warn!("free_inode failed for inode {ino_num}: {e:?}");
-            return;
+            return Err(e);
         }
     } else {
         error!(
@@ -1468,21 +1639,24 @@ pub fn delete_file<B: BlockDevice>(
             "Dir entry '{child_name}' not found under parent {parent_path}, but inode/data already freed"
         );
     }
+    Ok(())
 }
 
 /// 根据数据块列表为普通文件 inode 构建块映射：
-/// - 否则使用传统直接块指针（i_block[0..]）。
+/// - 文件系统支持 extent 时构建 extent 树；
+/// - 否则使用传统直接/间接块指针（i_block[0..]）。
 pub fn build_file_block_mapping<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     inode: &mut Ext4Inode,
+    inode_num: u32,
     data_blocks: &[u64],
     block_dev: &mut Jbd2Dev<B>,
-) {
+) -> BlockDevResult<()> {
     if data_blocks.is_empty() {
         inode.i_blocks_lo = 0;
         inode.l_i_blocks_high = 0;
         inode.i_block = [0; 15];
-        return;
+        return Ok(());
     }
 
     if fs.superblock.has_extents() {
@@ -1526,13 +1700,21 @@ pub fn build_file_block_mapping<B: BlockDevice>(
         exts_vec.push(ext);
 
         // 构造一个叶子根节点，并通过 ExtentTree 将其写入 inode.i_block
-        let mut tree = ExtentTree::new(inode);
+        let mut tree = ExtentTree::new_with_ino(fs, inode, inode_num);
         for extend in exts_vec {
-            tree.insert_extent(fs, extend, block_dev).expect("Extend insert Failed!");
+            tree.insert_extent(fs, extend, block_dev)?;
         }
     } else {
-        error!("not support tranditional block pointer");
+        // 传统直接/间接块指针，数据块已由调用方计入 i_blocks；
+        // 复用的 inode 可能残留旧的块指针，先清空再建映射
+        inode.i_flags &= !Ext4Inode::EXT4_EXTENTS_FL;
+        inode.i_block = [0; 15];
+        let mut map = BlockMap::new(inode);
+        for (lbn, &pblk) in data_blocks.iter().enumerate() {
+            map.set_block(fs, block_dev, lbn as u32, pblk)?;
+        }
     }
+    Ok(())
 }
 
 ///创建文件类型entry通用接口
@@ -1554,6 +1736,20 @@ pub fn mkfile_with_ino<B: BlockDevice>(
     path: &str,
     initial_data: Option<&[u8]>,
     file_type: Option<u8>,
+) -> Option<(u32, Ext4Inode)> {
+    fs.journaled(device, EXT4_DIR_TRANS_BLOCKS, |fs, device| {
+        do_mkfile_with_ino(device, fs, path, initial_data, file_type)
+    })
+    .ok()
+    .flatten()
+}
+
+fn do_mkfile_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+    initial_data: Option<&[u8]>,
+    file_type: Option<u8>,
 ) -> Option<(u32, Ext4Inode)> {
     // 规范化路径
     let norm_path = split_paren_child_and_tranlatevalid(path);
@@ -1609,19 +1805,20 @@ pub fn mkfile_with_ino<B: BlockDevice>(
 
 
 
+    // 启用 inline_data 时普通文件的初始数据在 inode 建好后按内联写入，放不下再转为数据块
+    let inline_initial = fs.superblock.has_inline_data()
+        && matches!(file_type, None | Some(Ext4DirEntry2::EXT4_FT_REG_FILE));
+
     // 如有初始数据，为文件分配一个或多个数据块并写入
     let mut data_blocks: Vec<u64> = Vec::new();
     let mut total_written: usize = 0;
-    if let Some(buf) = initial_data {
+    if !inline_initial
+        && let Some(buf) = initial_data
+    {
         let mut remaining = buf.len();
         let mut src_off = 0usize;
 
         while remaining > 0 {
-            // 如果未启用 extents，则最多只使用 12 个直接块
-            if !fs.superblock.has_extents() && data_blocks.len() >= 12 {
-                break;
-            }
-
             let blk = match fs.alloc_block(device) {
                 Ok(b) => b,
                 Err(e) => {
@@ -1630,10 +1827,10 @@ pub fn mkfile_with_ino<B: BlockDevice>(
                 }
             };
 
-            let write_len = core::cmp::min(remaining, BLOCK_SIZE);
+            let write_len = core::cmp::min(remaining, fs.block_size());
 
             // 将数据写入新分配的数据块，其余部分填零
-            fs.datablock_cache.modify_new(blk, |data| {
+            fs.datablock_cache.modify_new_data(blk, |data| {
                 for b in data.iter_mut() {
                     *b = 0;
                 }
@@ -1650,7 +1847,14 @@ pub fn mkfile_with_ino<B: BlockDevice>(
 
     // 构造新文件 inode 的内存版本，然后通过 modify_inode 一次性写回
     let imode;
-    let mut new_inode = Ext4Inode::default();
+    // 从分配时初始化好的 inode（i_extra_isize、时间戳）开始填写
+    let mut new_inode = match fs.get_inode_by_num(device, new_file_ino) {
+        Ok(inode) => inode,
+        Err(e) => {
+            error!("mkfile get_inode_by_num failed path={path} ino={new_file_ino} err={e:?}");
+            return None;
+        }
+    };
     if file_type.is_some() {
         imode = match file_type.unwrap(){
             Ext4DirEntry2::EXT4_FT_SYMLINK => Ext4Inode::S_IFLNK | 0o777,
@@ -1681,7 +1885,7 @@ pub fn mkfile_with_ino<B: BlockDevice>(
     if !data_blocks.is_empty() {
         // 有初始数据：多块或单块文件
         let used_databyte = data_blocks.len() as u64;
-        let iblocks_used = used_databyte.saturating_mul(BLOCK_SIZE as u64 / 512) as u64;
+        let iblocks_used = used_databyte.saturating_mul(fs.block_size() as u64 / 512);
         let used_blocks_lo = iblocks_used as u32;
         //let used_blocks_hi = (iblocks_used as u64 >> 32) as u16;
         new_inode.i_size_lo = size_lo;
@@ -1689,7 +1893,10 @@ pub fn mkfile_with_ino<B: BlockDevice>(
         new_inode.i_blocks_lo = used_blocks_lo;
         new_inode.l_i_blocks_high = (iblocks_used as u64 >> 32) as u16;
 
-        build_file_block_mapping(fs, &mut new_inode, &data_blocks, device);
+        if let Err(e) = build_file_block_mapping(fs, &mut new_inode, new_file_ino, &data_blocks, device) {
+            error!("mkfile build block mapping failed path={path} ino={new_file_ino} err={e:?}");
+            return None;
+        }
     } else {
         //无初始数据：空文件
         new_inode.i_size_lo = 0;
@@ -1713,6 +1920,17 @@ pub fn mkfile_with_ino<B: BlockDevice>(
         error!("mkfile modify_inode failed path={} ino={}", path, new_file_ino);
         return None;
     }
+    if let Err(e) = inherit_acl(fs, device, parent_ino_num, new_file_ino) {
+        error!("mkfile inherit default acl failed path={path} ino={new_file_ino} err={e:?}");
+        return None;
+    }
+    if inline_initial
+        && let Some(buf) = initial_data
+        && let Err(e) = write_file_with_ino(device, fs, new_file_ino, 0, buf)
+    {
+        error!("mkfile write initial data failed path={path} ino={new_file_ino} err={e:?}");
+        return None;
+    }
 
     //在父目录中插入一个普通文件类型的目录项（必要时自动扩展目录块）
 
@@ -1745,7 +1963,21 @@ pub fn mkfile_with_ino<B: BlockDevice>(
 
     // 返回新文件 inode
     match fs.get_inode_by_num(device, new_file_ino) {
-        Ok(inode) => Some((new_file_ino, inode)),
+        Ok(inode) => {
+            // 快速符号链接等把内容放在 i_block 里的 inode 不走 fast commit
+            if inode.is_file() {
+                let range = (!data_blocks.is_empty()).then(|| (0, data_blocks.len() as u32 - 1));
+                fs.fast_commit.track_inode(new_file_ino, range);
+                fs.fast_commit.track_dentry(FcRecord::Create(FcDentry {
+                    parent: parent_ino_num,
+                    ino: new_file_ino,
+                    name: child.as_bytes().to_vec(),
+                }));
+            } else {
+                fs.fast_commit.mark_ineligible();
+            }
+            Some((new_file_ino, inode))
+        }
         Err(e) => {
             error!(
                 "mkfile get_inode_by_num failed path={} ino={} err={:?} ({})",
@@ -1795,25 +2027,78 @@ pub fn write_file_with_ino<B: BlockDevice>(
     inode_num: u32,
     offset: u64,
     data: &[u8],
+) -> BlockDevResult<()> {
+    // data=journal 时数据块也进日志，大的写入按事务容量拆开，每段单独提交
+    let chunk = fs.journal_write_chunk(device).unwrap_or(data.len()).max(1);
+    for (idx, part) in data.chunks(chunk).enumerate() {
+        let part_offset = offset + (idx * chunk) as u64;
+        let credits = fs.data_trans_blocks(device, part.len() as u64);
+        fs.journaled(device, credits, |fs, device| {
+            do_write_file_with_ino(device, fs, inode_num, part_offset, part)?;
+            fs.touch_mtime_ctime(device, inode_num)
+        })??;
+    }
+    Ok(())
+}
+
+fn do_write_file_with_ino<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    data: &[u8],
 ) -> BlockDevResult<()> {
     if data.is_empty() {
         return Ok(());
     }
 
-    let mut inode = fs.get_inode_by_num(device, inode_num)?;
+    let inode = fs.get_inode_by_num(device, inode_num)?;
+    let block_bytes = fs.block_size() as u64;
+    let mut first_lbn = offset / block_bytes;
 
+    // 内联数据（或可改为内联的空文件）：放得下就直接写在 inode 内，否则先把原内容搬到数据块
+    if inode.has_inline_data() || can_inline_new_data(fs, &inode) {
+        if try_write_inline_data(fs, device, inode_num, offset, data)? {
+            return Ok(());
+        }
+        if inode.has_inline_data() {
+            let old = uninline_data(fs, device, inode_num)?;
+            if !old.is_empty() {
+                write_file_blocks(device, fs, inode_num, 0, &old)?;
+                first_lbn = 0;
+            }
+        }
+    }
+
+    write_file_blocks(device, fs, inode_num, offset, data)?;
+    let last_lbn = (offset + data.len() as u64 - 1) / block_bytes;
+    fs.fast_commit.track_inode(inode_num, Some((first_lbn as u32, last_lbn as u32)));
+    Ok(())
+}
+
+/// 按块映射写入（extent 或传统间接块），空洞处按需分配数据块，写到的 unwritten extent 转为已写入
+pub fn write_file_blocks<B: BlockDevice>(
+    device: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    inode_num: u32,
+    offset: u64,
+    data: &[u8],
+) -> BlockDevResult<()> {
+    let mut inode = fs.get_inode_by_num(device, inode_num)?;
 
     let old_size = inode.size() as u64;
-    let block_bytes = BLOCK_SIZE as u64;
+    let block_bytes = fs.block_size() as u64;
 
     // If extents are supported, make sure the inode has a valid extent header
     // before any extent-based operations. Some inodes may have EXTENTS flag set
-    // but the on-disk header is missing/invalid.
-    if fs.superblock.has_extents() {
-        if !inode.have_extend_header_and_use_extend() {
-            inode.i_flags |= Ext4Inode::EXT4_EXTENTS_FL;
-            inode.write_extend_header();
-        }
+    // but the on-disk header is missing/invalid. Inodes that still carry legacy
+    // indirect block pointers keep their mapping format.
+    if fs.superblock.has_extents()
+        && inode.i_block.iter().all(|&w| w == 0)
+        && !inode.have_extend_header_and_use_extend()
+    {
+        inode.i_flags |= Ext4Inode::EXT4_EXTENTS_FL;
+        inode.write_extend_header();
     }
 
     if offset > old_size {
@@ -1825,56 +2110,85 @@ pub fn write_file_with_ino<B: BlockDevice>(
     let start_lbn = offset / block_bytes;
     let end_lbn = (end - 1) / block_bytes;
 
-    // Extent files may be sparse. For writes that cross holes, allocate blocks on-demand.
-    if end > old_size {
-        if !fs.superblock.has_extents() || !inode.have_extend_header_and_use_extend() {
-            // 只在 extent 模式下支持扩展
-            return Err(BlockDevError::Unsupported);
-        }
-    }
-
+    // Files may be sparse. For writes that cross holes, allocate blocks on-demand.
+    // 只解析本次写入覆盖的块范围（extent 状态缓存）
+    let mapping = if inode.have_extend_header_and_use_extend() {
+        map_extent_range(fs, device, inode_num, &mut inode, start_lbn as u32, (end_lbn - start_lbn + 1) as u32)?
+    } else {
+        Vec::new()
+    };
     let mut blocks_map = if inode.have_extend_header_and_use_extend() {
-        Some(resolve_inode_block_allextend(fs, device, &mut inode)?)
+        let mut map = BTreeMap::new();
+        for es in mapping.iter().filter(|es| !es.is_hole()) {
+            for lbn in es.es_lblk..es.end() {
+                map.insert(lbn as u32, es.pblk() + (lbn - es.es_lblk));
+            }
+        }
+        Some(map)
     } else {
         None
     };
+    // 预分配（unwritten）的块内容未定义：先整块清零再写，写完后转换 extent
+    let unwritten: Vec<Ext4ExtentStatus> = mapping.into_iter().filter(|es| es.is_unwritten()).collect();
 
+    // 本次新分配的块 [fresh_start, fresh_end)，写入前整块清零
+    let (mut fresh_start, mut fresh_end) = (0u64, 0u64);
     for lbn in start_lbn..=end_lbn {
         let phys = if inode.have_extend_header_and_use_extend() {
             let map = blocks_map.as_mut().ok_or(BlockDevError::Corrupted)?;
-            if let Some(&b) = map.get(&(lbn as u32)) {
-                b
-            } else {
-                // Hole: allocate a new block and insert an extent for this single LBN.
-                let new_phys = fs.alloc_block(device)?;
-                fs.datablock_cache.modify_new(new_phys, |blk| {
-                    for b in blk.iter_mut() {
-                        *b = 0;
-                    }
-                });
+            if !map.contains_key(&(lbn as u32)) {
+                // Hole: allocate the whole run of holes at once and insert a single extent for it.
+                let run = (lbn..=end_lbn)
+                    .take_while(|l| !map.contains_key(&(*l as u32)))
+                    .count()
+                    .min(Ext4Extent::EXT_INIT_MAX_LEN as usize) as u32;
+                // 紧跟在前一个逻辑块的物理块之后分配，保持文件物理连续
+                let prev = match lbn.checked_sub(1) {
+                    Some(prev) if prev >= start_lbn => map.get(&(prev as u32)).copied(),
+                    Some(prev) => map_extent_range(fs, device, inode_num, &mut inode, prev as u32, 1)?
+                        .first()
+                        .and_then(|es| es.pblk_of(prev)),
+                    None => None,
+                };
+                let (new_phys, got) =
+                    fs.alloc_data_blocks(device, inode_num, lbn as u32, run, prev.map(|p| p + 1))?;
                 {
-                    let mut tree = ExtentTree::new(&mut inode);
-                    let ext = Ext4Extent::new(lbn as u32, new_phys, 1);
+                    let mut tree = ExtentTree::new_with_ino(fs, &mut inode, inode_num);
+                    let ext = Ext4Extent::new(lbn as u32, new_phys, got as u16);
                     tree.insert_extent(fs, ext, device)?;
                 }
-                map.insert(lbn as u32, new_phys);
+                for i in 0..got {
+                    map.insert(lbn as u32 + i, new_phys + i as u64);
+                }
 
-                let add_iblocks = (BLOCK_SIZE / 512) as u32;
-                inode.i_blocks_lo = inode.i_blocks_lo.saturating_add(add_iblocks);
+                let add_iblocks = (fs.block_size() / 512) as u64 * got as u64;
+                inode.i_blocks_lo = inode.i_blocks_lo.saturating_add(add_iblocks as u32);
                 inode.l_i_blocks_high =
-                    inode.l_i_blocks_high.saturating_add(((add_iblocks as u64) >> 32) as u16);
-
-
-                new_phys
+                    inode.l_i_blocks_high.saturating_add((add_iblocks >> 32) as u16);
+                fresh_start = lbn;
+                fresh_end = lbn + got as u64;
+            }
+            let b = *map.get(&(lbn as u32)).ok_or(BlockDevError::Corrupted)?;
+            if (fresh_start..fresh_end).contains(&lbn)
+                || unwritten.iter().any(|e| e.es_lblk <= lbn && lbn < e.end())
+            {
+                fs.datablock_cache.modify_new_data(b, |blk| blk.fill(0));
             }
+            b
         } else {
-            match resolve_inode_block(device, &mut inode, lbn as u32)? {
-                Some(b) => b as u64,
-                None => return Err(BlockDevError::Unsupported),
+            // 传统间接映射：空洞处分配数据块（必要时连带间接块），i_blocks 由 BlockMap 维护
+            let (new_phys, fresh) = BlockMap::new(&mut inode).map_or_alloc(fs, device, lbn as u32)?;
+            if fresh {
+                fs.datablock_cache.modify_new_data(new_phys, |blk| {
+                    for b in blk.iter_mut() {
+                        *b = 0;
+                    }
+                });
             }
+            new_phys
         };
 
-        fs.datablock_cache.modify(device, phys as u64, |blk| {
+        fs.datablock_cache.modify_data(device, phys as u64, |blk| {
             let block_start = lbn * block_bytes;
             let block_end = block_start + block_bytes;
 
@@ -1892,6 +2206,11 @@ pub fn write_file_with_ino<B: BlockDevice>(
         })?;
     }
 
+    if !unwritten.is_empty() {
+        let mut tree = ExtentTree::new_with_ino(fs, &mut inode, inode_num);
+        tree.convert_to_written(fs, device, start_lbn as u32, (end_lbn - start_lbn + 1) as u32)?;
+    }
+
     if end > old_size {
         inode.i_size_lo = (end as u64 & 0xffff_ffff) as u32;
         inode.i_size_high = ((end as u64) >> 32) as u32;
@@ -1,20 +1,36 @@
 //! Directory entry HashTree implementation
 //!
 //! Provides hash tree-based directory lookup functionality, replacing linear search to improve performance for large directories
-//! Supports Ext4 HTree index format, including multiple hash algorithms
+//! Supports Ext4 HTree index format, including multiple hash algorithms, and keeps the
+//! index up to date when entries are inserted into or removed from an indexed directory
 
 use crate::ext4_backend::blockdev::*;
-use crate::ext4_backend::config::*;
+use crate::ext4_backend::checksum::*;
+use crate::ext4_backend::dir::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
 use crate::ext4_backend::entries::*;
+use crate::ext4_backend::error::*;
 use crate::ext4_backend::ext4::*;
 use crate::ext4_backend::loopfile::*;
+use crate::ext4_backend::superblock::*;
 
+use alloc::vec;
 use alloc::vec::Vec;
 use log::error;
 use log::{debug,  warn};
 
+/// Offset of dx_root_info inside the root block (right after "." and "..")
+const DX_ROOT_INFO_OFFSET: usize = 24;
+/// Offset of the count/limit header inside the root block
+const DX_ROOT_COUNT_OFFSET: usize = 32;
+/// Offset of the count/limit header inside an interior node (after the fake dirent)
+const DX_NODE_COUNT_OFFSET: usize = 8;
+/// Size of one on-disk dx_entry
+const DX_ENTRY_SIZE: usize = 8;
+/// Size of the dx_tail reserved at the end of index blocks when metadata_csum is on
+const DX_TAIL_SIZE: usize = 8;
+
 /// Hash tree error type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashTreeError {
@@ -49,21 +65,88 @@ impl core::fmt::Display for HashTreeError {
 #[derive(Debug)]
 pub struct HashTreeSearchResult {
     /// Found directory entry
-    pub entry: Ext4DirEntryInfo<'static>,
+    pub entry: Ext4DirEntry2,
     /// Block number where entry is located
-    pub block_num: u32,
+    pub block_num: u64,
     /// Offset within the block
     pub offset: usize,
 }
 
+/// One index block (dx_root or dx_node) on the path from the root to a leaf
+#[derive(Debug, Clone)]
+struct DxFrame {
+    /// Physical block number
+    phys: u64,
+    /// Offset of the count/limit header
+    count_offset: usize,
+    /// Maximum number of entries the block can hold
+    limit: usize,
+    /// Index entries; the hash of entries[0] is implicit (its slot holds count/limit)
+    entries: Vec<Ext4DxEntry>,
+    /// Entry followed towards the leaf
+    at: usize,
+}
+
+impl DxFrame {
+    fn is_full(&self) -> bool {
+        self.entries.len() >= self.limit
+    }
+
+    /// Follow the last entry whose hash is <= `hash`
+    fn select(&mut self, hash: u32) {
+        self.at = self.entries[1..].partition_point(|e| e.hash <= hash);
+    }
+
+    /// Child block (logical) of the entry currently followed
+    fn child(&self) -> u32 {
+        self.entries[self.at].block
+    }
+
+    /// Write count/limit and all entries back into the block
+    fn store(&self, data: &mut [u8]) {
+        let co = self.count_offset;
+        write_u16_le(self.limit as u16, &mut data[co..co + 2]);
+        write_u16_le(self.entries.len() as u16, &mut data[co + 2..co + 4]);
+        write_u32_le(self.entries[0].block, &mut data[co + 4..co + 8]);
+        for i in 1..self.limit {
+            let off = co + i * DX_ENTRY_SIZE;
+            let (hash, block) = match self.entries.get(i) {
+                Some(e) => (e.hash, e.block),
+                None => (0, 0),
+            };
+            write_u32_le(hash, &mut data[off..off + 4]);
+            write_u32_le(block, &mut data[off + 4..off + 8]);
+        }
+    }
+}
+
+/// Index path resolved for one name
+struct DxPath {
+    /// Frames from the root (index 0) down to the bottom index node
+    frames: Vec<DxFrame>,
+    /// Hash of the name
+    hash: u32,
+    /// Hash version recorded in dx_root_info
+    hash_version: u8,
+}
+
+impl DxPath {
+    /// Logical block of the leaf the name hashes into
+    fn leaf(&self) -> u32 {
+        self.frames[self.frames.len() - 1].child()
+    }
+}
+
 /// Hash tree manager
 pub struct HashTreeManager {
     /// Hash seed (from superblock)
     hash_seed: [u32; 4],
     /// Hash version
     hash_version: u8,
-    /// Number of indirect levels
+    /// Number of indirect levels; 0 means the limit implied by the filesystem features
     indirect_levels: u8,
+    /// Hash names with unsigned chars (s_flags EXT2_FLAGS_UNSIGNED_HASH)
+    unsigned_hash: bool,
 }
 
 impl HashTreeManager {
@@ -73,14 +156,22 @@ impl HashTreeManager {
             hash_seed,
             hash_version,
             indirect_levels,
+            unsigned_hash: false,
         }
     }
 
+    /// Hash names with unsigned chars instead of signed ones
+    pub fn with_unsigned_hash(mut self, unsigned_hash: bool) -> Self {
+        self.unsigned_hash = unsigned_hash;
+        self
+    }
+
     /// Search for filename in directory (using hash tree)
     pub fn lookup<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
         dir_inode: &Ext4Inode,
         target_name: &[u8],
     ) -> Result<HashTreeSearchResult, HashTreeError> {
@@ -92,182 +183,662 @@ impl HashTreeManager {
         // 1. Check if directory has hash tree index enabled
         if !dir_inode.is_htree_indexed() {
            //warn!("Directory does not have hash tree index enabled, falling back to linear search");
-            return self.fallback_to_linear_search(fs, block_dev, dir_inode, target_name);
+            return self.fallback_to_linear_search(fs, block_dev, dir_ino, dir_inode, target_name);
         }
 
-        // 2. Calculate hash value of target filename
-        let target_hash =
-            htree_dir::calculate_hash(target_name, self.hash_version, &self.hash_seed);
-        debug!("Target hash value: 0x{target_hash:08x}");
-
-        // 3. Read root node
-        let root_block = self.get_root_block( block_dev, dir_inode)?;
-        let root_data = self.read_block_data(fs, block_dev, root_block)?;
-
-        // 4. Parse root node
-        let root_info = self.parse_root_node(&root_data)?;
-
-        // 5. Search in hash tree
-        match self.search_in_hash_tree(fs, block_dev, &root_info, target_hash, target_name) {
-            Ok(result) => Ok(result),
+        // 2. Walk the index down to the leaf covering the name hash
+        let mut path = match self.probe(fs, block_dev, dir_ino, dir_inode, target_name, None) {
+            Ok(path) => path,
             Err(e) => {
                 warn!(
                     "Hash tree lookup failed: {e}, falling back to linear search"
                 );
-                self.fallback_to_linear_search(fs, block_dev, dir_inode, target_name)
+                return self.fallback_to_linear_search(fs, block_dev, dir_ino, dir_inode, target_name);
+            }
+        };
+        debug!("Target hash value: 0x{:08x}", path.hash);
+
+        // 3. Search the leaf, continuing into following leaves on hash collisions
+        loop {
+            let phys = self.map_block(fs, block_dev, dir_ino, dir_inode, path.leaf())?;
+            let data = self.read_block_data(fs, block_dev, phys)?;
+            if let Ok(result) = self.search_in_leaf_data(&data, target_name, phys) {
+                return Ok(result);
+            }
+            if !self.next_leaf(fs, block_dev, dir_ino, dir_inode, &mut path, None)? {
+                return Err(HashTreeError::EntryNotFound);
+            }
+        }
+    }
+
+    /// Insert `entry` into an indexed directory: the entry goes into the leaf its hash
+    /// maps to, splitting that leaf and growing the index when it is full.
+    /// Returns `Ok(false)` when the index cannot be used and the caller should fall
+    /// back to a linear insert.
+    pub fn insert_entry<B: BlockDevice>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        entry: &Ext4DirEntry2,
+    ) -> BlockDevResult<bool> {
+        let seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        let name = &entry.name[..entry.name_len as usize];
+        let mut path = match self.probe(fs, block_dev, dir_ino, dir_inode, name, seed) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Hash tree of dir {dir_ino} unusable for insert: {e}");
+                return Ok(false);
             }
+        };
+
+        let usable_len = fs.dir_block_usable_len();
+        let leaf_phys = self.map_leaf(fs, block_dev, dir_ino, dir_inode, path.leaf())?;
+        let mut inserted = false;
+        fs.datablock_cache.modify(block_dev, leaf_phys, |data| {
+            inserted = classic_dir::insert_entry(data, usable_len, entry);
+        })?;
+        if inserted {
+            fs.update_dir_block_csum(block_dev, dir_ino, leaf_phys)?;
+            return Ok(true);
         }
+
+        // Leaf is full: make sure the bottom index node can take one more entry, then split
+        self.make_room(fs, block_dev, dir_ino, dir_inode, &mut path)?;
+        let target = self.split_leaf(fs, block_dev, dir_ino, dir_inode, &mut path, leaf_phys)?;
+
+        fs.datablock_cache.modify(block_dev, target, |data| {
+            inserted = classic_dir::insert_entry(data, usable_len, entry);
+        })?;
+        if !inserted {
+            error!("Hash tree insert into dir {dir_ino}: no room after leaf split");
+            return Err(BlockDevError::NoSpace);
+        }
+        fs.update_dir_block_csum(block_dev, dir_ino, target)?;
+        Ok(true)
     }
 
-    /// Get hash tree root block number
-    fn get_root_block<B: BlockDevice>(
+    /// Remove the entry named `name` from an indexed directory. Only the leaf is
+    /// changed; index entries stay in place, like the kernel does.
+    /// Returns `Ok(None)` when the index cannot be used, otherwise whether it was found.
+    pub fn remove_entry<B: BlockDevice>(
         &self,
+        fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
         dir_inode: &Ext4Inode,
-    ) -> Result<u32, HashTreeError> {
-        // Root block is usually the first data block of the directory
-        match resolve_inode_block(block_dev, &mut dir_inode.clone(), 0) {
-            Ok(Some(block)) => Ok(block),
-            Ok(None) => Err(HashTreeError::InvalidHashTree),
-            Err(_) => Err(HashTreeError::BlockOutOfRange),
+        name: &[u8],
+    ) -> BlockDevResult<Option<bool>> {
+        let seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        let mut path = match self.probe(fs, block_dev, dir_ino, dir_inode, name, seed) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Hash tree of dir {dir_ino} unusable for remove: {e}");
+                return Ok(None);
+            }
+        };
+
+        let usable_len = fs.dir_block_usable_len();
+        loop {
+            let phys = self.map_leaf(fs, block_dev, dir_ino, dir_inode, path.leaf())?;
+            let mut removed = false;
+            fs.datablock_cache.modify(block_dev, phys, |data| {
+                removed = classic_dir::remove_entry(data, usable_len, name);
+            })?;
+            if removed {
+                fs.update_dir_block_csum(block_dev, dir_ino, phys)?;
+                return Ok(Some(true));
+            }
+            match self.next_leaf(fs, block_dev, dir_ino, dir_inode, &mut path, seed) {
+                Ok(true) => {}
+                Ok(false) => return Ok(Some(false)),
+                Err(e) => {
+                    warn!("Hash tree of dir {dir_ino} unusable for remove: {e}");
+                    return Ok(None);
+                }
+            }
         }
     }
 
-    /// Read block data
-    fn read_block_data<B: BlockDevice>(
+    /// Convert a full single-block linear directory into an indexed one (like the
+    /// kernel's make_indexed_dir): the entries after ".." move to a new leaf, block 0
+    /// becomes the dx_root and `entry` is then inserted through the index.
+    /// Returns `Ok(false)` when block 0 does not start with "." and ".." as expected.
+    pub fn make_indexed_dir<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
-        block_num: u32,
-    ) -> Result<Vec<u8>, HashTreeError> {
-        match fs.datablock_cache.get_or_load(block_dev, block_num as u64) {
-            Ok(cached_block) => Ok(cached_block.data.clone()),
-            Err(_) => Err(HashTreeError::BlockOutOfRange),
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        entry: &Ext4DirEntry2,
+    ) -> BlockDevResult<bool> {
+        let block_size = fs.block_size();
+        let usable_len = fs.dir_block_usable_len();
+        let has_tail = usable_len < block_size;
+        let root_phys = self.map_leaf(fs, block_dev, dir_ino, dir_inode, 0)?;
+
+        let mut moved: Vec<(u32, Ext4DirEntry2)> = Vec::new();
+        {
+            let data = &fs.datablock_cache.get_or_load(block_dev, root_phys)?.data;
+            let dot_len = read_u16_le(&data[4..6]) as usize;
+            let dotdot_len = read_u16_le(&data[16..18]) as usize;
+            if dot_len != 12 || data[6] != 1 || data[8] != b'.' || data[18] != 2 || &data[20..22] != b".." {
+                warn!("Dir {dir_ino} block 0 does not start with \".\" and \"..\", not indexing");
+                return Ok(false);
+            }
+            let start = 12 + dotdot_len;
+            if start < usable_len {
+                for (info, _) in DirEntryIterator::new(&data[start..usable_len]) {
+                    moved.push((0, Ext4DirEntry2::new(info.inode, 0, info.file_type, info.name)));
+                }
+            }
+        }
+
+        let hash_version = if self.hash_version <= Ext4DxRootInfo::DX_HASH_TEA {
+            self.hash_version
+        } else {
+            Ext4DxRootInfo::DX_HASH_HALF_MD4
+        };
+        let (leaf_lblk, leaf_phys) = append_dir_block(fs, block_dev, dir_ino, dir_inode)?;
+        fs.datablock_cache.modify_new(leaf_phys, |data| {
+            write_leaf(data, &moved, usable_len, has_tail);
+        });
+        fs.update_dir_block_csum(block_dev, dir_ino, leaf_phys)?;
+
+        let root = DxFrame {
+            phys: root_phys,
+            count_offset: DX_ROOT_COUNT_OFFSET,
+            limit: Self::root_limit(fs),
+            entries: vec![Ext4DxEntry {
+                hash: 0,
+                block: leaf_lblk,
+            }],
+            at: 0,
+        };
+        let csum_seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        self.store_frame(fs, block_dev, &root, csum_seed, |data| {
+            data[12 + 8 + 2..].fill(0);
+            write_u16_le(rec_len_to_disk(block_size - 12), &mut data[16..18]);
+            let info = Ext4DxRootInfo {
+                reserved_zero: 0,
+                hash_version,
+                info_length: Ext4DxRootInfo::INFO_LENGTH,
+                indirect_levels: 0,
+                unused_flags: 0,
+            };
+            write_u32_le(info.reserved_zero, &mut data[DX_ROOT_INFO_OFFSET..DX_ROOT_INFO_OFFSET + 4]);
+            data[DX_ROOT_INFO_OFFSET + 4] = info.hash_version;
+            data[DX_ROOT_INFO_OFFSET + 5] = info.info_length;
+            data[DX_ROOT_INFO_OFFSET + 6] = info.indirect_levels;
+            data[DX_ROOT_INFO_OFFSET + 7] = info.unused_flags;
+        })?;
+
+        dir_inode.i_flags |= Ext4Inode::EXT4_INDEX_FL;
+        let flags = dir_inode.i_flags;
+        fs.modify_inode(block_dev, dir_ino, |inode| inode.i_flags = flags)?;
+        debug!(
+            "Dir {dir_ino} converted to hash tree ({} entries moved to lblk {leaf_lblk})",
+            moved.len()
+        );
+
+        if self.insert_entry(fs, block_dev, dir_ino, dir_inode, entry)? {
+            Ok(true)
+        } else {
+            error!("Freshly indexed dir {dir_ino} rejected insert");
+            Err(BlockDevError::Corrupted)
         }
     }
 
-    /// Parse root node
-    fn parse_root_node(&self, data: &[u8]) -> Result<HashTreeNode, HashTreeError> {
-        if data.len() < core::mem::size_of::<Ext4DxRoot>() {
-            return Err(HashTreeError::BufferTooSmall);
+    /// Deepest index level allowed: 2 levels below the root with largedir, else 1
+    fn max_levels(&self, fs: &Ext4FileSystem) -> u8 {
+        let fs_max = if fs
+            .superblock
+            .has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_LARGEDIR)
+        {
+            3
+        } else {
+            2
+        };
+        match self.indirect_levels {
+            0 => fs_max,
+            n => n.min(fs_max),
         }
+    }
 
-        // Parse root node info
-        let dot = Ext4DirEntryInfo::parse_from_bytes(&data[0..8])
-            .ok_or(HashTreeError::CorruptedHashTree)?;
+    /// Hash `name` with the version of this directory's root
+    fn name_hash(&self, name: &[u8], root_hash_version: u8) -> u32 {
+        let mut version = root_hash_version;
+        if self.unsigned_hash && version <= Ext4DxRootInfo::DX_HASH_TEA {
+            version += 3;
+        }
+        htree_dir::calculate_hash(name, version, &self.hash_seed)
+    }
 
-        let dotdot = Ext4DirEntryInfo::parse_from_bytes(&data[dot.inode as usize..])
-            .ok_or(HashTreeError::CorruptedHashTree)?;
+    /// Number of dx entries that fit in the root block
+    fn root_limit(fs: &Ext4FileSystem) -> usize {
+        let tail = if fs.superblock.has_metadata_csum() { DX_TAIL_SIZE } else { 0 };
+        (fs.block_size() - DX_ROOT_COUNT_OFFSET - tail) / DX_ENTRY_SIZE
+    }
 
-        // Extract root info
-        let info_offset = dot.inode as usize + dotdot.inode as usize;
-        if info_offset + core::mem::size_of::<Ext4DxRootInfo>() > data.len() {
-            return Err(HashTreeError::CorruptedHashTree);
+    /// Number of dx entries that fit in an interior node
+    fn node_limit(fs: &Ext4FileSystem) -> usize {
+        let tail = if fs.superblock.has_metadata_csum() { DX_TAIL_SIZE } else { 0 };
+        (fs.block_size() - DX_NODE_COUNT_OFFSET - tail) / DX_ENTRY_SIZE
+    }
+
+    /// Walk from dx_root down to the bottom index node for `name`
+    /// (index blocks are checksum-verified when `csum_seed` is given)
+    fn probe<B: BlockDevice>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &Ext4Inode,
+        name: &[u8],
+        csum_seed: Option<u32>,
+    ) -> Result<DxPath, HashTreeError> {
+        let root_phys = self.map_block(fs, block_dev, dir_ino, dir_inode, 0)?;
+        let data = self.read_block_data(fs, block_dev, root_phys)?;
+        if dx_count_offset(&data) != Some(DX_ROOT_COUNT_OFFSET) {
+            return Err(HashTreeError::InvalidHashTree);
         }
 
-        let info_bytes = &data[info_offset..info_offset + core::mem::size_of::<Ext4DxRootInfo>()];
-        let hash_version = info_bytes[5]; // hash_version field is at offset 5
-        let indirect_levels = info_bytes[6]; // indirect_levels field is at offset 6
+        let info = &data[DX_ROOT_INFO_OFFSET..DX_ROOT_COUNT_OFFSET];
+        let hash_version = info[4];
+        let indirect_levels = info[6];
+        if read_u32_le(&info[0..4]) != 0 || info[5] != Ext4DxRootInfo::INFO_LENGTH {
+            return Err(HashTreeError::InvalidHashTree);
+        }
+        if hash_version > Ext4DxRootInfo::DX_HASH_TEA {
+            return Err(HashTreeError::UnsupportedHashVersion);
+        }
+        if indirect_levels >= self.max_levels(fs) {
+            error!(
+                "Hash tree has {} indirect levels, more than supported (default hash v{})",
+                indirect_levels, self.hash_version
+            );
+            return Err(HashTreeError::InvalidHashTree);
+        }
 
-        // Parse hash entries
-        let entries_offset = info_offset + core::mem::size_of::<Ext4DxRootInfo>();
-        let entries = self.parse_dx_entries(&data[entries_offset..])?;
+        let hash = self.name_hash(name, hash_version);
+        let mut frames = Vec::new();
+        let mut frame = self.parse_frame(fs, root_phys, &data, DX_ROOT_COUNT_OFFSET, csum_seed)?;
+        loop {
+            frame.select(hash);
+            let child = frame.child();
+            frames.push(frame);
+            if frames.len() > indirect_levels as usize {
+                break;
+            }
+            let phys = self.map_block(fs, block_dev, dir_ino, dir_inode, child)?;
+            let data = self.read_block_data(fs, block_dev, phys)?;
+            frame = self.parse_frame(fs, phys, &data, DX_NODE_COUNT_OFFSET, csum_seed)?;
+        }
 
-        Ok(HashTreeNode::Root {
+        Ok(DxPath {
+            frames,
+            hash,
             hash_version,
-            indirect_levels,
-            entries,
         })
     }
 
-    /// Parse DX entry array
-    fn parse_dx_entries(&self, data: &[u8]) -> Result<Vec<Ext4DxEntry>, HashTreeError> {
-        let mut entries = Vec::new();
-        let mut offset = 0;
+    /// Parse one index block and check it against the expected geometry
+    fn parse_frame(
+        &self,
+        fs: &Ext4FileSystem,
+        phys: u64,
+        data: &[u8],
+        count_offset: usize,
+        csum_seed: Option<u32>,
+    ) -> Result<DxFrame, HashTreeError> {
+        if dx_count_offset(data) != Some(count_offset) {
+            return Err(HashTreeError::CorruptedHashTree);
+        }
+        let expected_limit = if count_offset == DX_ROOT_COUNT_OFFSET {
+            Self::root_limit(fs)
+        } else {
+            Self::node_limit(fs)
+        };
+        let limit = read_u16_le(&data[count_offset..]) as usize;
+        let count = read_u16_le(&data[count_offset + 2..]) as usize;
+        if limit != expected_limit || count == 0 || count > limit {
+            return Err(HashTreeError::CorruptedHashTree);
+        }
+        if let Some(seed) = csum_seed
+            && !verify_dx_block_csum(seed, data, count_offset)
+        {
+            error!("Hash tree block {phys} checksum mismatch");
+            return Err(HashTreeError::CorruptedHashTree);
+        }
 
-        while offset + core::mem::size_of::<Ext4DxEntry>() <= data.len() {
-            let hash = read_u32_le(&data[offset..offset + 4]);
-            let block = read_u32_le(&data[offset + 4..offset + 8]);
+        let mut entries = vec![Ext4DxEntry {
+            hash: 0,
+            block: read_u32_le(&data[count_offset + 4..]),
+        }];
+        let end = count_offset + count * DX_ENTRY_SIZE;
+        entries.extend(self.parse_dx_entries(&data[count_offset + DX_ENTRY_SIZE..end])?);
+        if entries.len() != count {
+            return Err(HashTreeError::CorruptedHashTree);
+        }
 
-            if block == 0 {
+        Ok(DxFrame {
+            phys,
+            count_offset,
+            limit,
+            entries,
+            at: 0,
+        })
+    }
+
+    /// Step to the next leaf if it continues the run of `path.hash`
+    /// (same as ext4_htree_next_block); reloads the index frames below the step point
+    fn next_leaf<B: BlockDevice>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &Ext4Inode,
+        path: &mut DxPath,
+        csum_seed: Option<u32>,
+    ) -> Result<bool, HashTreeError> {
+        let mut depth = path.frames.len();
+        loop {
+            if depth == 0 {
+                return Ok(false);
+            }
+            let frame = &mut path.frames[depth - 1];
+            if frame.at + 1 < frame.entries.len() {
+                frame.at += 1;
                 break;
             }
+            depth -= 1;
+        }
 
-            entries.push(Ext4DxEntry { hash, block });
-            offset += core::mem::size_of::<Ext4DxEntry>();
+        let bhash = path.frames[depth - 1].entries[path.frames[depth - 1].at].hash;
+        if bhash & !1 != path.hash {
+            return Ok(false);
         }
 
-        Ok(entries)
+        for d in depth..path.frames.len() {
+            let child = path.frames[d - 1].child();
+            let phys = self.map_block(fs, block_dev, dir_ino, dir_inode, child)?;
+            let data = self.read_block_data(fs, block_dev, phys)?;
+            path.frames[d] = self.parse_frame(fs, phys, &data, DX_NODE_COUNT_OFFSET, csum_seed)?;
+        }
+        Ok(true)
     }
 
-    /// Search in hash tree
-    fn search_in_hash_tree<B: BlockDevice>(
+    /// Guarantee the bottom index node has a free slot, splitting full nodes
+    /// upwards and adding a level under the root when the root itself is full
+    fn make_room<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
-        node: &HashTreeNode,
-        target_hash: u32,
-        target_name: &[u8],
-    ) -> Result<HashTreeSearchResult, HashTreeError> {
-        match node {
-            HashTreeNode::Root { entries, .. } => {
-                self.search_in_entries(fs, block_dev, entries, target_hash, target_name, 0)
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        path: &mut DxPath,
+    ) -> BlockDevResult<()> {
+        loop {
+            // Lowest level from which every frame down to the bottom is full
+            let mut level = path.frames.len();
+            while level > 0 && path.frames[level - 1].is_full() {
+                level -= 1;
             }
-            HashTreeNode::Internal { entries, .. } => {
-                self.search_in_entries(fs, block_dev, entries, target_hash, target_name, 0)
+            if level == path.frames.len() {
+                return Ok(());
             }
-            HashTreeNode::Leaf { block_num, .. } => {
-                self.search_in_leaf_block(fs, block_dev, *block_num, target_name)
+
+            if level == 0 {
+                if path.frames.len() >= self.max_levels(fs) as usize {
+                    error!("Hash tree of dir {dir_ino} is full");
+                    return Err(BlockDevError::NoSpace);
+                }
+                self.add_level(fs, block_dev, dir_ino, dir_inode, path)?;
+            } else {
+                self.split_node(fs, block_dev, dir_ino, dir_inode, path, level)?;
             }
         }
     }
 
-    /// Search in entry list
-    fn search_in_entries<B: BlockDevice>(
+    /// Move all root entries into a new interior node and point the root at it
+    fn add_level<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
-        entries: &[Ext4DxEntry],
-        target_hash: u32,
-        target_name: &[u8],
-        level: u32,
-    ) -> Result<HashTreeSearchResult, HashTreeError> {
-        // Find appropriate entry (largest entry with hash <= target hash)
-        let mut selected_entry = None;
-        for entry in entries {
-            if entry.hash <= target_hash {
-                selected_entry = Some(entry);
-            } else {
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        path: &mut DxPath,
+    ) -> BlockDevResult<()> {
+        let csum_seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        let (lblk, phys) = append_dir_block(fs, block_dev, dir_ino, dir_inode)?;
+        let root = &mut path.frames[0];
+        let node = DxFrame {
+            phys,
+            count_offset: DX_NODE_COUNT_OFFSET,
+            limit: Self::node_limit(fs),
+            entries: core::mem::take(&mut root.entries),
+            at: root.at,
+        };
+        root.entries.push(Ext4DxEntry { hash: 0, block: lblk });
+        root.at = 0;
+
+        self.write_new_node(fs, &node, csum_seed);
+        let root = path.frames[0].clone();
+        self.store_frame(fs, block_dev, &root, csum_seed, |data| {
+            data[DX_ROOT_INFO_OFFSET + 6] += 1;
+        })?;
+        debug!("Hash tree of dir {dir_ino} grew a level (node lblk {lblk})");
+        path.frames.insert(1, node);
+        Ok(())
+    }
+
+    /// Split the full node at `level` in half and add the upper half to its parent
+    fn split_node<B: BlockDevice>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        path: &mut DxPath,
+        level: usize,
+    ) -> BlockDevResult<()> {
+        let csum_seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        let (lblk, phys) = append_dir_block(fs, block_dev, dir_ino, dir_inode)?;
+        let old = &mut path.frames[level];
+        let half = old.entries.len() / 2;
+        let mut new = DxFrame {
+            phys,
+            count_offset: DX_NODE_COUNT_OFFSET,
+            limit: old.limit,
+            entries: old.entries.split_off(half),
+            at: 0,
+        };
+        let hash2 = new.entries[0].hash;
+
+        let parent = &mut path.frames[level - 1];
+        parent
+            .entries
+            .insert(parent.at + 1, Ext4DxEntry { hash: hash2, block: lblk });
+
+        self.write_new_node(fs, &new, csum_seed);
+        let old = path.frames[level].clone();
+        self.store_frame(fs, block_dev, &old, csum_seed, |_| {})?;
+        let parent = path.frames[level - 1].clone();
+        self.store_frame(fs, block_dev, &parent, csum_seed, |_| {})?;
+
+        // Keep the path on whichever half now covers the hash
+        if old.at >= half {
+            new.at = old.at - half;
+            path.frames[level] = new;
+            path.frames[level - 1].at += 1;
+        }
+        Ok(())
+    }
+
+    /// Split a full leaf by hash into itself and a new block, add the new block to
+    /// the bottom index node, and return the leaf (physical) where `path.hash` belongs
+    fn split_leaf<B: BlockDevice>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &mut Ext4Inode,
+        path: &mut DxPath,
+        leaf_phys: u64,
+    ) -> BlockDevResult<u64> {
+        let csum_seed = fs.inode_csum_seed(dir_ino, dir_inode.i_generation);
+        let usable_len = fs.dir_block_usable_len();
+        let has_tail = usable_len < fs.block_size();
+
+        let mut map: Vec<(u32, Ext4DirEntry2)> = Vec::new();
+        {
+            let data = &fs.datablock_cache.get_or_load(block_dev, leaf_phys)?.data;
+            for (info, _) in DirEntryIterator::new(&data[..usable_len]) {
+                let hash = self.name_hash(info.name, path.hash_version);
+                map.push((hash, Ext4DirEntry2::new(info.inode, 0, info.file_type, info.name)));
+            }
+        }
+        if map.len() < 2 {
+            error!("Hash tree leaf {leaf_phys} of dir {dir_ino} is full but cannot be split");
+            return Err(BlockDevError::Corrupted);
+        }
+        map.sort_by_key(|(hash, _)| *hash);
+
+        // Move entries from the end until about half of the block, size-wise
+        let count = map.len();
+        let mut size = 0usize;
+        let mut moved = 0usize;
+        for (_, e) in map.iter().rev() {
+            let len = Ext4DirEntry2::entry_len(e.name_len) as usize;
+            if size + len / 2 > usable_len / 2 {
                 break;
             }
+            size += len;
+            moved += 1;
         }
+        let split = if moved + 1 < count { count - moved } else { count / 2 };
+        let hash2 = map[split].0;
+        let continued = (hash2 == map[split - 1].0) as u32;
+
+        let (new_lblk, new_phys) = append_dir_block(fs, block_dev, dir_ino, dir_inode)?;
+        fs.datablock_cache.modify_new(new_phys, |data| {
+            write_leaf(data, &map[split..], usable_len, has_tail);
+        });
+        fs.datablock_cache.modify(block_dev, leaf_phys, |data| {
+            write_leaf(data, &map[..split], usable_len, has_tail);
+        })?;
+        fs.update_dir_block_csum(block_dev, dir_ino, new_phys)?;
+        fs.update_dir_block_csum(block_dev, dir_ino, leaf_phys)?;
+
+        let bottom = path.frames.len() - 1;
+        let frame = &mut path.frames[bottom];
+        frame.entries.insert(
+            frame.at + 1,
+            Ext4DxEntry {
+                hash: hash2 | continued,
+                block: new_lblk,
+            },
+        );
+        let frame = frame.clone();
+        self.store_frame(fs, block_dev, &frame, csum_seed, |_| {})?;
+
+        Ok(if path.hash >= hash2 { new_phys } else { leaf_phys })
+    }
 
-        let entry = selected_entry.ok_or(HashTreeError::EntryNotFound)?;
+    /// Write an index frame back to its block (after `extra` edits) and refresh its dx_tail
+    fn store_frame<B: BlockDevice, F: FnOnce(&mut [u8])>(
+        &self,
+        fs: &mut Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        frame: &DxFrame,
+        csum_seed: Option<u32>,
+        extra: F,
+    ) -> BlockDevResult<()> {
+        fs.datablock_cache.modify(block_dev, frame.phys, |data| {
+            extra(data);
+            frame.store(data);
+            if let Some(seed) = csum_seed {
+                set_dx_block_csum(seed, data, frame.count_offset);
+            }
+        })
+    }
 
-        // Read target block
-        let block_data = self.read_block_data(fs, block_dev, entry.block)?;
+    /// Initialise a freshly allocated interior node block
+    fn write_new_node(&self, fs: &mut Ext4FileSystem, frame: &DxFrame, csum_seed: Option<u32>) {
+        let block_size = fs.block_size();
+        fs.datablock_cache.modify_new(frame.phys, |data| {
+            data.fill(0);
+            write_u16_le(rec_len_to_disk(block_size), &mut data[4..6]);
+            frame.store(data);
+            if let Some(seed) = csum_seed {
+                set_dx_block_csum(seed, data, frame.count_offset);
+            }
+        });
+    }
 
-        // Check if this is a leaf node
-        if level >= self.indirect_levels as u32 {
-            // Leaf node, search for specific directory entries within it
-            self.search_in_leaf_data(&block_data, target_name, entry.block)
-        } else {
-            // Internal node, recursive search
-            let internal_node = self.parse_internal_node(&block_data)?;
-            self.search_in_hash_tree(fs, block_dev, &internal_node, target_hash, target_name)
+    /// Map a logical directory block of an index walk to its physical block
+    fn map_block<B: BlockDevice>(
+        &self,
+        fs: &Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &Ext4Inode,
+        lblk: u32,
+    ) -> Result<u64, HashTreeError> {
+        match resolve_inode_block(fs, block_dev, dir_ino, &mut dir_inode.clone(), lblk) {
+            Ok(Some(block)) => Ok(block),
+            Ok(None) => Err(HashTreeError::InvalidHashTree),
+            Err(_) => Err(HashTreeError::BlockOutOfRange),
         }
     }
 
-    /// Search in leaf block
-    fn search_in_leaf_block<B: BlockDevice>(
+    /// Same as `map_block` for the update paths, which report `BlockDevError`
+    fn map_leaf<B: BlockDevice>(
+        &self,
+        fs: &Ext4FileSystem,
+        block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
+        dir_inode: &Ext4Inode,
+        lblk: u32,
+    ) -> BlockDevResult<u64> {
+        resolve_inode_block(fs, block_dev, dir_ino, &mut dir_inode.clone(), lblk)?.ok_or_else(|| {
+            error!("Hash tree leaf {lblk} is not mapped");
+            BlockDevError::Corrupted
+        })
+    }
+
+    /// Read block data
+    fn read_block_data<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
-        block_num: u32,
-        target_name: &[u8],
-    ) -> Result<HashTreeSearchResult, HashTreeError> {
-        let block_data = self.read_block_data(fs, block_dev, block_num)?;
-        self.search_in_leaf_data(&block_data, target_name, block_num)
+        block_num: u64,
+    ) -> Result<Vec<u8>, HashTreeError> {
+        match fs.datablock_cache.get_or_load(block_dev, block_num) {
+            Ok(cached_block) => Ok(cached_block.data.clone()),
+            Err(_) => Err(HashTreeError::BlockOutOfRange),
+        }
+    }
+
+    /// Parse DX entry array
+    fn parse_dx_entries(&self, data: &[u8]) -> Result<Vec<Ext4DxEntry>, HashTreeError> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset + core::mem::size_of::<Ext4DxEntry>() <= data.len() {
+            let hash = read_u32_le(&data[offset..offset + 4]);
+            let block = read_u32_le(&data[offset + 4..offset + 8]);
+
+            if block == 0 {
+                break;
+            }
+
+            entries.push(Ext4DxEntry { hash, block });
+            offset += core::mem::size_of::<Ext4DxEntry>();
+        }
+
+        Ok(entries)
     }
 
     /// Search in leaf data
@@ -275,56 +846,35 @@ impl HashTreeManager {
         &self,
         data: &[u8],
         target_name: &[u8],
-        block_num: u32,
+        block_num: u64,
     ) -> Result<HashTreeSearchResult, HashTreeError> {
-        let iter = DirEntryIterator::new(data);
-
-        for (entry, offset) in iter {
-            if entry.name == target_name {
+        let mut offset = 0usize;
+        while offset + 8 <= data.len() {
+            let rec_len = rec_len_from_disk(read_u16_le(&data[offset + 4..offset + 6]));
+            if rec_len < 8 || offset + rec_len > data.len() {
+                break;
+            }
+            if let Some(entry) = Ext4DirEntryInfo::parse_from_bytes(&data[offset..offset + rec_len])
+                && entry.name == target_name
+            {
                 return Ok(HashTreeSearchResult {
-                    entry: unsafe { core::mem::transmute(entry) },
+                    entry: Ext4DirEntry2::new(entry.inode, rec_len_to_disk(rec_len), entry.file_type, entry.name),
                     block_num,
-                    offset: offset as usize,
+                    offset,
                 });
             }
+            offset += rec_len;
         }
 
         Err(HashTreeError::EntryNotFound)
     }
 
-    /// Parse internal node
-    fn parse_internal_node(&self, data: &[u8]) -> Result<HashTreeNode, HashTreeError> {
-        if data.len() < core::mem::size_of::<Ext4DxNode>() {
-            return Err(HashTreeError::BufferTooSmall);
-        }
-
-        // Skip fake directory entries
-        let fake_entry_size = core::mem::size_of::<Ext4DirEntry2>();
-        let countlimit_offset = fake_entry_size;
-
-        if countlimit_offset + core::mem::size_of::<Ext4DxCountlimit>() > data.len() {
-            return Err(HashTreeError::CorruptedHashTree);
-        }
-
-        let countlimit_bytes =
-            &data[countlimit_offset..countlimit_offset + core::mem::size_of::<Ext4DxCountlimit>()];
-        let _count = read_u16_le(&countlimit_bytes[2..4]) as usize; // count field is at offset 2
-
-        // Parse entries
-        let entries_offset = countlimit_offset + core::mem::size_of::<Ext4DxCountlimit>();
-        let entries = self.parse_dx_entries(&data[entries_offset..])?;
-
-        Ok(HashTreeNode::Internal {
-            entries,
-            level: 0, // Should actually read from node
-        })
-    }
-
     /// Fall back to linear search
     fn fallback_to_linear_search<B: BlockDevice>(
         &self,
         fs: &mut Ext4FileSystem,
         block_dev: &mut Jbd2Dev<B>,
+        dir_ino: u32,
         dir_inode: &Ext4Inode,
         target_name: &[u8],
     ) -> Result<HashTreeSearchResult, HashTreeError> {
@@ -333,48 +883,48 @@ impl HashTreeManager {
             core::str::from_utf8(target_name)
         );
 
-        let total_size = dir_inode.size() as usize;
-        let block_bytes = BLOCK_SIZE;
-        let total_blocks = if total_size == 0 {
-            0
-        } else {
-            total_size.div_ceil(block_bytes)
+        let block_bytes = fs.block_size();
+        let mut inode_clone = *dir_inode;
+        let blocks_map = match resolve_inode_block_allextend(fs, block_dev, dir_ino, &mut inode_clone) {
+            Ok(v) => v,
+            Err(_) => return Err(HashTreeError::BlockOutOfRange),
         };
 
-        // Fast path for extent-based directories: resolve all blocks once, then scan.
-        if dir_inode.have_extend_header_and_use_extend() {
-            let mut inode_clone = dir_inode.clone();
-            let blocks_map = match resolve_inode_block_allextend(fs, block_dev, &mut inode_clone) {
-                Ok(v) => v,
+        for &phys in blocks_map.values() {
+            let cached_block = match fs.datablock_cache.get_or_load(block_dev, phys) {
+                Ok(block) => block,
                 Err(_) => return Err(HashTreeError::BlockOutOfRange),
             };
 
-            for lbn in 0..total_blocks {
-                let phys = match blocks_map.get(&(lbn as u32)) {
-                    Some(v) => *v,
-                    None => continue,
-                };
-
-                let cached_block = match fs.datablock_cache.get_or_load(block_dev, phys as u64) {
-                    Ok(block) => block,
-                    Err(_) => return Err(HashTreeError::BlockOutOfRange),
-                };
-
-                let block_data = &cached_block.data[..block_bytes];
-                if let Some(entry) = classic_dir::find_entry(block_data, target_name) {
-                    return Ok(HashTreeSearchResult {
-                        entry: unsafe { core::mem::transmute(entry) },
-                        block_num: phys as u32,
-                        offset: 0,
-                    });
-                }
+            let block_data = &cached_block.data[..block_bytes];
+            if let Ok(result) = self.search_in_leaf_data(block_data, target_name, phys) {
+                return Ok(result);
             }
-            
-            return Err(HashTreeError::EntryNotFound);
         }
 
-        error!("FS NOT SUPPORT NORMAL MULTIPUL POINTER ,PLEASE TURN ON EXTEND FEATURE!");
-        Err(HashTreeError::CorruptedHashTree)
+        Err(HashTreeError::EntryNotFound)
+    }
+}
+
+/// Lay out `entries` from the start of a leaf block; the last one absorbs the slack
+fn write_leaf(data: &mut [u8], entries: &[(u32, Ext4DirEntry2)], usable_len: usize, has_tail: bool) {
+    data.fill(0);
+    let mut offset = 0usize;
+    for (i, (_, entry)) in entries.iter().enumerate() {
+        let nlen = entry.name_len as usize;
+        let rec_len = if i + 1 == entries.len() {
+            usable_len - offset
+        } else {
+            Ext4DirEntry2::entry_len(entry.name_len) as usize
+        };
+        let mut e = *entry;
+        e.rec_len = rec_len_to_disk(rec_len);
+        e.to_disk_bytes(&mut data[offset..offset + 8]);
+        data[offset + 8..offset + 8 + nlen].copy_from_slice(&entry.name[..nlen]);
+        offset += rec_len;
+    }
+    if has_tail {
+        init_dir_tail(data);
     }
 }
 
@@ -394,7 +944,7 @@ pub enum HashTreeNode {
     },
     /// Leaf node
     Leaf {
-        block_num: u32,
+        block_num: u64,
         entries: Vec<Ext4DirEntryInfo<'static>>,
     },
 }
@@ -430,17 +980,19 @@ pub fn create_hash_tree_manager(fs: &Ext4FileSystem) -> HashTreeManager {
         fs.superblock.s_def_hash_version,
         0, // indirect_levels, needs to be read from directory inode
     )
+    .with_unsigned_hash(fs.superblock.s_flags & Ext4Superblock::EXT2_FLAGS_UNSIGNED_HASH != 0)
 }
 
 /// Convenient directory lookup function
 pub fn lookup_directory_entry<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
+    dir_ino: u32,
     dir_inode: &Ext4Inode,
     target_name: &[u8],
 ) -> Result<HashTreeSearchResult, HashTreeError> {
     let manager = create_hash_tree_manager(fs);
-    manager.lookup(fs, block_dev, dir_inode, target_name)
+    manager.lookup(fs, block_dev, dir_ino, dir_inode, target_name)
 }
 
 #[cfg(test)]
@@ -449,6 +1001,9 @@ mod tests {
 
     use alloc::vec::Vec;
 use crate::ext4_backend::error::BlockDevError;
+use crate::ext4_backend::xattr::XattrBlockCache;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::test_support::*;
     // Mock block device
     struct MockBlockDevice {
         data: Vec<u8>,
@@ -468,7 +1023,7 @@ use crate::ext4_backend::error::BlockDevError;
 
     impl BlockDevice for MockBlockDevice {
 
-        fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> Result<(), BlockDevError> {
+        fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> Result<(), BlockDevError> {
             if !self.is_open {
                 return Err(BlockDevError::DeviceNotOpen);
             }
@@ -487,7 +1042,7 @@ use crate::ext4_backend::error::BlockDevError;
             Ok(())
         }
 
-        fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> Result<(), BlockDevError> {
+        fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> Result<(), BlockDevError> {
             if !self.is_open {
                 return Err(BlockDevError::DeviceNotOpen);
             }
@@ -549,6 +1104,13 @@ use crate::ext4_backend::error::BlockDevError;
             group_count: 1,
             mounted: true,
             journal_sb_block_start: None,
+            xattr_cache: XattrBlockCache::new(),
+            extent_status: crate::ext4_backend::extent_status::ExtentStatusCache::new(100),
+            mballoc: crate::ext4_backend::mballoc::MbState::new(&superblock, 1),
+            read_only: false,
+            fast_commit: Default::default(),
+            time_source: alloc::boxed::Box::new(crate::ext4_backend::time::NullTimeSource),
+            atime_policy: Default::default(),
         }
     }
 
@@ -670,6 +1232,41 @@ use crate::ext4_backend::error::BlockDevError;
         assert_eq!(entries[1].block, 2);
     }
 
+    #[test]
+    fn test_dx_frame_select_and_store() {
+        let fs = create_test_fs();
+        let manager = create_hash_tree_manager(&fs);
+        let mut frame = DxFrame {
+            phys: 7,
+            count_offset: DX_NODE_COUNT_OFFSET,
+            limit: (1024 - DX_NODE_COUNT_OFFSET - DX_TAIL_SIZE) / DX_ENTRY_SIZE,
+            entries: vec![
+                Ext4DxEntry { hash: 0, block: 1 },
+                Ext4DxEntry { hash: 100, block: 2 },
+                Ext4DxEntry { hash: 200, block: 3 },
+            ],
+            at: 0,
+        };
+        for (hash, child) in [(50, 1), (100, 2), (150, 2), (0xffff_fff0, 3)] {
+            frame.select(hash);
+            assert_eq!(frame.child(), child);
+        }
+
+        let mut block = vec![0u8; 1024];
+        write_u16_le(rec_len_to_disk(1024), &mut block[4..6]);
+        frame.store(&mut block);
+        assert_eq!(dx_count_offset(&block), Some(DX_NODE_COUNT_OFFSET));
+        assert_eq!(read_u16_le(&block[10..12]), 3);
+        let entries = manager.parse_dx_entries(&block[16..32]).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[1].hash, entries[1].block), (200, 3));
+
+        set_dx_block_csum(0xdead_beef, &mut block, DX_NODE_COUNT_OFFSET);
+        assert!(verify_dx_block_csum(0xdead_beef, &block, DX_NODE_COUNT_OFFSET));
+        block[20] ^= 1;
+        assert!(!verify_dx_block_csum(0xdead_beef, &block, DX_NODE_COUNT_OFFSET));
+    }
+
     #[test]
     fn test_hash_tree_node_types() {
         // Test root node
@@ -713,16 +1310,117 @@ use crate::ext4_backend::error::BlockDevError;
         // Create a mock block device
         let mut mock_device = MockBlockDevice::new(1024 * 1024);
         mock_device.open().unwrap();
-        let mut mock_dev = Jbd2Dev::initial_jbd2dev(0, mock_device, false);
+        let mut mock_dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mock_device, false);
         dir_inode.write_extend_header();
         dir_inode.i_flags |=Ext4Inode::EXT4_EXTENTS_FL;
         let result = manager.fallback_to_linear_search(
             &mut fs,
             &mut mock_dev,
+            2,
             &dir_inode,
             b"nonexistent.txt",
         );
 
         assert!(matches!(result, Err(HashTreeError::EntryNotFound)));
     }
+
+    fn dir_inode(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, path: &str) -> (u32, Ext4Inode) {
+        get_file_inode(fs, dev, path).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_make_indexed_dir_then_lookup_every_name() {
+        let (mut dev, mut fs) = setup();
+        mkdir(&mut dev, &mut fs, "/d").unwrap();
+
+        // 单块目录写满后下一次插入会触发 make_indexed_dir
+        let mut names = Vec::new();
+        let mut extra = 0;
+        while extra < 20 {
+            let name = alloc::format!("entry_with_a_longer_name_{:04}", names.len());
+            mkfile(&mut dev, &mut fs, &alloc::format!("/d/{name}"), None, None).unwrap();
+            names.push(name);
+            if dir_inode(&mut fs, &mut dev, "/d").1.is_htree_indexed() {
+                extra += 1;
+            }
+        }
+        assert!(names.len() > 20);
+
+        let (ino, inode) = dir_inode(&mut fs, &mut dev, "/d");
+        let manager = create_hash_tree_manager(&fs);
+        let path = manager.probe(&mut fs, &mut dev, ino, &inode, b"x", None).unwrap();
+        assert_eq!(path.frames.len(), 1);
+        assert!(path.frames[0].entries.len() >= 2);
+
+        for name in &names {
+            let found = manager.lookup(&mut fs, &mut dev, ino, &inode, name.as_bytes()).unwrap();
+            assert_eq!(&found.entry.name[..found.entry.name_len as usize], name.as_bytes());
+            assert!(get_file_inode(&mut fs, &mut dev, &alloc::format!("/d/{name}")).unwrap().is_some());
+        }
+        assert!(matches!(
+            manager.lookup(&mut fs, &mut dev, ino, &inode, b"missing"),
+            Err(HashTreeError::EntryNotFound)
+        ));
+    }
+
+    /// 只沿索引查找：probe 必须成功（校验索引块），名字必须落在它指向的叶子（或哈希冲突的后继叶子）
+    fn probe_finds(
+        fs: &mut Ext4FileSystem,
+        dev: &mut Jbd2Dev<MemDev>,
+        ino: u32,
+        inode: &Ext4Inode,
+        name: &[u8],
+    ) -> bool {
+        let manager = create_hash_tree_manager(fs);
+        let seed = fs.inode_csum_seed(ino, inode.i_generation);
+        let mut path = manager.probe(fs, dev, ino, inode, name, seed).unwrap();
+        loop {
+            let phys = manager.map_block(fs, dev, ino, inode, path.leaf()).unwrap();
+            let data = manager.read_block_data(fs, dev, phys).unwrap();
+            if manager.search_in_leaf_data(&data, name, phys).is_ok() {
+                return true;
+            }
+            if !manager.next_leaf(fs, dev, ino, inode, &mut path, seed).unwrap() {
+                return false;
+            }
+        }
+    }
+
+    #[test]
+    fn test_htree_thousands_of_names_two_levels() {
+        let (mut dev, mut fs) = setup();
+        mkdir(&mut dev, &mut fs, "/d").unwrap();
+        mkfile(&mut dev, &mut fs, "/target", None, None).unwrap();
+
+        // 用硬链接插入名字，不受 inode 数限制；长名字让叶子更快分裂，根索引写满后长出第二层
+        let names: Vec<_> = (0..3000)
+            .map(|i| alloc::format!("a_fairly_long_directory_entry_name_{i:05}"))
+            .collect();
+        for name in &names {
+            link(&mut fs, &mut dev, &alloc::format!("/d/{name}"), "/target");
+        }
+
+        let (ino, inode) = dir_inode(&mut fs, &mut dev, "/d");
+        let manager = create_hash_tree_manager(&fs);
+        let seed = fs.inode_csum_seed(ino, inode.i_generation);
+        let path = manager.probe(&mut fs, &mut dev, ino, &inode, b"x", seed).unwrap();
+        assert_eq!(path.frames.len(), 2, "index should have grown a second level");
+        for name in &names {
+            assert!(probe_finds(&mut fs, &mut dev, ino, &inode, name.as_bytes()), "{name}");
+        }
+
+        for name in names.iter().step_by(2) {
+            unlink(&mut fs, &mut dev, &alloc::format!("/d/{name}"));
+        }
+        let (ino, inode) = dir_inode(&mut fs, &mut dev, "/d");
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(probe_finds(&mut fs, &mut dev, ino, &inode, name.as_bytes()), i % 2 == 1, "{name}");
+            assert_eq!(
+                get_file_inode(&mut fs, &mut dev, &alloc::format!("/d/{name}")).unwrap().is_some(),
+                i % 2 == 1
+            );
+        }
+        let (_, target) = dir_inode(&mut fs, &mut dev, "/target");
+        assert_eq!(target.i_links_count as usize, 1 + names.len() / 2);
+    }
 }
@@ -0,0 +1,537 @@
+//! 内联数据（inline_data）模块
+//!
+//! 小文件和小目录的内容直接存放在 inode 中：前 60 字节写在 i_block，超出部分写在
+//! inode 内的 system.data 扩展属性里，i_size 等于两者之和（目录）或文件长度（文件）。
+//! 内联目录的 i_block 前 4 字节是父目录 inode 号（没有 "." 和 ".." 目录项），
+//! 其后的 56 字节和 system.data 的值各是一段独立的目录项区域。
+//! inode 内放不下时自动转换为数据块存储。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::checksum::*;
+use crate::ext4_backend::dir::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::endian::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::xattr::*;
+use alloc::vec::Vec;
+use log::{debug, error};
+
+/// i_block 能容纳的内联数据长度
+pub const EXT4_MIN_INLINE_DATA_SIZE: usize = 60;
+/// 内联目录开头存放父目录 inode 号的字节数
+pub const EXT4_INLINE_DOTDOT_SIZE: usize = 4;
+
+/// i_block 按小端展开成字节
+fn i_block_bytes(inode: &Ext4Inode) -> [u8; EXT4_MIN_INLINE_DATA_SIZE] {
+    let mut raw = [0u8; EXT4_MIN_INLINE_DATA_SIZE];
+    for (i, word) in inode.i_block.iter().enumerate() {
+        raw[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    raw
+}
+
+/// 把至多 60 字节写入 i_block，其余清零
+fn set_i_block_bytes(inode: &mut Ext4Inode, data: &[u8]) {
+    let mut raw = [0u8; EXT4_MIN_INLINE_DATA_SIZE];
+    let len = data.len().min(EXT4_MIN_INLINE_DATA_SIZE);
+    raw[..len].copy_from_slice(&data[..len]);
+    for (i, word) in inode.i_block.iter_mut().enumerate() {
+        *word = read_u32_le(&raw[i * 4..i * 4 + 4]);
+    }
+}
+
+/// 读取内联 inode 的全部内容（长度为 i_size）
+pub fn read_inline_data<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<u8>> {
+    let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let size = inode.size() as usize;
+    let mut data = i_block_bytes(&inode).to_vec();
+    if size > EXT4_MIN_INLINE_DATA_SIZE {
+        match getxattr(fs, block_dev, inode_num, EXT4_XATTR_SYSTEM_DATA)? {
+            Some(value) => data.extend_from_slice(&value),
+            None => {
+                error!("Inline inode {inode_num} has size {size} but no system.data");
+                return Err(BlockDevError::Corrupted);
+            }
+        }
+    }
+    data.resize(size, 0);
+    Ok(data)
+}
+
+/// inode 最多能内联多少字节；inode 内放不下 system.data 时返回 None
+pub fn max_inline_data_size<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Option<usize>> {
+    Ok(ibody_xattr_free(fs, block_dev, inode_num, EXT4_XATTR_SYSTEM_DATA)?
+        .map(|free| EXT4_MIN_INLINE_DATA_SIZE + free))
+}
+
+/// len 字节能否内联存放在 inode 中
+fn fits_inline<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    len: usize,
+) -> BlockDevResult<bool> {
+    Ok(max_inline_data_size(fs, block_dev, inode_num)?.is_some_and(|max| len <= max))
+}
+
+/// 把 data 整体存为 inode 的内联内容，并设置内联标志和 i_size；放不下时不做修改并返回 false
+fn store_inline_data<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    data: &[u8],
+) -> BlockDevResult<bool> {
+    if !fits_inline(fs, block_dev, inode_num, data.len())? {
+        return Ok(false);
+    }
+    let value = data.get(EXT4_MIN_INLINE_DATA_SIZE..).unwrap_or(&[]);
+    set_ibody_xattr(fs, block_dev, inode_num, EXT4_XATTR_SYSTEM_DATA, value)?;
+
+    let size = data.len() as u64;
+    fs.modify_inode(block_dev, inode_num, |inode| {
+        set_i_block_bytes(inode, data);
+        inode.i_flags |= Ext4Inode::EXT4_INLINE_DATA_FL;
+        inode.i_flags &= !Ext4Inode::EXT4_EXTENTS_FL;
+        inode.i_size_lo = size as u32;
+        inode.i_size_high = (size >> 32) as u32;
+    })?;
+    Ok(true)
+}
+
+/// 普通文件能否改为内联存储：启用了 inline_data 且文件为空、没有任何数据块
+pub fn can_inline_new_data(fs: &Ext4FileSystem, inode: &Ext4Inode) -> bool {
+    if !fs.superblock.has_inline_data() || !inode.is_file() || inode.size() != 0 {
+        return false;
+    }
+    if inode.have_extend_header_and_use_extend() {
+        // extent 头的 eh_entries 位于第 2~3 字节，eh_depth 位于第 6~7 字节
+        inode.i_block[0] >> 16 == 0 && inode.i_block[1] >> 16 == 0
+    } else {
+        inode.i_block.iter().all(|&w| w == 0)
+    }
+}
+
+/// 在 offset 处写入 data 并保持内联存储；写入后超出内联容量时不做修改并返回 false
+pub fn try_write_inline_data<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    offset: u64,
+    data: &[u8],
+) -> BlockDevResult<bool> {
+    let inode = fs.get_inode_by_num(block_dev, inode_num)?;
+    let mut content = if inode.has_inline_data() {
+        read_inline_data(fs, block_dev, inode_num)?
+    } else {
+        Vec::new()
+    };
+    let Some(end) = (offset as usize).checked_add(data.len()) else {
+        return Ok(false);
+    };
+    // 先检查容量再扩展缓冲区，远超 EOF 的写入不能按 offset 分配内存
+    if end > content.len() {
+        if !fits_inline(fs, block_dev, inode_num, end)? {
+            return Ok(false);
+        }
+        content.resize(end, 0);
+    }
+    content[offset as usize..end].copy_from_slice(data);
+    store_inline_data(fs, block_dev, inode_num, &content)
+}
+
+/// 把内联文件截断或扩展到 size 并保持内联存储；超出内联容量时不做修改并返回 false
+pub fn try_truncate_inline_data<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    size: u64,
+) -> BlockDevResult<bool> {
+    if size > u32::MAX as u64 || !fits_inline(fs, block_dev, inode_num, size as usize)? {
+        return Ok(false);
+    }
+    let mut content = read_inline_data(fs, block_dev, inode_num)?;
+    content.resize(size as usize, 0);
+    store_inline_data(fs, block_dev, inode_num, &content)
+}
+
+/// 取消内联：删除 system.data、清除内联标志并把 inode 变成空的块存储 inode（i_size 为 0），
+/// 返回原来的内联内容，由调用方写回数据块
+pub fn uninline_data<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<u8>> {
+    let content = read_inline_data(fs, block_dev, inode_num)?;
+    match removexattr(fs, block_dev, inode_num, EXT4_XATTR_SYSTEM_DATA) {
+        Ok(()) | Err(BlockDevError::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+    let has_extents = fs.superblock.has_extents();
+    fs.modify_inode(block_dev, inode_num, |inode| {
+        inode.i_flags &= !Ext4Inode::EXT4_INLINE_DATA_FL;
+        inode.i_block = [0; 15];
+        inode.i_size_lo = 0;
+        inode.i_size_high = 0;
+        if has_extents {
+            inode.i_flags |= Ext4Inode::EXT4_EXTENTS_FL;
+            inode.write_extend_header();
+        }
+    })?;
+    debug!("Inode {inode_num} converted from inline data, {} bytes", content.len());
+    Ok(content)
+}
+
+/// 新目录以内联形式创建：i_block 记录父目录，其余 56 字节为一个空目录项。
+/// 未启用 inline_data 或 inode 内放不下 system.data 时返回 false，由调用方分配目录块
+pub fn create_inline_dir<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    parent_ino: u32,
+) -> BlockDevResult<bool> {
+    if !fs.superblock.has_inline_data() {
+        return Ok(false);
+    }
+    let mut content = [0u8; EXT4_MIN_INLINE_DATA_SIZE];
+    write_u32_le(parent_ino, &mut content[0..4]);
+    let region_len = EXT4_MIN_INLINE_DATA_SIZE - EXT4_INLINE_DOTDOT_SIZE;
+    write_u16_le(region_len as u16, &mut content[8..10]);
+    store_inline_data(fs, block_dev, inode_num, &content)
+}
+
+/// 内联目录的两段目录项区域：i_block 中的 [4, 60) 和 system.data 的值
+fn inline_dir_regions(content: &[u8]) -> [&[u8]; 2] {
+    let (head, tail) = content.split_at(EXT4_MIN_INLINE_DATA_SIZE.min(content.len()));
+    [head.get(EXT4_INLINE_DOTDOT_SIZE..).unwrap_or(&[]), tail]
+}
+
+/// 读取内联目录内容，长度不足 60 字节视为损坏
+fn read_inline_dir<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<u8>> {
+    let content = read_inline_data(fs, block_dev, inode_num)?;
+    if content.len() < EXT4_MIN_INLINE_DATA_SIZE {
+        error!("Inline dir {inode_num} too small: {}", content.len());
+        return Err(BlockDevError::Corrupted);
+    }
+    Ok(content)
+}
+
+/// 内联目录的父目录 inode 号
+pub fn inline_dir_parent<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<u32> {
+    let content = read_inline_dir(fs, block_dev, inode_num)?;
+    Ok(read_u32_le(&content[0..4]))
+}
+
+/// 列出内联目录的全部目录项（不含隐含的 "." 和 ".."）
+pub fn inline_dir_entries<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<Ext4DirEntry2>> {
+    let content = read_inline_dir(fs, block_dev, inode_num)?;
+    let mut out = Vec::new();
+    for region in inline_dir_regions(&content) {
+        for (entry, rec_len) in DirEntryIterator::new(region) {
+            out.push(Ext4DirEntry2::new(entry.inode, rec_len_to_disk(rec_len), entry.file_type, entry.name));
+        }
+    }
+    Ok(out)
+}
+
+/// 在内联目录中按名字查找目录项
+pub fn find_inline_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &[u8],
+) -> BlockDevResult<Option<Ext4DirEntry2>> {
+    Ok(inline_dir_entries(fs, block_dev, inode_num)?
+        .into_iter()
+        .find(|e| &e.name[..e.name_len as usize] == name))
+}
+
+/// 向内联目录插入目录项：i_block 放不下时把 system.data 扩展到 inode 剩余空间再放；
+/// 仍放不下返回 false，由调用方转换为块目录
+pub fn add_inline_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    entry: &Ext4DirEntry2,
+) -> BlockDevResult<bool> {
+    let mut content = read_inline_dir(fs, block_dev, inode_num)?;
+    let (head, tail) = content.split_at_mut(EXT4_MIN_INLINE_DATA_SIZE);
+    let head = &mut head[EXT4_INLINE_DOTDOT_SIZE..];
+    let head_len = head.len();
+    let tail_len = tail.len();
+    let inserted = classic_dir::insert_entry(head, head_len, entry)
+        || (tail_len > 0 && classic_dir::insert_entry(tail, tail_len, entry));
+    if !inserted {
+        if tail_len > 0 {
+            return Ok(false);
+        }
+        let Some(max) = max_inline_data_size(fs, block_dev, inode_num)? else {
+            return Ok(false);
+        };
+        let extra = max - EXT4_MIN_INLINE_DATA_SIZE;
+        if extra < Ext4DirEntry2::entry_len(entry.name_len) as usize {
+            return Ok(false);
+        }
+        // 新的一段目录项区域：先放一个占满整段的空目录项
+        content.resize(max, 0);
+        let tail = &mut content[EXT4_MIN_INLINE_DATA_SIZE..];
+        write_u16_le(extra as u16, &mut tail[4..6]);
+        if !classic_dir::insert_entry(tail, extra, entry) {
+            return Ok(false);
+        }
+    }
+    store_inline_data(fs, block_dev, inode_num, &content)
+}
+
+/// 从内联目录删除名为 name 的目录项，成功返回 true
+pub fn remove_inline_dir_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &[u8],
+) -> BlockDevResult<bool> {
+    let mut content = read_inline_dir(fs, block_dev, inode_num)?;
+    let (head, tail) = content.split_at_mut(EXT4_MIN_INLINE_DATA_SIZE);
+    let head = &mut head[EXT4_INLINE_DOTDOT_SIZE..];
+    let head_len = head.len();
+    let tail_len = tail.len();
+    if !classic_dir::remove_entry(head, head_len, name)
+        && !classic_dir::remove_entry(tail, tail_len, name)
+    {
+        return Ok(false);
+    }
+    store_inline_data(fs, block_dev, inode_num, &content)
+}
+
+/// 修改内联目录记录的父目录 inode 号（目录被移动到其他父目录时）
+pub fn set_inline_dir_parent<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    parent_ino: u32,
+) -> BlockDevResult<()> {
+    fs.modify_inode(block_dev, inode_num, |inode| inode.i_block[0] = parent_ino)
+}
+
+/// 内联目录放不下新目录项时转换为单块目录：写入 "." ".." 和原有目录项，
+/// 完成后 dir_inode 更新为转换后的 inode
+pub fn convert_inline_dir<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    dir_inode: &mut Ext4Inode,
+) -> BlockDevResult<()> {
+    let parent_ino = inline_dir_parent(fs, block_dev, inode_num)?;
+    let entries = inline_dir_entries(fs, block_dev, inode_num)?;
+    uninline_data(fs, block_dev, inode_num)?;
+    *dir_inode = fs.get_inode_by_num(block_dev, inode_num)?;
+
+    let (_lbn, block) = append_dir_block(fs, block_dev, inode_num, dir_inode)?;
+    let block_bytes = fs.block_size();
+    let usable_len = fs.dir_block_usable_len();
+    let mut fits = true;
+    fs.datablock_cache.modify(block_dev, block, |data| {
+        data.fill(0);
+        let dot = Ext4DirEntry2::new(inode_num, 12, Ext4DirEntry2::EXT4_FT_DIR, b".");
+        dot.to_disk_bytes(&mut data[0..8]);
+        data[8] = b'.';
+        let dotdot = Ext4DirEntry2::new(
+            parent_ino,
+            (usable_len - 12) as u16,
+            Ext4DirEntry2::EXT4_FT_DIR,
+            b"..",
+        );
+        dotdot.to_disk_bytes(&mut data[12..20]);
+        data[20..22].copy_from_slice(b"..");
+        for entry in &entries {
+            fits &= classic_dir::insert_entry(data, usable_len, entry);
+        }
+        if usable_len < block_bytes {
+            init_dir_tail(data);
+        }
+    })?;
+    if !fits {
+        error!("Inline dir {inode_num} entries do not fit in one block");
+        return Err(BlockDevError::Corrupted);
+    }
+    fs.update_dir_block_csum(block_dev, inode_num, block)?;
+    debug!("Inline dir {inode_num} converted to block {block}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::loopfile::get_file_inode;
+    use crate::ext4_backend::superblock::Ext4Superblock;
+    use crate::ext4_backend::test_support::*;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn setup_inline() -> (Jbd2Dev<MemDev>, Ext4FileSystem) {
+        let (dev, mut fs) = setup();
+        fs.superblock.s_feature_incompat |= Ext4Superblock::EXT4_FEATURE_INCOMPAT_INLINE_DATA;
+        (dev, fs)
+    }
+
+    #[test]
+    fn test_i_block_bytes_roundtrip() {
+        let mut inode = Ext4Inode::default();
+        let data: Vec<u8> = (0..60u8).collect();
+        set_i_block_bytes(&mut inode, &data);
+        assert_eq!(inode.i_block[0], u32::from_le_bytes([0, 1, 2, 3]));
+        assert_eq!(&i_block_bytes(&inode)[..], &data[..]);
+
+        set_i_block_bytes(&mut inode, b"abc");
+        assert_eq!(&i_block_bytes(&inode)[..4], b"abc\0");
+        assert!(inode.i_block[1..].iter().all(|&w| w == 0));
+    }
+
+    #[test]
+    fn test_inline_dir_regions() {
+        let mut content = [0u8; 60];
+        write_u32_le(2, &mut content[0..4]);
+        write_u16_le(56, &mut content[8..10]);
+        let regions = inline_dir_regions(&content);
+        assert_eq!(regions[0].len(), 56);
+        assert!(regions[1].is_empty());
+
+        // 在 i_block 区域插入两个目录项，再按名字找回
+        let mut content = content.to_vec();
+        for (ino, name) in [(12u32, &b"a.conf"[..]), (13, b"b")] {
+            let e = Ext4DirEntry2::new(ino, 0, Ext4DirEntry2::EXT4_FT_REG_FILE, name);
+            assert!(classic_dir::insert_entry(&mut content[4..60], 56, &e));
+        }
+        content.extend_from_slice(&[0u8; 12]);
+        write_u16_le(12, &mut content[64..66]);
+        let names: Vec<&[u8]> = inline_dir_regions(&content)
+            .into_iter()
+            .flat_map(|r| DirEntryIterator::new(r).map(|(e, _)| e.name))
+            .collect();
+        assert_eq!(names, [&b"a.conf"[..], b"b"]);
+    }
+
+    #[test]
+    fn test_inline_file_grows_then_converts() {
+        let (mut dev, mut fs) = setup_inline();
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/f", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/f", 0, b"hello").unwrap();
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(inode.has_inline_data());
+        assert_eq!(inode.size(), 5);
+        assert_eq!(read_file(&mut dev, &mut fs, "/f").unwrap().unwrap(), b"hello");
+
+        // 超过 i_block 的 60 字节：多出的部分放进 system.data
+        let data: Vec<u8> = (0..100u8).collect();
+        write_file(&mut dev, &mut fs, "/f", 0, &data).unwrap();
+        assert!(fs.get_inode_by_num(&mut dev, ino).unwrap().has_inline_data());
+        assert_eq!(
+            getxattr(&mut fs, &mut dev, ino, EXT4_XATTR_SYSTEM_DATA).unwrap().unwrap(),
+            &data[EXT4_MIN_INLINE_DATA_SIZE..]
+        );
+        assert_eq!(read_file(&mut dev, &mut fs, "/f").unwrap().unwrap(), data);
+
+        // inode 内放不下：自动转为 extent 映射，原内容搬到数据块
+        let more = vec![7u8; 3000];
+        write_file(&mut dev, &mut fs, "/f", 100, &more).unwrap();
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(!inode.has_inline_data());
+        assert!(inode.have_extend_header_and_use_extend());
+        assert_eq!(getxattr(&mut fs, &mut dev, ino, EXT4_XATTR_SYSTEM_DATA).unwrap(), None);
+        let mut expect = data.clone();
+        expect.extend_from_slice(&more);
+        assert_eq!(read_file(&mut dev, &mut fs, "/f").unwrap().unwrap(), expect);
+
+        // 远超 EOF 的写入和截断直接走块存储，不按 offset 分配缓冲区
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/far", Some(b"x"), None).unwrap();
+        assert!(fs.get_inode_by_num(&mut dev, ino).unwrap().has_inline_data());
+        assert!(!try_write_inline_data(&mut fs, &mut dev, ino, 1 << 40, b"y").unwrap());
+        assert!(!try_truncate_inline_data(&mut fs, &mut dev, ino, 1 << 40).unwrap());
+        write_file(&mut dev, &mut fs, "/far", 1 << 36, b"tail").unwrap();
+        let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(!inode.has_inline_data());
+        assert_eq!(inode.size(), (1 << 36) + 4);
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_inline_dir_add_remove_convert() {
+        let (mut dev, mut fs) = setup_inline();
+        mkdir(&mut dev, &mut fs, "/d").unwrap();
+        let (ino, inode) = get_file_inode(&mut fs, &mut dev, "/d").unwrap().unwrap();
+        assert!(inode.has_inline_data());
+        assert_eq!(inline_dir_parent(&mut fs, &mut dev, ino).unwrap(), 2);
+
+        mkfile(&mut dev, &mut fs, "/d/a", Some(b"1"), None).unwrap();
+        mkfile(&mut dev, &mut fs, "/d/b", Some(b"2"), None).unwrap();
+        assert_eq!(list_dir(&mut fs, &mut dev, "/d"), ["a", "b"]);
+        unlink(&mut fs, &mut dev, "/d/a");
+        assert_eq!(list_dir(&mut fs, &mut dev, "/d"), ["b"]);
+
+        // 名字够多时先用上 system.data，再转换为块目录
+        let names: Vec<String> = (0..40).map(|i| alloc::format!("file-with-a-long-name-{i:02}")).collect();
+        let (mut grew, mut converted) = (false, false);
+        for name in &names {
+            mkfile(&mut dev, &mut fs, &alloc::format!("/d/{name}"), None, None).unwrap();
+            let inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+            if inode.has_inline_data() {
+                assert!(!converted);
+                grew |= inode.size() as usize > EXT4_MIN_INLINE_DATA_SIZE;
+            } else {
+                converted = true;
+            }
+        }
+        assert!(grew && converted);
+        let mut listed = list_dir(&mut fs, &mut dev, "/d");
+        listed.sort();
+        let mut expect = vec![String::from("."), String::from(".."), String::from("b")];
+        expect.extend(names.iter().cloned());
+        assert_eq!(listed, expect);
+        for name in &names {
+            let path = alloc::format!("/d/{name}");
+            assert!(get_file_inode(&mut fs, &mut dev, &path).unwrap().is_some());
+        }
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_convert_inline_dir_keeps_entries() {
+        let (mut dev, mut fs) = setup_inline();
+        mkdir(&mut dev, &mut fs, "/p").unwrap();
+        mkdir(&mut dev, &mut fs, "/p/d").unwrap();
+        mkfile(&mut dev, &mut fs, "/p/d/x", Some(b"x"), None).unwrap();
+        let (ino, mut inode) = get_file_inode(&mut fs, &mut dev, "/p/d").unwrap().unwrap();
+        let (parent, _) = get_file_inode(&mut fs, &mut dev, "/p").unwrap().unwrap();
+        assert!(inode.has_inline_data());
+
+        convert_inline_dir(&mut fs, &mut dev, ino, &mut inode).unwrap();
+        assert!(!inode.has_inline_data());
+        assert_eq!(list_dir(&mut fs, &mut dev, "/p/d"), [".", "..", "x"]);
+        let dotdot = lookup_dir_entry(&mut fs, &mut dev, ino, &mut inode, b"..").unwrap();
+        assert_eq!(dotdot, Some(parent));
+        assert_eq!(read_file(&mut dev, &mut fs, "/p/d/x").unwrap().unwrap(), b"x");
+        fs.umount(&mut dev).unwrap();
+    }
+}
@@ -3,15 +3,20 @@
 //! 提供inode结构的缓存管理，支持延迟写回和LRU淘汰
 
 use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::checksum::*;
 use crate::ext4_backend::config::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
 use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use crate::ext4_backend::error::*;
+use log::error;
 /// Inode缓存键（全局inode号）
 pub type InodeCacheKey = u64;
 
+/// Ext4Inode 结构体覆盖的磁盘字节数（128 字节基本部分 + 32 字节扩展字段）
+const INODE_FIELDS_END: usize = 160;
+
 /// 缓存的inode数据
 #[derive(Debug, Clone)]
 pub struct CachedInode {
@@ -27,12 +32,15 @@ pub struct CachedInode {
     pub inode_num: u64,
     /// 最后访问时间戳
     pub last_access: u64,
+    /// 结构体之后的原始字节（inode 内扩展属性区），写回时原样保留
+    pub tail: Vec<u8>,
 }
 
 impl CachedInode {
     pub fn new(inode: Ext4Inode, inode_num: u64, block_num: u64, offset: usize) -> Self {
         Self {
             inode,
+            tail: Vec::new(),
             dirty: false,
             block_num,
             offset_in_block: offset,
@@ -70,6 +78,8 @@ pub struct InodeCache {
     access_counter: u64,
     /// 每个inode的大小=
     inode_size: usize,
+    /// metadata_csum 校验种子（未开启时为 None）
+    csum_seed: Option<u32>,
 }
 
 impl InodeCache {
@@ -82,9 +92,35 @@ impl InodeCache {
             max_entries,
             access_counter: 0,
             inode_size,
+            csum_seed: None,
         }
     }
 
+    /// 设置 metadata_csum 校验种子，写回时计算 inode 校验和，加载时校验
+    pub fn set_csum_seed(&mut self, csum_seed: Option<u32>) {
+        self.csum_seed = csum_seed;
+    }
+
+    /// 拼出 inode 的完整原始字节（结构体字段 + 保留的尾部字节），不含校验和
+    fn raw_inode(inode_size: usize, cached: &CachedInode) -> Vec<u8> {
+        let mut buffer = alloc::vec![0u8; inode_size];
+        cached.inode.to_disk_bytes(&mut buffer);
+        if inode_size > INODE_FIELDS_END {
+            let n = cached.tail.len().min(inode_size - INODE_FIELDS_END);
+            buffer[INODE_FIELDS_END..INODE_FIELDS_END + n].copy_from_slice(&cached.tail[..n]);
+        }
+        buffer
+    }
+
+    /// 序列化 inode，开启 metadata_csum 时填入校验和
+    fn encode_inode(&self, cached: &CachedInode) -> Vec<u8> {
+        let mut buffer = Self::raw_inode(self.inode_size, cached);
+        if let Some(seed) = self.csum_seed {
+            set_inode_csum(seed, cached.inode_num as u32, &mut buffer);
+        }
+        buffer
+    }
+
     /// 创建默认配置的缓存
     pub fn default(inode_size:u16) -> Self {
         Self::new(INODE_CACHE_MAX, inode_size as usize)
@@ -124,19 +160,30 @@ impl InodeCache {
     fn load_inode<B: BlockDevice>(
         &self,
         block_dev: &mut Jbd2Dev<B>,
+        inode_num: u64,
         block_num: u64,
         offset: usize,
-    ) -> BlockDevResult<Ext4Inode> {
-        block_dev.read_block(block_num as u32)?;
+    ) -> BlockDevResult<CachedInode> {
+        block_dev.read_block(block_num)?;
         let buffer = block_dev.buffer();
 
         if offset + self.inode_size > buffer.len() {
             return Err(BlockDevError::Corrupted);
         }
 
-        let inode = Ext4Inode::from_disk_bytes(&buffer[offset..offset + self.inode_size]);
+        let raw = &buffer[offset..offset + self.inode_size];
+        if let Some(seed) = self.csum_seed
+            && !verify_inode_csum(seed, inode_num as u32, raw)
+        {
+            error!("Inode {inode_num} checksum mismatch");
+            return Err(BlockDevError::ChecksumError);
+        }
+        let mut cached = CachedInode::new(Ext4Inode::from_disk_bytes(raw), inode_num, block_num, offset);
+        if raw.len() > INODE_FIELDS_END {
+            cached.tail = raw[INODE_FIELDS_END..].to_vec();
+        }
 
-        Ok(inode)
+        Ok(cached)
     }
 
     /// 获取inode（如果不存在则从磁盘加载，只读）
@@ -159,8 +206,7 @@ impl InodeCache {
             }
 
             // 从磁盘加载
-            let inode = self.load_inode(block_dev,  block_num, offset)?;
-            let cached = CachedInode::new(inode, inode_num, block_num, offset);
+            let cached = self.load_inode(block_dev, inode_num, block_num, offset)?;
             self.cache.insert(inode_num, cached);
         }
 
@@ -187,8 +233,7 @@ impl InodeCache {
                 self.evict_lru(block_dev)?;
             }
 
-            let inode = self.load_inode(block_dev,  block_num, offset)?;
-            let cached = CachedInode::new(inode, inode_num, block_num, offset);
+            let cached = self.load_inode(block_dev, inode_num, block_num, offset)?;
             self.cache.insert(inode_num, cached);
         }
 
@@ -244,6 +289,44 @@ impl InodeCache {
         Ok(())
     }
 
+    /// 读取 inode 的完整原始字节（含 inode 内扩展属性区，不含最新校验和）
+    pub fn get_raw_or_load<B: BlockDevice>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u64,
+        block_num: u64,
+        offset: usize,
+    ) -> BlockDevResult<Vec<u8>> {
+        let inode_size = self.inode_size;
+        let cached = self.get_or_load(block_dev, inode_num, block_num, offset)?;
+        Ok(Self::raw_inode(inode_size, cached))
+    }
+
+    /// 以原始字节的形式修改 inode（可改动结构体之后的扩展属性区），并自动标记为脏
+    pub fn modify_raw<B, F>(
+        &mut self,
+        block_dev: &mut Jbd2Dev<B>,
+        inode_num: u64,
+        block_num: u64,
+        offset: usize,
+        f: F,
+    ) -> BlockDevResult<()>
+    where
+        B: BlockDevice,
+        F: FnOnce(&mut [u8]),
+    {
+        let inode_size = self.inode_size;
+        let cached = self.get_or_load_mut(block_dev, inode_num, block_num, offset)?;
+        let mut raw = Self::raw_inode(inode_size, cached);
+        f(&mut raw);
+        cached.inode = Ext4Inode::from_disk_bytes(&raw);
+        if raw.len() > INODE_FIELDS_END {
+            cached.tail = raw[INODE_FIELDS_END..].to_vec();
+        }
+        cached.mark_dirty();
+        Ok(())
+    }
+
     /// 使用句柄修改inode的便捷方法
     pub fn modify_by_handle<B, F>(
         &mut self,
@@ -283,12 +366,12 @@ impl InodeCache {
     ) -> BlockDevResult<()> {
         if let Some(cached) = self.cache.remove(&inode_num)
             && cached.dirty {
-                Self::write_inode_static(
+                let buffer = self.encode_inode(&cached);
+                Self::write_inode_bytes_static(
                     block_dev,
-                    &cached.inode,
                     cached.block_num,
                     cached.offset_in_block,
-                    self.inode_size,
+                    &buffer,
                 )?;
             }
         Ok(())
@@ -302,8 +385,7 @@ impl InodeCache {
             .values()
             .filter(|cached| cached.dirty)
             .map(|cached| {
-                let mut buffer = alloc::vec![0u8; self.inode_size];
-                cached.inode.to_disk_bytes(&mut buffer);
+                let buffer = self.encode_inode(cached);
                 (cached.block_num, cached.offset_in_block, buffer)
             })
             .collect();
@@ -320,7 +402,7 @@ impl InodeCache {
             let (block_num, _, _) = dirty_inodes[idx];
 
             // 读出当前 inode 表块到 Jbd2Dev 的 buffer
-            block_dev.read_block(block_num as u32)?;
+            block_dev.read_block(block_num)?;
             {
                 let buffer = block_dev.buffer_mut();
 
@@ -337,7 +419,7 @@ impl InodeCache {
             }
 
             // 该 inode 表块只调用一次 write_block，作为 metadata 走 JBD2
-            block_dev.write_block(block_num as u32, true)?;
+            block_dev.write_block(block_num, true)?;
         }
 
         // 清除所有脏标记
@@ -358,8 +440,7 @@ impl InodeCache {
             && cached.dirty {
                 let block_num = cached.block_num;
                 let offset = cached.offset_in_block;
-                let mut buffer = alloc::vec![0u8; self.inode_size];
-                cached.inode.to_disk_bytes(&mut buffer);
+                let buffer = self.encode_inode(cached);
 
                 Self::write_inode_bytes_static(block_dev, block_num, offset, &buffer)?;
 
@@ -370,19 +451,6 @@ impl InodeCache {
         Ok(())
     }
 
-    /// 写inode到磁盘
-    fn write_inode_static<B: BlockDevice>(
-        block_dev: &mut Jbd2Dev<B>,
-        inode: &Ext4Inode,
-        block_num: u64,
-        offset: usize,
-        inode_size: usize,
-    ) -> BlockDevResult<()> {
-        let mut buffer = alloc::vec![0u8; inode_size];
-        inode.to_disk_bytes(&mut buffer);
-        Self::write_inode_bytes_static(block_dev, block_num, offset, &buffer)
-    }
-
     /// 写inode字节到磁盘
     fn write_inode_bytes_static<B: BlockDevice>(
         block_dev: &mut Jbd2Dev<B>,
@@ -390,12 +458,12 @@ impl InodeCache {
         offset: usize,
         data: &[u8],
     ) -> BlockDevResult<()> {
-        block_dev.read_block(block_num as u32)?;
+        block_dev.read_block(block_num)?;
         let buffer = block_dev.buffer_mut();
 
         buffer[offset..offset + data.len()].copy_from_slice(data);
 
-        block_dev.write_block(block_num as u32, true)?; //只供崩溃恢复用
+        block_dev.write_block(block_num, true)?; //只供崩溃恢复用
         Ok(())
     }
 
@@ -1,57 +1,401 @@
 use crate::ext4_backend::blockdev::*;
-use crate::ext4_backend::config::*;
+use crate::ext4_backend::checksum::{set_superblock_csum, verify_superblock_csum};
+use crate::ext4_backend::config::{
+    DEFAULT_INODE_SIZE, EXT4_SUPER_MAGIC, MIN_BLOCK_SIZE, RESERVED_INODES, SUPERBLOCK_OFFSET,
+};
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::endian::*;
 use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::fast_commit::{FcRecord, FcScanner};
 use crate::ext4_backend::file::*;
 use crate::ext4_backend::jbd2::jbdstruct::*;
 use crate::ext4_backend::loopfile::*;
 use crate::ext4_backend::error::*;
+use crate::ext4_backend::superblock::*;
+use alloc::collections::BTreeMap;
 use alloc::vec;
 use log::debug;
+use log::error;
 use log::info;
 use log::warn;
 
 use alloc::vec::Vec;
 
 
+/// 日志中一个完整提交的事务（重放扫描结果）
+struct LoggedTransaction {
+    sequence: u32,
+    tags: Vec<(JouranlBlockTag3S, u32)>, // (tag, 元数据所在的日志相对块号)
+    revoked: Vec<u64>,
+}
+
+/// 事务号比较（处理回绕）：a 是否在 b 之后
+fn tid_gt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
 impl JBD2DEVSYSTEM {
-    ///计算下一个日志块的位置(处理回绕),返回当前的（可以直接用，直接写，已经处理过偏移）!
-    pub fn set_next_log_block<B:BlockDevice>(&mut self,block_dev: &mut B) -> u32 {
-       //处理第一次使用journal提交
-       if self.jbd2_super_block.s_start==0 {
-           //更新内存的s_start 
-           self.jbd2_super_block.s_start = self.jbd2_super_block.s_first;
-           //写入超级块
-           let mut sb_data = [0u8; BLOCK_SIZE];
-           block_dev.read(&mut sb_data, self.start_block, 1).expect("Read superblock failed");
-           self.jbd2_super_block.to_disk_bytes(&mut sb_data);
-           block_dev.write(&sb_data, self.start_block, 1).expect("Write superblock failed");
-           self.head+=1;
-           let mut target_use = self.start_block + self.jbd2_super_block.s_start+self.head-1;
-           //处理环绕
-           if target_use - self.start_block > self.max_len {
-               self.head = 0;
-               target_use = self.start_block + self.jbd2_super_block.s_start;
-           }
-           return target_use;
-       }else {
-        //不是第一次提交
-           self.head+=1;
-           //处理环绕
-           let mut target_use = self.start_block + self.jbd2_super_block.s_start+self.head-1;
-           if target_use - self.start_block > self.max_len {
-               self.head = 0;
-               target_use = self.start_block + self.jbd2_super_block.s_start;
-           }
-           return target_use;
-       }
-       
+    /// 读一个日志块：外部日志在独立的日志设备上，内部日志在文件系统设备上
+    fn read_log_block<B: BlockDevice>(&mut self, block_dev: &mut B, buf: &mut [u8], block_id: u64) -> BlockDevResult<()> {
+        match self.journal_dev.as_deref_mut() {
+            Some(dev) => read_fs_blocks(dev, self.block_size, buf, block_id, 1),
+            None => read_fs_blocks(block_dev, self.block_size, buf, block_id, 1),
+        }
+    }
+
+    /// 写一个日志块
+    fn write_log_block<B: BlockDevice>(&mut self, block_dev: &mut B, buf: &[u8], block_id: u64) -> BlockDevResult<()> {
+        match self.journal_dev.as_deref_mut() {
+            Some(dev) => write_fs_blocks(dev, self.block_size, buf, block_id, 1),
+            None => write_fs_blocks(block_dev, self.block_size, buf, block_id, 1),
+        }
+    }
+
+    /// 日志所在设备落盘
+    fn flush_log<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        match self.journal_dev.as_deref_mut() {
+            Some(dev) => dev.flush(),
+            None => block_dev.flush(),
+        }
+    }
+
+    /// 事务日志区的结束位置（不含）：启用 fast commit 时日志末尾的 fast commit 区不参与回绕
+    fn log_end(&self) -> u32 {
+        self.max_len - self.fc_blocks
+    }
+
+    /// 日志区的相对块号范围是 [s_first, log_end)，返回 rel 之后的下一个日志块（处理回绕）
+    fn next_log_rel(&self, rel: u32) -> u32 {
+        if rel + 1 >= self.log_end() {
+            self.jbd2_super_block.s_first
+        } else {
+            rel + 1
+        }
+    }
+
+    ///取下一个日志块并推进 head(处理回绕),返回物理块号（可以直接写）
+    pub fn set_next_log_block(&mut self) -> u64 {
+        let rel = self.head;
+        self.head = self.next_log_rel(rel);
+        self.start_block + rel as u64
+    }
+
+    /// 日志区还能写入的块数：[s_start, head) 之间是尚未回收的日志，留一块空隙区分满和空
+    pub fn log_free_blocks(&self) -> u32 {
+        let first = self.jbd2_super_block.s_first;
+        let size = self.log_end().saturating_sub(first);
+        let tail = self.jbd2_super_block.s_start;
+        if tail == 0 {
+            return size.saturating_sub(1);
+        }
+        let used = if self.head >= tail {
+            self.head - tail
+        } else {
+            size - (tail - self.head)
+        };
+        size.saturating_sub(used + 1)
+    }
+
+    /// 单个 revoke 块能容纳的撤销记录数（启用校验和时末尾留出块校验和）
+    fn revoke_records_per_block(&self) -> usize {
+        let usable = self.block_size - Jbd2JournalRevokeHeadS::disk_size() - self.jbd2_super_block.tail_bytes();
+        usable / self.jbd2_super_block.revoke_record_bytes()
+    }
+
+    /// 提交一个事务需要的日志块数：revoke 块 + descriptor 块 + 元数据块 + commit 块
+    fn transaction_log_blocks(&self, updates: usize, revokes: usize) -> u32 {
+        let descriptors = updates.div_ceil(self.tags_per_descriptor());
+        let revoke_blocks = revokes.div_ceil(self.revoke_records_per_block());
+        (revoke_blocks + descriptors + updates + 1) as u32
+    }
+
+    /// 把内存中的 journal 超级块写回（read-modify-write，避免破坏其它字节）并落盘
+    fn write_journal_superblock<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        self.jbd2_super_block.update_checksum();
+        let mut blk = vec![0u8; self.block_size];
+        self.read_log_block(block_dev, &mut blk, self.sb_block)?;
+        self.jbd2_super_block.to_disk_bytes(&mut blk[0..1024]);
+        debug!(
+            "[JBD2] write journal superblock to block={} (sequence={} s_start={})",
+            self.sb_block, self.jbd2_super_block.s_sequence, self.jbd2_super_block.s_start
+        );
+        //直接写，避免鬼打墙
+        self.write_log_block(block_dev, &blk, self.sb_block)?;
+        self.flush_log(block_dev)
+    }
+
+    /// 推进日志尾：尾部指向最旧的仍在检查点链表上的事务，链表为空时指向 head
+    fn update_tail<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        let (tail, tid) = match self.checkpoint_list.front() {
+            Some(cp) => (cp.start, cp.tid),
+            None => (self.head, self.sequence),
+        };
+        if self.jbd2_super_block.s_start == tail && self.jbd2_super_block.s_sequence == tid {
+            return Ok(());
+        }
+        debug!(
+            "[JBD2 checkpoint] advance tail: s_start {} -> {tail}, s_sequence {} -> {tid}",
+            self.jbd2_super_block.s_start, self.jbd2_super_block.s_sequence
+        );
+        self.jbd2_super_block.s_start = tail;
+        self.jbd2_super_block.s_sequence = tid;
+        self.write_journal_superblock(block_dev)
+    }
+
+    /// 检查点：确认已提交事务的元数据都已写回原位置并落盘，把它们移出检查点链表并推进日志尾，回收日志空间
+    pub fn checkpoint<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        if self.jbd2_super_block.s_start == 0 {
+            return Ok(());
+        }
+        // 事务提交时已经同步写回原位置，这里只需要保证它们已经落盘
+        block_dev.flush()?;
+        while let Some(cp) = self.checkpoint_list.pop_front() {
+            debug!("[JBD2 checkpoint] tid={} start={} len={} checkpointed", cp.tid, cp.start, cp.len);
+        }
+        // 日志里已没有可重放的事务，不再需要为这些块记录撤销
+        self.logged_blocks.clear();
+        self.update_tail(block_dev)
+    }
+
+    /// 打开日志特性（只能在日志为空时调用，例如重放之后）
+    /// 启用 checksum v3 时关闭 v2 并使用 crc32c
+    pub fn set_features<B: BlockDevice>(&mut self, block_dev: &mut B, incompat: u32) -> BlockDevResult<()> {
+        let sb = &mut self.jbd2_super_block;
+        if sb.s_start != 0 {
+            return Err(BlockDevError::DeviceBusy);
+        }
+        if sb.s_feature_incompat & incompat == incompat {
+            return Ok(());
+        }
+        sb.s_feature_incompat |= incompat;
+        if incompat & JBD2_FEATURE_INCOMPAT_CSUM_V3 != 0 {
+            sb.s_feature_incompat &= !JBD2_FEATURE_INCOMPAT_CSUM_V2;
+            sb.s_checksum_type = JBD2_CRC32C_CHKSUM;
+        }
+        debug!("[JBD2] journal incompat features now 0x{:x}", sb.s_feature_incompat);
+        self.write_journal_superblock(block_dev)
+    }
+
+    /// 在日志末尾划出 fast commit 区（日志长度的 1/64，与 mke2fs 相同），剩余日志不能少于 JBD2_MIN_JOURNAL_BLOCKS
+    /// 日志里还有事务时先提交并做检查点；返回 fast commit 是否可用
+    pub fn enable_fast_commit<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<bool> {
+        if self.fc_blocks == 0 {
+            let num = self.max_len / 64;
+            if num < 2 || self.max_len - num < JBD2_MIN_JOURNAL_BLOCKS {
+                warn!("[JBD2] journal too small for a fast commit area: max_len={}", self.max_len);
+                return Ok(false);
+            }
+            if self.handle_count > 0 {
+                return Err(BlockDevError::DeviceBusy);
+            }
+            if self.jbd2_super_block.s_start != 0 {
+                self.commit_transaction(block_dev)?;
+                self.mark_journal_empty(block_dev)?;
+            }
+            self.jbd2_super_block.s_num_fc_blks = num;
+            self.set_features(block_dev, JBD2_FEATURE_INCOMPAT_FAST_COMMIT)?;
+            self.fc_blocks = num;
+        }
+        self.fc_enabled = true;
+        Ok(true)
+    }
+
+    /// 把编码好的 fast commit 块追加到 fast commit 区并落盘
+    /// 与 ordered 模式一样数据块先落盘；区域写满时返回 NoSpace，由调用方改做完整提交
+    pub fn write_fast_commit<B: BlockDevice>(&mut self, block_dev: &mut B, blocks: &[Vec<u8>]) -> BlockDevResult<()> {
+        if self.aborted {
+            return Err(BlockDevError::ReadOnly);
+        }
+        if !self.fc_enabled || self.fc_blocks == 0 {
+            return Err(BlockDevError::Unsupported);
+        }
+        // 与 jbd2 相同：fast commit 块从 log_end + 1 开始
+        let first = self.log_end() + 1 + self.fc_off;
+        if first as u64 + blocks.len() as u64 > self.max_len as u64 {
+            return Err(BlockDevError::NoSpace);
+        }
+        // 重放只在 s_start 非 0 时进行：日志为空时先把尾部定在 s_first
+        self.reserve_log_space(block_dev, 0)?;
+        block_dev.flush()?;
+        for (idx, blk) in blocks.iter().enumerate() {
+            let phys = self.start_block + (first + idx as u32) as u64;
+            self.write_log_block(block_dev, blk, phys)?;
+        }
+        self.flush_log(block_dev)?;
+        self.fc_off += blocks.len() as u32;
+        debug!(
+            "[JBD2 fast commit] tid={} wrote {} blocks, fc_off={}",
+            self.sequence,
+            blocks.len(),
+            self.fc_off
+        );
+        Ok(())
+    }
+
+    /// 文件系统层应用完 fast commit 记录后调用：跳过 fast commit 用掉的事务号，
+    /// 区里的旧记录从此不会再和新的 fast commit 混在一起，然后把日志标记为空
+    pub fn finish_fast_commit_replay<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        self.fc_replay.clear();
+        self.sequence = self.sequence.wrapping_add(1);
+        self.mark_journal_empty(block_dev)
+    }
+
+    /// 做检查点并把日志标记为空（s_start=0），卸载时调用
+    pub fn mark_journal_empty<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<()> {
+        self.checkpoint(block_dev)?;
+        self.jbd2_super_block.s_start = 0;
+        self.jbd2_super_block.s_sequence = self.sequence;
+        self.head = 0;
+        self.write_journal_superblock(block_dev)
+    }
+
+    /// 确保日志有 `needed` 个空闲块：日志为空时从 s_first 开始，空间不足时强制检查点
+    fn reserve_log_space<B: BlockDevice>(&mut self, block_dev: &mut B, needed: u32) -> BlockDevResult<()> {
+        if self.head == 0 {
+            // 本次挂载第一次写日志（重放后日志已为空）：日志从 s_first 开始，尾部就是即将写入的事务
+            self.head = self.jbd2_super_block.s_first;
+            self.update_tail(block_dev)?;
+        }
+        if self.log_free_blocks() < needed {
+            debug!(
+                "[JBD2 checkpoint] log space low: need={needed} free={}, force checkpoint",
+                self.log_free_blocks()
+            );
+            self.checkpoint(block_dev)?;
+        }
+        if self.log_free_blocks() < needed {
+            error!(
+                "[JBD2 commit] transaction needs {needed} log blocks but journal only has {}",
+                self.log_free_blocks()
+            );
+            return Err(BlockDevError::NoSpace);
+        }
+        Ok(())
+    }
+
+    /// 单个 descriptor 块能容纳的 tag 数（第一个 tag 之后跟 16 字节 UUID，启用校验和时末尾留出块校验和）
+    pub fn tags_per_descriptor(&self) -> usize {
+        let usable = self.block_size
+            - JournalHeaderS::disk_size()
+            - JBD2_UUID_BYTES
+            - self.jbd2_super_block.tail_bytes();
+        usable / self.jbd2_super_block.tag_bytes()
+    }
+
+    /// 一个事务最多记录的元数据块数（日志长度的 1/4，与 jbd2 相同）
+    pub fn max_transaction_blocks(&self) -> u32 {
+        (self.max_len / 4).max(1)
+    }
+
+    /// 把元数据块放进运行中的事务，同一块在一个事务里只保留最新内容
+    /// 本事务之前撤销过的块重新写入时取消撤销
+    pub fn queue_update(&mut self, update: Jbd2Update) {
+        self.revoke_queue.retain(|b| *b != update.0);
+        match self.commit_queue.iter_mut().find(|u| u.0 == update.0) {
+            Some(old) => old.1 = update.1,
+            None => self.commit_queue.push(update),
+        }
+    }
+
+    /// 运行中事务里某块的最新内容
+    pub fn queued_block(&self, block_id: u64) -> Option<&[u8]> {
+        self.commit_queue
+            .iter()
+            .find(|u| u.0 == block_id)
+            .map(|u| &*u.1)
+    }
+
+    /// 块被当作普通数据直接写回时，把它移出运行中的事务
+    pub fn forget_block(&mut self, block_id: u64) {
+        self.commit_queue.retain(|u| u.0 != block_id);
+    }
+
+    /// 块被释放：移出运行中的事务，若日志里还有它的旧内容则记一条撤销，避免重放覆盖块的新用途
+    pub fn revoke_block(&mut self, block_id: u64) {
+        self.forget_block(block_id);
+        if self.logged_blocks.contains(&block_id) && !self.revoke_queue.contains(&block_id) {
+            debug!("[JBD2 revoke] tid={} block={block_id}", self.sequence);
+            self.revoke_queue.push(block_id);
+        }
+    }
+
+    /// 运行中的事务是否为空
+    pub fn is_transaction_empty(&self) -> bool {
+        self.commit_queue.is_empty() && self.revoke_queue.is_empty()
     }
+
+    /// 把撤销记录写成 revoke 块，一个块写不下时拆成多个
+    fn write_revoke_blocks<B: BlockDevice>(&mut self, block_dev: &mut B, tid: u32, revokes: &[u64]) -> BlockDevResult<()> {
+        let record_bytes = self.jbd2_super_block.revoke_record_bytes();
+        let header_size = Jbd2JournalRevokeHeadS::disk_size();
+        let per_block = self.revoke_records_per_block();
+        for chunk in revokes.chunks(per_block) {
+            let mut buf = vec![0u8; self.block_size];
+            let mut off = header_size;
+            for &blk in chunk {
+                if record_bytes == 8 {
+                    buf[off..off + 8].copy_from_slice(&blk.to_be_bytes());
+                } else {
+                    buf[off..off + 4].copy_from_slice(&(blk as u32).to_be_bytes());
+                }
+                off += record_bytes;
+            }
+            let head = Jbd2JournalRevokeHeadS {
+                r_header: JournalHeaderS {
+                    h_magic: JBD2_MAGIC,
+                    h_blocktype: JBD2_REVOKE_BLOCK,
+                    h_sequence: tid,
+                },
+                r_count: off as u32,
+            };
+            head.to_disk_bytes(&mut buf[0..header_size]);
+            self.jbd2_super_block.set_block_tail_csum(&mut buf);
+            let block_id = self.set_next_log_block();
+            debug!(
+                "[JBD2 commit] tid={tid} revoke_block_id={block_id} records={}",
+                chunk.len()
+            );
+            self.write_log_block(block_dev, &buf, block_id)?;
+        }
+        Ok(())
+    }
+
+    /// 日志是否已因提交失败而中止
+    pub fn is_aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// 中止日志：丢弃运行中的事务，之后拒绝新的事务；尽力把错误码记到 journal 超级块的 s_errno
+    pub fn abort<B: BlockDevice>(&mut self, block_dev: &mut B, err: BlockDevError) {
+        if self.aborted {
+            return;
+        }
+        error!("[JBD2] journal aborted at tid={}: {err}", self.sequence);
+        self.aborted = true;
+        self.commit_queue.clear();
+        self.revoke_queue.clear();
+        self.handle_count = 0;
+        self.reserved_credits = 0;
+        self.deferred = false;
+        self.jbd2_super_block.s_errno = JBD2_EIO_ERRNO;
+        if let Err(e) = self.write_journal_superblock(block_dev) {
+            warn!("[JBD2] record journal error failed: {e}");
+        }
+    }
+
     ///提交事务
     /// 允许使用原始块设备!
-    /// update:Vec<JBD2_UPDATE>
-    pub fn commit_transaction<B: BlockDevice>(&mut self, block_dev: &mut B) -> Result<bool, ()> {
+    /// 元数据块超过一个 descriptor 的容量时，按顺序写多个 descriptor，最后只写一个 commit 块
+    /// commit 块落盘后再把元数据写回原位置
+    /// 任何一步失败都会中止日志并返回错误
+    pub fn commit_transaction<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<bool> {
+        if self.aborted {
+            return Err(BlockDevError::ReadOnly);
+        }
+        self.do_commit_transaction(block_dev).inspect_err(|e| self.abort(block_dev, *e))
+    }
+
+    fn do_commit_transaction<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<bool> {
         let tid = self.sequence; //事务id
         debug!(
             "[JBD2 commit] begin: tid={} updates_len={} head={} start_block={} max_len={} seq_in_superblock={} s_start={}",
@@ -64,86 +408,103 @@ impl JBD2DEVSYSTEM {
             self.jbd2_super_block.s_start,
         );
 
-        if self.commit_queue.len() <= 0 {
+        if self.is_transaction_empty() {
             warn!("No thing need to commit");
             return Ok(false);
         }
 
-        let mut desc_buffer = vec![0; BLOCK_SIZE];
+        let needed = self.transaction_log_blocks(self.commit_queue.len(), self.revoke_queue.len());
+        self.reserve_log_space(block_dev, needed)?;
+        let start = self.head;
 
-        //写header->内存缓存
-        let mut new_jbd_header = JournalHeaderS::default();
-        new_jbd_header.h_blocktype = 1; //Descriptor
-        new_jbd_header.h_sequence = tid; //设置事务id
-        new_jbd_header.to_disk_bytes(&mut desc_buffer[0..JournalHeaderS::disk_size()]);
+        let updates = core::mem::take(&mut self.commit_queue);
+        let revokes = core::mem::take(&mut self.revoke_queue);
+        self.write_revoke_blocks(block_dev, tid, &revokes)?;
+        let tag_bytes = self.jbd2_super_block.tag_bytes();
+        let per_desc = self.tags_per_descriptor();
+
+        for (desc_idx, chunk) in updates.chunks(per_desc).enumerate() {
+            let mut desc_buffer = vec![0; self.block_size];
+            //日志里实际写入的内容：逃逸的块开头填0，tag 校验和覆盖的也是这份内容
+            let logged: Vec<Vec<u8>> = chunk
+                .iter()
+                .map(|up| {
+                    let mut check_data = up.1.to_vec();
+                    let magic = u32::from_be_bytes(check_data[0..4].try_into().unwrap());
+                    if magic == JBD2_MAGIC {
+                        debug!("Find excape data,will fill 0");
+                        check_data[0..4].fill(0);
+                    }
+                    check_data
+                })
+                .collect();
 
-        let mut current_offset = 12; //跳过头
-        //写many tag，目前开发测试简化为一个descriptor块能塞下:)
-        for (idx, update) in self.commit_queue.iter().enumerate() {
-            //检查逃逸escape 如果数据块开头也是jbd2_magic 要标志逃逸
-            let mut tag = JournalBlockTagS {
-                t_blocknr: update.0 as u32,
-                t_checksum: 0,
-                t_flags: 0, //后面记得处理逃逸
+            //写header->内存缓存
+            let new_jbd_header = JournalHeaderS {
+                h_blocktype: 1, //Descriptor
+                h_sequence: tid, //设置事务id
+                ..Default::default()
             };
-            let magic: u32 = u32::from_le_bytes(update.1[0..4].try_into().unwrap());
-            if magic == JBD2_MAGIC {
-                tag.t_flags |= JOURANL_ESCAPE;
-                debug!("JOURNAL ERROR ,Updates data escape!!!");
-            }
+            new_jbd_header.to_disk_bytes(&mut desc_buffer[0..JournalHeaderS::disk_size()]);
 
-            //最后一个
-            if idx == self.commit_queue.len() - 1 {
-                tag.t_flags |= JBD2_FLAG_LAST_TAG;
+            let mut current_offset = JournalHeaderS::disk_size(); //跳过头
+            for (idx, update) in chunk.iter().enumerate() {
+                //检查逃逸escape 如果数据块开头也是jbd2_magic 要标志逃逸
+                let mut tag = JouranlBlockTag3S::new(update.0, 0);
+                let magic: u32 = u32::from_be_bytes(update.1[0..4].try_into().unwrap());
+                if magic == JBD2_MAGIC {
+                    tag.t_flags |= JOURANL_ESCAPE as u32;
+                    debug!("JOURNAL ERROR ,Updates data escape!!!");
+                }
+                // 只有每个 descriptor 的第一个 tag 后面跟 UUID
+                if idx > 0 {
+                    tag.t_flags |= JBD2_FLAG_SAME_UUID as u32;
+                }
+                //本 descriptor 的最后一个
+                if idx == chunk.len() - 1 {
+                    tag.t_flags |= JBD2_FLAG_LAST_TAG as u32;
+                }
+                if self.jbd2_super_block.has_csum_v2or3() {
+                    tag.t_checksum = self.jbd2_super_block.block_tag_csum(tid, &logged[idx]);
+                }
+                debug!(
+                    "[JBD2 commit] tid={} desc_idx={} tag_idx={} t_blocknr={} t_flags=0x{:x}",
+                    tid, desc_idx, idx, tag.blocknr(), tag.t_flags,
+                );
+                tag.write_tag(&self.jbd2_super_block, &mut desc_buffer[current_offset..current_offset + tag_bytes]);
+                current_offset += tag_bytes;
+                if idx == 0 {
+                    desc_buffer[current_offset..current_offset + JBD2_UUID_BYTES]
+                        .copy_from_slice(&self.jbd2_super_block.s_uuid);
+                    current_offset += JBD2_UUID_BYTES;
+                }
             }
-            debug!(
-                "[JBD2 commit] tid={} tag_idx={} t_blocknr={} t_flags=0x{:x}",
-                tid, idx, tag.t_blocknr, tag.t_flags,
-            );
-            tag.to_disk_bytes(&mut desc_buffer[current_offset..current_offset + 8]);
-            current_offset += 8;
-        }
 
-        //实际写入盘 这里可以直接写
-        let block_id = self.set_next_log_block(block_dev);
-        debug!(
-            "[JBD2 commit] tid={tid} descriptor_block_id={block_id} (absolute)"
-        );
-        block_dev.write(&desc_buffer, block_id, 1).expect("Jouranl block write failed!");
-
-        let mut no_escape: Vec<(u64, [u8; BLOCK_SIZE])> = Vec::new();
-        //逃逸处理
-        for update in self.commit_queue.iter() {
-            //逃逸处理
-            let mut check_data: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
-            check_data.copy_from_slice(&*update.1);
-            let magic = u32::from_le_bytes(check_data[0..4].try_into().unwrap());
-            if magic == JBD2_MAGIC {
-                debug!("Find excape data,will fill 0");
-                check_data[0..4].fill(0);
-            }
-            no_escape.push((update.0, check_data));
-        }
+            self.jbd2_super_block.set_block_tail_csum(&mut desc_buffer);
 
-        //写实际的metadata CORE!!!!!
-        for (idx, up) in no_escape.iter().enumerate() {
-            let metadata_journal_block_id = self.set_next_log_block(block_dev);
+            //实际写入盘 这里可以直接写
+            let block_id = self.set_next_log_block();
             debug!(
-                "[JBD2 commit] tid={} meta_idx={} journal_block_id={} (absolute) target_phys_block={}",
-                tid, idx, metadata_journal_block_id, up.0
+                "[JBD2 commit] tid={tid} descriptor_block_id={block_id} (absolute)"
             );
-            block_dev.write(&up.1, metadata_journal_block_id, 1).expect("Jouranl block write failed!");
-        }
+            self.write_log_block(block_dev, &desc_buffer, block_id)?;
 
-        block_dev.flush().expect("Jouranl block write failed!");
+            //写实际的metadata CORE!!!!!
+            for (idx, (up, check_data)) in chunk.iter().zip(logged.iter()).enumerate() {
+                let metadata_journal_block_id = self.set_next_log_block();
+                debug!(
+                    "[JBD2 commit] tid={} meta_idx={} journal_block_id={} (absolute) target_phys_block={}",
+                    tid, idx, metadata_journal_block_id, up.0
+                );
+                self.write_log_block(block_dev, check_data, metadata_journal_block_id)?;
+            }
+        }
 
-        //清空update缓存
-        self.commit_queue.clear();
-        debug!("[JBD2 BUFFER] BUFFER ALREADY CLEA");
+        self.flush_log(block_dev)?;
 
         //写入Commit Block
 
-        let mut commit_buffer = [0_u8; BLOCK_SIZE];
+        let mut commit_buffer = vec![0_u8; self.block_size];
 
         let commit_block = CommitHeader {
             //commit block type 2
@@ -161,14 +522,36 @@ impl JBD2DEVSYSTEM {
         };
 
         commit_block.to_disk_bytes(&mut commit_buffer);
-        let commit_block_id = self.set_next_log_block(block_dev);
+        self.jbd2_super_block.set_commit_csum(&mut commit_buffer);
+        let commit_block_id = self.set_next_log_block();
         debug!(
             "[JBD2 commit] tid={tid} commit_block_id={commit_block_id} (absolute)"
         );
-        block_dev.write(&commit_buffer, commit_block_id, 1).expect("Jouranl block write failed!");
+        self.write_log_block(block_dev, &commit_buffer, commit_block_id)?;
         //至此，commit已经完成，metadata数据已经安全:）
-        block_dev.flush().expect("Jouranl block write failed!");
-        self.sequence += 1;
+        self.flush_log(block_dev)?;
+
+        //把元数据写回原位置
+        for up in updates.iter() {
+            write_fs_blocks(block_dev, self.block_size, &up.1, up.0, 1)?;
+            self.logged_blocks.insert(up.0);
+        }
+        block_dev.flush()?;
+        debug!("[JBD2 BUFFER] BUFFER ALREADY CLEA");
+
+        // 事务仍占用日志空间，直到检查点推进日志尾
+        self.checkpoint_list.push_back(Jbd2Checkpoint { tid, start, len: needed });
+        self.sequence = self.sequence.wrapping_add(1);
+        // 完整提交之后 fast commit 区里的记录都已过期；e2fsck 把事务号不符的 HEAD 当作日志损坏，
+        // 所以在提交落盘之后清掉区里的第一个块
+        if self.fc_off > 0 {
+            let zero = vec![0u8; self.block_size];
+            let phys = self.start_block + (self.log_end() + 1) as u64;
+            self.write_log_block(block_dev, &zero, phys)?;
+            self.flush_log(block_dev)?;
+        }
+        self.fc_off = 0;
+        self.deferred = false;
         debug!(
             "[JBD2 commit] end: tid={} new_sequence={}",
             tid, self.sequence
@@ -178,216 +561,250 @@ impl JBD2DEVSYSTEM {
         Ok(true)
     }
 
-    ///事务重放：从当前 superblock 状态开始，尽可能重放连续的完整事务 replay前确保全部commit
-    pub fn replay<B: BlockDevice>(&mut self, block_dev: &mut B) {
-        // 注意：journal_superblock_s 里的 s_first / s_start 是“日志区内部的相对块号”，
-        // 真实物理块号 = self.start_block + rel。
+    /// 解析 descriptor 块里的 tags（tag 大小由 journal 特性决定，64BIT 时带高 32 位）
+    fn parse_descriptor_tags(&self, desc_buf: &[u8]) -> Vec<JouranlBlockTag3S> {
+        let tag_bytes = self.jbd2_super_block.tag_bytes();
+        let mut tags: Vec<JouranlBlockTag3S> = Vec::new();
+        let mut off = JournalHeaderS::disk_size(); // 跳过 header
+        let end = self.block_size - self.jbd2_super_block.tail_bytes();
+        while off + tag_bytes <= end {
+            // 注意：t_blocknr==0 在 ext4 上是合法的（例如 superblock/group desc 等元数据），
+            // 不能直接用 "t_blocknr==0" 当作 tag 结束条件。
+            // 我们只在“当前 tag 全 0 且后续全部为 0 padding”时，才认为 descriptor 结束。
+            if desc_buf[off..end].iter().all(|b| *b == 0) {
+                break;
+            }
+            let tag = JouranlBlockTag3S::read_tag(&self.jbd2_super_block, &desc_buf[off..off + tag_bytes]);
+            let last = (tag.t_flags & JBD2_FLAG_LAST_TAG as u32) != 0;
+            let same_uuid = (tag.t_flags & JBD2_FLAG_SAME_UUID as u32) != 0;
+            tags.push(tag);
+            off += tag_bytes;
+            if !same_uuid {
+                off += JBD2_UUID_BYTES;
+            }
+            if last {
+                break;
+            }
+        }
+        tags
+    }
 
-        // 扫描起点（相对块号）：只使用 s_start。s_start==0 表示没有需要重放的事务。
-        let mut journal_rel = self.jbd2_super_block.s_start;
-        if journal_rel == 0 {
-            return;
+    /// 解析 revoke 块里的块号记录
+    fn parse_revoke_records(&self, buf: &[u8]) -> Vec<u64> {
+        let record_bytes = self.jbd2_super_block.revoke_record_bytes();
+        let head = Jbd2JournalRevokeHeadS::from_disk_bytes(buf);
+        let end = (head.r_count as usize).min(self.block_size - self.jbd2_super_block.tail_bytes());
+        let mut off = Jbd2JournalRevokeHeadS::disk_size();
+        let mut records = Vec::new();
+        while off + record_bytes <= end {
+            let blk = if record_bytes == 8 {
+                u64::from_be_bytes(buf[off..off + 8].try_into().unwrap())
+            } else {
+                u32::from_be_bytes(buf[off..off + 4].try_into().unwrap()) as u64
+            };
+            records.push(blk);
+            off += record_bytes;
         }
+        records
+    }
 
+    /// PASS_SCAN：从 s_start 顺序扫描日志，收集所有完整提交的事务，遇到不匹配的块即为日志末尾
+    /// 启用校验和时，校验失败的块说明写入被撕裂，该事务及之后的日志都不重放
+    fn scan_log<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<Vec<LoggedTransaction>> {
+        // 注意：journal_superblock_s 里的 s_first / s_start 是“日志区内部的相对块号”，
+        // 真实物理块号 = self.start_block + rel。
+        let mut found = Vec::new();
         let first_rel = self.jbd2_super_block.s_first; // 第一个日志块（相对 superblock）
         let maxlen = self.jbd2_super_block.s_maxlen; // 可用日志块数量（不含 superblock）
-        let last_rel = first_rel.saturating_add(maxlen.saturating_sub(1));
-        let mut expect_seq = self.jbd2_super_block.s_sequence;
-
-        // 简单防护：maxlen 为 0 直接返回
-        if maxlen == 0 {
-            return;
+        // s_start==0 表示没有需要重放的事务；maxlen 为 0 直接返回
+        if self.jbd2_super_block.s_start == 0 || maxlen == 0 {
+            return Ok(found);
         }
+        let last_rel = maxlen.saturating_sub(1);
 
+        let mut rel = self.jbd2_super_block.s_start;
+        let mut current = LoggedTransaction {
+            sequence: self.jbd2_super_block.s_sequence,
+            tags: Vec::new(),
+            revoked: Vec::new(),
+        };
+        let mut buf = vec![0u8; self.block_size];
+        let mut mbuf = vec![0u8; self.block_size];
+        let mut walked = 0u32;
         debug!(
-            "[JBD2 replay] begin: journal_sb_phys={} first_rel={} last_rel={} s_start(rel)={} maxlen={} expect_seq={}",
-            self.start_block,
-            first_rel,
-            last_rel,
-            journal_rel,
-            maxlen,
-            expect_seq,
+            "[JBD2 replay] scan begin: journal_sb_phys={} first_rel={} last_rel={} s_start(rel)={} maxlen={} expect_seq={}",
+            self.start_block, first_rel, last_rel, rel, maxlen, current.sequence,
         );
-
-        // 相对块号前进（含回绕）
-        let advance_rel = |rel: &mut u32| {
-            if *rel >= last_rel {
-                *rel = first_rel;
-            } else {
-                *rel = rel.saturating_add(1);
-            }
-        };
-
-        loop {
-            // 1) 读取 descriptor 块并做基本校验
-            let mut desc_buf = [0u8; BLOCK_SIZE];
-            let desc_phys = self.start_block + journal_rel; // descriptor 物理块号
-            if let Err(e) = block_dev.read(&mut desc_buf, desc_phys, 1) {
-                debug!(
-                    "[JBD2 replay] read descriptor failed at rel_block={journal_rel} phys_block={desc_phys} err={e:?}"
-                );
-                break;
-            }
-
-            let hdr = JournalHeaderS::from_disk_bytes(&desc_buf[0..12]);
+        'scan: while walked < maxlen {
+            let phys = self.start_block + rel as u64;
+            self.read_log_block(block_dev, &mut buf, phys).inspect_err(|e| {
+                error!("[JBD2 replay] read failed at rel_block={rel} phys_block={phys} err={e:?}");
+            })?;
+            let hdr = JournalHeaderS::from_disk_bytes(&buf[0..12]);
             debug!(
-                "[JBD2 replay] descriptor: phys_block={} h_magic=0x{:x} h_blocktype={} h_sequence={} expect_seq={}",
-                desc_phys, hdr.h_magic, hdr.h_blocktype, hdr.h_sequence, expect_seq
+                "[JBD2 replay] block: phys_block={} h_magic=0x{:x} h_blocktype={} h_sequence={} expect_seq={}",
+                phys, hdr.h_magic, hdr.h_blocktype, hdr.h_sequence, current.sequence
             );
-            if hdr.h_magic != JBD2_MAGIC || hdr.h_blocktype != 1 {
-                // 不是合法的 descriptor，认为后面没有可重放事务
-                break;
-            }
-            if hdr.h_sequence != expect_seq {
-                // 序列号不匹配，认为没有更多可重放事务
+            if hdr.h_magic != JBD2_MAGIC || hdr.h_sequence != current.sequence {
+                // 不是合法的日志块或序列号不匹配，认为日志到此结束
                 break;
             }
-
-            // 2) 解析 descriptor 里的 tags
-            let mut tags: Vec<JournalBlockTagS> = Vec::new();
-            let mut off = 12usize; // 跳过 header
-            let mut tag_idx = 0usize;
-            while off + 8 <= BLOCK_SIZE {
-                let tag = JournalBlockTagS::from_disk_bytes(&desc_buf[off..off + 8]);
-
-                // 注意：t_blocknr==0 在 ext4 上是合法的（例如 superblock/group desc 等元数据），
-                // 不能直接用 "t_blocknr==0" 当作 tag 结束条件。
-                // 我们只在“当前 8 字节全 0 且后续全部为 0 padding”时，才认为 descriptor 结束。
-                if tag.t_blocknr == 0 && tag.t_checksum == 0 && tag.t_flags == 0 {
-                    if desc_buf[off + 8..].iter().all(|b| *b == 0) {
+            match hdr.h_blocktype {
+                JBD2_DESCRIPTOR_BLOCK => {
+                    if !self.jbd2_super_block.verify_block_tail_csum(&buf) {
+                        warn!("[JBD2 replay] tid={} descriptor block {phys} checksum mismatch", current.sequence);
                         break;
                     }
+                    for tag in self.parse_descriptor_tags(&buf) {
+                        rel = self.next_log_rel(rel);
+                        walked += 1;
+                        let meta_phys = self.start_block + rel as u64;
+                        if self.jbd2_super_block.has_csum_v2or3() {
+                            self.read_log_block(block_dev, &mut mbuf, meta_phys)?;
+                            if !self.jbd2_super_block.verify_block_tag_csum(&tag, current.sequence, &mbuf) {
+                                warn!(
+                                    "[JBD2 replay] tid={} journal block {meta_phys} for block {} checksum mismatch",
+                                    current.sequence,
+                                    tag.blocknr()
+                                );
+                                break 'scan;
+                            }
+                        }
+                        current.tags.push((tag, rel));
+                    }
                 }
-
-                debug!(
-                    "[JBD2 replay] tid={} tag_idx={} t_blocknr={} t_flags=0x{:x}",
-                    expect_seq, tag_idx, tag.t_blocknr, tag.t_flags
-                );
-
-                let last = (tag.t_flags & JBD2_FLAG_LAST_TAG) != 0;
-                tags.push(tag);
-                off += 8;
-                tag_idx += 1;
-
-                if last {
-                    break;
+                JBD2_REVOKE_BLOCK => {
+                    if !self.jbd2_super_block.verify_block_tail_csum(&buf) {
+                        warn!("[JBD2 replay] tid={} revoke block {phys} checksum mismatch", current.sequence);
+                        break;
+                    }
+                    let records = self.parse_revoke_records(&buf);
+                    current.revoked.extend(records);
+                }
+                JBD2_COMMIT_BLOCK => {
+                    if !self.jbd2_super_block.verify_commit_csum(&buf) {
+                        warn!("[JBD2 replay] tid={} commit block {phys} checksum mismatch", current.sequence);
+                        break;
+                    }
+                    let next = current.sequence.wrapping_add(1);
+                    found.push(core::mem::replace(
+                        &mut current,
+                        LoggedTransaction {
+                            sequence: next,
+                            tags: Vec::new(),
+                            revoked: Vec::new(),
+                        },
+                    ));
                 }
+                _ => break,
             }
+            rel = self.next_log_rel(rel);
+            walked += 1;
+        }
+        // 没有 commit 块的尾部事务不完整，丢弃
+        Ok(found)
+    }
 
-            if tags.is_empty() {
-                // 没有任何 tag，无事务可重放
+    /// 扫描 fast commit 区：记录的事务号必须等于最后一个完整事务的下一个事务号
+    fn scan_fast_commit<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<Vec<FcRecord>> {
+        let mut scanner = FcScanner::new(self.sequence);
+        let mut buf = vec![0u8; self.block_size];
+        for rel in self.log_end() + 1..self.max_len {
+            self.read_log_block(block_dev, &mut buf, self.start_block + rel as u64)?;
+            if !scanner.scan_block(&buf) {
                 break;
             }
+        }
+        let records = scanner.finish();
+        debug!("[JBD2 replay] tid={} fast commit records: {}", self.sequence, records.len());
+        Ok(records)
+    }
 
-            // 3) 读取对应数量的 metadata 日志块
-            let mut meta_blocks: Vec<[u8; BLOCK_SIZE]> = Vec::new();
-            for (idx, _) in tags.iter().enumerate() {
-                // 下一个 journal 块（相对块号），注意处理回绕
-                advance_rel(&mut journal_rel);
-                let meta_phys = self.start_block + journal_rel;
-                let mut mbuf = [0u8; BLOCK_SIZE];
-                if let Err(e) = block_dev.read(&mut mbuf, meta_phys, 1) {
-                    debug!(
-                        "[JBD2 replay] read meta block failed: idx={idx} rel_block={journal_rel} phys_block={meta_phys} err={e:?}"
-                    );
-                    return;
-                }
-                debug!(
-                    "[JBD2 replay] tid={expect_seq} loaded meta_idx={idx} from rel_block={journal_rel} phys_block={meta_phys}"
-                );
-                meta_blocks.push(mbuf);
-            }
+    ///事务重放：PASS_SCAN 找出完整事务，PASS_REVOKE 收集撤销记录，PASS_REPLAY 按序写回未被撤销的块
+    /// 之后扫描 fast commit 区，记录留给文件系统层应用（见 `Jbd2Dev::take_fast_commit_records`）
+    /// 返回是否有事务被重放；写回失败时返回错误，日志保持原样等待下次重放
+    pub fn replay<B: BlockDevice>(&mut self, block_dev: &mut B) -> BlockDevResult<bool> {
+        if !self.jbd2_super_block.verify_checksum() {
+            error!("[JBD2 replay] journal superblock checksum mismatch, refuse to replay");
+            return Err(BlockDevError::ChecksumError);
+        }
+        let had_log = self.jbd2_super_block.s_start != 0;
+        let transactions = self.scan_log(block_dev)?;
 
-            // 4) 读取 commit 块并验证
-            advance_rel(&mut journal_rel);
-            let commit_rel = journal_rel;
-            let commit_phys = self.start_block + commit_rel;
-            let mut cbuf = [0u8; BLOCK_SIZE];
-            if let Err(e) = block_dev.read(&mut cbuf, commit_phys, 1) {
-                debug!(
-                    "[JBD2 replay] read commit failed at rel_block={commit_rel} phys_block={commit_phys} err={e:?}"
-                );
-                return;
-            }
-            let chdr = JournalHeaderS::from_disk_bytes(&cbuf[0..12]);
-            debug!(
-                "[JBD2 replay] commit: rel_block={} phys_block={} h_magic=0x{:x} h_blocktype={} h_sequence={} expect_seq={}",
-                commit_rel, commit_phys, chdr.h_magic, chdr.h_blocktype, chdr.h_sequence, expect_seq
-            );
-            if chdr.h_magic != JBD2_MAGIC || chdr.h_blocktype != 2 || chdr.h_sequence != expect_seq
-            {
-                // 没有匹配的 commit，事务不完整，不再继续
-                break;
+        // PASS_REVOKE：每个块记下撤销它的最新事务号
+        let mut revoked: BTreeMap<u64, u32> = BTreeMap::new();
+        for t in transactions.iter() {
+            for &blk in t.revoked.iter() {
+                let seq = revoked.entry(blk).or_insert(t.sequence);
+                if tid_gt(t.sequence, *seq) {
+                    *seq = t.sequence;
+                }
             }
+        }
 
-            // 5) 真正重放：把每个 metadata 块写回主盘对应的 t_blocknr
-            for (i, tag) in tags.iter().enumerate() {
-                let phys = tag.t_blocknr;
-                let data = &mut meta_blocks[i];
+        // PASS_REPLAY：事务 T 里的块若在不早于 T 的事务里被撤销，则跳过
+        let mut mbuf = vec![0u8; self.block_size];
+        for t in transactions.iter() {
+            for (idx, (tag, rel)) in t.tags.iter().enumerate() {
+                let phys = tag.blocknr();
+                if let Some(&rseq) = revoked.get(&phys)
+                    && !tid_gt(t.sequence, rseq)
+                {
+                    debug!("[JBD2 replay] tid={} skip revoked phys_block={phys} (revoked by {rseq})", t.sequence);
+                    continue;
+                }
+                let meta_phys = self.start_block + *rel as u64;
+                self.read_log_block(block_dev, &mut mbuf, meta_phys).inspect_err(|e| {
+                    error!(
+                        "[JBD2 replay] read meta block failed: idx={idx} rel_block={rel} phys_block={meta_phys} err={e:?}"
+                    );
+                })?;
 
                 //检查是否逃逸
-                if (tag.t_flags & 1) != 0 {
-                    // JBD2_FLAG_ESCAPE = 1
-                    let magic_bytes = JBD2_MAGIC.to_be_bytes();
-                    data[0] = magic_bytes[0];
-                    data[1] = magic_bytes[1];
-                    data[2] = magic_bytes[2];
-                    data[3] = magic_bytes[3];
+                if (tag.t_flags & JOURANL_ESCAPE as u32) != 0 {
+                    mbuf[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
                     debug!("Restored JBD2 Magic for block {phys}");
                 }
                 debug!(
-                    "[JBD2 replay] tid={expect_seq} apply meta_idx={i} to phys_block={phys} (journal data from idx={i})"
+                    "[JBD2 replay] tid={} apply meta_idx={idx} to phys_block={phys} (journal rel_block={rel})",
+                    t.sequence
                 );
-
-                let _ = block_dev.write(data, phys, 1);
+                write_fs_blocks(block_dev, self.block_size, &mbuf, phys, 1)?;
             }
-            let _ = block_dev.flush();
-
-            // 6) 更新内存中的 journal superblock 状态
-            expect_seq = expect_seq.wrapping_add(1);
-            self.jbd2_super_block.s_sequence = expect_seq;
-            //更新内存
-            self.sequence = expect_seq;
-
-            // s_start 指向下一个事务起点（commit 后一块），保持为相对块号
-            let mut next_desc_rel = commit_rel;
-            advance_rel(&mut next_desc_rel);
-            self.jbd2_super_block.s_start = next_desc_rel;
+        }
+        block_dev.flush()?;
 
+        // 更新内存中的 journal superblock 状态
+        if let Some(last) = transactions.last() {
+            let next = last.sequence.wrapping_add(1);
+            self.jbd2_super_block.s_sequence = next;
+            self.sequence = next;
             debug!(
-                "[JBD2 replay] transaction applied: new_sequence={} new_s_start(rel)={}",
-                self.jbd2_super_block.s_sequence,
-                self.jbd2_super_block.s_start
+                "[JBD2 replay] {} transactions applied, {} revoke records, new_sequence={next}",
+                transactions.len(),
+                revoked.len()
             );
-
-            // 下一轮从新的 descriptor 起点开始
-            journal_rel = next_desc_rel;
         }
-
-        // 已经没有更多可重放事务：将 s_start 置 0 表示 journal clean
-        self.jbd2_super_block.s_start = 0;
-
-        self.head=0; //重放完成后，head归0，从s_start开始写入
-
-        // replay 完成后写回 journal superblock（read-modify-write，避免破坏其它字节）
-        let sb_block = self.start_block;
-        if sb_block != 0 {
-            let mut blk = [0u8; BLOCK_SIZE];
-            if block_dev.read(&mut blk, sb_block, 1).is_ok() {
-                self.jbd2_super_block.to_disk_bytes(&mut blk[0..1024]);
-                debug!(
-                    "[JBD2 replay] write journal superblock to block={} (sequence={} s_start={})",
-                    sb_block, self.jbd2_super_block.s_sequence, self.jbd2_super_block.s_start
-                );
-                //直接写，避免鬼打墙
-                let _ = block_dev.write(&blk, sb_block, 1);
-                let _ = block_dev.flush();
-            }
+        self.logged_blocks.clear();
+        self.checkpoint_list.clear();
+        self.fc_replay = if had_log && self.fc_blocks > 0 {
+            self.scan_fast_commit(block_dev)?
+        } else {
+            Vec::new()
+        };
+        if !self.fc_replay.is_empty() {
+            // fast commit 记录应用并提交之前日志保持原样，中途崩溃时下次挂载还能重放
+            self.head = 0;
+            return Ok(!transactions.is_empty());
         }
+
+        // 已经没有更多可重放事务：将 s_start 置 0 表示 journal clean，之后从 s_first 开始写入
+        self.mark_journal_empty(block_dev)?;
         debug!(
         "[JBD2 replay] end: final_sequence={} final_s_start={} ",
         self.jbd2_super_block.s_sequence, self.jbd2_super_block.s_start
     );
+        Ok(!transactions.is_empty())
     }
     
 }
@@ -395,7 +812,7 @@ impl JBD2DEVSYSTEM {
 ///dump jouranl inode
 pub fn dump_journal_inode<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &mut Jbd2Dev<B>) {
     let mut indo = fs.get_inode_by_num(block_dev, 8).expect("journal");
-    let datablock = resolve_inode_block( block_dev, &mut indo, 0)
+    let datablock = resolve_inode_block(fs, block_dev, 8, &mut indo, 0)
         .unwrap()
         .unwrap();
     let journal_data = fs
@@ -410,52 +827,758 @@ pub fn dump_journal_inode<B: BlockDevice>(fs: &mut Ext4FileSystem, block_dev: &m
 }
 
 ///jouranl目录创建 journal超级块写入
+/// 与 mke2fs 相同，日志放在文件系统中间的块组里并且物理连续；`blocks` 含 journal 超级块
+/// 同时把 journal inode 的块映射备份到超级块的 s_jnl_blocks，并设置 has_journal
 pub fn create_journal_entry<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
+    blocks: u32,
 ) -> BlockDevResult<()> {
+    if blocks < JBD2_MIN_JOURNAL_BLOCKS {
+        error!("[JBD2] journal needs at least {JBD2_MIN_JOURNAL_BLOCKS} blocks, got {blocks}");
+        return Err(BlockDevError::InvalidInput);
+    }
     //分配新数据块放superblock
     let journal_inode_num = JOURNAL_FILE_INODE;
-    let free_block = fs
-        .alloc_blocks(block_dev, 4096)
-        .expect("No enough block can alloc out!");
+    let block_size = fs.superblock.block_size() as usize;
+    let middle_group = fs.group_count / 2;
+    let free_block = fs.alloc_blocks_from_group(block_dev, middle_group, blocks)?;
 
     // Ensure journal area starts clean: otherwise old image contents could look like valid
     // descriptor/commit blocks and replay would corrupt filesystem metadata.
-    let zero = [0u8; BLOCK_SIZE];
+    let zero = vec![0u8; block_size];
     for &b in free_block.iter() {
-        block_dev.write_blocks(&zero, b as u32, 1, true)?;
+        block_dev.write_blocks(&zero, b, 1, true)?;
     }
     //journal inode 额外参数
-    let mut jour_inode = fs
-        .get_inode_by_num(block_dev, journal_inode_num as u32)
-        .unwrap();
-    jour_inode.write_extend_header();
-    build_file_block_mapping(fs, &mut jour_inode, &free_block, block_dev);
+    let mut jour_inode = Ext4Inode::default();
+    build_file_block_mapping(fs, &mut jour_inode, journal_inode_num as u32, &free_block, block_dev)?;
     debug!("When create jouranl inode: iblock:{:?}", jour_inode.i_block);
-    let inode_size: usize = BLOCK_SIZE * free_block.len();
+    let inode_size = block_size as u64 * free_block.len() as u64;
+    // extent 树的节点块已计入 i_blocks，再加上日志块本身（以 512 字节为单位）
+    let i_blocks = jour_inode.blocks_count() + inode_size / 512;
     //初始化 然后写入 journal inode
     fs.modify_inode(block_dev, journal_inode_num as u32, |inode| {
         inode.i_mode = Ext4Inode::S_IFREG | 0o600;
         inode.i_links_count = 1;
         inode.i_size_lo = inode_size as u32;
-        inode.i_flags = Ext4Inode::EXT4_EXTENTS_FL;
-        inode.i_blocks_lo = (inode_size / 512) as u32;
+        inode.i_size_high = (inode_size >> 32) as u32;
+        inode.i_flags = jour_inode.i_flags;
+        inode.i_blocks_lo = i_blocks as u32;
+        inode.l_i_blocks_high = (i_blocks >> 32) as u16;
         inode.i_block = jour_inode.i_block;
-    })
-    .expect("Jouranl inode create faild!");
+    })?;
 
-    let mut jbd2_sb = JournalSuperBllockS::default();
-
-    jbd2_sb.s_maxlen = (free_block.len()-1) as u32; //修正块数 排除超级块
-    jbd2_sb.s_start = 0; //相对于superblock
-    jbd2_sb.s_blocksize = BLOCK_SIZE_U32;
-    jbd2_sb.s_sequence = 1;
-    jbd2_sb.s_first = 1; //第一个日志块 相对于superblock
+    let mut jbd2_sb = JournalSuperBllockS {
+        s_maxlen: free_block.len() as u32, // 含 journal 超级块
+        s_start: 0, //相对于superblock
+        s_sequence: 1,
+        s_first: 1, //第一个日志块 相对于superblock
+        ..Default::default()
+    };
+    jbd2_sb.set_block_size(block_size as u32);
+    // 64 位文件系统的元数据块号可能超过 32 位，tag 需要携带高 32 位
+    if fs
+        .superblock
+        .has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT)
+    {
+        jbd2_sb.s_feature_incompat |= JBD2_FEATURE_INCOMPAT_64BIT;
+    }
+    // 内部日志沿用文件系统 UUID；开启 metadata_csum 时日志块也带 v3 校验和
+    jbd2_sb.s_uuid = fs.superblock.s_uuid;
+    if fs.superblock.has_metadata_csum() {
+        jbd2_sb.s_feature_incompat |= JBD2_FEATURE_INCOMPAT_CSUM_V3;
+        jbd2_sb.s_checksum_type = JBD2_CRC32C_CHKSUM;
+    }
+    jbd2_sb.update_checksum();
 
     fs.datablock_cache.modify_new(free_block[0], |data| {
         jbd2_sb.to_disk_bytes(data);
     });
-    info!("Journal inode created!");
+
+    // s_jnl_blocks：前 15 项是 i_block，之后是 i_size_high 和 i_size
+    let sb = &mut fs.superblock;
+    sb.s_jnl_blocks[..15].copy_from_slice(&jour_inode.i_block);
+    sb.s_jnl_blocks[15] = (inode_size >> 32) as u32;
+    sb.s_jnl_blocks[16] = inode_size as u32;
+    sb.s_jnl_backup_type = Ext4Superblock::EXT3_JNL_BACKUP_BLOCKS;
+    sb.s_journal_inum = journal_inode_num as u32;
+    sb.s_feature_compat |= Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL;
+    fs.sync_superblock(block_dev)?;
+    info!("Journal inode created: {} blocks starting at {}", free_block.len(), free_block[0]);
     Ok(())
 }
+
+/// 删除内部日志：释放日志块和 extent 节点块，清空 journal inode 以及超级块里的日志信息
+/// 日志里还有未重放的事务时拒绝删除
+pub fn remove_journal_entry<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+) -> BlockDevResult<()> {
+    if !fs.superblock.has_journal() || fs.superblock.has_external_journal() {
+        return Err(BlockDevError::InvalidInput);
+    }
+    if fs
+        .superblock
+        .has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_RECOVER)
+    {
+        error!("[JBD2] journal needs recovery, refuse to remove it");
+        return Err(BlockDevError::DeviceBusy);
+    }
+    let journal_inode_num = fs.superblock.s_journal_inum;
+    let mut j_inode = fs.get_inode_by_num(block_dev, journal_inode_num)?;
+    if j_inode.i_mode != 0 {
+        let mut used: Vec<u64> = resolve_inode_block_allextend(fs, block_dev, journal_inode_num, &mut j_inode)?
+            .into_values()
+            .collect();
+        used.extend(resolve_extent_node_blocks(fs, block_dev, journal_inode_num, &mut j_inode)?);
+        used.extend(resolve_inode_index_blocks(block_dev, &mut j_inode)?);
+        for blk in used {
+            fs.free_block(block_dev, blk)?;
+        }
+        fs.modify_inode(block_dev, journal_inode_num, |inode| {
+            *inode = Ext4Inode::default();
+        })?;
+    }
+
+    let sb = &mut fs.superblock;
+    sb.s_jnl_blocks = [0; 17];
+    sb.s_jnl_backup_type = 0;
+    sb.s_journal_inum = 0;
+    sb.s_feature_compat &= !(Ext4Superblock::EXT4_FEATURE_COMPAT_HAS_JOURNAL
+        | Ext4Superblock::EXT4_FEATURE_COMPAT_FAST_COMMIT);
+    fs.sync_superblock(block_dev)?;
+    info!("Journal inode removed");
+    Ok(())
+}
+
+/// 外部日志设备上 journal 超级块所在块号：紧跟在设备自己的 ext4 超级块之后（与 mke2fs -O journal_dev 相同）
+pub fn journal_dev_sb_block(block_size: usize) -> u64 {
+    SUPERBLOCK_OFFSET / block_size as u64 + 1
+}
+
+/// 读出外部日志设备的 ext4 超级块和 journal 超级块，并检查它确实是一个日志设备
+fn read_journal_dev<J: BlockDevice + ?Sized>(
+    dev: &mut J,
+    block_size: usize,
+) -> BlockDevResult<(Ext4Superblock, JournalSuperBllockS)> {
+    let mut blk = vec![0u8; block_size];
+    let offset = (SUPERBLOCK_OFFSET % block_size as u64) as usize;
+    read_fs_blocks(dev, block_size, &mut blk, SUPERBLOCK_OFFSET / block_size as u64, 1)?;
+    let raw = &blk[offset..offset + Ext4Superblock::SUPERBLOCK_SIZE];
+    let dev_sb = Ext4Superblock::from_disk_bytes(raw);
+    if dev_sb.s_magic != EXT4_SUPER_MAGIC
+        || !dev_sb.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_JOURNAL_DEV)
+    {
+        error!("[JBD2] external device is not a journal device");
+        return Err(BlockDevError::Corrupted);
+    }
+    if !verify_superblock_csum(&dev_sb, raw) {
+        error!("[JBD2] external journal device superblock checksum mismatch");
+        return Err(BlockDevError::ChecksumError);
+    }
+    if dev_sb.block_size() as usize != block_size {
+        error!(
+            "[JBD2] external journal block size {} does not match filesystem block size {block_size}",
+            dev_sb.block_size()
+        );
+        return Err(BlockDevError::InvalidBlockSize {
+            size: dev_sb.block_size() as usize,
+            expected: block_size,
+        });
+    }
+    read_fs_blocks(dev, block_size, &mut blk, journal_dev_sb_block(block_size), 1)?;
+    let jsb = JournalSuperBllockS::from_disk_bytes(&blk);
+    if jsb.s_header.h_magic != JBD2_MAGIC {
+        error!("[JBD2] external journal superblock has bad magic 0x{:x}", jsb.s_header.h_magic);
+        return Err(BlockDevError::Corrupted);
+    }
+    Ok((dev_sb, jsb))
+}
+
+/// 把整个设备格式化为外部日志设备（相当于 mke2fs -O journal_dev）
+/// 设备开头是只带 INCOMPAT_JOURNAL_DEV 的 ext4 超级块，之后是 journal 超级块，其余全部是日志区
+pub fn format_journal_device<J: BlockDevice + ?Sized>(
+    dev: &mut J,
+    block_size: usize,
+    uuid: [u8; 16],
+) -> BlockDevResult<()> {
+    let total = (dev.total_blocks() * dev.block_size() as u64 / block_size as u64).min(u32::MAX as u64);
+    let sb_block = journal_dev_sb_block(block_size);
+    if total < sb_block + 1 + JBD2_MIN_JOURNAL_BLOCKS as u64 {
+        error!("[JBD2] journal device too small: {total} blocks");
+        return Err(BlockDevError::NoSpace);
+    }
+
+    // 清空旧内容，避免残留的日志块被当成有效事务
+    const ZERO_CHUNK: u64 = 64;
+    let zero = vec![0u8; block_size * ZERO_CHUNK as usize];
+    let mut blk = 0u64;
+    while blk < total {
+        let n = (total - blk).min(ZERO_CHUNK);
+        write_fs_blocks(dev, block_size, &zero[..n as usize * block_size], blk, n as u32)?;
+        blk += n;
+    }
+
+    let log_block_size = block_size.trailing_zeros() - MIN_BLOCK_SIZE.trailing_zeros();
+    let dev_sb = Ext4Superblock {
+        s_magic: EXT4_SUPER_MAGIC,
+        s_blocks_count_lo: total as u32,
+        s_log_block_size: log_block_size,
+        s_log_cluster_size: log_block_size,
+        s_first_data_block: if block_size == MIN_BLOCK_SIZE { 1 } else { 0 },
+        s_blocks_per_group: block_size as u32 * 8,
+        s_clusters_per_group: block_size as u32 * 8,
+        s_state: Ext4Superblock::EXT4_VALID_FS,
+        s_creator_os: Ext4Superblock::EXT4_OS_LINUX,
+        s_rev_level: Ext4Superblock::EXT4_DYNAMIC_REV,
+        s_inode_size: DEFAULT_INODE_SIZE,
+        s_first_ino: RESERVED_INODES + 1,
+        s_feature_compat: 0,
+        s_feature_incompat: Ext4Superblock::EXT4_FEATURE_INCOMPAT_JOURNAL_DEV,
+        s_feature_ro_compat: 0,
+        s_reserved_gdt_blocks: 0,
+        s_journal_inum: 0,
+        s_uuid: uuid,
+        ..Default::default()
+    };
+    let mut buf = vec![0u8; block_size];
+    let offset = (SUPERBLOCK_OFFSET % block_size as u64) as usize;
+    let raw = &mut buf[offset..offset + Ext4Superblock::SUPERBLOCK_SIZE];
+    dev_sb.to_disk_bytes(raw);
+    set_superblock_csum(&dev_sb, raw);
+    write_fs_blocks(dev, block_size, &buf, SUPERBLOCK_OFFSET / block_size as u64, 1)?;
+
+    // 外部日志的 s_first/s_maxlen 都是设备上的绝对块号，使用者在挂载文件系统时登记
+    let mut jsb = JournalSuperBllockS {
+        s_maxlen: total as u32,
+        s_first: (sb_block + 1) as u32,
+        s_sequence: 1,
+        s_start: 0,
+        s_uuid: uuid,
+        s_nr_users: 0,
+        ..Default::default()
+    };
+    jsb.set_block_size(block_size as u32);
+    jsb.update_checksum();
+    buf.fill(0);
+    jsb.to_disk_bytes(&mut buf[0..1024]);
+    write_fs_blocks(dev, block_size, &buf, sb_block, 1)?;
+    dev.flush()?;
+    info!("External journal device formatted: {total} blocks");
+    Ok(())
+}
+
+/// 把文件系统登记为外部日志设备的使用者（s_users），返回日志设备的 UUID，调用方写进 s_journal_uuid
+/// 与内核一致，一个日志设备只支持一个文件系统
+pub fn attach_journal_device<J: BlockDevice + ?Sized>(
+    dev: &mut J,
+    block_size: usize,
+    fs_uuid: [u8; 16],
+) -> BlockDevResult<[u8; 16]> {
+    let (dev_sb, mut jsb) = read_journal_dev(dev, block_size)?;
+    if jsb.s_nr_users > 0 && jsb.s_users[..16] != fs_uuid[..] {
+        error!("[JBD2] external journal is already used by another filesystem");
+        return Err(BlockDevError::DeviceBusy);
+    }
+    jsb.s_nr_users = 1;
+    jsb.s_users[..16].copy_from_slice(&fs_uuid);
+    jsb.update_checksum();
+    let sb_block = journal_dev_sb_block(block_size);
+    let mut buf = vec![0u8; block_size];
+    read_fs_blocks(dev, block_size, &mut buf, sb_block, 1)?;
+    jsb.to_disk_bytes(&mut buf[0..1024]);
+    write_fs_blocks(dev, block_size, &buf, sb_block, 1)?;
+    dev.flush()?;
+    Ok(dev_sb.s_uuid)
+}
+
+/// 挂载时打开外部日志设备：设备 UUID 必须与文件系统超级块的 s_journal_uuid 一致，且只有一个使用者
+pub fn load_journal_device<J: BlockDevice + ?Sized>(
+    dev: &mut J,
+    block_size: usize,
+    journal_uuid: &[u8; 16],
+) -> BlockDevResult<JournalSuperBllockS> {
+    let (dev_sb, jsb) = read_journal_dev(dev, block_size)?;
+    if dev_sb.s_uuid != *journal_uuid || jsb.s_uuid != *journal_uuid {
+        error!("[JBD2] external journal UUID does not match s_journal_uuid");
+        return Err(BlockDevError::InvalidInput);
+    }
+    if jsb.s_nr_users != 1 {
+        error!("[JBD2] external journal has {} users (unsupported)", jsb.s_nr_users);
+        return Err(BlockDevError::Unsupported);
+    }
+    Ok(jsb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::config::JBD2_BUFFER_MAX;
+    use crate::ext4_backend::fast_commit::encode_fast_commit;
+    use crate::ext4_backend::datablock_cache::DataBlockCache;
+    use crate::ext4_backend::ext4::{
+        add_journal, mkfs_with_journal, mount, remove_journal, resize_journal, JournalSize,
+    };
+    use alloc::collections::{BTreeSet, VecDeque};
+    use crate::ext4_backend::test_support::{MemDev, SECTOR_SIZE};
+
+    const BS: usize = 1024;
+    const JOURNAL_START: u64 = 100;
+
+    /// 日志区（journal 超级块之后）的写入全部失败，模拟提交时的 I/O 错误
+    struct FailingLogDev(MemDev);
+
+    impl BlockDevice for FailingLogDev {
+        fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+            let blk = block_id * SECTOR_SIZE as u64 / BS as u64;
+            if blk > JOURNAL_START && blk < JOURNAL_START + 1500 {
+                return Err(BlockDevError::WriteError);
+            }
+            self.0.write(buffer, block_id, count)
+        }
+
+        fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+            self.0.read(buffer, block_id, count)
+        }
+
+        fn open(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> BlockDevResult<()> {
+            Ok(())
+        }
+
+        fn total_blocks(&self) -> u64 {
+            self.0.total_blocks()
+        }
+
+        fn block_size(&self) -> u32 {
+            self.0.block_size()
+        }
+    }
+
+    fn journal_sb() -> JournalSuperBllockS {
+        let mut sb = JournalSuperBllockS::default();
+        sb.s_blocksize = BS as u32;
+        sb.s_maxlen = 1500;
+        sb.s_first = 1;
+        sb.s_sequence = 1;
+        sb
+    }
+
+    fn system(sb: JournalSuperBllockS) -> JBD2DEVSYSTEM {
+        let sb_fc_blocks = sb.num_fc_blocks();
+        JBD2DEVSYSTEM {
+            block_size: BS,
+            start_block: JOURNAL_START,
+            sb_block: JOURNAL_START,
+            max_len: sb.s_maxlen,
+            head: 0,
+            sequence: sb.s_sequence,
+            jbd2_super_block: sb,
+            commit_queue: Vec::new(),
+            handle_count: 0,
+            reserved_credits: 0,
+            revoke_queue: Vec::new(),
+            logged_blocks: BTreeSet::new(),
+            checkpoint_list: VecDeque::new(),
+            aborted: false,
+            journal_dev: None,
+            fc_blocks: sb_fc_blocks,
+            fc_enabled: false,
+            fc_off: 0,
+            deferred: false,
+            fc_replay: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_commit_spans_descriptors_and_replays() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sys = system(journal_sb());
+        let count = 300usize;
+        assert!(count > sys.tags_per_descriptor() * 2);
+        for i in 0..count {
+            let mut block = vec![i as u8; BS];
+            if i == 7 {
+                block[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+            }
+            sys.queue_update(Jbd2Update(2000 + i as u64, block.into()));
+        }
+        assert_eq!(sys.commit_transaction(&mut dev), Ok(true));
+        assert_eq!(dev.0.borrow()[2001 * BS], 1);
+
+        // 模拟提交后、写回原位置前崩溃：原位置丢失，靠重放恢复
+        dev.0.borrow_mut()[2000 * BS..2300 * BS].fill(0);
+        let mut sb_buf = vec![0u8; BS];
+        read_fs_blocks(&mut dev, BS, &mut sb_buf, JOURNAL_START, 1).unwrap();
+        let mut replayed = system(JournalSuperBllockS::from_disk_bytes(&sb_buf));
+        assert!(replayed.replay(&mut dev).unwrap());
+
+        let data = dev.0.borrow();
+        for i in 0..count {
+            let home = &data[(2000 + i) * BS..(2001 + i) * BS];
+            if i == 7 {
+                assert_eq!(&home[0..4], &JBD2_MAGIC.to_be_bytes());
+                assert!(home[4..].iter().all(|b| *b == 7));
+            } else {
+                assert!(home.iter().all(|b| *b == i as u8), "block {i}");
+            }
+        }
+        assert_eq!(replayed.sequence, 2);
+    }
+
+    fn reload(dev: &mut MemDev) -> JBD2DEVSYSTEM {
+        let mut sb_buf = vec![0u8; BS];
+        read_fs_blocks(dev, BS, &mut sb_buf, JOURNAL_START, 1).unwrap();
+        system(JournalSuperBllockS::from_disk_bytes(&sb_buf))
+    }
+
+    #[test]
+    fn test_revoked_block_not_replayed() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sys = system(journal_sb());
+        sys.queue_update(Jbd2Update(2000, vec![1u8; BS].into()));
+        sys.queue_update(Jbd2Update(2001, vec![2u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+
+        // 2000 被释放并当作数据块重新使用，之后的事务只记撤销
+        sys.revoke_block(2000);
+        sys.revoke_block(2000);
+        assert_eq!(sys.revoke_queue, vec![2000]);
+        sys.queue_update(Jbd2Update(2002, vec![3u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+        dev.0.borrow_mut()[2000 * BS..2001 * BS].fill(0xaa);
+        dev.0.borrow_mut()[2001 * BS..2003 * BS].fill(0);
+
+        let mut replayed = reload(&mut dev);
+        assert!(replayed.replay(&mut dev).unwrap());
+        assert!(dev.0.borrow()[2000 * BS..2001 * BS].iter().all(|b| *b == 0xaa));
+        assert!(dev.0.borrow()[2001 * BS..2002 * BS].iter().all(|b| *b == 2));
+        assert!(dev.0.borrow()[2002 * BS..2003 * BS].iter().all(|b| *b == 3));
+        assert_eq!(replayed.sequence, 3);
+    }
+
+    #[test]
+    fn test_rejournaled_block_after_revoke_is_replayed() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sys = system(journal_sb());
+        sys.queue_update(Jbd2Update(2000, vec![1u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+        sys.revoke_block(2000);
+        sys.queue_update(Jbd2Update(2001, vec![2u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+
+        // 块重新用作元数据：同一事务内写入会取消撤销，之后的事务照常重放
+        sys.revoke_block(2001);
+        sys.queue_update(Jbd2Update(2001, vec![4u8; BS].into()));
+        assert!(sys.revoke_queue.is_empty());
+        sys.queue_update(Jbd2Update(2000, vec![5u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+        dev.0.borrow_mut()[2000 * BS..2002 * BS].fill(0);
+
+        let mut replayed = reload(&mut dev);
+        assert!(replayed.replay(&mut dev).unwrap());
+        assert!(dev.0.borrow()[2000 * BS..2001 * BS].iter().all(|b| *b == 5));
+        assert!(dev.0.borrow()[2001 * BS..2002 * BS].iter().all(|b| *b == 4));
+        // 干净的日志再次重放什么也不做
+        assert!(!reload(&mut dev).replay(&mut dev).unwrap());
+    }
+
+    #[test]
+    fn test_checkpoint_advances_tail_when_log_wraps() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sb = journal_sb();
+        sb.s_maxlen = 40;
+        let mut sys = system(sb);
+        // 每个事务 1 个 descriptor + 3 个元数据块 + 1 个 commit 块，39 个日志块装不下 20 个事务
+        let mut tails = BTreeSet::new();
+        for i in 0..20u8 {
+            for blk in 2000..2003u64 {
+                sys.queue_update(Jbd2Update(blk, vec![i + 1; BS].into()));
+            }
+            assert_eq!(sys.commit_transaction(&mut dev), Ok(true));
+            assert!(sys.head >= 1 && sys.head < 40);
+            tails.insert(reload(&mut dev).jbd2_super_block.s_start);
+        }
+        assert!(tails.len() > 1, "tail never advanced");
+
+        // 崩溃前原位置的写回丢失，从推进后的日志尾重放得到最新内容
+        dev.0.borrow_mut()[2000 * BS..2003 * BS].fill(0);
+        let mut replayed = reload(&mut dev);
+        assert!(replayed.replay(&mut dev).unwrap());
+        assert!(dev.0.borrow()[2000 * BS..2003 * BS].iter().all(|b| *b == 20));
+        assert_eq!(replayed.sequence, 21);
+
+        // 超过整个日志的事务无法提交
+        for blk in 0..40u64 {
+            sys.queue_update(Jbd2Update(2100 + blk, vec![1u8; BS].into()));
+        }
+        assert_eq!(sys.commit_transaction(&mut dev), Err(BlockDevError::NoSpace));
+        assert!(sys.is_aborted());
+        assert_eq!(sys.commit_transaction(&mut dev), Err(BlockDevError::ReadOnly));
+    }
+
+    fn csum_journal_sb(incompat: u32) -> JournalSuperBllockS {
+        let mut sb = journal_sb();
+        sb.s_uuid = [0x5a; 16];
+        sb.s_feature_incompat = incompat;
+        sb.s_checksum_type = JBD2_CRC32C_CHKSUM;
+        sb
+    }
+
+    #[test]
+    fn test_checksummed_journal_replays() {
+        for incompat in [
+            JBD2_FEATURE_INCOMPAT_CSUM_V3 | JBD2_FEATURE_INCOMPAT_64BIT,
+            JBD2_FEATURE_INCOMPAT_CSUM_V2,
+        ] {
+            let mut dev = MemDev::new(2400 * BS);
+            let mut sys = system(csum_journal_sb(incompat));
+            sys.queue_update(Jbd2Update(2000, vec![1u8; BS].into()));
+            sys.commit_transaction(&mut dev).unwrap();
+            sys.revoke_block(2000);
+            let mut escaped = vec![2u8; BS];
+            escaped[0..4].copy_from_slice(&JBD2_MAGIC.to_be_bytes());
+            sys.queue_update(Jbd2Update(2001, escaped.into()));
+            sys.commit_transaction(&mut dev).unwrap();
+            dev.0.borrow_mut()[2000 * BS..2002 * BS].fill(0);
+
+            let mut replayed = reload(&mut dev);
+            assert!(replayed.jbd2_super_block.verify_checksum());
+            assert!(replayed.replay(&mut dev).unwrap());
+            assert!(dev.0.borrow()[2000 * BS..2001 * BS].iter().all(|b| *b == 0));
+            assert_eq!(&dev.0.borrow()[2001 * BS..2001 * BS + 4], &JBD2_MAGIC.to_be_bytes());
+            assert_eq!(replayed.sequence, 3);
+        }
+    }
+
+    #[test]
+    fn test_torn_transaction_not_replayed() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sys = system(csum_journal_sb(JBD2_FEATURE_INCOMPAT_CSUM_V3));
+        sys.queue_update(Jbd2Update(2000, vec![1u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+        let second = sys.head;
+        sys.queue_update(Jbd2Update(2000, vec![2u8; BS].into()));
+        sys.queue_update(Jbd2Update(2001, vec![2u8; BS].into()));
+        sys.commit_transaction(&mut dev).unwrap();
+        dev.0.borrow_mut()[2000 * BS..2002 * BS].fill(0);
+
+        // 第二个事务的一个元数据块只写了一半：commit 块存在，但 tag 校验和对不上
+        let torn = (JOURNAL_START + second as u64 + 2) as usize * BS;
+        dev.0.borrow_mut()[torn + BS / 2..torn + BS].fill(0);
+
+        let mut replayed = reload(&mut dev);
+        assert!(replayed.replay(&mut dev).unwrap());
+        assert!(dev.0.borrow()[2000 * BS..2001 * BS].iter().all(|b| *b == 1));
+        assert!(dev.0.borrow()[2001 * BS..2002 * BS].iter().all(|b| *b == 0));
+        assert_eq!(replayed.sequence, 2);
+    }
+
+    #[test]
+    fn test_nested_handles_commit_once() {
+        let dev = MemDev::new(2400 * BS);
+        let mut jdev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, dev, true);
+        jdev.set_block_size(BS).unwrap();
+        jdev.set_journal_superblock(journal_sb(), JOURNAL_START);
+
+        let outer = jdev.begin_transaction(8).unwrap();
+        let outer_tid = outer.tid;
+        let inner = jdev.begin_transaction(8).unwrap();
+        assert_eq!(outer.tid, inner.tid);
+        assert_eq!(jdev.transaction_depth(), 2);
+        for i in 0..(JBD2_BUFFER_MAX as u64 + 5) {
+            jdev.read_block(2000 + i).unwrap();
+            jdev.buffer_mut().fill(i as u8 + 1);
+            jdev.write_block(2000 + i, true).unwrap();
+        }
+        jdev.commit(inner).unwrap();
+        // 事务未提交前，读到的是事务中的最新内容
+        jdev.read_block(2003).unwrap();
+        assert!(jdev.buffer().iter().all(|b| *b == 4));
+        jdev.commit(outer).unwrap();
+        assert_eq!(jdev.transaction_depth(), 0);
+
+        // 整个操作只产生一个事务
+        let next = jdev.begin_transaction(1).unwrap();
+        assert_eq!(next.tid, outer_tid + 1);
+        jdev.commit(next).unwrap();
+        let mut buf = vec![0u8; BS];
+        jdev.read_blocks(&mut buf, 2010, 1).unwrap();
+        assert!(buf.iter().all(|b| *b == 11));
+    }
+
+    #[test]
+    fn test_journal_modes_order_data_blocks() {
+        for mode in [JournalMode::Journal, JournalMode::Ordered, JournalMode::Writeback] {
+            let dev = MemDev::new(2400 * BS);
+            let mut jdev = Jbd2Dev::initial_jbd2dev(mode, dev, true);
+            jdev.set_block_size(BS).unwrap();
+            jdev.set_journal_superblock(journal_sb(), JOURNAL_START);
+            let mut cache = DataBlockCache::new(8, BS);
+
+            let handle = jdev.begin_transaction(4).unwrap();
+            cache.modify_new_data(2000, |data| data.fill(9));
+            cache.modify_new(2001, |data| data.fill(7));
+            // 与 Ext4FileSystem::journal_stop 相同：writeback 只写元数据
+            if mode == JournalMode::Writeback {
+                cache.flush_metadata(&mut jdev).unwrap();
+            } else {
+                cache.flush_all(&mut jdev).unwrap();
+            }
+            let mut raw = vec![0u8; BS];
+            jdev.read_blocks(&mut raw, 2001, 1).unwrap();
+            assert!(raw.iter().all(|b| *b == 7));
+            let data_on_disk = |jdev: &mut Jbd2Dev<MemDev>| {
+                let mut buf = vec![0u8; BS];
+                jdev.read_blocks(&mut buf, 2000, 1).unwrap();
+                buf[0]
+            };
+            match mode {
+                // journal：数据块在事务里，读到事务中的内容；ordered：数据块在提交前已经落盘
+                JournalMode::Journal | JournalMode::Ordered => assert_eq!(data_on_disk(&mut jdev), 9),
+                // writeback：数据块仍在缓存里
+                JournalMode::Writeback => {
+                    assert_eq!(data_on_disk(&mut jdev), 0);
+                    assert_eq!(cache.stats().dirty_entries, 1);
+                }
+            }
+            jdev.commit(handle).unwrap();
+
+            // 模拟原位置写回丢失后重放：只有 data=journal 能恢复数据块
+            jdev.write_blocks(&vec![0u8; 2 * BS], 2000, 2, false).unwrap();
+            assert!(jdev.journal_replay().unwrap());
+            let expected = if mode == JournalMode::Journal { 9 } else { 0 };
+            assert_eq!(data_on_disk(&mut jdev), expected, "{mode:?}");
+            jdev.read_blocks(&mut raw, 2001, 1).unwrap();
+            assert_eq!(raw[0], 7);
+        }
+    }
+
+    #[test]
+    fn test_commit_io_error_aborts_journal() {
+        let dev = FailingLogDev(MemDev::new(2400 * BS));
+        let mut jdev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, dev, true);
+        jdev.set_block_size(BS).unwrap();
+        jdev.set_journal_superblock(journal_sb(), JOURNAL_START);
+
+        let handle = jdev.begin_transaction(1).unwrap();
+        jdev.read_block(2000).unwrap();
+        jdev.buffer_mut().fill(3);
+        jdev.write_block(2000, true).unwrap();
+        assert_eq!(jdev.commit(handle), Err(BlockDevError::WriteError));
+        assert!(jdev.is_journal_aborted());
+
+        // 事务没有写进日志，原位置也不能被改写
+        let mut buf = vec![0u8; BS];
+        jdev.read_blocks(&mut buf, 2000, 1).unwrap();
+        assert!(buf.iter().all(|b| *b == 0));
+
+        // 中止后拒绝新事务和元数据写入，错误码记在 journal 超级块里
+        assert_eq!(jdev.begin_transaction(1).err(), Some(BlockDevError::ReadOnly));
+        assert_eq!(jdev.write_block(2000, true), Err(BlockDevError::ReadOnly));
+        jdev.read_blocks(&mut buf, JOURNAL_START, 1).unwrap();
+        assert_eq!(JournalSuperBllockS::from_disk_bytes(&buf).s_errno, JBD2_EIO_ERRNO);
+    }
+
+    #[test]
+    fn test_external_journal_device() {
+        let fs_mem = MemDev::new(2400 * BS);
+        let mut log_mem = MemDev::new(1100 * BS);
+        let fs_uuid = [0x11; 16];
+        let journal_uuid = [0x22; 16];
+        format_journal_device(&mut log_mem, BS, journal_uuid).unwrap();
+        assert_eq!(attach_journal_device(&mut log_mem, BS, fs_uuid), Ok(journal_uuid));
+        // 一个日志设备只能给一个文件系统用
+        assert_eq!(attach_journal_device(&mut log_mem, BS, [0x33; 16]), Err(BlockDevError::DeviceBusy));
+
+        let mut jdev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, fs_mem.clone(), true);
+        jdev.set_block_size(BS).unwrap();
+        jdev.set_journal_device(log_mem.clone());
+        assert_eq!(jdev.load_journal_device(&[0x33; 16]), Err(BlockDevError::InvalidInput));
+        jdev.load_journal_device(&journal_uuid).unwrap();
+
+        let handle = jdev.begin_transaction(1).unwrap();
+        jdev.read_block(2000).unwrap();
+        jdev.buffer_mut().fill(5);
+        jdev.write_block(2000, true).unwrap();
+        jdev.commit(handle).unwrap();
+
+        // 日志块写在日志设备的 s_first 处，文件系统设备上只有原位置被改写
+        let first = (journal_dev_sb_block(BS) + 1) as usize * BS;
+        assert_eq!(log_mem.0.borrow()[first..first + 4], JBD2_MAGIC.to_be_bytes());
+        assert_eq!(fs_mem.0.borrow()[2000 * BS], 5);
+        assert_eq!(fs_mem.0.borrow()[..2000 * BS].iter().filter(|b| **b != 0).count(), 0);
+
+        // 原位置写回丢失后，重新挂载时从日志设备重放
+        fs_mem.0.borrow_mut()[2000 * BS..2001 * BS].fill(0);
+        let mut remount = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, fs_mem.clone(), true);
+        remount.set_block_size(BS).unwrap();
+        remount.set_journal_device(log_mem.clone());
+        remount.load_journal_device(&journal_uuid).unwrap();
+        assert_eq!(remount.journal_replay(), Ok(true));
+        assert!(fs_mem.0.borrow()[2000 * BS..2001 * BS].iter().all(|b| *b == 5));
+    }
+
+    #[test]
+    fn test_fast_commit_area_scanned_on_replay() {
+        let mut dev = MemDev::new(2400 * BS);
+        let mut sys = system(journal_sb());
+        assert_eq!(sys.enable_fast_commit(&mut dev), Ok(true));
+        let rec = FcRecord::DelRange { ino: 12, lblk: 3, len: 2 };
+        let blocks = encode_fast_commit(core::slice::from_ref(&rec), sys.sequence, true, BS).unwrap();
+        sys.write_fast_commit(&mut dev, &blocks).unwrap();
+
+        // 日志里没有完整事务，fast commit 区的记录留给文件系统层应用
+        let mut replayed = reload(&mut dev);
+        assert_eq!(replayed.replay(&mut dev), Ok(false));
+        assert_eq!(replayed.fc_replay, vec![rec]);
+
+        // 完整提交之后区里的第一个块被清掉，旧记录不再被接受
+        sys.queue_update(Jbd2Update(2000, vec![1u8; BS].into()));
+        assert_eq!(sys.commit_transaction(&mut dev), Ok(true));
+        let fc_first = (JOURNAL_START + (sys.log_end() + 1) as u64) as usize * BS;
+        assert!(dev.0.borrow()[fc_first..fc_first + BS].iter().all(|b| *b == 0));
+        let mut replayed = reload(&mut dev);
+        assert_eq!(replayed.replay(&mut dev), Ok(true));
+        assert!(replayed.fc_replay.is_empty());
+    }
+
+    #[test]
+    fn test_mkfs_journal_size_and_resize() {
+        let mem = MemDev::new(16 << 20);
+        let journal_blocks = |dev: &mut Jbd2Dev<MemDev>| {
+            let mut fs = mount(dev).unwrap();
+            let blocks = if fs.superblock.has_journal() {
+                let inum = fs.superblock.s_journal_inum;
+                Some((fs.get_inode_by_num(dev, inum).unwrap().size() / BS as u64) as u32)
+            } else {
+                None
+            };
+            fs.umount(dev).unwrap();
+            blocks
+        };
+
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem.clone(), false);
+        mkfs_with_journal(&mut dev, BS, JournalSize::Blocks(2048)).unwrap();
+        assert_eq!(journal_blocks(&mut dev), Some(2048));
+        assert_eq!(add_journal(&mut dev, 1024), Err(BlockDevError::AlreadyExists));
+        assert_eq!(resize_journal(&mut dev, 100), Err(BlockDevError::InvalidInput));
+        resize_journal(&mut dev, 3000).unwrap();
+        assert_eq!(journal_blocks(&mut dev), Some(3000));
+
+        // 没有日志时挂载自动关闭 journaling
+        remove_journal(&mut dev).unwrap();
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem.clone(), true);
+        assert_eq!(journal_blocks(&mut dev), None);
+        assert!(!dev.is_use_journal());
+
+        add_journal(&mut dev, 1024).unwrap();
+        let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem, true);
+        assert_eq!(journal_blocks(&mut dev), Some(1024));
+        assert!(dev.is_use_journal());
+    }
+}
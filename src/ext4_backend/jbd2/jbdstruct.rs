@@ -1,25 +1,97 @@
+use crate::ext4_backend::blockdev::BlockDevice;
+use crate::ext4_backend::checksum::crc32c;
 use crate::ext4_backend::config::*;
 use crate::ext4_backend::endian::*;
+use crate::ext4_backend::fast_commit::FcRecord;
 use alloc::boxed::Box;
+use alloc::collections::{BTreeSet, VecDeque};
 use alloc::vec::Vec;
 use core::convert::TryInto;
 pub const JOURNAL_FILE_INODE: u64 = 8;
 /// 根据 ext4 标准，journal 的 inode 为 8
 pub const JBD2_MAGIC: u32 = 0xC03B_3998u32; // jbd2 magic number (on-disk big-endian)
-pub const JOURNAL_BLOCK_COUNT: u32 = 32 * 1024 * 1024 / BLOCK_SIZE_U32;
+/// 单个事务最多占用的日志块数（约 32MiB）
+pub fn journal_max_transaction(block_size: u32) -> u32 {
+    32 * 1024 * 1024 / block_size
+}
+/// 按块大小换算日志块数：默认约 16MiB，限制在 1024..=4096 块之间
+pub fn journal_blocks_for(block_size: usize) -> u32 {
+    ((16 * 1024 * 1024 / block_size) as u32).clamp(1024, 4096)
+}
+/// 日志块类型：descriptor / commit / revoke
+pub const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+pub const JBD2_COMMIT_BLOCK: u32 = 2;
+pub const JBD2_REVOKE_BLOCK: u32 = 5;
 pub const JOURANL_ESCAPE: u16 = 0x1;
+/// tag 之后不跟 UUID（与上一个 tag 相同）
+pub const JBD2_FLAG_SAME_UUID: u16 = 0x2;
 pub const JBD2_FLAG_LAST_TAG: u16 = 0x8;
+/// descriptor 中第一个 tag 之后携带的 UUID 长度
+pub const JBD2_UUID_BYTES: usize = 16;
+
+/// journal 超级块不兼容特性：支持 revoke 块
+pub const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+/// journal 超级块不兼容特性：tag 携带 64 位块号
+pub const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+/// journal 超级块不兼容特性：checksum v2
+pub const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+/// journal 超级块不兼容特性：checksum v3（使用 JouranlBlockTag3S 布局）
+pub const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+/// journal 超级块不兼容特性：日志末尾划出 fast commit 区
+pub const JBD2_FEATURE_INCOMPAT_FAST_COMMIT: u32 = 0x20;
+/// s_num_fc_blks 为 0 时 fast commit 区的块数（与 jbd2 的 JBD2_DEFAULT_FAST_COMMIT_BLOCKS 相同）
+pub const JBD2_DEFAULT_FAST_COMMIT_BLOCKS: u32 = 256;
+/// journal 超级块 s_checksum_type 的 crc32c 编号（jbd2 自己的编号，不同于 ext4 超级块的 1）
+pub const JBD2_CRC32C_CHKSUM: u8 = 4;
+/// journal 超级块中 s_checksum 的偏移
+pub const JBD2_SUPERBLOCK_CSUM_OFFSET: usize = 0xFC;
+/// commit 块中 h_chksum[0] 的偏移
+pub const JBD2_COMMIT_CSUM_OFFSET: usize = 0x10;
+/// 日志中止时写入 s_errno 的错误码（-EIO）
+pub const JBD2_EIO_ERRNO: u32 = (-5i32) as u32;
+/// 日志最少块数（与 jbd2 的 JBD2_MIN_JOURNAL_BLOCKS 相同）
+pub const JBD2_MIN_JOURNAL_BLOCKS: u32 = 1024;
 #[repr(C)]
 ///（主物理块号，元数据内容）
-pub struct Jbd2Update(pub u64, pub Box<[u8; BLOCK_SIZE]>);
+pub struct Jbd2Update(pub u64, pub Box<[u8]>);
 #[repr(C)]
 pub struct JBD2DEVSYSTEM {
+    pub block_size: usize, // 文件系统块大小（日志块与之相同）
     pub jbd2_super_block: JournalSuperBllockS,
-    pub start_block: u32, // Journal 超级块 开始块号
+    pub start_block: u64, // 日志相对块号的基准：内部日志为 journal inode 起始块，外部日志为 0
+    pub sb_block: u64,    // journal 超级块所在块号：内部日志等于 start_block，外部日志在设备超级块之后
     pub max_len: u32,     // 日志总块数
-    pub head: u32,        //commit游标(相对块号)
+    pub head: u32,        //下一个可写的日志块(相对块号)，日志为空时从 s_first 开始
     pub sequence: u32,    //当前期待事务ID(验证和写commit用)
     pub commit_queue: Vec<Jbd2Update>, //事务缓存
+    pub handle_count: u32,     //运行中事务上打开的句柄数（支持嵌套）
+    pub reserved_credits: u32, //运行中事务已预留的元数据块额度
+    pub revoke_queue: Vec<u64>, //运行中事务撤销的块号
+    pub logged_blocks: BTreeSet<u64>, //日志中仍可能被重放的块号，释放这些块时需要撤销
+    pub checkpoint_list: VecDeque<Jbd2Checkpoint>, //已提交、仍占用日志空间的事务（从旧到新）
+    pub aborted: bool, //日志写入出错后中止，之后拒绝新事务
+    pub journal_dev: Option<Box<dyn BlockDevice>>, //外部日志设备，None 时日志在文件系统设备上
+    pub fc_blocks: u32,  //fast commit 区块数（日志末尾），0 表示没有 fast commit 区
+    pub fc_enabled: bool, //运行时是否允许写 fast commit
+    pub fc_off: u32,     //fast commit 区已写入的块数，完整提交后清零
+    pub deferred: bool,  //运行中的事务推迟到 fsync 或后续操作时再完整提交
+    pub fc_replay: Vec<FcRecord>, //重放扫描出的 fast commit 记录，等待文件系统层应用
+}
+
+/// 已提交事务在日志中的位置，日志尾推进越过它之后其日志块才能被复用
+#[derive(Debug, Clone, Copy)]
+pub struct Jbd2Checkpoint {
+    pub tid: u32,   // 事务id
+    pub start: u32, // 事务第一个日志块(相对块号)
+    pub len: u32,   // 事务占用的日志块数
+}
+
+/// 事务句柄：一次文件系统操作在日志中的原子单位
+/// 由 `Jbd2Dev::begin_transaction` 创建，必须交还给 `Jbd2Dev::commit`
+#[derive(Debug)]
+pub struct Jbd2Handle {
+    pub tid: u32,     // 所属事务id
+    pub credits: u32, // 本句柄预留的元数据块数
 }
 
 #[repr(C)]
@@ -87,8 +159,10 @@ pub struct JournalSuperBllockS {
     pub s_checksum_type: u8,      // 0x50 __u8
     pub s_padding2: [u8; 3],      // 0x51 padding
 
+    pub s_num_fc_blks: u32,   // 0x54 __be32: fast commit 区块数，0 表示默认值
+
     // padding up to 0xFC
-    pub s_padding: [u32; 42], // 0x54..0xFC
+    pub s_padding: [u32; 41], // 0x58..0xFC
     pub s_checksum: u32,      // 0xFC __be32: checksum of superblock (with this zeroed)
 
     // 0x100 .. 0x3FF: list of users (16 * 48 = 768 bytes)
@@ -113,17 +187,175 @@ impl Default for JournalSuperBllockS {
             s_uuid: [0; 16],
             s_nr_users: 1,
             s_dynsuper: 0,
-            s_max_transaction: JOURNAL_BLOCK_COUNT,
-            s_max_trans_data: JOURNAL_BLOCK_COUNT * 10,
+            s_max_transaction: journal_max_transaction(BLOCK_SIZE_U32),
+            s_max_trans_data: journal_max_transaction(BLOCK_SIZE_U32) * 10,
             s_checksum_type: 0,
             s_padding2: [0; 3],
-            s_padding: [0; 42],
+            s_num_fc_blks: 0,
+            s_padding: [0; 41],
             s_checksum: 0,
             s_users: [0; 768],
         }
     }
 }
 
+impl JournalSuperBllockS {
+    /// 设置日志块大小，并按块大小换算单个事务的块数上限
+    pub fn set_block_size(&mut self, block_size: u32) {
+        self.s_blocksize = block_size;
+        self.s_max_transaction = journal_max_transaction(block_size);
+        self.s_max_trans_data = self.s_max_transaction * 10;
+    }
+
+    /// 判断不兼容特性是否启用
+    pub fn has_feature_incompat(&self, feature: u32) -> bool {
+        self.s_feature_incompat & feature != 0
+    }
+
+    /// revoke 块中每条记录的字节数（64BIT 时为 8）
+    pub fn revoke_record_bytes(&self) -> usize {
+        if self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// 是否启用 checksum v2/v3
+    pub fn has_csum_v2or3(&self) -> bool {
+        self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3)
+    }
+
+    /// fast commit 区块数，未启用 FAST_COMMIT 时为 0
+    pub fn num_fc_blocks(&self) -> u32 {
+        if !self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_FAST_COMMIT) {
+            return 0;
+        }
+        match self.s_num_fc_blks {
+            0 => JBD2_DEFAULT_FAST_COMMIT_BLOCKS,
+            n => n,
+        }
+    }
+
+    /// 校验和种子：crc32c(~0, s_uuid)
+    pub fn csum_seed(&self) -> u32 {
+        crc32c(!0, &self.s_uuid)
+    }
+
+    /// descriptor / revoke 块末尾校验和占用的字节数
+    pub fn tail_bytes(&self) -> usize {
+        if self.has_csum_v2or3() {
+            Jbd2JournalBlockTail::disk_size()
+        } else {
+            0
+        }
+    }
+
+    /// 超级块校验和：覆盖整个 1024 字节结构，s_checksum 视为 0
+    pub fn compute_checksum(&self) -> u32 {
+        let mut bytes = [0u8; 1024];
+        self.to_disk_bytes(&mut bytes);
+        bytes[JBD2_SUPERBLOCK_CSUM_OFFSET..JBD2_SUPERBLOCK_CSUM_OFFSET + 4].fill(0);
+        crc32c(!0, &bytes)
+    }
+
+    /// 启用校验和时刷新 s_checksum，写盘前调用
+    pub fn update_checksum(&mut self) {
+        if self.has_csum_v2or3() {
+            self.s_checksum = self.compute_checksum();
+        }
+    }
+
+    /// 校验超级块校验和（未启用时总是通过）
+    pub fn verify_checksum(&self) -> bool {
+        !self.has_csum_v2or3() || self.s_checksum == self.compute_checksum()
+    }
+
+    /// 在 descriptor / revoke 块末尾填写块校验和
+    pub fn set_block_tail_csum(&self, block: &mut [u8]) {
+        if !self.has_csum_v2or3() {
+            return;
+        }
+        let off = block.len() - Jbd2JournalBlockTail::disk_size();
+        block[off..].fill(0);
+        let tail = Jbd2JournalBlockTail {
+            t_checksum: crc32c(self.csum_seed(), block),
+        };
+        tail.to_disk_bytes(&mut block[off..]);
+    }
+
+    /// 校验 descriptor / revoke 块末尾的块校验和
+    pub fn verify_block_tail_csum(&self, block: &[u8]) -> bool {
+        if !self.has_csum_v2or3() {
+            return true;
+        }
+        let off = block.len() - Jbd2JournalBlockTail::disk_size();
+        let tail = Jbd2JournalBlockTail::from_disk_bytes(&block[off..]);
+        let mut crc = crc32c(self.csum_seed(), &block[..off]);
+        crc = crc32c(crc, &[0; 4]);
+        crc == tail.t_checksum
+    }
+
+    /// 在 commit 块的 h_chksum[0] 填写块校验和
+    pub fn set_commit_csum(&self, block: &mut [u8]) {
+        if !self.has_csum_v2or3() {
+            return;
+        }
+        let range = JBD2_COMMIT_CSUM_OFFSET..JBD2_COMMIT_CSUM_OFFSET + 4;
+        block[range.clone()].fill(0);
+        let csum = crc32c(self.csum_seed(), block);
+        block[range].copy_from_slice(&csum.to_be_bytes());
+    }
+
+    /// 校验 commit 块校验和
+    pub fn verify_commit_csum(&self, block: &[u8]) -> bool {
+        if !self.has_csum_v2or3() {
+            return true;
+        }
+        let off = JBD2_COMMIT_CSUM_OFFSET;
+        let stored = u32::from_be_bytes(block[off..off + 4].try_into().unwrap());
+        let mut crc = crc32c(self.csum_seed(), &block[..off]);
+        crc = crc32c(crc, &[0; 4]);
+        crc = crc32c(crc, &block[off + 4..]);
+        crc == stored
+    }
+
+    /// 日志中元数据块的 tag 校验和：crc32c(seed, 事务号) 再覆盖日志里的块内容（转义后）
+    pub fn block_tag_csum(&self, sequence: u32, block: &[u8]) -> u32 {
+        let crc = crc32c(self.csum_seed(), &sequence.to_be_bytes());
+        crc32c(crc, block)
+    }
+
+    /// 校验 tag 中的块校验和，v2 只保存低 16 位
+    pub fn verify_block_tag_csum(&self, tag: &JouranlBlockTag3S, sequence: u32, block: &[u8]) -> bool {
+        if !self.has_csum_v2or3() {
+            return true;
+        }
+        let csum = self.block_tag_csum(sequence, block);
+        if self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            tag.t_checksum == csum
+        } else {
+            tag.t_checksum == csum as u16 as u32
+        }
+    }
+
+    /// descriptor 块中每个 tag 的字节数（与 jbd2 journal_tag_bytes 一致，不含 UUID）
+    pub fn tag_bytes(&self) -> usize {
+        if self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        let mut size = 12;
+        if self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V2) {
+            size += 2;
+        }
+        if self.has_feature_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            size
+        } else {
+            size - 4
+        }
+    }
+}
+
 impl DiskFormat for JournalSuperBllockS {
     fn from_disk_bytes(bytes: &[u8]) -> Self {
         // expect 1024 bytes
@@ -153,11 +385,10 @@ impl DiskFormat for JournalSuperBllockS {
         let mut s_padding2 = [0u8; 3];
         s_padding2.copy_from_slice(&bytes[81..84]);
 
-        let mut s_padding = [0u32; 42];
-        let mut off = 84usize;
-        for i in 0..42 {
-            s_padding[i] = u32::from_be_bytes(bytes[off..off + 4].try_into().unwrap());
-            off += 4;
+        let s_num_fc_blks = u32::from_be_bytes(bytes[84..88].try_into().unwrap());
+        let mut s_padding = [0u32; 41];
+        for (word, raw) in s_padding.iter_mut().zip(bytes[88..0xFC].chunks_exact(4)) {
+            *word = u32::from_be_bytes(raw.try_into().unwrap());
         }
 
         let s_checksum = u32::from_be_bytes(bytes[0xFC..0x100].try_into().unwrap());
@@ -183,6 +414,7 @@ impl DiskFormat for JournalSuperBllockS {
             s_max_trans_data,
             s_checksum_type,
             s_padding2,
+            s_num_fc_blks,
             s_padding,
             s_checksum,
             s_users,
@@ -213,8 +445,9 @@ impl DiskFormat for JournalSuperBllockS {
         bytes[80] = self.s_checksum_type;
         bytes[81..84].copy_from_slice(&self.s_padding2);
 
-        let mut off = 84usize;
-        for i in 0..42 {
+        bytes[84..88].copy_from_slice(&self.s_num_fc_blks.to_be_bytes());
+        let mut off = 88usize;
+        for i in 0..41 {
             bytes[off..off + 4].copy_from_slice(&self.s_padding[i].to_be_bytes());
             off += 4;
         }
@@ -267,6 +500,60 @@ pub struct JouranlBlockTag3S {
                              // Optionally followed by a uuid (16 bytes) unless SAME_UUID flag set.
 }
 
+impl JouranlBlockTag3S {
+    /// 以 64 位目标块号构造 tag
+    pub fn new(blocknr: u64, flags: u32) -> Self {
+        JouranlBlockTag3S {
+            t_blocknr: blocknr as u32,
+            t_flags: flags,
+            t_blocknr_high: (blocknr >> 32) as u32,
+            t_checksum: 0,
+        }
+    }
+
+    /// 完整的 64 位目标块号
+    pub fn blocknr(&self) -> u64 {
+        (self.t_blocknr_high as u64) << 32 | self.t_blocknr as u64
+    }
+
+    /// 按 journal 特性从 descriptor 中解析 tag，统一转换为 tag3 表示
+    /// 未开启 64BIT 时忽略高 32 位
+    pub fn read_tag(jsb: &JournalSuperBllockS, bytes: &[u8]) -> Self {
+        if jsb.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            return Self::from_disk_bytes(bytes);
+        }
+        let tag = JournalBlockTagS::from_disk_bytes(bytes);
+        let t_blocknr_high = if jsb.has_feature_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            u32::from_be_bytes(bytes[8..12].try_into().unwrap())
+        } else {
+            0
+        };
+        JouranlBlockTag3S {
+            t_blocknr: tag.t_blocknr,
+            t_flags: tag.t_flags as u32,
+            t_blocknr_high,
+            t_checksum: tag.t_checksum as u32,
+        }
+    }
+
+    /// 按 journal 特性把 tag 写入 descriptor，写入 `jsb.tag_bytes()` 字节
+    pub fn write_tag(&self, jsb: &JournalSuperBllockS, bytes: &mut [u8]) {
+        if jsb.has_feature_incompat(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            self.to_disk_bytes(bytes);
+            return;
+        }
+        let tag = JournalBlockTagS {
+            t_blocknr: self.t_blocknr,
+            t_checksum: self.t_checksum as u16,
+            t_flags: self.t_flags as u16,
+        };
+        tag.to_disk_bytes(bytes);
+        if jsb.has_feature_incompat(JBD2_FEATURE_INCOMPAT_64BIT) {
+            bytes[8..12].copy_from_slice(&self.t_blocknr_high.to_be_bytes());
+        }
+    }
+}
+
 impl DiskFormat for JouranlBlockTag3S {
     fn from_disk_bytes(bytes: &[u8]) -> Self {
         let t_blocknr = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
@@ -410,7 +697,8 @@ mod tests {
             s_max_trans_data: 0,
             s_checksum_type: 4,
             s_padding2: [0; 3],
-            s_padding: [0xDEAD_BEEFu32; 42],
+            s_num_fc_blks: 64,
+            s_padding: [0xDEAD_BEEFu32; 41],
             s_checksum: 0xFEED_FACE,
             s_users: [0x55u8; 16 * 48],
         };
@@ -435,6 +723,7 @@ mod tests {
         assert_eq!(parsed.s_sequence, sb.s_sequence);
         assert_eq!(parsed.s_start, sb.s_start);
         assert_eq!(parsed.s_checksum, sb.s_checksum);
+        assert_eq!(parsed.s_num_fc_blks, sb.s_num_fc_blks);
         assert_eq!(&parsed.s_users[..], &sb.s_users[..]);
     }
 
@@ -470,6 +759,40 @@ mod tests {
         assert_eq!(parsed3.t_checksum, tag3.t_checksum);
     }
 
+    #[test]
+    fn test_tag_layout_follows_journal_features() {
+        let mut jsb = JournalSuperBllockS::default();
+        let blocknr = 0x0000_0012_3456_789Au64;
+        let tag = JouranlBlockTag3S::new(blocknr, JBD2_FLAG_LAST_TAG as u32);
+
+        // 无 64BIT：8 字节，高位被丢弃
+        assert_eq!(jsb.tag_bytes(), 8);
+        let mut b = [0u8; 16];
+        tag.write_tag(&jsb, &mut b);
+        assert_eq!(&b[8..], &[0u8; 8]);
+        let parsed = JouranlBlockTag3S::read_tag(&jsb, &b);
+        assert_eq!(parsed.blocknr(), blocknr & 0xFFFF_FFFF);
+
+        // 64BIT：12 字节，高 32 位紧跟 flags
+        jsb.s_feature_incompat |= JBD2_FEATURE_INCOMPAT_64BIT;
+        assert_eq!(jsb.tag_bytes(), 12);
+        let mut b = [0u8; 16];
+        tag.write_tag(&jsb, &mut b);
+        assert_eq!(&b[8..12], &0x12u32.to_be_bytes());
+        let parsed = JouranlBlockTag3S::read_tag(&jsb, &b);
+        assert_eq!(parsed.blocknr(), blocknr);
+        assert_eq!(parsed.t_flags, JBD2_FLAG_LAST_TAG as u32);
+
+        // CSUM_V3：16 字节 tag3 布局
+        jsb.s_feature_incompat |= JBD2_FEATURE_INCOMPAT_CSUM_V3;
+        assert_eq!(jsb.tag_bytes(), 16);
+        let mut b = [0u8; 16];
+        tag.write_tag(&jsb, &mut b);
+        assert_eq!(&b[4..8], &(JBD2_FLAG_LAST_TAG as u32).to_be_bytes());
+        let parsed = JouranlBlockTag3S::read_tag(&jsb, &b);
+        assert_eq!(parsed.blocknr(), blocknr);
+    }
+
     #[test]
     fn test_block_tail_and_revoke_roundtrip() {
         let tail = Jbd2JournalBlockTail {
@@ -5,31 +5,31 @@ use alloc::vec::Vec;
 use log::{error, info};
 
 use crate::ext4_backend::blockdev::*;
-use crate::ext4_backend::config::*;
+use crate::ext4_backend::blockmap::*;
 use crate::ext4_backend::disknode::*;
 use crate::ext4_backend::entries::*;
 use crate::ext4_backend::ext4::*;
 use crate::ext4_backend::extents_tree::*;
 use crate::ext4_backend::hashtree::*;
+use crate::ext4_backend::inline_data::*;
 use crate::ext4_backend::error::*;
 use log::debug;
 
 ///支持extend数和多级索引(多级索引将来弃用)
 /// 根据 inode 的逻辑块号解析到物理块号，支持 12 个直接块和 1/2/3 级间接块
+/// inode_num 用于校验 extent 块的校验和（metadata_csum）
 pub fn resolve_inode_block<B: BlockDevice>(
+    fs: &Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
     inode: &mut Ext4Inode,
     logical_block: u32,
-) -> BlockDevResult<Option<u32>> {
+) -> BlockDevResult<Option<u64>> {
     // 优先走 extent 树（支持多层索引）；失败时再回退到传统多级指针逻辑
     if inode.have_extend_header_and_use_extend() {
-        let mut tree = ExtentTree::new(inode);
+        let mut tree = ExtentTree::new_with_ino(fs, inode, inode_num);
         if let Some(ext) = tree.find_extent(block_dev, logical_block)? {
-            let mut len = ext.ee_len as u32;
-            // 最高位表示 uninitialized 标志，长度使用低 15 位
-            if (len & 0x8000) != 0 {
-                len &= 0x7FFF;
-            }
+            let len = ext.actual_len();
             if len == 0 {
                 return Ok(None);
             }
@@ -41,79 +41,35 @@ pub fn resolve_inode_block<B: BlockDevice>(
 
             let base = ((ext.ee_start_hi as u64) << 32) | ext.ee_start_lo as u64;
             let phys = base + (logical_block - start_lbn) as u64;
-            if phys > u32::MAX as u64 {
-                return Err(BlockDevError::Corrupted);
-            }
-            return Ok(Some(phys as u32));
+            return Ok(Some(phys));
         }
         error!("Can't find proper extend for this logical block");
         return Err(BlockDevError::ReadError);
-    }else {
-        error!("Only Support Extend mode!");
-        return Err(BlockDevError::Unsupported);
     }
 
-    
+    if !inode.maps_data_blocks() {
+        return Ok(None);
+    }
+    BlockMap::new(inode).lookup(block_dev, logical_block)
 }
 
 pub fn resolve_inode_block_allextend<B: BlockDevice>(
-    _fs: &mut Ext4FileSystem,
+    fs: &mut Ext4FileSystem,
     block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
     inode: &mut Ext4Inode,
 ) -> BlockDevResult<BTreeMap<u32, u64>> {
-    if !inode.have_extend_header_and_use_extend() {
+    if !inode.maps_data_blocks() {
         return Ok(BTreeMap::new());
     }
-
-    fn push_extent_blocks(out: &mut Vec<(u32, u64)>, ext: &Ext4Extent) {
-        let mut len = ext.ee_len as u32;
-        // 最高位表示 uninitialized 标志，长度使用低 15 位
-        if (len & 0x8000) != 0 {
-            len &= 0x7FFF;
-        }
-        if len == 0 {
-            return;
-        }
-        let base = ((ext.ee_start_hi as u64) << 32) | ext.ee_start_lo as u64;
-        for i in 0..len {
-            let lbn = ext.ee_block.saturating_add(i);
-            out.push((lbn, base + i as u64));
-        }
-    }
-
-    fn walk_node<B: BlockDevice>(
-        dev: &mut Jbd2Dev<B>,
-        node: &ExtentNode,
-        out: &mut Vec<(u32, u64)>,
-    ) -> BlockDevResult<()> {
-        match node {
-            ExtentNode::Leaf { entries, .. } => {
-                for ext in entries {
-                    push_extent_blocks(out, ext);
-                }
-                Ok(())
-            }
-            ExtentNode::Index { entries, .. } => {
-                for idx in entries {
-                    let child_block = ((idx.ei_leaf_hi as u64) << 32) | (idx.ei_leaf_lo as u64);
-                    dev.read_block(child_block as u32)?;
-                    let buf = dev.buffer();
-                    let child = ExtentTree::parse_node(buf).ok_or(BlockDevError::Corrupted)?;
-                    walk_node(dev, &child, out)?;
-                }
-                Ok(())
-            }
-        }
+    if !inode.have_extend_header_and_use_extend() {
+        return BlockMap::new(inode).data_blocks(block_dev);
     }
 
-    let tree = ExtentTree::new(inode);
-    let root = match tree.load_root_from_inode() {
-        Some(n) => n,
-        None => return Ok(BTreeMap::new()),
-    };
-
     let mut blocks: Vec<(u32, u64)> = Vec::new();
-    walk_node(block_dev, &root, &mut blocks)?;
+    for ext in resolve_inode_extents(fs, block_dev, inode_num, inode)? {
+        push_extent_blocks(&mut blocks, &ext);
+    }
     blocks.sort_unstable_by_key(|(lbn, _)| *lbn);
     blocks.dedup_by_key(|(lbn, _)| *lbn);
 
@@ -124,6 +80,75 @@ pub fn resolve_inode_block_allextend<B: BlockDevice>(
     Ok(out)
 }
 
+/// 读取用的逻辑块映射：与 resolve_inode_block_allextend 相同，但不含 unwritten extent 的块（读出为 0）
+pub fn resolve_inode_written_blocks<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<BTreeMap<u32, u64>> {
+    let mut blocks = resolve_inode_block_allextend(fs, block_dev, inode_num, inode)?;
+    if inode.have_extend_header_and_use_extend() {
+        for ext in resolve_inode_extents(fs, block_dev, inode_num, inode)? {
+            if !ext.is_initialized() {
+                for lbn in ext.ee_block..ext.ee_block.saturating_add(ext.actual_len()) {
+                    blocks.remove(&lbn);
+                }
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+fn push_extent_blocks(out: &mut Vec<(u32, u64)>, ext: &Ext4Extent) {
+    let len = ext.actual_len();
+    if len == 0 {
+        return;
+    }
+    let base = ext.start_block();
+    for i in 0..len {
+        let lbn = ext.ee_block.saturating_add(i);
+        out.push((lbn, base + i as u64));
+    }
+}
+
+/// 按逻辑块号顺序收集 extent 树的全部叶子 extent（传统间接映射的 inode 返回空）
+pub fn resolve_inode_extents<B: BlockDevice>(
+    fs: &Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<Vec<Ext4Extent>> {
+    if !inode.have_extend_header_and_use_extend() {
+        return Ok(Vec::new());
+    }
+    ExtentTree::new_with_ino(fs, inode, inode_num).extents_in_range(block_dev, 0, u32::MAX)
+}
+
+/// 收集 inode 的间接块（仅传统间接映射的 inode 有，extent inode 返回空）
+pub fn resolve_inode_index_blocks<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<Vec<u64>> {
+    if !inode.maps_data_blocks() || inode.have_extend_header_and_use_extend() {
+        return Ok(Vec::new());
+    }
+    BlockMap::new(inode).index_blocks(block_dev)
+}
+
+/// 收集 extent 树在 i_block 之外的节点块（传统间接映射的 inode 返回空）
+pub fn resolve_extent_node_blocks<B: BlockDevice>(
+    fs: &Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    inode: &mut Ext4Inode,
+) -> BlockDevResult<Vec<u64>> {
+    if !inode.have_extend_header_and_use_extend() {
+        return Ok(Vec::new());
+    }
+    ExtentTree::new_with_ino(fs, inode, inode_num).node_blocks(block_dev)
+}
+
 ///传入完整的路径信息按照特性进行扫描。
 pub fn get_file_inode<B: BlockDevice>(
     fs: &mut Ext4FileSystem,
@@ -144,14 +169,9 @@ pub fn get_file_inode<B: BlockDevice>(
     // 从根目录开始逐级解析，并维护一个路径栈以支持 ".." 回溯
     let mut current_inode = fs.get_root(block_dev)?;
     let mut current_ino_num: u32 = fs.root_inode;
-    let mut path_vec: Vec<Ext4Inode> = Vec::new();
-    path_vec.push(current_inode);
-
-    // 根目录所在的 inode 表起始块目前按 group0 处理
-    let inode_table_start = match fs.group_descs.first() {
-        Some(desc) => desc.inode_table(),
-        None => return Err(BlockDevError::Corrupted),
-    };
+    let mut path_vec: Vec<(u32, Ext4Inode)> = Vec::new();
+    path_vec.push((current_ino_num, current_inode));
+
     for name in components {
         if !current_inode.is_dir() {
             // 中间层不是目录，路径非法
@@ -166,8 +186,9 @@ pub fn get_file_inode<B: BlockDevice>(
             // 回溯到父目录：栈中至少保留根目录一层
             if path_vec.len() > 1 {
                 path_vec.pop();
-                if let Some(parent_inode) = path_vec.last() {
-                    current_inode = *parent_inode;
+                if let Some(&(parent_ino_num, parent_inode)) = path_vec.last() {
+                    current_ino_num = parent_ino_num;
+                    current_inode = parent_inode;
                 }
             }
             continue;
@@ -176,40 +197,46 @@ pub fn get_file_inode<B: BlockDevice>(
         let target = name.as_bytes();
         let mut found_inode_num: Option<u64> = None;
 
-        // 尝试使用哈希树查找
-        match lookup_directory_entry(fs, block_dev, &current_inode, target) {
-            Ok(result) => {
-                found_inode_num = Some(result.entry.inode as u64);
-            }
-            Err(_) => {
-                // 哈希树查找失败，回退到线性查找
-                debug!("Hash tree lookup failed, falling back to linear search");
-
-                // 使用 resolve_inode_block_allextend 获取所有物理块，然后逐块线性查找
-                let total_size = current_inode.size() as usize;
-                let block_bytes = BLOCK_SIZE;
-                let blocks = resolve_inode_block_allextend(fs, block_dev, &mut current_inode)?;
-                info!(
-                    "Directory inode size: {} bytes, blocks used: {}",
-                    &total_size,
-                    &blocks.len()
-                );
-
-                for (idx, phys) in blocks.iter().enumerate() {
-                    info!("Scan dir block idx {} phys {}", &idx, phys.1);
-                    let cached_block = fs.datablock_cache.get_or_load(block_dev, *phys.1)?;
-                    let block_data = &cached_block.data[..block_bytes];
-
-                    if let Some(entry) = classic_dir::find_entry(block_data, target) {
-                        found_inode_num = Some(entry.inode as u64);
-                        break;
+        if current_inode.has_inline_data() {
+            // 内联目录：目录项在 inode 内
+            found_inode_num = find_inline_dir_entry(fs, block_dev, current_ino_num, target)?
+                .map(|e| e.inode as u64);
+        } else {
+            // 尝试使用哈希树查找
+            match lookup_directory_entry(fs, block_dev, current_ino_num, &current_inode, target) {
+                Ok(result) => {
+                    found_inode_num = Some(result.entry.inode as u64);
+                }
+                // 哈希树（或其内部的线性回退）已确认不存在，无需再次扫描
+                Err(HashTreeError::EntryNotFound) => {}
+                Err(_) => {
+                    // 哈希树查找失败，回退到线性查找
+                    debug!("Hash tree lookup failed, falling back to linear search");
+
+                    // 使用 resolve_inode_block_allextend 获取所有物理块，然后逐块线性查找
+                    let total_size = current_inode.size() as usize;
+                    let block_bytes = fs.block_size();
+                    let blocks = resolve_inode_block_allextend(fs, block_dev, current_ino_num, &mut current_inode)?;
+                    info!(
+                        "Directory inode size: {} bytes, blocks used: {}",
+                        &total_size,
+                        &blocks.len()
+                    );
+
+                    for (idx, phys) in blocks.iter().enumerate() {
+                        info!("Scan dir block idx {} phys {}", &idx, phys.1);
+                        let cached_block = fs.datablock_cache.get_or_load(block_dev, *phys.1)?;
+                        let block_data = &cached_block.data[..block_bytes];
+
+                        if let Some(entry) = classic_dir::find_entry(block_data, target) {
+                            found_inode_num = Some(entry.inode as u64);
+                            break;
+                        }
                     }
                 }
             }
         }
 
-   
-
         let inode_num = match found_inode_num {
             Some(n) => n,
             None => return Ok(None),
@@ -217,11 +244,19 @@ pub fn get_file_inode<B: BlockDevice>(
 
         let inode_num_u32 = inode_num as u32;
 
+        // inode 表起始块按 inode 所在块组取
+        let (inode_group_idx, _idx_in_group) = fs.inode_allocator.global_to_group(inode_num_u32);
+        let inode_table_start = fs
+            .group_descs
+            .get(inode_group_idx as usize)
+            .ok_or(BlockDevError::Corrupted)?
+            .inode_table();
+
         let (block_num, offset, _group_idx) = fs.inodetable_cahce.calc_inode_location(
             inode_num_u32,
             fs.superblock.s_inodes_per_group,
             inode_table_start,
-            BLOCK_SIZE,
+            fs.block_size(),
         );
 
         let cached_inode = fs
@@ -229,10 +264,56 @@ pub fn get_file_inode<B: BlockDevice>(
             .get_or_load(block_dev, inode_num, block_num, offset)?;
         current_inode = cached_inode.inode;
         current_ino_num = inode_num_u32;
-        path_vec.push(current_inode);
+        path_vec.push((current_ino_num, current_inode));
     }
 
  
 
     Ok(Some((current_ino_num, current_inode)))
 }
+
+#[cfg(all(test, feature = "CONFIG_META_CSUM_ENABLE"))]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::endian::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::test_support::*;
+
+    #[test]
+    fn test_corrupted_extent_index_block_fails_read() {
+        let mem = MemDev::new(8 << 20);
+        let (mut dev, mut fs) = setup_fs(mem.clone(), 1024);
+        let (ino, _) = mkfile_with_ino(&mut dev, &mut fs, "/sparse", None, None).unwrap();
+        // 隔块写入几百个单块 extent，叶子超过 i_block 能索引的个数，树长到两层外部节点
+        for i in 0..600u64 {
+            write_file(&mut dev, &mut fs, "/sparse", i * 2048, &[1; 1024]).unwrap();
+        }
+        assert!(read_file(&mut dev, &mut fs, "/sparse").unwrap().is_some());
+
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        let nodes = resolve_extent_node_blocks(&fs, &mut dev, ino, &mut inode).unwrap();
+        let index_block = nodes
+            .into_iter()
+            .find(|&blk| {
+                let data = &fs.datablock_cache.get_or_load(&mut dev, blk).unwrap().data;
+                read_u16_le(&data[6..8]) > 0
+            })
+            .expect("no external extent index block");
+        umount(fs, &mut dev).unwrap();
+
+        // 改掉索引块里第一条索引的逻辑块号，头部仍然合法，只有校验和能发现
+        let off = index_block as usize * 1024 + 12;
+        mem.0.borrow_mut()[off] ^= 0x80;
+
+        let mut fs = mount(&mut dev).unwrap();
+        assert!(matches!(
+            read_file(&mut dev, &mut fs, "/sparse"),
+            Err(BlockDevError::ChecksumError)
+        ));
+        let mut inode = fs.get_inode_by_num(&mut dev, ino).unwrap();
+        assert!(matches!(
+            resolve_inode_block(&fs, &mut dev, ino, &mut inode, 0),
+            Err(BlockDevError::ChecksumError)
+        ));
+    }
+}
@@ -0,0 +1,429 @@
+//! 多块分配器（mballoc 风格）
+//!
+//! 每个块组维护一份空闲区段摘要：按 2 的幂分级统计极大空闲段的个数，并记录最长空闲段。
+//! 选组时从目标块（goal）所在的组开始，先找最长空闲段放得下整个请求的组，再放宽到只要求最小长度；
+//! 组内先尝试从目标块直接续接，否则取放得下请求的最小空闲段（best fit），都不行时取最长段。
+//!
+//! 顺序写的文件按文件大小归一化出一个 inode 预分配窗口，后续写入直接续接在窗口里；
+//! 小文件共用一个局部性窗口（内核按 CPU 各一个，这里单核只有一个），让它们挨在一起。
+//! 预分配只保存在内存中，不写位图：搜索空闲块时把窗口当作已占用，崩溃或卸载后自然消失。
+
+use crate::ext4_backend::config::*;
+use crate::ext4_backend::superblock::*;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+/// 摘要的分级数：第 k 级统计长度在 [2^k, 2^(k+1)) 的空闲段，足够覆盖单组最大块数
+pub const MB_ORDERS: usize = 16;
+
+/// 块组的空闲区段摘要
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MbGroupInfo {
+    /// 空闲块数（预分配窗口算作占用）
+    pub free: u32,
+    /// 最长空闲段
+    pub largest: u32,
+    /// counters[k]：长度在 [2^k, 2^(k+1)) 的极大空闲段个数
+    pub counters: [u32; MB_ORDERS],
+}
+
+impl MbGroupInfo {
+    /// 扫描位图生成摘要，只看前 `blocks` 位
+    pub fn from_bitmap(bitmap: &[u8], blocks: u32) -> Self {
+        let mut info = Self::default();
+        for (_, len) in free_runs(bitmap, blocks) {
+            info.free += len;
+            info.largest = info.largest.max(len);
+            info.counters[(31 - len.leading_zeros() as usize).min(MB_ORDERS - 1)] += 1;
+        }
+        info
+    }
+
+    /// 空闲段个数
+    pub fn fragments(&self) -> u32 {
+        self.counters.iter().sum()
+    }
+}
+
+/// 按位置顺序列出位图前 `blocks` 位中的极大空闲段 (起始位, 长度)
+pub fn free_runs(bitmap: &[u8], blocks: u32) -> impl Iterator<Item = (u32, u32)> + '_ {
+    let blocks = blocks.min(bitmap.len() as u32 * 8);
+    let is_free = move |bit: u32| bitmap[(bit / 8) as usize] & (1 << (bit % 8)) == 0;
+    let mut bit = 0u32;
+    core::iter::from_fn(move || {
+        // 整字节已占用时按字节跳过
+        while bit < blocks && !is_free(bit) {
+            if bit % 8 == 0 && bitmap[(bit / 8) as usize] == 0xFF {
+                bit += 8;
+            } else {
+                bit += 1;
+            }
+        }
+        if bit >= blocks {
+            return None;
+        }
+        let start = bit;
+        while bit < blocks && is_free(bit) {
+            if bit % 8 == 0 && bit + 8 <= blocks && bitmap[(bit / 8) as usize] == 0 {
+                bit += 8;
+            } else {
+                bit += 1;
+            }
+        }
+        Some((start, bit - start))
+    })
+}
+
+/// 组内查找，返回 (起始位, 长度)：
+/// goal 处空闲段不短于 `min` 时直接续接；否则取不短于 `want` 的最小空闲段（同样大小取离 goal 近的）；
+/// 再否则取最长的空闲段（不短于 `min`）。返回长度不超过 `want`
+pub fn find_in_group(bitmap: &[u8], blocks: u32, goal: Option<u32>, want: u32, min: u32) -> Option<(u32, u32)> {
+    let min = min.max(1);
+    let mut best: Option<(u32, u32)> = None;
+    let mut longest: Option<(u32, u32)> = None;
+    for (start, len) in free_runs(bitmap, blocks) {
+        if let Some(g) = goal
+            && start <= g
+            && g < start + len
+        {
+            let avail = start + len - g;
+            if avail >= min {
+                return Some((g, avail.min(want)));
+            }
+        }
+        if len >= want {
+            let dist = |s: u32| goal.map_or(0, |g| s.abs_diff(g));
+            if best.is_none_or(|(bs, bl)| len < bl || (len == bl && dist(start) < dist(bs))) {
+                best = Some((start, len));
+            }
+        }
+        if len >= min && longest.is_none_or(|(_, ll)| len > ll) {
+            longest = Some((start, len));
+        }
+    }
+    best.or(longest).map(|(start, len)| (start, len.min(want)))
+}
+
+/// inode 预分配窗口：逻辑块 [lblk, lblk + len) 依次对应物理块 [pblk, pblk + len)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodePrealloc {
+    pub lblk: u32,
+    pub pblk: u64,
+    pub len: u32,
+    last_use: u64,
+}
+
+/// 分配器的内存状态：块组摘要和预分配窗口
+pub struct MbState {
+    /// None 表示需要重新扫描位图
+    groups: Vec<Option<MbGroupInfo>>,
+    inode_pa: BTreeMap<u32, InodePrealloc>,
+    /// 小文件共用的局部性窗口 (起始物理块, 块数)
+    group_pa: Option<(u64, u32)>,
+    blocks_per_group: u32,
+    first_data_block: u32,
+    access_counter: u64,
+}
+
+impl MbState {
+    pub fn new(sb: &Ext4Superblock, group_count: u32) -> Self {
+        Self {
+            groups: alloc::vec![None; group_count as usize],
+            inode_pa: BTreeMap::new(),
+            group_pa: None,
+            blocks_per_group: sb.s_blocks_per_group.max(1),
+            first_data_block: sb.s_first_data_block,
+            access_counter: 0,
+        }
+    }
+
+    pub fn group_info(&self, group_idx: u32) -> Option<MbGroupInfo> {
+        self.groups.get(group_idx as usize).copied().flatten()
+    }
+
+    pub fn set_group_info(&mut self, group_idx: u32, info: MbGroupInfo) {
+        if let Some(slot) = self.groups.get_mut(group_idx as usize) {
+            *slot = Some(info);
+        }
+    }
+
+    /// 组内位图或预分配有变化，下次使用前重新扫描
+    pub fn invalidate_group(&mut self, group_idx: u32) {
+        if let Some(slot) = self.groups.get_mut(group_idx as usize) {
+            *slot = None;
+        }
+    }
+
+    /// 物理块所在的块组
+    pub fn group_of(&self, block: u64) -> u32 {
+        (block.saturating_sub(self.first_data_block as u64) / self.blocks_per_group as u64) as u32
+    }
+
+    /// 块组的第一个块
+    pub fn group_start(&self, group_idx: u32) -> u64 {
+        self.first_data_block as u64 + group_idx as u64 * self.blocks_per_group as u64
+    }
+
+    fn invalidate_range(&mut self, pblk: u64, len: u32) {
+        if len == 0 {
+            return;
+        }
+        for g in self.group_of(pblk)..=self.group_of(pblk + len as u64 - 1) {
+            self.invalidate_group(g);
+        }
+    }
+
+    /// 与组内 [0, blocks) 重叠的预分配区段（组内相对位置）
+    pub fn reserved_in_group(&self, group_idx: u32, blocks: u32) -> Vec<(u32, u32)> {
+        let start = self.group_start(group_idx);
+        let end = start + blocks as u64;
+        self.inode_pa
+            .values()
+            .map(|pa| (pa.pblk, pa.len))
+            .chain(self.group_pa)
+            .filter(|&(p, l)| p < end && p + l as u64 > start)
+            .map(|(p, l)| {
+                let s = p.max(start);
+                ((s - start) as u32, ((p + l as u64).min(end) - s) as u32)
+            })
+            .collect()
+    }
+
+    /// 从 inode 窗口中取逻辑块 lblk 起最多 len 块，返回 (物理块, 块数)；
+    /// 窗口中 lblk 之前的部分随之放弃，lblk 不在窗口内时返回 None
+    pub fn use_inode_pa(&mut self, inode_num: u32, lblk: u32, len: u32) -> Option<(u64, u32)> {
+        let pa = self.inode_pa.get(&inode_num).copied()?;
+        if lblk < pa.lblk || lblk - pa.lblk >= pa.len {
+            return None;
+        }
+        let skip = lblk - pa.lblk;
+        let got = len.min(pa.len - skip);
+        let pblk = pa.pblk + skip as u64;
+        self.access_counter += 1;
+        let rest = pa.len - skip - got;
+        if rest == 0 {
+            self.inode_pa.remove(&inode_num);
+        } else {
+            self.inode_pa.insert(
+                inode_num,
+                InodePrealloc {
+                    lblk: lblk + got,
+                    pblk: pblk + got as u64,
+                    len: rest,
+                    last_use: self.access_counter,
+                },
+            );
+        }
+        self.invalidate_range(pa.pblk, pa.len);
+        Some((pblk, got))
+    }
+
+    /// 记录 inode 的新窗口（替换旧窗口）；窗口数超过上限时丢弃最久未用的
+    pub fn add_inode_pa(&mut self, inode_num: u32, lblk: u32, pblk: u64, len: u32) {
+        self.discard_inode(inode_num);
+        if len == 0 {
+            return;
+        }
+        if self.inode_pa.len() >= MB_MAX_INODE_PREALLOC {
+            let victim = self.inode_pa.iter().min_by_key(|(_, pa)| pa.last_use).map(|(ino, _)| *ino);
+            if let Some(victim) = victim {
+                self.discard_inode(victim);
+            }
+        }
+        self.access_counter += 1;
+        self.inode_pa.insert(inode_num, InodePrealloc { lblk, pblk, len, last_use: self.access_counter });
+        self.invalidate_range(pblk, len);
+    }
+
+    pub fn inode_pa(&self, inode_num: u32) -> Option<InodePrealloc> {
+        self.inode_pa.get(&inode_num).copied()
+    }
+
+    /// 从局部性窗口头部取 len 块
+    pub fn use_group_pa(&mut self, len: u32) -> Option<u64> {
+        let (pblk, avail) = self.group_pa?;
+        if avail < len {
+            return None;
+        }
+        self.group_pa = (avail > len).then(|| (pblk + len as u64, avail - len));
+        self.invalidate_range(pblk, avail);
+        Some(pblk)
+    }
+
+    /// 设置新的局部性窗口（旧窗口剩余部分放弃）
+    pub fn set_group_pa(&mut self, pblk: u64, len: u32) {
+        if let Some((p, l)) = self.group_pa.take() {
+            self.invalidate_range(p, l);
+        }
+        if len > 0 {
+            self.group_pa = Some((pblk, len));
+            self.invalidate_range(pblk, len);
+        }
+    }
+
+    /// 丢弃 inode 的窗口（截断、释放块或释放 inode 时）
+    pub fn discard_inode(&mut self, inode_num: u32) {
+        if let Some(pa) = self.inode_pa.remove(&inode_num) {
+            self.invalidate_range(pa.pblk, pa.len);
+        }
+    }
+
+    /// 丢弃全部预分配，返回是否有窗口被丢弃（空间不足时先丢弃再重试）
+    pub fn discard_all(&mut self) -> bool {
+        let had = !self.inode_pa.is_empty() || self.group_pa.is_some();
+        let inodes: Vec<u32> = self.inode_pa.keys().copied().collect();
+        for ino in inodes {
+            self.discard_inode(ino);
+        }
+        self.set_group_pa(0, 0);
+        had
+    }
+
+    /// 按写入后的文件末尾（块）归一化 inode 窗口大小：下一个 2 的幂字节数，
+    /// 限制在 [MB_PREALLOC_MIN_BYTES, MB_PREALLOC_MAX_BYTES]，并从 lblk 对齐到窗口边界
+    pub fn normalize_window(&self, block_size: u64, lblk: u32, len: u32) -> u32 {
+        let end_bytes = (lblk as u64 + len as u64) * block_size;
+        let bytes = end_bytes
+            .next_power_of_two()
+            .clamp(MB_PREALLOC_MIN_BYTES, MB_PREALLOC_MAX_BYTES);
+        let window = (bytes / block_size).clamp(1, self.blocks_per_group.min(32768) as u64) as u32;
+        (window - lblk % window).max(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::blockdev::*;
+    use crate::ext4_backend::ext4::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::loopfile::*;
+    use crate::ext4_backend::test_support::*;
+
+    /// 由 (起始, 长度) 的已占用段构造位图
+    fn bitmap(blocks: u32, used: &[(u32, u32)]) -> Vec<u8> {
+        let mut data = alloc::vec![0u8; blocks.div_ceil(8) as usize];
+        for &(s, l) in used {
+            for b in s..s + l {
+                data[(b / 8) as usize] |= 1 << (b % 8);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_free_runs_and_summary() {
+        let data = bitmap(64, &[(0, 3), (5, 1), (16, 16), (40, 2)]);
+        let runs: Vec<(u32, u32)> = free_runs(&data, 64).collect();
+        assert_eq!(runs, alloc::vec![(3, 2), (6, 10), (32, 8), (42, 22)]);
+        let info = MbGroupInfo::from_bitmap(&data, 64);
+        assert_eq!(info.free, 42);
+        assert_eq!(info.largest, 22);
+        assert_eq!(info.fragments(), 4);
+        assert_eq!((info.counters[1], info.counters[3], info.counters[4]), (1, 2, 1));
+        // 只看前 blocks 位
+        assert_eq!(free_runs(&data, 50).last(), Some((42, 8)));
+    }
+
+    #[test]
+    fn test_find_in_group_goal_and_best_fit() {
+        let data = bitmap(64, &[(0, 3), (5, 1), (16, 16), (40, 2)]);
+        // goal 处可续接
+        assert_eq!(find_in_group(&data, 64, Some(8), 4, 1), Some((8, 4)));
+        // goal 已占用：取放得下的最小段
+        assert_eq!(find_in_group(&data, 64, Some(20), 8, 1), Some((32, 8)));
+        assert_eq!(find_in_group(&data, 64, None, 9, 9), Some((6, 9)));
+        // 放不下时取最长段
+        assert_eq!(find_in_group(&data, 64, None, 30, 4), Some((42, 22)));
+        assert_eq!(find_in_group(&data, 64, None, 30, 23), None);
+    }
+
+    #[test]
+    fn test_prealloc_windows() {
+        let sb = Ext4Superblock { s_blocks_per_group: 8192, s_first_data_block: 1, ..Default::default() };
+        let mut mb = MbState::new(&sb, 4);
+        mb.set_group_info(1, MbGroupInfo::default());
+        mb.add_inode_pa(12, 10, 8200, 20);
+        assert!(mb.group_info(1).is_none());
+        assert_eq!(mb.reserved_in_group(1, 8192), alloc::vec![(7, 20)]);
+        assert_eq!(mb.use_inode_pa(12, 9, 4), None);
+        assert_eq!(mb.use_inode_pa(12, 12, 4), Some((8202, 4)));
+        assert_eq!(mb.inode_pa(12).map(|pa| (pa.lblk, pa.pblk, pa.len)), Some((16, 8206, 14)));
+        assert_eq!(mb.use_inode_pa(12, 16, 100), Some((8206, 14)));
+        assert!(mb.inode_pa(12).is_none());
+
+        mb.set_group_pa(100, 8);
+        assert_eq!(mb.use_group_pa(3), Some(100));
+        assert_eq!(mb.use_group_pa(6), None);
+        assert_eq!(mb.reserved_in_group(0, 8192), alloc::vec![(102, 5)]);
+        assert!(mb.discard_all());
+        assert!(!mb.discard_all());
+
+        // 1K 块：写到 4K 处得到 16K 窗口，写到 17K 处得到到 32K 边界为止的窗口
+        assert_eq!(mb.normalize_window(1024, 0, 4), 16);
+        assert_eq!(mb.normalize_window(1024, 16, 1), 16);
+        assert_eq!(mb.normalize_window(1024, 40, 30), 88);
+    }
+
+    fn extents_of(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, ino: u32) -> Vec<(u32, u64, u32)> {
+        let mut inode = fs.get_inode_by_num(dev, ino).unwrap();
+        resolve_inode_extents(fs, dev, ino, &mut inode)
+            .unwrap()
+            .iter()
+            .map(|e| (e.ee_block, e.start_block(), e.actual_len()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sequential_files_get_contiguous_extents_in_their_group() {
+        let (mut dev, mut fs) = setup_fs(MemDev::new(32 << 20), 1024);
+        let (a, _) = mkfile_with_ino(&mut dev, &mut fs, "/a", None, None).unwrap();
+        // 把 0 号组的空闲 inode 数清零，让下一个 inode 落到 1 号组
+        fs.group_descs[0].bg_free_inodes_count_lo = 0;
+        fs.group_descs[0].bg_free_inodes_count_hi = 0;
+        let (b, _) = mkfile_with_ino(&mut dev, &mut fs, "/b", None, None).unwrap();
+        let ipg = fs.superblock.s_inodes_per_group;
+        assert_eq!(((a - 1) / ipg, (b - 1) / ipg), (0, 1));
+
+        // 先后顺序追加写两个文件，每个文件的块应连成一段并位于自己 inode 所在的组
+        let chunk = [0x11u8; 65536];
+        for (path, ino) in [("/a", a), ("/b", b)] {
+            for i in 0..16u64 {
+                write_file(&mut dev, &mut fs, path, i * 65536, &chunk).unwrap();
+            }
+            let extents = extents_of(&mut fs, &mut dev, ino);
+            let mut next = extents[0].1;
+            for &(_, pblk, len) in &extents {
+                assert_eq!(pblk, next, "{path} is fragmented: {extents:?}");
+                next = pblk + len as u64;
+            }
+            assert_eq!(extents.iter().map(|e| e.2).sum::<u32>(), 1024);
+            assert_eq!(fs.mballoc.group_of(extents[0].1), (ino - 1) / ipg);
+        }
+    }
+
+    #[test]
+    fn test_enospc_discards_reserved_windows() {
+        let (mut dev, mut fs) = setup();
+        // 流式写入留下 inode 窗口，小文件留下共享的局部性窗口
+        let (a, _) = mkfile_with_ino(&mut dev, &mut fs, "/a", None, None).unwrap();
+        write_file(&mut dev, &mut fs, "/a", 0, &[1u8; 20 * 1024]).unwrap();
+        mkfile(&mut dev, &mut fs, "/small", Some(&[2u8; 100]), None).unwrap();
+        write_file(&mut dev, &mut fs, "/small", 0, &[2u8; 2048]).unwrap();
+        let window = fs.mballoc.inode_pa(a).expect("no inode window").len;
+        assert!(window > 0);
+
+        // 写满文件系统：剩余空间只够拿到被预留的窗口时，分配器必须丢弃窗口再重试
+        mkfile(&mut dev, &mut fs, "/fill", None, None).unwrap();
+        let mut off = 0u64;
+        for chunk in [256 * 1024usize, 1024] {
+            let data = alloc::vec![3u8; chunk];
+            while write_file(&mut dev, &mut fs, "/fill", off, &data).is_ok() {
+                off += chunk as u64;
+            }
+        }
+        assert!(fs.mballoc.inode_pa(a).is_none());
+        assert!(fs.superblock.free_blocks_count() < window as u64);
+        assert_eq!(read_file(&mut dev, &mut fs, "/a").unwrap().unwrap(), alloc::vec![1u8; 20 * 1024]);
+    }
+}
@@ -0,0 +1,370 @@
+//! inode 元数据的查询与修改
+//!
+//! stat/lstat 把 inode 整理成与 statx 类似的 [`Stat`]；chmod、chown、utimens 修改权限位、
+//! 属主和时间戳并更新 ctime；readlink 读取符号链接本身的目标。
+//! 除 lstat 和 readlink 外，路径最后一级是符号链接时都会跟随到目标。
+
+use crate::ext4_backend::acl::*;
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::config::*;
+use crate::ext4_backend::dir::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::file::*;
+use crate::ext4_backend::loopfile::*;
+use crate::ext4_backend::superblock::*;
+use crate::ext4_backend::time::*;
+use alloc::vec::Vec;
+
+/// inode 元数据（对应 statx 的结果）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    /// inode 号
+    pub ino: u32,
+    /// 文件类型和权限位
+    pub mode: u16,
+    /// 硬链接数
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// 文件大小（字节）
+    pub size: u64,
+    /// 占用的空间，以 512 字节为单位（含 extent 树等元数据块）
+    pub blocks: u64,
+    /// 文件系统块大小
+    pub blksize: u32,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    /// 创建时间；小 inode 没有这个字段
+    pub crtime: Option<Timespec>,
+    /// inode 代数（i_generation）
+    pub generation: u32,
+    /// inode 标志（i_flags）
+    pub flags: u32,
+}
+
+impl Stat {
+    pub fn from_inode(fs: &Ext4FileSystem, ino: u32, inode: &Ext4Inode) -> Self {
+        // 没有 huge_file 特性时 i_blocks 只有低 32 位；带 HUGE_FILE 标志时以文件系统块为单位
+        let blocks = if !fs
+            .superblock
+            .has_feature_ro_compat(Ext4Superblock::EXT4_FEATURE_RO_COMPAT_HUGE_FILE)
+        {
+            inode.i_blocks_lo as u64
+        } else if inode.i_flags & Ext4Inode::EXT4_HUGE_FILE_FL != 0 {
+            inode.blocks_count() * (fs.block_size() as u64 / 512)
+        } else {
+            inode.blocks_count()
+        };
+        Self {
+            ino,
+            mode: inode.i_mode,
+            nlink: inode.i_links_count as u32,
+            uid: inode.uid(),
+            gid: inode.gid(),
+            size: inode.size(),
+            blocks,
+            blksize: fs.block_size() as u32,
+            atime: inode.atime(),
+            mtime: inode.mtime(),
+            ctime: inode.ctime(),
+            crtime: inode.crtime(),
+            generation: inode.i_generation,
+            flags: inode.i_flags,
+        }
+    }
+
+    /// 文件类型位（S_IFMT 部分）
+    pub fn file_type(&self) -> u16 {
+        self.mode & Ext4Inode::S_IFMT
+    }
+}
+
+/// utimens 中单个时间戳的修改方式（对应 UTIME_NOW / UTIME_OMIT）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeUpdate {
+    /// 设为当前时间
+    Now,
+    /// 保持不变
+    Omit,
+    /// 设为指定时间
+    Set(Timespec),
+}
+
+/// 解析路径；最后一级是符号链接时跟随到目标
+fn lookup_follow<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    path: &str,
+) -> BlockDevResult<(u32, Ext4Inode)> {
+    let mut path = split_paren_child_and_tranlatevalid(path);
+    for _ in 0..=MAX_SYMLINK_FOLLOW {
+        let (ino, mut inode) = lookup_nofollow(fs, block_dev, &path)?;
+        if !inode.is_symlink() {
+            return Ok((ino, inode));
+        }
+        let target = read_symlink_target(block_dev, fs, ino, &mut inode)?;
+        let target = core::str::from_utf8(&target).map_err(|_| BlockDevError::Corrupted)?;
+        path = resolve_symlink_path(&path, target);
+    }
+    Err(BlockDevError::InvalidInput)
+}
+
+/// 解析路径，不跟随最后一级符号链接
+fn lookup_nofollow<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    path: &str,
+) -> BlockDevResult<(u32, Ext4Inode)> {
+    let path = split_paren_child_and_tranlatevalid(path);
+    get_file_inode(fs, block_dev, &path)?.ok_or(BlockDevError::NotFound)
+}
+
+/// 查询路径指向的文件（跟随符号链接）的元数据
+pub fn stat<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+) -> BlockDevResult<Stat> {
+    let (ino, inode) = lookup_follow(fs, block_dev, path)?;
+    Ok(Stat::from_inode(fs, ino, &inode))
+}
+
+/// 查询路径本身的元数据；是符号链接时返回链接自己的信息
+pub fn lstat<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+) -> BlockDevResult<Stat> {
+    let (ino, inode) = lookup_nofollow(fs, block_dev, path)?;
+    Ok(Stat::from_inode(fs, ino, &inode))
+}
+
+/// 按 inode 号查询元数据
+pub fn stat_ino<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    ino: u32,
+) -> BlockDevResult<Stat> {
+    let inode = fs.get_inode_by_num(block_dev, ino)?;
+    Ok(Stat::from_inode(fs, ino, &inode))
+}
+
+/// 读取符号链接本身的目标，不跟随最后一级链接；不是符号链接时返回 InvalidInput
+pub fn readlink<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+) -> BlockDevResult<Vec<u8>> {
+    let (ino, mut inode) = lookup_nofollow(fs, block_dev, path)?;
+    if !inode.is_symlink() {
+        return Err(BlockDevError::InvalidInput);
+    }
+    let target = read_symlink_target(block_dev, fs, ino, &mut inode)?;
+    fs.touch_atime(block_dev, ino);
+    Ok(target)
+}
+
+/// 修改权限位（低 12 位，含 setuid/setgid/sticky），文件类型不变；有访问 ACL 时同步改写 ACL
+pub fn chmod<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+    mode: u16,
+) -> BlockDevResult<()> {
+    let (ino, _) = lookup_follow(fs, block_dev, path)?;
+    fs.journaled(block_dev, EXT4_DATA_TRANS_BLOCKS, |fs, block_dev| {
+        do_chmod(fs, block_dev, ino, mode)
+    })?
+}
+
+fn do_chmod<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    ino: u32,
+    mode: u16,
+) -> BlockDevResult<()> {
+    let mode = mode & !Ext4Inode::S_IFMT;
+    if let Some(mut acl) = get_acl(fs, block_dev, ino, AclType::Access)? {
+        acl.chmod(mode);
+        set_acl(fs, block_dev, ino, AclType::Access, Some(&acl))?;
+    }
+    let now = fs.now();
+    fs.modify_inode(block_dev, ino, |inode| {
+        inode.i_mode = (inode.i_mode & Ext4Inode::S_IFMT) | mode;
+        inode.set_ctime_ts(now);
+    })?;
+    fs.fast_commit.track_inode(ino, None);
+    Ok(())
+}
+
+/// 修改属主和属组（None 表示不变）；同 POSIX，非目录改属主后清除 setuid 位，
+/// 以及带组执行权限时的 setgid 位
+pub fn chown<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+    uid: Option<u32>,
+    gid: Option<u32>,
+) -> BlockDevResult<()> {
+    let (ino, _) = lookup_follow(fs, block_dev, path)?;
+    fs.journaled(block_dev, EXT4_INODE_TRANS_BLOCKS, |fs, block_dev| {
+        let now = fs.now();
+        fs.modify_inode(block_dev, ino, |inode| {
+            if let Some(uid) = uid {
+                inode.set_uid(uid);
+            }
+            if let Some(gid) = gid {
+                inode.set_gid(gid);
+            }
+            if (uid.is_some() || gid.is_some()) && !inode.is_dir() {
+                inode.i_mode &= !Ext4Inode::S_ISUID;
+                if inode.i_mode & Ext4Inode::S_IXGRP != 0 {
+                    inode.i_mode &= !Ext4Inode::S_ISGID;
+                }
+            }
+            inode.set_ctime_ts(now);
+        })?;
+        fs.fast_commit.track_inode(ino, None);
+        Ok(())
+    })?
+}
+
+/// 修改 atime 和 mtime，ctime 更新为当前时间；两个都是 Omit 时什么也不做
+pub fn utimens<B: BlockDevice>(
+    block_dev: &mut Jbd2Dev<B>,
+    fs: &mut Ext4FileSystem,
+    path: &str,
+    atime: TimeUpdate,
+    mtime: TimeUpdate,
+) -> BlockDevResult<()> {
+    let (ino, _) = lookup_follow(fs, block_dev, path)?;
+    if atime == TimeUpdate::Omit && mtime == TimeUpdate::Omit {
+        return Ok(());
+    }
+    fs.journaled(block_dev, EXT4_INODE_TRANS_BLOCKS, |fs, block_dev| {
+        let now = fs.now();
+        let resolve = |update| match update {
+            TimeUpdate::Now => Some(now),
+            TimeUpdate::Omit => None,
+            TimeUpdate::Set(ts) => Some(ts),
+        };
+        let (atime, mtime) = (resolve(atime), resolve(mtime));
+        fs.modify_inode(block_dev, ino, |inode| {
+            if let Some(ts) = atime {
+                inode.set_atime_ts(ts);
+            }
+            if let Some(ts) = mtime {
+                inode.set_mtime_ts(ts);
+            }
+            inode.set_ctime_ts(now);
+        })?;
+        fs.fast_commit.track_inode(ino, None);
+        Ok(())
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+    use alloc::boxed::Box;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    struct TestClock(Rc<Cell<i64>>);
+
+    impl TimeSource for TestClock {
+        fn now(&self) -> Timespec {
+            Timespec::new(self.0.get(), 0)
+        }
+    }
+
+    #[test]
+    fn test_stat_and_symlinks() {
+        let clock = Rc::new(Cell::new(1000));
+        let (mut dev, mut fs) = setup();
+        fs.time_source = Box::new(TestClock(clock.clone()));
+        mkfile(&mut dev, &mut fs, "/a", Some(&[7u8; 3000]), None).unwrap();
+        create_symbol_link(&mut dev, &mut fs, "/a", "/l").unwrap();
+
+        let a = stat(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!(a.file_type(), Ext4Inode::S_IFREG);
+        assert_eq!((a.size, a.nlink, a.blksize), (3000, 1, 1024));
+        assert_eq!(a.blocks, 6);
+        assert_eq!(a.mtime, Timespec::new(1000, 0));
+
+        // stat 跟随链接，lstat 不跟随
+        assert_eq!(stat(&mut dev, &mut fs, "/l").unwrap(), a);
+        let l = lstat(&mut dev, &mut fs, "/l").unwrap();
+        assert_eq!(l.file_type(), Ext4Inode::S_IFLNK);
+        assert_eq!(l.size, 2);
+        assert_eq!(readlink(&mut dev, &mut fs, "/l").unwrap(), b"/a");
+        assert_eq!(readlink(&mut dev, &mut fs, "/a"), Err(BlockDevError::InvalidInput));
+        assert_eq!(stat(&mut dev, &mut fs, "/missing"), Err(BlockDevError::NotFound));
+        assert_eq!(stat_ino(&mut dev, &mut fs, a.ino).unwrap(), a);
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_chmod_chown_utimens() {
+        let clock = Rc::new(Cell::new(1000));
+        let (mut dev, mut fs) = setup();
+        fs.time_source = Box::new(TestClock(clock.clone()));
+        mkfile(&mut dev, &mut fs, "/a", Some(b"x"), None).unwrap();
+
+        clock.set(2000);
+        chmod(&mut dev, &mut fs, "/a", 0o6755).unwrap();
+        let a = stat(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!(a.mode, Ext4Inode::S_IFREG | 0o6755);
+        assert_eq!(a.ctime, Timespec::new(2000, 0));
+
+        // 改属主清除 setuid，组可执行时同时清除 setgid
+        clock.set(3000);
+        chown(&mut dev, &mut fs, "/a", Some(100_000), None).unwrap();
+        let a = stat(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!((a.uid, a.gid, a.mode), (100_000, 0, Ext4Inode::S_IFREG | 0o755));
+        assert_eq!(a.ctime, Timespec::new(3000, 0));
+        chown(&mut dev, &mut fs, "/a", None, Some(70_000)).unwrap();
+        assert_eq!(stat(&mut dev, &mut fs, "/a").unwrap().gid, 70_000);
+
+        clock.set(4000);
+        let past = Timespec::new(-5, 500);
+        utimens(&mut dev, &mut fs, "/a", TimeUpdate::Set(past), TimeUpdate::Omit).unwrap();
+        let a = stat(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!((a.atime, a.mtime, a.ctime), (past, Timespec::new(1000, 0), Timespec::new(4000, 0)));
+        clock.set(5000);
+        utimens(&mut dev, &mut fs, "/a", TimeUpdate::Omit, TimeUpdate::Now).unwrap();
+        let a = stat(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!((a.atime, a.mtime), (past, Timespec::new(5000, 0)));
+        // 两个都是 Omit 时 ctime 不变
+        clock.set(6000);
+        utimens(&mut dev, &mut fs, "/a", TimeUpdate::Omit, TimeUpdate::Omit).unwrap();
+        assert_eq!(stat(&mut dev, &mut fs, "/a").unwrap().ctime, Timespec::new(5000, 0));
+        fs.umount(&mut dev).unwrap();
+    }
+
+    #[test]
+    fn test_chmod_updates_access_acl() {
+        let clock = Rc::new(Cell::new(1000));
+        let (mut dev, mut fs) = setup();
+        fs.time_source = Box::new(TestClock(clock.clone()));
+        mkfile(&mut dev, &mut fs, "/a", Some(b"x"), None).unwrap();
+        let ino = stat(&mut dev, &mut fs, "/a").unwrap().ino;
+        let mut acl = Acl::from_mode(0o640);
+        acl.entries.insert(1, AclEntry { tag: AclTag::User(1000), perm: 7 });
+        acl.entries.insert(3, AclEntry { tag: AclTag::Mask, perm: 7 });
+        set_acl(&mut fs, &mut dev, ino, AclType::Access, Some(&acl)).unwrap();
+
+        chmod(&mut dev, &mut fs, "/a", 0o604).unwrap();
+        let acl = get_acl(&mut fs, &mut dev, ino, AclType::Access).unwrap().unwrap();
+        let perm = |tag| acl.entries.iter().find(|e| e.tag == tag).unwrap().perm;
+        assert_eq!((perm(AclTag::UserObj), perm(AclTag::Mask), perm(AclTag::Other)), (6, 0, 4));
+        // 没有 MASK 时才改 GROUP_OBJ
+        assert_eq!(perm(AclTag::GroupObj), 4);
+        assert_eq!(stat(&mut dev, &mut fs, "/a").unwrap().mode & 0o7777, 0o604);
+        fs.umount(&mut dev).unwrap();
+    }
+}
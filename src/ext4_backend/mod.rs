@@ -1,9 +1,12 @@
+pub mod acl;
 pub mod api;
 pub mod bitmap;
 pub mod bitmap_cache;
 pub mod blockdev;
 pub mod blockgroup_description;
+pub mod blockmap;
 pub mod bmalloc;
+pub mod checksum;
 pub mod config;
 pub mod datablock_cache;
 pub mod dir;
@@ -11,12 +14,23 @@ pub mod disknode;
 pub mod endian;
 pub mod entries;
 pub mod ext4;
+pub mod extent_status;
 pub mod extents_tree;
+pub mod fallocate;
+pub mod fast_commit;
+pub mod fiemap;
 pub mod file;
 pub mod hashtree;
 pub mod error;
+pub mod inline_data;
 pub mod inodetable_cache;
 pub mod jbd2;
 pub mod loopfile;
+pub mod mballoc;
+pub mod metadata;
 pub mod superblock;
+#[cfg(test)]
+pub mod test_support;
+pub mod time;
 pub mod tool;
+pub mod xattr;
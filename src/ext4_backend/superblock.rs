@@ -1,3 +1,4 @@
+use crate::ext4_backend::checksum::crc32c;
 use crate::ext4_backend::config::*;
 use crate::ext4_backend::endian::*;
 use crate::ext4_backend::jbd2::jbdstruct::*;
@@ -287,11 +288,18 @@ impl Ext4Superblock {
 
     /// 获取块组数量
     pub fn block_groups_count(&self) -> u32 {
-        let blocks = self.blocks_count();
+        let blocks = self
+            .blocks_count()
+            .saturating_sub(self.s_first_data_block as u64);
         let blocks_per_group = self.s_blocks_per_group as u64;
         blocks.div_ceil(blocks_per_group) as u32
     }
 
+    /// 主 GDT 起始块号（紧跟超级块所在块，1K 块时为 2，否则为 1）
+    pub fn gdt_start_block(&self) -> u64 {
+        self.s_first_data_block as u64 + 1
+    }
+
     /// 每组块数
     pub fn blocks_per_group(&self) -> u32 {
         self.s_blocks_per_group
@@ -321,7 +329,7 @@ impl Ext4Superblock {
     /// 每个块组的组描述符大小（字节）
     pub fn get_desc_size(&self) -> u16 {
         if self.s_desc_size == 0 {
-            if self.has_feature_compat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT) {
+            if self.has_feature_incompat(Ext4Superblock::EXT4_FEATURE_INCOMPAT_64BIT) {
                 return GROUP_DESC_SIZE;
             } else {
                 return GROUP_DESC_SIZE_OLD;
@@ -362,10 +370,35 @@ impl Ext4Superblock {
         self.has_feature_incompat(Self::EXT4_FEATURE_INCOMPAT_EXTENTS)
     }
 
+    /// 是否启用了 inline_data 特性
+    pub fn has_inline_data(&self) -> bool {
+        self.has_feature_incompat(Self::EXT4_FEATURE_INCOMPAT_INLINE_DATA)
+    }
+
     /// 是否启用了 journal 特性
     pub fn has_journal(&self) -> bool {
         self.has_feature_compat(Self::EXT4_FEATURE_COMPAT_HAS_JOURNAL)
     }
+
+    /// 日志是否在外部设备上：s_journal_inum 为 0，日志设备由 s_journal_uuid 指定
+    pub fn has_external_journal(&self) -> bool {
+        self.has_journal() && self.s_journal_inum == 0
+    }
+
+    /// 是否启用了 metadata_csum（需同时开启 CONFIG_META_CSUM_ENABLE 特性）
+    pub fn has_metadata_csum(&self) -> bool {
+        cfg!(feature = "CONFIG_META_CSUM_ENABLE")
+            && self.has_feature_ro_compat(Self::EXT4_FEATURE_RO_COMPAT_METADATA_CSUM)
+    }
+
+    /// 元数据校验和种子：有 csum_seed 特性时取 s_checksum_seed，否则由 UUID 计算
+    pub fn csum_seed(&self) -> u32 {
+        if self.has_feature_incompat(Self::EXT4_FEATURE_INCOMPAT_CSUM_SEED) {
+            self.s_checksum_seed
+        } else {
+            crc32c(!0, &self.s_uuid)
+        }
+    }
 }
 
 // 文件系统状态常量
@@ -375,6 +408,13 @@ impl Ext4Superblock {
     pub const EXT4_ORPHAN_FS: u16 = 0x0004; // 孤儿正在被恢复
 }
 
+// s_flags 杂项标志常量
+impl Ext4Superblock {
+    pub const EXT2_FLAGS_SIGNED_HASH: u32 = 0x0001; // 目录哈希按有符号 char 计算
+    pub const EXT2_FLAGS_UNSIGNED_HASH: u32 = 0x0002; // 目录哈希按无符号 char 计算
+    pub const EXT2_FLAGS_TEST_FILESYS: u32 = 0x0004; // 测试用文件系统
+}
+
 // 错误处理方式常量
 impl Ext4Superblock {
     pub const EXT4_ERRORS_CONTINUE: u16 = 1; // 继续执行
@@ -397,6 +437,11 @@ impl Ext4Superblock {
     pub const EXT4_DYNAMIC_REV: u32 = 1; // 动态inode大小
 }
 
+// 日志 inode 备份类型
+impl Ext4Superblock {
+    pub const EXT3_JNL_BACKUP_BLOCKS: u8 = 1; // s_jnl_blocks 保存 i_block 与 i_size
+}
+
 // 兼容特性标志
 impl Ext4Superblock {
     // 兼容特性标志
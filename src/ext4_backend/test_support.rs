@@ -0,0 +1,101 @@
+//! 测试辅助：内存块设备与 mkfs + 挂载
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::entries::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::inline_data::*;
+use crate::ext4_backend::loopfile::*;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// 以 512 字节扇区为单位的内存设备；克隆共享同一份数据，可用来模拟崩溃后重新挂载或直接改盘上内容
+#[derive(Clone)]
+pub struct MemDev(pub Rc<RefCell<Vec<u8>>>);
+
+impl MemDev {
+    pub fn new(size: usize) -> Self {
+        Self::filled(size, 0)
+    }
+
+    /// 用指定字节填满设备（检查未初始化的块不会被读出）
+    pub fn filled(size: usize, byte: u8) -> Self {
+        Self(Rc::new(RefCell::new(vec![byte; size])))
+    }
+}
+
+impl BlockDevice for MemDev {
+    fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+        let start = block_id as usize * SECTOR_SIZE;
+        let len = count as usize * SECTOR_SIZE;
+        self.0.borrow_mut()[start..start + len].copy_from_slice(&buffer[..len]);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
+        let start = block_id as usize * SECTOR_SIZE;
+        let len = count as usize * SECTOR_SIZE;
+        buffer[..len].copy_from_slice(&self.0.borrow()[start..start + len]);
+        Ok(())
+    }
+
+    fn open(&mut self) -> BlockDevResult<()> {
+        Ok(())
+    }
+
+    fn close(&mut self) -> BlockDevResult<()> {
+        Ok(())
+    }
+
+    fn total_blocks(&self) -> u64 {
+        (self.0.borrow().len() / SECTOR_SIZE) as u64
+    }
+
+    fn block_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+}
+
+/// 在 mem 上按 block_size 格式化，返回日志设备（尚未挂载）
+pub fn format(mem: MemDev, block_size: usize) -> Jbd2Dev<MemDev> {
+    let mut dev = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, mem, false);
+    mkfs(&mut dev, block_size).unwrap();
+    dev
+}
+
+/// 格式化并挂载
+pub fn setup_fs(mem: MemDev, block_size: usize) -> (Jbd2Dev<MemDev>, Ext4FileSystem) {
+    let mut dev = format(mem, block_size);
+    let fs = mount(&mut dev).unwrap();
+    (dev, fs)
+}
+
+/// 8 MiB、1K 块的文件系统
+pub fn setup() -> (Jbd2Dev<MemDev>, Ext4FileSystem) {
+    setup_fs(MemDev::new(8 << 20), 1024)
+}
+
+/// 列出目录下的名字（含 . 和 ..），按目录块中的顺序
+pub fn list_dir(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, path: &str) -> Vec<String> {
+    let (ino, mut inode) = get_file_inode(fs, dev, path).unwrap().unwrap();
+    let name = |n: &[u8]| String::from_utf8_lossy(n).into_owned();
+    if inode.has_inline_data() {
+        return inline_dir_entries(fs, dev, ino)
+            .unwrap()
+            .iter()
+            .map(|e| name(&e.name[..e.name_len as usize]))
+            .collect();
+    }
+    let blocks = resolve_inode_block_allextend(fs, dev, ino, &mut inode).unwrap();
+    let mut names = Vec::new();
+    for &pblk in blocks.values() {
+        let data = fs.datablock_cache.get_or_load(dev, pblk).unwrap().data.clone();
+        names.extend(DirEntryIterator::new(&data).map(|(e, _)| name(e.name)));
+    }
+    names
+}
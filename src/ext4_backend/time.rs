@@ -0,0 +1,208 @@
+//! inode 时间戳
+//!
+//! 时钟由使用者在挂载时通过 [`TimeSource`] 提供。ext4 的秒字段只有 32 位，
+//! 大 inode 的 `i_*time_extra` 低 2 位是秒数的 epoch 扩展位（可表示到 2446 年），
+//! 高 30 位是纳秒；没有扩展字段的 inode 只保存有符号 32 位秒数。
+
+use alloc::boxed::Box;
+
+/// 时间点：相对 Unix 纪元的秒数和纳秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    pub const fn new(sec: i64, nsec: u32) -> Self {
+        Self { sec, nsec }
+    }
+}
+
+/// 文件系统的时钟来源
+pub trait TimeSource {
+    /// 当前的墙上时间
+    fn now(&self) -> Timespec;
+}
+
+/// 没有时钟时使用：所有时间戳都是 0
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullTimeSource;
+
+impl TimeSource for NullTimeSource {
+    fn now(&self) -> Timespec {
+        Timespec::default()
+    }
+}
+
+/// 读文件时 atime 的更新策略（对应 strictatime / relatime / noatime 挂载选项）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtimePolicy {
+    /// 每次读取都更新
+    Strict,
+    /// atime 不晚于 mtime/ctime 或已超过一天时才更新
+    #[default]
+    Relatime,
+    /// 从不更新
+    Noatime,
+}
+
+impl AtimePolicy {
+    /// 读取 inode 后是否需要把 atime 更新为 `now`
+    pub fn need_update(self, atime: Timespec, mtime: Timespec, ctime: Timespec, now: Timespec) -> bool {
+        match self {
+            AtimePolicy::Strict => atime != now,
+            AtimePolicy::Noatime => false,
+            AtimePolicy::Relatime => {
+                mtime >= atime || ctime >= atime || now.sec - atime.sec >= RELATIME_INTERVAL
+            }
+        }
+    }
+}
+
+/// relatime 下 atime 至少每隔这么多秒更新一次
+const RELATIME_INTERVAL: i64 = 24 * 60 * 60;
+
+/// 挂载选项
+pub struct MountOptions {
+    /// inode 时间戳使用的时钟
+    pub time_source: Box<dyn TimeSource>,
+    /// atime 更新策略
+    pub atime: AtimePolicy,
+}
+
+impl Default for MountOptions {
+    fn default() -> Self {
+        Self {
+            time_source: Box::new(NullTimeSource),
+            atime: AtimePolicy::default(),
+        }
+    }
+}
+
+/// 扩展字段中 epoch 位的掩码
+const EXT4_EPOCH_MASK: u32 = 0x3;
+/// 扩展字段中纳秒的起始位
+const EXT4_NSEC_SHIFT: u32 = 2;
+
+/// 编码成 (秒字段, 扩展字段)
+pub fn encode_time(ts: Timespec) -> (u32, u32) {
+    let epoch = ((ts.sec - ts.sec as i32 as i64) >> 32) as u32 & EXT4_EPOCH_MASK;
+    (ts.sec as u32, epoch | (ts.nsec << EXT4_NSEC_SHIFT))
+}
+
+/// 从 (秒字段, 扩展字段) 解码
+pub fn decode_time(sec: u32, extra: u32) -> Timespec {
+    let epoch = (extra & EXT4_EPOCH_MASK) as i64;
+    Timespec::new(sec as i32 as i64 + (epoch << 32), extra >> EXT4_NSEC_SHIFT)
+}
+
+/// 没有扩展字段时只能保存有符号 32 位秒数，超出范围的时间被截断到边界
+pub fn clamp_legacy_time(ts: Timespec) -> u32 {
+    ts.sec.clamp(i32::MIN as i64, i32::MAX as i64) as i32 as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::test_support::*;
+    use crate::ext4_backend::dir::get_inode_with_num;
+    use crate::ext4_backend::ext4::mount_with_options;
+    use crate::ext4_backend::file::*;
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    /// 测试用时钟，秒数由测试推进
+    struct TestClock(Rc<Cell<i64>>);
+
+    impl TimeSource for TestClock {
+        fn now(&self) -> Timespec {
+            Timespec::new(self.0.get(), 7)
+        }
+    }
+
+    #[test]
+    fn test_time_encoding_roundtrip() {
+        for ts in [
+            Timespec::new(0, 0),
+            Timespec::new(1_700_000_000, 123_456_789),
+            Timespec::new(-1, 999_999_999),
+            Timespec::new(-(1 << 31), 0),
+            // 2038 年之后需要 epoch 位
+            Timespec::new(1 << 31, 5),
+            Timespec::new(4_000_000_000, 1),
+            Timespec::new((3 << 32) + (1 << 31) - 1, 0),
+        ] {
+            let (sec, extra) = encode_time(ts);
+            assert_eq!(decode_time(sec, extra), ts);
+        }
+        // 旧 inode（扩展字段为 0）的秒数按有符号解释
+        assert_eq!(decode_time(u32::MAX, 0), Timespec::new(-1, 0));
+        assert_eq!(clamp_legacy_time(Timespec::new(1 << 40, 0)), i32::MAX as u32);
+    }
+
+    #[test]
+    fn test_relatime_policy() {
+        let t = |sec| Timespec::new(sec, 0);
+        let policy = AtimePolicy::Relatime;
+        // atime 早于 mtime：更新
+        assert!(policy.need_update(t(100), t(200), t(200), t(300)));
+        // atime 已晚于 mtime/ctime 且未满一天：不更新
+        assert!(!policy.need_update(t(300), t(200), t(200), t(400)));
+        assert!(policy.need_update(t(300), t(200), t(200), t(300 + RELATIME_INTERVAL)));
+        assert!(!AtimePolicy::Noatime.need_update(t(0), t(200), t(200), t(400)));
+        assert!(AtimePolicy::Strict.need_update(t(300), t(200), t(200), t(301)));
+    }
+
+    #[test]
+    fn test_operations_stamp_times() {
+        let t = |sec| Timespec::new(sec, 7);
+        let clock = Rc::new(Cell::new(1000));
+        let mut dev = format(MemDev::new(8 << 20), 1024);
+        let options = MountOptions {
+            time_source: Box::new(TestClock(clock.clone())),
+            atime: AtimePolicy::Relatime,
+        };
+        let mut fs = mount_with_options(&mut dev, options).unwrap();
+        let stat = |fs: &mut _, dev: &mut _, path| get_inode_with_num(fs, dev, path).unwrap().unwrap().1;
+
+        mkfile(&mut dev, &mut fs, "/a", Some(b"hello"), None).unwrap();
+        let a = stat(&mut fs, &mut dev, "/a");
+        assert_eq!((a.atime(), a.mtime(), a.ctime(), a.crtime()), (t(1000), t(1000), t(1000), Some(t(1000))));
+        assert_eq!(stat(&mut fs, &mut dev, "/").mtime(), t(1000));
+
+        clock.set(2000);
+        write_file(&mut dev, &mut fs, "/a", 5, b" world").unwrap();
+        let a = stat(&mut fs, &mut dev, "/a");
+        assert_eq!((a.mtime(), a.ctime(), a.crtime()), (t(2000), t(2000), Some(t(1000))));
+
+        // relatime：atime 不晚于 mtime 时更新，之后一天内不再更新
+        clock.set(3000);
+        read_file(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!(stat(&mut fs, &mut dev, "/a").atime(), t(3000));
+        clock.set(3100);
+        read_file(&mut dev, &mut fs, "/a").unwrap();
+        assert_eq!(stat(&mut fs, &mut dev, "/a").atime(), t(3000));
+
+        clock.set(4000);
+        link(&mut fs, &mut dev, "/b", "/a");
+        let a = stat(&mut fs, &mut dev, "/a");
+        assert_eq!((a.mtime(), a.ctime()), (t(2000), t(4000)));
+        assert_eq!(stat(&mut fs, &mut dev, "/").mtime(), t(4000));
+
+        clock.set(5000);
+        truncate(&mut dev, &mut fs, "/a", 3).unwrap();
+        assert_eq!(stat(&mut fs, &mut dev, "/a").mtime(), t(5000));
+
+        clock.set(6000);
+        unlink(&mut fs, &mut dev, "/b");
+        assert_eq!(stat(&mut fs, &mut dev, "/a").ctime(), t(6000));
+        assert_eq!(stat(&mut fs, &mut dev, "/").ctime(), t(6000));
+
+        clock.set(7000);
+        rename(&mut dev, &mut fs, "/a", "/c").unwrap();
+        let c = stat(&mut fs, &mut dev, "/c");
+        assert_eq!((c.mtime(), c.ctime(), c.crtime()), (t(5000), t(7000), Some(t(1000))));
+        fs.umount(&mut dev).unwrap();
+    }
+}
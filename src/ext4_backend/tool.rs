@@ -92,18 +92,20 @@ pub fn cloc_group_layout(
     group0_inode_table: u32,
     gdt_blocks: u32,
 ) -> BlcokGroupLayout {
+    // 1K 块时块 0 为引导块，块组从 s_first_data_block 开始计数
+    let first_data_block = sb.s_first_data_block;
     if gid == 0 {
         return BlcokGroupLayout {
-            group_start_block: 0,
+            group_start_block: first_data_block as u64,
             group_blcok_bitmap_startblocks: group0_block_bitmap as u64,
             group_inode_bitmap_startblocks: group0_inode_bitmap as u64,
             group_inode_table_startblocks: group0_inode_table as u64,
-            metadata_blocks_in_group: (group0_inode_table + inode_table_blocks),
+            metadata_blocks_in_group: (group0_inode_table + inode_table_blocks) - first_data_block,
         };
     }
 
-    // 普通块组从其起始块开始布置
-    let group_start = gid * blocks_per_group;
+    // 普通块组从其起始块开始布置（按 64 位计算，超过 16TiB 时不会溢出）
+    let group_start = gid as u64 * blocks_per_group as u64 + first_data_block as u64;
 
     // 是否启用 sparse super
     let sparse_feature =
@@ -113,7 +115,7 @@ pub fn cloc_group_layout(
     let has_backup = sparse_feature && need_redundant_backup(gid);
 
     let (block_bitmap, inode_bitmap, inode_table, meta_blocks) = if has_backup {
-        let bb = group_start + 1 + gdt_blocks;
+        let bb = group_start + 1 + gdt_blocks as u64;
         let ib = bb + 1;
         let it = ib + 1;
         let meta = 1 + gdt_blocks + 1 + 1 + inode_table_blocks;
@@ -127,10 +129,10 @@ pub fn cloc_group_layout(
     };
 
     BlcokGroupLayout {
-        group_start_block: group_start as u64,
-        group_blcok_bitmap_startblocks: block_bitmap as u64,
-        group_inode_bitmap_startblocks: inode_bitmap as u64,
-        group_inode_table_startblocks: inode_table as u64,
+        group_start_block: group_start,
+        group_blcok_bitmap_startblocks: block_bitmap,
+        group_inode_bitmap_startblocks: inode_bitmap,
+        group_inode_table_startblocks: inode_table,
         metadata_blocks_in_group: meta_blocks,
     }
 }
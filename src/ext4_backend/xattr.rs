@@ -0,0 +1,926 @@
+//! 扩展属性（xattr）模块
+//!
+//! 属性可存放在 inode 内（i_extra_isize 之后到 inode 末尾）或外部扩展属性块中，
+//! 外部块可被多个 inode 共享（h_refcount 引用计数，按 h_hash 查找内容相同的块）。
+//! 对外提供按 inode 号的 getxattr / setxattr / listxattr / removexattr。
+
+use crate::ext4_backend::blockdev::*;
+use crate::ext4_backend::checksum::*;
+use crate::ext4_backend::disknode::*;
+use crate::ext4_backend::endian::*;
+use crate::ext4_backend::error::*;
+use crate::ext4_backend::ext4::*;
+use crate::ext4_backend::superblock::*;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use log::{debug, error, warn};
+
+/// 扩展属性头部魔数（inode 内与外部块相同）
+pub const EXT4_XATTR_MAGIC: u32 = 0xEA02_0000;
+/// 外部块的最大引用计数，达到后不再被共享
+pub const EXT4_XATTR_REFCOUNT_MAX: u32 = 1024;
+/// 属性名（去掉名字空间前缀后）的最大长度
+pub const EXT4_XATTR_NAME_MAX: usize = 255;
+
+/// setxattr 标志：属性已存在时失败
+pub const XATTR_CREATE: u32 = 0x1;
+/// setxattr 标志：属性不存在时失败
+pub const XATTR_REPLACE: u32 = 0x2;
+
+/// 名字空间索引（e_name_index）
+pub const EXT4_XATTR_INDEX_USER: u8 = 1;
+pub const EXT4_XATTR_INDEX_POSIX_ACL_ACCESS: u8 = 2;
+pub const EXT4_XATTR_INDEX_POSIX_ACL_DEFAULT: u8 = 3;
+pub const EXT4_XATTR_INDEX_TRUSTED: u8 = 4;
+pub const EXT4_XATTR_INDEX_SECURITY: u8 = 6;
+pub const EXT4_XATTR_INDEX_SYSTEM: u8 = 7;
+
+/// 内联数据超出 i_block 的部分存放在该属性中（只能位于 inode 内，不出现在 listxattr 结果里）
+pub const EXT4_XATTR_SYSTEM_DATA: &str = "system.data";
+
+/// 名字空间前缀表：不以 '.' 结尾的是整名匹配（POSIX ACL，磁盘上名字为空），需排在 "system." 之前
+const XATTR_PREFIXES: [(u8, &str); 6] = [
+    (EXT4_XATTR_INDEX_POSIX_ACL_ACCESS, "system.posix_acl_access"),
+    (EXT4_XATTR_INDEX_POSIX_ACL_DEFAULT, "system.posix_acl_default"),
+    (EXT4_XATTR_INDEX_USER, "user."),
+    (EXT4_XATTR_INDEX_TRUSTED, "trusted."),
+    (EXT4_XATTR_INDEX_SECURITY, "security."),
+    (EXT4_XATTR_INDEX_SYSTEM, "system."),
+];
+
+/// 外部块头部（ext4_xattr_header）大小
+const XATTR_BLOCK_HEADER_SIZE: usize = 32;
+/// inode 内头部（ext4_xattr_ibody_header，仅魔数）大小
+const XATTR_IBODY_HEADER_SIZE: usize = 4;
+/// 条目（ext4_xattr_entry，不含名字）大小
+const XATTR_ENTRY_SIZE: usize = 16;
+/// 条目和值的对齐掩码（4 字节对齐）
+const XATTR_ROUND: usize = 3;
+/// 新写入 inode 内属性时使用的最小 i_extra_isize（覆盖 Ext4Inode 的全部扩展字段）
+const XATTR_MIN_EXTRA_ISIZE: u16 = 32;
+
+/// 一个扩展属性：名字空间索引 + 去掉前缀的名字 + 值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ext4Xattr {
+    pub name_index: u8,
+    pub name: Vec<u8>,
+    pub value: Vec<u8>,
+}
+
+impl Ext4Xattr {
+    /// 由完整属性名（如 "user.mime_type"）构造；不认识的名字空间返回 Unsupported
+    pub fn new(full_name: &str, value: &[u8]) -> BlockDevResult<Self> {
+        let (name_index, name) = parse_xattr_name(full_name)?;
+        Ok(Self {
+            name_index,
+            name,
+            value: value.to_vec(),
+        })
+    }
+
+    /// 完整属性名；名字空间索引不认识时返回 None
+    pub fn full_name(&self) -> Option<String> {
+        let (_, prefix) = XATTR_PREFIXES
+            .iter()
+            .find(|(idx, _)| *idx == self.name_index)?;
+        let mut full = String::from(*prefix);
+        full.push_str(&String::from_utf8_lossy(&self.name));
+        Some(full)
+    }
+
+    /// 条目（ext4_xattr_entry + 名字）在磁盘上占用的字节数
+    fn entry_len(&self) -> usize {
+        (XATTR_ENTRY_SIZE + self.name.len() + XATTR_ROUND) & !XATTR_ROUND
+    }
+
+    /// 值在磁盘上占用的字节数（4 字节对齐）
+    fn value_len(&self) -> usize {
+        (self.value.len() + XATTR_ROUND) & !XATTR_ROUND
+    }
+
+    /// 是否与另一属性同名
+    fn same_name(&self, other: &Ext4Xattr) -> bool {
+        self.name_index == other.name_index && self.name == other.name
+    }
+
+    /// 是否为内联数据属性 system.data
+    fn is_system_data(&self) -> bool {
+        self.name_index == EXT4_XATTR_INDEX_SYSTEM && self.name == b"data"
+    }
+}
+
+/// 把完整属性名拆成 (名字空间索引, 名字)
+fn parse_xattr_name(full_name: &str) -> BlockDevResult<(u8, Vec<u8>)> {
+    for &(idx, prefix) in XATTR_PREFIXES.iter() {
+        if !prefix.ends_with('.') {
+            if full_name == prefix {
+                return Ok((idx, Vec::new()));
+            }
+            continue;
+        }
+        if let Some(rest) = full_name.strip_prefix(prefix) {
+            if rest.is_empty() || rest.len() > EXT4_XATTR_NAME_MAX {
+                return Err(BlockDevError::InvalidInput);
+            }
+            return Ok((idx, rest.as_bytes().to_vec()));
+        }
+    }
+    Err(BlockDevError::Unsupported)
+}
+
+/// 条目哈希（ext4_xattr_hash_entry）：名字逐字节移位异或，再按 32 位小端字混入值
+pub fn xattr_entry_hash(name: &[u8], value: &[u8]) -> u32 {
+    let mut hash = 0u32;
+    for &c in name {
+        hash = (hash << 5) ^ (hash >> 27) ^ c as u32;
+    }
+    for chunk in value.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        hash = (hash << 16) ^ (hash >> 16) ^ u32::from_le_bytes(word);
+    }
+    hash
+}
+
+/// 外部块哈希（ext4_xattr_rehash）：混合所有条目哈希，任一条目哈希为 0 时整块哈希为 0
+pub fn xattr_block_hash(attrs: &[Ext4Xattr]) -> u32 {
+    let mut hash = 0u32;
+    for a in attrs {
+        let h = xattr_entry_hash(&a.name, &a.value);
+        if h == 0 {
+            return 0;
+        }
+        hash = (hash << 16) ^ (hash >> 16) ^ h;
+    }
+    hash
+}
+
+/// 属性集合占用的字节数：条目 + 值 + 4 字节结束标记
+fn region_size(attrs: &[Ext4Xattr]) -> usize {
+    attrs
+        .iter()
+        .map(|a| a.entry_len() + a.value_len())
+        .sum::<usize>()
+        + 4
+}
+
+/// 解析一段存储区中的属性：条目从 entries_start 开始，以 4 字节 0 结束，e_value_offs 相对 value_base
+fn parse_region(
+    region: &[u8],
+    entries_start: usize,
+    value_base: usize,
+) -> BlockDevResult<Vec<Ext4Xattr>> {
+    let mut attrs = Vec::new();
+    let mut off = entries_start;
+    loop {
+        if off + 4 > region.len() {
+            return Err(BlockDevError::Corrupted);
+        }
+        if read_u32_le(&region[off..off + 4]) == 0 {
+            break;
+        }
+        let name_len = region[off] as usize;
+        let name_end = off + XATTR_ENTRY_SIZE + name_len;
+        if name_end > region.len() {
+            return Err(BlockDevError::Corrupted);
+        }
+        let value_offs = read_u16_le(&region[off + 2..off + 4]) as usize;
+        let value_inum = read_u32_le(&region[off + 4..off + 8]);
+        let value_size = read_u32_le(&region[off + 8..off + 12]) as usize;
+        if value_inum != 0 {
+            // ea_inode：值存放在独立 inode 中
+            error!("Xattr value stored in inode {value_inum} is not supported");
+            return Err(BlockDevError::Unsupported);
+        }
+        let value_start = value_base + value_offs;
+        if value_size > 0 && value_start + value_size > region.len() {
+            return Err(BlockDevError::Corrupted);
+        }
+        attrs.push(Ext4Xattr {
+            name_index: region[off + 1],
+            name: region[off + XATTR_ENTRY_SIZE..name_end].to_vec(),
+            value: region[value_start..value_start + value_size].to_vec(),
+        });
+        off += (name_end - off + XATTR_ROUND) & !XATTR_ROUND;
+    }
+    Ok(attrs)
+}
+
+/// 把属性写入一段存储区：条目从 entries_start 顺序排列并以 4 字节 0 结束，值从区尾向前紧排。
+/// with_hash 为 false 时 e_hash 写 0（inode 内条目与内核一致不计算哈希）。空间不足返回 false
+fn write_region(
+    region: &mut [u8],
+    entries_start: usize,
+    value_base: usize,
+    attrs: &[Ext4Xattr],
+    with_hash: bool,
+) -> bool {
+    if entries_start + region_size(attrs) > region.len() {
+        return false;
+    }
+    region[entries_start..].fill(0);
+    let mut off = entries_start;
+    let mut value_end = region.len();
+    for a in attrs {
+        let value_offs = if a.value.is_empty() {
+            0
+        } else {
+            value_end -= a.value_len();
+            region[value_end..value_end + a.value.len()].copy_from_slice(&a.value);
+            value_end - value_base
+        };
+        let hash = if with_hash {
+            xattr_entry_hash(&a.name, &a.value)
+        } else {
+            0
+        };
+        region[off] = a.name.len() as u8;
+        region[off + 1] = a.name_index;
+        write_u16_le(value_offs as u16, &mut region[off + 2..off + 4]);
+        write_u32_le(0, &mut region[off + 4..off + 8]);
+        write_u32_le(a.value.len() as u32, &mut region[off + 8..off + 12]);
+        write_u32_le(hash, &mut region[off + 12..off + 16]);
+        region[off + XATTR_ENTRY_SIZE..off + XATTR_ENTRY_SIZE + a.name.len()].copy_from_slice(&a.name);
+        off += a.entry_len();
+    }
+    true
+}
+
+/// 外部块内条目按 (名字空间索引, 名字长度, 名字) 排序，内核在块内按此顺序查找
+fn sort_block_attrs(attrs: &mut [Ext4Xattr]) {
+    attrs.sort_by(|a, b| {
+        (a.name_index, a.name.len(), &a.name).cmp(&(b.name_index, b.name.len(), &b.name))
+    });
+}
+
+/// inode 内扩展属性区在原始 inode 字节中的起始偏移；i_extra_isize 为 0 或不合法时返回 None
+fn ibody_start(raw: &[u8]) -> Option<usize> {
+    let base = Ext4Inode::GOOD_OLD_INODE_SIZE as usize;
+    if raw.len() <= base {
+        return None;
+    }
+    let extra_isize = read_u16_le(&raw[base..base + 2]) as usize;
+    let start = base + extra_isize;
+    if extra_isize == 0 || extra_isize & XATTR_ROUND != 0 || start + XATTR_IBODY_HEADER_SIZE + 4 > raw.len() {
+        return None;
+    }
+    Some(start)
+}
+
+/// 读取 inode 内的扩展属性
+fn read_ibody(raw: &[u8]) -> BlockDevResult<Vec<Ext4Xattr>> {
+    let Some(start) = ibody_start(raw) else {
+        return Ok(Vec::new());
+    };
+    if read_u32_le(&raw[start..start + 4]) != EXT4_XATTR_MAGIC {
+        return Ok(Vec::new());
+    }
+    parse_region(&raw[start..], XATTR_IBODY_HEADER_SIZE, XATTR_IBODY_HEADER_SIZE)
+}
+
+/// inode 内可用于属性的字节数；i_extra_isize 为 0 时按扩展到 want_extra_isize 计算
+fn ibody_capacity(raw: &[u8], want_extra_isize: u16) -> usize {
+    let base = Ext4Inode::GOOD_OLD_INODE_SIZE as usize;
+    if raw.len() <= base {
+        return 0;
+    }
+    let extra_isize = match read_u16_le(&raw[base..base + 2]) {
+        0 => want_extra_isize,
+        n => n,
+    } as usize;
+    let start = base + extra_isize;
+    if extra_isize & XATTR_ROUND != 0 || start + XATTR_IBODY_HEADER_SIZE >= raw.len() {
+        return 0;
+    }
+    raw.len() - start - XATTR_IBODY_HEADER_SIZE
+}
+
+/// 改写 inode 内扩展属性区（调用方已确认放得下）；没有属性时清空魔数
+fn write_ibody(raw: &mut [u8], want_extra_isize: u16, attrs: &[Ext4Xattr]) {
+    let base = Ext4Inode::GOOD_OLD_INODE_SIZE as usize;
+    if raw.len() <= base {
+        return;
+    }
+    if read_u16_le(&raw[base..base + 2]) == 0 {
+        if attrs.is_empty() {
+            return;
+        }
+        write_u16_le(want_extra_isize, &mut raw[base..base + 2]);
+    }
+    let Some(start) = ibody_start(raw) else {
+        return;
+    };
+    let region = &mut raw[start..];
+    if attrs.is_empty() {
+        region.fill(0);
+        return;
+    }
+    if write_region(region, XATTR_IBODY_HEADER_SIZE, XATTR_IBODY_HEADER_SIZE, attrs, false) {
+        write_u32_le(EXT4_XATTR_MAGIC, &mut region[0..4]);
+    }
+}
+
+/// 生成外部扩展属性块内容（引用计数为 1，校验和由调用方填写）
+fn build_xattr_block(block: &mut [u8], attrs: &[Ext4Xattr]) -> bool {
+    block.fill(0);
+    if !write_region(block, XATTR_BLOCK_HEADER_SIZE, 0, attrs, true) {
+        return false;
+    }
+    write_u32_le(EXT4_XATTR_MAGIC, &mut block[0..4]);
+    write_u32_le(1, &mut block[4..8]);
+    write_u32_le(1, &mut block[8..12]);
+    write_u32_le(xattr_block_hash(attrs), &mut block[12..16]);
+    true
+}
+
+/// 扩展属性块共享索引（对应内核 mbcache）：h_hash -> 块号，只记录本次挂载期间读写过的块
+#[derive(Debug, Default)]
+pub struct XattrBlockCache {
+    by_hash: BTreeMap<u32, Vec<u64>>,
+}
+
+impl XattrBlockCache {
+    pub fn new() -> Self {
+        Self {
+            by_hash: BTreeMap::new(),
+        }
+    }
+
+    /// 记录一个块；哈希为 0 的块不参与共享
+    pub fn insert(&mut self, hash: u32, block: u64) {
+        if hash == 0 {
+            return;
+        }
+        let blocks = self.by_hash.entry(hash).or_default();
+        if !blocks.contains(&block) {
+            blocks.push(block);
+        }
+    }
+
+    /// 移除一个块（块被释放或内容改变时）
+    pub fn remove(&mut self, hash: u32, block: u64) {
+        if let Some(blocks) = self.by_hash.get_mut(&hash) {
+            blocks.retain(|&b| b != block);
+            if blocks.is_empty() {
+                self.by_hash.remove(&hash);
+            }
+        }
+    }
+
+    /// 哈希相同的候选块
+    pub fn candidates(&self, hash: u32) -> Vec<u64> {
+        self.by_hash.get(&hash).cloned().unwrap_or_default()
+    }
+}
+
+/// 外部块头部中的 (h_refcount, h_hash)
+struct XattrBlockHeader {
+    refcount: u32,
+    hash: u32,
+}
+
+/// 读取并校验外部扩展属性块，返回头部和全部属性，并登记到共享索引
+fn load_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    block_nr: u64,
+) -> BlockDevResult<(XattrBlockHeader, Vec<Ext4Xattr>)> {
+    let seed = fs
+        .superblock
+        .has_metadata_csum()
+        .then(|| fs.superblock.csum_seed());
+    let data = &fs.datablock_cache.get_or_load(block_dev, block_nr)?.data;
+    if read_u32_le(&data[0..4]) != EXT4_XATTR_MAGIC || read_u32_le(&data[8..12]) != 1 {
+        error!("Xattr block {block_nr} has bad magic or h_blocks");
+        return Err(BlockDevError::Corrupted);
+    }
+    if let Some(seed) = seed
+        && !verify_xattr_block_csum(seed, block_nr, data)
+    {
+        error!("Xattr block {block_nr} checksum mismatch");
+        return Err(BlockDevError::ChecksumError);
+    }
+    let header = XattrBlockHeader {
+        refcount: read_u32_le(&data[4..8]),
+        hash: read_u32_le(&data[12..16]),
+    };
+    let attrs = parse_region(data, XATTR_BLOCK_HEADER_SIZE, 0)?;
+    if header.refcount < EXT4_XATTR_REFCOUNT_MAX {
+        fs.xattr_cache.insert(header.hash, block_nr);
+    }
+    Ok((header, attrs))
+}
+
+/// 修改外部块的引用计数并重算校验和
+fn set_block_refcount<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    block_nr: u64,
+    refcount: u32,
+) -> BlockDevResult<()> {
+    let seed = fs
+        .superblock
+        .has_metadata_csum()
+        .then(|| fs.superblock.csum_seed());
+    fs.datablock_cache.modify(block_dev, block_nr, |data| {
+        write_u32_le(refcount, &mut data[4..8]);
+        if let Some(seed) = seed {
+            set_xattr_block_csum(seed, block_nr, data);
+        }
+    })
+}
+
+/// 放弃对外部块的一次引用：引用计数减到 0 时释放块
+fn put_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    block_nr: u64,
+) -> BlockDevResult<()> {
+    let (header, _) = load_xattr_block(fs, block_dev, block_nr)?;
+    if header.refcount <= 1 {
+        fs.xattr_cache.remove(header.hash, block_nr);
+        fs.datablock_cache.invalidate(block_nr);
+        fs.free_block(block_dev, block_nr)?;
+        debug!("Xattr block {block_nr} freed");
+    } else {
+        set_block_refcount(fs, block_dev, block_nr, header.refcount - 1)?;
+        if header.refcount - 1 < EXT4_XATTR_REFCOUNT_MAX {
+            fs.xattr_cache.insert(header.hash, block_nr);
+        }
+    }
+    Ok(())
+}
+
+/// 更新 inode 的 i_file_acl，并按块数变化调整 i_blocks（512 字节为单位）
+fn set_inode_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    block_nr: u64,
+    delta_blocks: i64,
+) -> BlockDevResult<()> {
+    let sectors = delta_blocks * (fs.block_size() / 512) as i64;
+    fs.modify_inode(block_dev, inode_num, |inode| {
+        inode.i_file_acl_lo = block_nr as u32;
+        inode.l_i_file_acl_high = (block_nr >> 32) as u16;
+        let blocks = (inode.blocks_count() as i64 + sectors).max(0) as u64;
+        inode.i_blocks_lo = blocks as u32;
+        inode.l_i_blocks_high = (blocks >> 32) as u16;
+    })
+}
+
+/// 在共享索引中查找内容与 attrs 相同、仍可共享的块
+fn find_shared_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    hash: u32,
+    attrs: &[Ext4Xattr],
+    exclude: u64,
+) -> Option<u64> {
+    for block_nr in fs.xattr_cache.candidates(hash) {
+        if block_nr == exclude {
+            continue;
+        }
+        match load_xattr_block(fs, block_dev, block_nr) {
+            Ok((header, found))
+                if header.hash == hash && header.refcount < EXT4_XATTR_REFCOUNT_MAX && found == attrs =>
+            {
+                return Some(block_nr);
+            }
+            Ok(_) => {}
+            Err(_) => fs.xattr_cache.remove(hash, block_nr),
+        }
+    }
+    None
+}
+
+/// 把外部块中的属性集合改为 attrs：优先共享内容相同的块，独占的旧块原地改写，
+/// 旧块仍被其他 inode 引用时写时复制到新块
+fn store_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    old_block: u64,
+    attrs: &[Ext4Xattr],
+) -> BlockDevResult<()> {
+    if attrs.is_empty() {
+        if old_block != 0 {
+            put_xattr_block(fs, block_dev, old_block)?;
+            set_inode_xattr_block(fs, block_dev, inode_num, 0, -1)?;
+        }
+        return Ok(());
+    }
+
+    let mut image = vec![0u8; fs.block_size()];
+    if !build_xattr_block(&mut image, attrs) {
+        return Err(BlockDevError::NoSpace);
+    }
+    let hash = read_u32_le(&image[12..16]);
+    let new_ref = if old_block == 0 { 1 } else { 0 };
+
+    if let Some(shared) = find_shared_block(fs, block_dev, hash, attrs, old_block) {
+        let (header, _) = load_xattr_block(fs, block_dev, shared)?;
+        set_block_refcount(fs, block_dev, shared, header.refcount + 1)?;
+        if header.refcount + 1 >= EXT4_XATTR_REFCOUNT_MAX {
+            fs.xattr_cache.remove(hash, shared);
+        }
+        if old_block != 0 {
+            put_xattr_block(fs, block_dev, old_block)?;
+        }
+        debug!("Inode {inode_num} shares xattr block {shared}");
+        return set_inode_xattr_block(fs, block_dev, inode_num, shared, new_ref);
+    }
+
+    let seed = fs
+        .superblock
+        .has_metadata_csum()
+        .then(|| fs.superblock.csum_seed());
+    if old_block != 0 {
+        let (header, _) = load_xattr_block(fs, block_dev, old_block)?;
+        if header.refcount == 1 {
+            fs.xattr_cache.remove(header.hash, old_block);
+            fs.datablock_cache.modify(block_dev, old_block, |data| {
+                data.copy_from_slice(&image);
+                if let Some(seed) = seed {
+                    set_xattr_block_csum(seed, old_block, data);
+                }
+            })?;
+            fs.xattr_cache.insert(hash, old_block);
+            return Ok(());
+        }
+    }
+
+    let block_nr = fs.alloc_block(block_dev)?;
+    fs.datablock_cache.modify_new(block_nr, |data| {
+        data.copy_from_slice(&image);
+        if let Some(seed) = seed {
+            set_xattr_block_csum(seed, block_nr, data);
+        }
+    });
+    fs.xattr_cache.insert(hash, block_nr);
+    if old_block != 0 {
+        put_xattr_block(fs, block_dev, old_block)?;
+    }
+    set_inode_xattr_block(fs, block_dev, inode_num, block_nr, new_ref)
+}
+
+/// 一个 inode 当前的全部扩展属性
+struct InodeXattrs {
+    /// 原始 inode 字节
+    raw: Vec<u8>,
+    /// inode 内的属性
+    ibody: Vec<Ext4Xattr>,
+    /// 外部块号（0 表示没有）
+    block_nr: u64,
+    /// 外部块内的属性
+    block: Vec<Ext4Xattr>,
+}
+
+/// 读取 inode 的全部扩展属性
+fn load_xattrs<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<InodeXattrs> {
+    let raw = fs.get_inode_raw(block_dev, inode_num)?;
+    let ibody = read_ibody(&raw)?;
+    let block_nr = Ext4Inode::from_disk_bytes(&raw).file_acl();
+    let block = if block_nr != 0 {
+        load_xattr_block(fs, block_dev, block_nr)?.1
+    } else {
+        Vec::new()
+    };
+    Ok(InodeXattrs {
+        raw,
+        ibody,
+        block_nr,
+        block,
+    })
+}
+
+/// 读取扩展属性的值；属性不存在时返回 None
+pub fn getxattr<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &str,
+) -> BlockDevResult<Option<Vec<u8>>> {
+    let key = Ext4Xattr::new(name, &[])?;
+    let xattrs = load_xattrs(fs, block_dev, inode_num)?;
+    Ok(xattrs
+        .ibody
+        .into_iter()
+        .chain(xattrs.block)
+        .find(|a| a.same_name(&key))
+        .map(|a| a.value))
+}
+
+/// 列出 inode 的全部扩展属性名（inode 内的在前），跳过不认识的名字空间
+pub fn listxattr<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<Vec<String>> {
+    let xattrs = load_xattrs(fs, block_dev, inode_num)?;
+    Ok(xattrs
+        .ibody
+        .iter()
+        .chain(xattrs.block.iter())
+        .filter(|a| !a.is_system_data())
+        .filter_map(|a| a.full_name())
+        .collect())
+}
+
+/// 设置扩展属性。flags 可取 XATTR_CREATE（已存在时返回 AlreadyExists）
+/// 或 XATTR_REPLACE（不存在时返回 NotFound）；优先放在 inode 内，放不下时放入外部块
+pub fn setxattr<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &str,
+    value: &[u8],
+    flags: u32,
+) -> BlockDevResult<()> {
+    // 扩展属性没有对应的 fast commit 记录，本次操作只能完整提交
+    fs.fast_commit.mark_ineligible();
+    let attr = Ext4Xattr::new(name, value)?;
+    let InodeXattrs {
+        raw,
+        mut ibody,
+        block_nr,
+        mut block,
+    } = load_xattrs(fs, block_dev, inode_num)?;
+
+    let in_ibody = ibody.iter().any(|a| a.same_name(&attr));
+    let in_block = block.iter().any(|a| a.same_name(&attr));
+    if flags & XATTR_CREATE != 0 && (in_ibody || in_block) {
+        return Err(BlockDevError::AlreadyExists);
+    }
+    if flags & XATTR_REPLACE != 0 && !in_ibody && !in_block {
+        return Err(BlockDevError::NotFound);
+    }
+    ibody.retain(|a| !a.same_name(&attr));
+    block.retain(|a| !a.same_name(&attr));
+
+    let want_extra_isize = fs.superblock.s_want_extra_isize.max(XATTR_MIN_EXTRA_ISIZE);
+    let mut ibody_changed = in_ibody;
+    let mut block_changed = in_block;
+    ibody.push(attr);
+    if region_size(&ibody) <= ibody_capacity(&raw, want_extra_isize) {
+        ibody_changed = true;
+    } else {
+        let attr = ibody.pop().ok_or(BlockDevError::Corrupted)?;
+        block.push(attr);
+        sort_block_attrs(&mut block);
+        if XATTR_BLOCK_HEADER_SIZE + region_size(&block) > fs.block_size() {
+            return Err(BlockDevError::NoSpace);
+        }
+        block_changed = true;
+    }
+
+    if ibody_changed {
+        fs.modify_inode_raw(block_dev, inode_num, |raw| {
+            write_ibody(raw, want_extra_isize, &ibody)
+        })?;
+    }
+    if block_changed {
+        store_xattr_block(fs, block_dev, inode_num, block_nr, &block)?;
+    }
+    if !fs
+        .superblock
+        .has_feature_compat(Ext4Superblock::EXT4_FEATURE_COMPAT_EXT_ATTR)
+    {
+        fs.superblock.s_feature_compat |= Ext4Superblock::EXT4_FEATURE_COMPAT_EXT_ATTR;
+    }
+    debug!("setxattr inode={inode_num} name={name} len={}", value.len());
+    Ok(())
+}
+
+/// 只在 inode 内设置属性（内联数据的 system.data 必须位于 inode 内），inode 内放不下时返回 NoSpace
+pub fn set_ibody_xattr<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &str,
+    value: &[u8],
+) -> BlockDevResult<()> {
+    fs.fast_commit.mark_ineligible();
+    let attr = Ext4Xattr::new(name, value)?;
+    let raw = fs.get_inode_raw(block_dev, inode_num)?;
+    let mut ibody = read_ibody(&raw)?;
+    ibody.retain(|a| !a.same_name(&attr));
+    ibody.push(attr);
+
+    let want_extra_isize = fs.superblock.s_want_extra_isize.max(XATTR_MIN_EXTRA_ISIZE);
+    if region_size(&ibody) > ibody_capacity(&raw, want_extra_isize) {
+        return Err(BlockDevError::NoSpace);
+    }
+    fs.modify_inode_raw(block_dev, inode_num, |raw| {
+        write_ibody(raw, want_extra_isize, &ibody)
+    })?;
+    debug!("set ibody xattr inode={inode_num} name={name} len={}", value.len());
+    Ok(())
+}
+
+/// inode 内还能给属性 name 存放多长的值（该属性现有的值可被覆盖，按 4 字节向下取整）；
+/// 连空值的条目都放不下时返回 None
+pub fn ibody_xattr_free<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &str,
+) -> BlockDevResult<Option<usize>> {
+    let key = Ext4Xattr::new(name, &[])?;
+    let raw = fs.get_inode_raw(block_dev, inode_num)?;
+    let mut ibody = read_ibody(&raw)?;
+    ibody.retain(|a| !a.same_name(&key));
+    ibody.push(key);
+
+    let want_extra_isize = fs.superblock.s_want_extra_isize.max(XATTR_MIN_EXTRA_ISIZE);
+    Ok(ibody_capacity(&raw, want_extra_isize)
+        .checked_sub(region_size(&ibody))
+        .map(|free| free & !XATTR_ROUND))
+}
+
+/// 删除扩展属性；属性不存在时返回 NotFound
+pub fn removexattr<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+    name: &str,
+) -> BlockDevResult<()> {
+    fs.fast_commit.mark_ineligible();
+    let key = Ext4Xattr::new(name, &[])?;
+    let InodeXattrs {
+        mut ibody,
+        block_nr,
+        mut block,
+        ..
+    } = load_xattrs(fs, block_dev, inode_num)?;
+
+    if ibody.iter().any(|a| a.same_name(&key)) {
+        ibody.retain(|a| !a.same_name(&key));
+        let want_extra_isize = fs.superblock.s_want_extra_isize.max(XATTR_MIN_EXTRA_ISIZE);
+        return fs.modify_inode_raw(block_dev, inode_num, |raw| {
+            write_ibody(raw, want_extra_isize, &ibody)
+        });
+    }
+    if block.iter().any(|a| a.same_name(&key)) {
+        block.retain(|a| !a.same_name(&key));
+        return store_xattr_block(fs, block_dev, inode_num, block_nr, &block);
+    }
+    Err(BlockDevError::NotFound)
+}
+
+/// 释放 inode 对外部扩展属性块的引用（删除 inode 时调用），共享块只减少引用计数；
+/// i_file_acl 指向的块不是合法属性块时只清除引用，不释放该块
+pub fn release_xattr_block<B: BlockDevice>(
+    fs: &mut Ext4FileSystem,
+    block_dev: &mut Jbd2Dev<B>,
+    inode_num: u32,
+) -> BlockDevResult<()> {
+    let block_nr = fs.get_inode_by_num(block_dev, inode_num)?.file_acl();
+    if block_nr == 0 {
+        return Ok(());
+    }
+    match put_xattr_block(fs, block_dev, block_nr) {
+        Ok(()) => {}
+        Err(BlockDevError::Corrupted) | Err(BlockDevError::ChecksumError) => {
+            warn!("Inode {inode_num} references invalid xattr block {block_nr}, dropping reference");
+        }
+        Err(e) => return Err(e),
+    }
+    set_inode_xattr_block(fs, block_dev, inode_num, 0, -1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext4_backend::file::*;
+    use crate::ext4_backend::test_support::*;
+
+    fn attr(name: &str, value: &[u8]) -> Ext4Xattr {
+        Ext4Xattr::new(name, value).unwrap()
+    }
+
+    #[test]
+    fn test_xattr_name_prefixes() {
+        assert_eq!(parse_xattr_name("user.foo").unwrap(), (EXT4_XATTR_INDEX_USER, b"foo".to_vec()));
+        assert_eq!(
+            parse_xattr_name("system.posix_acl_access").unwrap(),
+            (EXT4_XATTR_INDEX_POSIX_ACL_ACCESS, Vec::new())
+        );
+        assert_eq!(
+            parse_xattr_name("system.data").unwrap(),
+            (EXT4_XATTR_INDEX_SYSTEM, b"data".to_vec())
+        );
+        assert_eq!(parse_xattr_name("user."), Err(BlockDevError::InvalidInput));
+        assert_eq!(parse_xattr_name("bogus.x"), Err(BlockDevError::Unsupported));
+        assert_eq!(
+            attr("security.selinux", b"x").full_name().as_deref(),
+            Some("security.selinux")
+        );
+    }
+
+    #[test]
+    fn test_block_region_roundtrip() {
+        let mut attrs = vec![
+            attr("user.b", b"hello"),
+            attr("security.capability", &[1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            attr("user.a", b""),
+        ];
+        sort_block_attrs(&mut attrs);
+        assert_eq!(attrs[0].name, b"a");
+
+        let mut block = vec![0u8; 1024];
+        assert!(build_xattr_block(&mut block, &attrs));
+        assert_eq!(read_u32_le(&block[0..4]), EXT4_XATTR_MAGIC);
+        assert_eq!(read_u32_le(&block[12..16]), xattr_block_hash(&attrs));
+        assert_eq!(parse_region(&block, XATTR_BLOCK_HEADER_SIZE, 0).unwrap(), attrs);
+
+        let big = vec![attr("user.big", &[0u8; 1024])];
+        assert!(!build_xattr_block(&mut block, &big));
+    }
+
+    #[test]
+    fn test_ibody_extends_extra_isize() {
+        let mut raw = vec![0u8; 256];
+        assert!(read_ibody(&raw).unwrap().is_empty());
+        assert_eq!(ibody_capacity(&raw, 32), 256 - 160 - 4);
+
+        let attrs = vec![attr("user.k", b"v")];
+        write_ibody(&mut raw, 32, &attrs);
+        assert_eq!(read_u16_le(&raw[128..130]), 32);
+        assert_eq!(read_ibody(&raw).unwrap(), attrs);
+
+        write_ibody(&mut raw, 32, &[]);
+        assert!(read_ibody(&raw).unwrap().is_empty());
+        assert!(raw[160..].iter().all(|&b| b == 0));
+    }
+
+    fn refcount(fs: &mut Ext4FileSystem, dev: &mut Jbd2Dev<MemDev>, block_nr: u64) -> u32 {
+        read_u32_le(&fs.datablock_cache.get_or_load(dev, block_nr).unwrap().data[4..8])
+    }
+
+    #[test]
+    fn test_shared_xattr_block_refcount_and_release() {
+        let (mut dev, mut fs) = setup();
+        let (a, _) = mkfile_with_ino(&mut dev, &mut fs, "/a", None, None).unwrap();
+        let (b, _) = mkfile_with_ino(&mut dev, &mut fs, "/b", None, None).unwrap();
+        let sectors = (fs.block_size() / 512) as u64;
+        let free_before = fs.superblock.free_blocks_count();
+        let blocks_before = fs.get_inode_by_num(&mut dev, a).unwrap().blocks_count();
+
+        // 值放不进 inode，只能进外部块；两个 inode 的属性集合相同，共享同一块
+        let value = vec![0x5a; 600];
+        setxattr(&mut fs, &mut dev, a, "user.big", &value, 0).unwrap();
+        setxattr(&mut fs, &mut dev, b, "user.big", &value, 0).unwrap();
+        let ia = fs.get_inode_by_num(&mut dev, a).unwrap();
+        let ib = fs.get_inode_by_num(&mut dev, b).unwrap();
+        let shared = ia.file_acl();
+        assert_ne!(shared, 0);
+        assert_eq!(ib.file_acl(), shared);
+        assert_eq!(refcount(&mut fs, &mut dev, shared), 2);
+        assert_eq!(ia.blocks_count(), blocks_before + sectors);
+        assert_eq!(ib.blocks_count(), blocks_before + sectors);
+        assert_eq!(fs.superblock.free_blocks_count(), free_before - 1);
+
+        // 一个 inode 删掉属性只减少引用计数，另一个仍能读到
+        removexattr(&mut fs, &mut dev, a, "user.big").unwrap();
+        let ia = fs.get_inode_by_num(&mut dev, a).unwrap();
+        assert_eq!(ia.file_acl(), 0);
+        assert_eq!(ia.blocks_count(), blocks_before);
+        assert_eq!(refcount(&mut fs, &mut dev, shared), 1);
+        assert_eq!(getxattr(&mut fs, &mut dev, b, "user.big").unwrap(), Some(value.clone()));
+        assert_eq!(getxattr(&mut fs, &mut dev, a, "user.big").unwrap(), None);
+        assert_eq!(fs.superblock.free_blocks_count(), free_before - 1);
+
+        // 再次设置时重新共享 b 的块
+        setxattr(&mut fs, &mut dev, a, "user.big", &value, 0).unwrap();
+        assert_eq!(fs.get_inode_by_num(&mut dev, a).unwrap().file_acl(), shared);
+        assert_eq!(refcount(&mut fs, &mut dev, shared), 2);
+
+        // 删除 inode 时 release_xattr_block 放掉引用，最后一个引用释放块
+        delete_file(&mut fs, &mut dev, "/a").unwrap();
+        assert_eq!(refcount(&mut fs, &mut dev, shared), 1);
+        assert_eq!(fs.superblock.free_blocks_count(), free_before - 1);
+        delete_file(&mut fs, &mut dev, "/b").unwrap();
+        assert_eq!(fs.superblock.free_blocks_count(), free_before);
+        assert!(fs.xattr_cache.candidates(xattr_block_hash(&[attr("user.big", &value)])).is_empty());
+    }
+
+    #[test]
+    fn test_reused_inode_drops_xattrs_and_bumps_generation() {
+        let (mut dev, mut fs) = setup();
+        let (ino, inode) = mkfile_with_ino(&mut dev, &mut fs, "/f", None, None).unwrap();
+        setxattr(&mut fs, &mut dev, ino, "user.k", b"v", 0).unwrap();
+        delete_file(&mut fs, &mut dev, "/f").unwrap();
+
+        let (reused, new_inode) = mkfile_with_ino(&mut dev, &mut fs, "/g", None, None).unwrap();
+        assert_eq!(reused, ino);
+        assert_eq!(new_inode.i_generation, inode.i_generation.wrapping_add(1));
+        assert!(listxattr(&mut fs, &mut dev, reused).unwrap().is_empty());
+    }
+}
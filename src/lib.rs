@@ -6,10 +6,16 @@
 
 extern crate alloc;
 pub mod ext4_backend;
+pub use ext4_backend::acl::*;
 pub use ext4_backend::api::*;
 pub use ext4_backend::blockdev::*;
 pub use ext4_backend::config::*;
 pub use ext4_backend::dir::*;
 pub use ext4_backend::ext4::*;
+pub use ext4_backend::fallocate::*;
+pub use ext4_backend::fiemap::*;
 pub use ext4_backend::file::*;
+pub use ext4_backend::metadata::*;
 pub use ext4_backend::error::*;
+pub use ext4_backend::time::*;
+pub use ext4_backend::xattr::*;
@@ -48,6 +48,9 @@ impl Log for SimpleLogger {
 // 全局静态实例
 static LOGGER: SimpleLogger = SimpleLogger;
 
+/// 宿主机镜像文件的扇区大小，文件系统块大小为其整数倍即可
+const SECTOR_SIZE: u32 = 512;
+
 /// 简单的基于宿主机文件的块设备实现
 struct FileBlockDev {
     file: File,
@@ -57,7 +60,7 @@ struct FileBlockDev {
 impl FileBlockDev {
     fn open_or_create<P: AsRef<Path>>(path: P, total_blocks: u64) -> std::io::Result<Self> {
         let path = path.as_ref();
-        let block_size = BLOCK_SIZE as u64;
+        let block_size = SECTOR_SIZE as u64;
         let size_bytes = total_blocks * block_size;
 
         let file = OpenOptions::new()
@@ -77,7 +80,7 @@ impl FileBlockDev {
 }
 
 impl BlockDevice for FileBlockDev {
-    fn write(&mut self, buffer: &[u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+    fn write(&mut self, buffer: &[u8], block_id: u64, count: u32) -> BlockDevResult<()> {
         let block_size = self.block_size() as usize;
         let required = block_size * count as usize;
         if buffer.len() < required {
@@ -87,7 +90,7 @@ impl BlockDevice for FileBlockDev {
             });
         }
 
-        let offset = block_id as u64 * block_size as u64;
+        let offset = block_id * block_size as u64;
         let bytes = &buffer[..required];
 
         self.file
@@ -100,7 +103,7 @@ impl BlockDevice for FileBlockDev {
         Ok(())
     }
 
-    fn read(&mut self, buffer: &mut [u8], block_id: u32, count: u32) -> BlockDevResult<()> {
+    fn read(&mut self, buffer: &mut [u8], block_id: u64, count: u32) -> BlockDevResult<()> {
         let block_size = self.block_size() as usize;
         let required = block_size * count as usize;
         if buffer.len() < required {
@@ -110,7 +113,7 @@ impl BlockDevice for FileBlockDev {
             });
         }
 
-        let offset = block_id as u64 * block_size as u64;
+        let offset = block_id * block_size as u64;
 
         let mut f = &self.file;
         f.seek(SeekFrom::Start(offset))
@@ -134,7 +137,7 @@ impl BlockDevice for FileBlockDev {
     }
 
     fn block_size(&self) -> u32 {
-        BLOCK_SIZE as u32
+        SECTOR_SIZE
     }
 }
 
@@ -153,11 +156,11 @@ fn main() {
     log::set_max_level(level);
 
     // 简单地创建一个 8G 的镜像文件
-    let blocks: u64 = (8192u64 * 1024 * 1024) / (BLOCK_SIZE as u64);
+    let blocks: u64 = (8192u64 * 1024 * 1024) / (SECTOR_SIZE as u64);
     let img_path = "ext4.img";
 
     info!(
-        "使用宿主机文件作为块设备: {img_path} (blocks={blocks}, block_size={BLOCK_SIZE})"
+        "使用宿主机文件作为块设备: {img_path} (sectors={blocks}, sector_size={SECTOR_SIZE})"
     );
 
     let host_dev = match FileBlockDev::open_or_create(img_path, blocks) {
@@ -169,7 +172,7 @@ fn main() {
     };
 
     // 包一层 Jbd2Dev，开启 journal
-    let mut jbd = Jbd2Dev::initial_jbd2dev(0, host_dev, false);
+    let mut jbd = Jbd2Dev::initial_jbd2dev(JournalMode::Ordered, host_dev, false);
 
     info!("=== 测试 Ext4 mkfs ===");
     test_mkfs(&mut jbd);
@@ -4,7 +4,7 @@ use std::io::Read;
 use std::io::Write;
 //mkfs
 pub fn test_mkfs<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>) {
-    mkfs(block_dev).expect("File system mount failed panic!");
+    mkfs(block_dev, BLOCK_SIZE).expect("File system mount failed panic!");
 }
 
 /// 大文件写入/读取测试
@@ -177,7 +177,7 @@ pub fn test_unlink<B: BlockDevice>(block_dev: &mut Jbd2Dev<B>, fs: &mut Ext4File
         .expect("read target after unlink failed");
     assert_eq!(data_target2, payload);
 
-    delete_file(fs, block_dev, "/linktest_unlink/target");
+    delete_file(fs, block_dev, "/linktest_unlink/target").unwrap();
     assert!(
         get_file_inode(fs, block_dev, "/linktest_unlink/target")
             .ok()
@@ -331,7 +331,7 @@ pub fn _test_journal_powerfail<B: BlockDevice>(
         .expect("flush superblock failed");
 
     // Commit the journal transaction, but do NOT call fs.umount (simulate power loss).
-    block_dev.umount_commit();
+    block_dev.umount_commit().expect("journal commit failed");
     drop(fs);
 
     // Remount: ext4::mount will inject journal superblock and replay.